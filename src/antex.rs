@@ -0,0 +1,394 @@
+use crate::earth::*;
+use crate::observables::*;
+use crate::satellites::*;
+use nalgebra::*;
+use std::collections::HashMap;
+use std::f64::consts::*;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind};
+
+#[derive(Clone, Debug, Default)]
+pub struct AntexFrequency {
+    // satellite offsets are in the body frame (x, y, z), receiver offsets in east,
+    // north, up, the file stores north, east, up in mm
+    pub pco: Vector3<f64>,          // m
+    pub noazi: Vec<f64>,            // m, one value per zenith (nadir) angle
+    pub grid: Vec<(f64, Vec<f64>)>, // (azimuth deg, m per zenith angle)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AntexAntenna {
+    pub antenna_type: String, // receiver type and radome, or satellite block
+    pub serial: String,       // receiver serial, or satellite id such as G05
+    pub prn: Option<usize>,   // gps satellites only
+    pub svn: String,
+    pub dazi: f64,               // deg, 0 without azimuth dependence
+    pub zenith: (f64, f64, f64), // first, last and step (deg)
+    pub valid_from: Option<f64>, // gps sec since the start of gps time
+    pub valid_until: Option<f64>,
+    pub frequencies: HashMap<String, AntexFrequency>, // G01, G02, G05
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AntennaModel {
+    // ionosphere free phase centre offset and variation, satellite offsets are in
+    // the body frame (x, y, z) and receiver offsets in east, north, up
+    pub pco: Vector3<f64>,    // m
+    pub pcv: Vec<(f64, f64)>, // (nadir or zenith angle deg, m), sorted by angle
+}
+
+impl AntennaModel {
+    pub fn pcv_at(&self, angle: f64) -> f64 {
+        // linear in angle, held at the ends of the table
+        match self.pcv.iter().position(|&(a, _)| a >= angle) {
+            None => self.pcv.last().map(|p| p.1).unwrap_or(0.),
+            Some(0) => self.pcv[0].1,
+            Some(i) => {
+                let (a0, v0) = self.pcv[i - 1];
+                let (a1, v1) = self.pcv[i];
+                v0 + (v1 - v0) * (angle - a0) / (a1 - a0)
+            }
+        }
+    }
+}
+
+fn frequency_code(band: Band) -> &'static str {
+    match band {
+        Band::L1 => "G01",
+        Band::L2 => "G02",
+        Band::L5 => "G05",
+    }
+}
+
+impl AntexAntenna {
+    pub fn is_satellite(&self) -> bool {
+        // receiver entries leave the svn and cospar columns blank
+        !self.svn.is_empty()
+    }
+
+    pub fn valid_at(&self, gps_week: i32, gps_time: f64) -> bool {
+        let t = gps_week as f64 * SECONDS_PER_WEEK + gps_time;
+        self.valid_from.is_none_or(|from| t >= from)
+            && self.valid_until.is_none_or(|until| t < until)
+    }
+
+    pub fn pco(&self, band: Band) -> Option<Vector3<f64>> {
+        self.frequencies.get(frequency_code(band)).map(|f| f.pco)
+    }
+
+    pub fn pcv(&self, band: Band, zenith: f64, azimuth: f64) -> Option<f64> {
+        // bilinear in zenith (nadir) angle and azimuth, the non azimuth dependent
+        // values when there is no grid
+        let f = self.frequencies.get(frequency_code(band))?;
+        let (zen1, _, dzen) = self.zenith;
+        let along = |row: &[f64]| -> f64 {
+            if row.is_empty() || dzen <= 0. {
+                return row.first().copied().unwrap_or(0.);
+            }
+            let x = ((zenith - zen1) / dzen).clamp(0., (row.len() - 1) as f64);
+            let i = (x.floor() as usize).min(row.len().saturating_sub(2));
+            let frac = x - i as f64;
+            match row.get(i + 1) {
+                Some(next) => row[i] + (next - row[i]) * frac,
+                None => row[i],
+            }
+        };
+        if self.dazi <= 0. || f.grid.len() < 2 {
+            return Some(along(&f.noazi));
+        }
+        let azimuth = azimuth.rem_euclid(360.);
+        let i = f.grid.partition_point(|(a, _)| *a <= azimuth).max(1) - 1;
+        let j = (i + 1).min(f.grid.len() - 1);
+        let (a0, a1) = (f.grid[i].0, f.grid[j].0);
+        let frac = if a1 > a0 {
+            (azimuth - a0) / (a1 - a0)
+        } else {
+            0.
+        };
+        let (v0, v1) = (along(&f.grid[i].1), along(&f.grid[j].1));
+        Some(v0 + (v1 - v0) * frac)
+    }
+
+    pub fn satellite_offset(
+        &self,
+        band: Band,
+        position: Vector3<f64>,
+        sun: Vector3<f64>,
+    ) -> Option<Vector3<f64>> {
+        // ecef vector from the centre of mass to the phase centre
+        let pco = self.pco(band)?;
+        let (ex, ey, ez) = nominal_attitude(position, sun);
+        Some(ex * pco[0] + ey * pco[1] + ez * pco[2])
+    }
+
+    pub fn satellite_correction(
+        &self,
+        band: Band,
+        position: Vector3<f64>,
+        receiver: Vector3<f64>,
+        sun: Vector3<f64>,
+    ) -> Option<f64> {
+        // range correction (m) added to the centre of mass range, offset projected
+        // on the line of sight plus the variation at the nadir angle, the
+        // azimuth is counted from the body x axis towards y
+        let pco = self.pco(band)?;
+        let (ex, ey, ez) = nominal_attitude(position, sun);
+        let los = (receiver - position).normalize();
+        let offset = ex * pco[0] + ey * pco[1] + ez * pco[2];
+        let nadir = los.dot(&ez).clamp(-1., 1.).acos() * 180. / PI;
+        let azimuth = los.dot(&ey).atan2(los.dot(&ex)) * 180. / PI;
+        Some(-los.dot(&offset) + self.pcv(band, nadir, azimuth)?)
+    }
+
+    pub fn receiver_offset(&self, band: Band) -> Option<Vector3<f64>> {
+        // east, north, up from the antenna reference point to the phase centre
+        self.pco(band)
+    }
+
+    pub fn receiver_correction(&self, band: Band, azimuth: f64, elevation: f64) -> Option<f64> {
+        // range correction (m) for a satellite at azimuth and elevation (deg)
+        let pco = self.pco(band)?;
+        let (az, el) = (azimuth * PI / 180., elevation * PI / 180.);
+        let los = Vector3::new(az.sin() * el.cos(), az.cos() * el.cos(), el.sin());
+        Some(-los.dot(&pco) + self.pcv(band, 90. - elevation, azimuth)?)
+    }
+
+    pub fn ionosphere_free(&self) -> Option<AntennaModel> {
+        // l1/l2 ionosphere free offset and variation averaged over the azimuth grid
+        // for ppp
+        let (f1, f2) = (Band::L1.frequency(), Band::L2.frequency());
+        let (g1, g2) = (f1 * f1 / (f1 * f1 - f2 * f2), f2 * f2 / (f1 * f1 - f2 * f2));
+        let pco = g1 * self.pco(Band::L1)? - g2 * self.pco(Band::L2)?;
+        let (zen1, zen2, dzen) = self.zenith;
+        let steps = if dzen > 0. {
+            ((zen2 - zen1) / dzen).round() as usize
+        } else {
+            0
+        };
+        // 360 deg repeats 0 deg in the grid, so it is left out of the mean
+        let azimuths: Vec<f64> = if self.dazi > 0. {
+            let count = (360. / self.dazi).round() as usize;
+            (0..count).map(|k| k as f64 * self.dazi).collect()
+        } else {
+            vec![0.]
+        };
+        let mut pcv = vec![];
+        for k in 0..=steps {
+            let angle = zen1 + k as f64 * dzen;
+            let mut sum = 0.;
+            for &azimuth in &azimuths {
+                let v1 = self.pcv(Band::L1, angle, azimuth)?;
+                let v2 = self.pcv(Band::L2, angle, azimuth)?;
+                sum += g1 * v1 - g2 * v2;
+            }
+            pcv.push((angle, sum / azimuths.len() as f64));
+        }
+        Some(AntennaModel { pco, pcv })
+    }
+}
+
+pub fn nominal_attitude(
+    position: Vector3<f64>,
+    sun: Vector3<f64>,
+) -> (Vector3<f64>, Vector3<f64>, Vector3<f64>) {
+    // nominal yaw steering body axes in ecef, z to the earth centre, y along the
+    // solar panel axis perpendicular to the sun and x completing the frame
+    let ez = -position.normalize();
+    let es = (sun - position).normalize();
+    let ey = ez.cross(&es).normalize();
+    let ex = ey.cross(&ez);
+    (ex, ey, ez)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Antex {
+    pub version: f64,
+    pub pcv_type: char, // A absolute, R relative
+    pub antennas: Vec<AntexAntenna>,
+}
+
+impl Antex {
+    pub fn satellite(&self, prn: usize, gps_week: i32, gps_time: f64) -> Option<&AntexAntenna> {
+        self.antennas
+            .iter()
+            .find(|a| a.prn == Some(prn) && a.valid_at(gps_week, gps_time))
+    }
+
+    pub fn receiver(&self, antenna_type: &str) -> Option<&AntexAntenna> {
+        // type with radome as in the file (20 characters), falls back to the
+        // antenna without radome (NONE)
+        let wanted = antenna_type.trim();
+        let bare = wanted.get(..16).unwrap_or(wanted).trim();
+        let receivers = || self.antennas.iter().filter(|a| !a.is_satellite());
+        receivers()
+            .find(|a| normalize_type(&a.antenna_type) == normalize_type(wanted))
+            .or_else(|| {
+                receivers().find(|a| {
+                    let t = a.antenna_type.get(..16).unwrap_or(&a.antenna_type).trim();
+                    t == bare && a.antenna_type.get(16..).is_none_or(|r| r.trim() == "NONE")
+                })
+            })
+    }
+
+    pub fn ppp_antennas(
+        &self,
+        receiver_type: &str,
+        gps_week: i32,
+        gps_time: f64,
+    ) -> (Option<AntennaModel>, HashMap<usize, AntennaModel>) {
+        // ionosphere free receiver and satellite models valid at the given time
+        let receiver = self
+            .receiver(receiver_type)
+            .and_then(|a| a.ionosphere_free());
+        let satellites = self
+            .antennas
+            .iter()
+            .filter(|a| a.valid_at(gps_week, gps_time))
+            .filter_map(|a| Some((a.prn?, a.ionosphere_free()?)))
+            .collect();
+        (receiver, satellites)
+    }
+}
+
+fn normalize_type(antenna_type: &str) -> String {
+    antenna_type
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn antex_time(data: &str) -> Option<f64> {
+    let fields: Vec<f64> = data
+        .split_whitespace()
+        .filter_map(|v| v.parse().ok())
+        .collect();
+    if fields.len() < 6 {
+        return None;
+    }
+    let (week, tow) = gps_time_from_date(
+        fields[0] as i32,
+        fields[1] as i32,
+        fields[2] as i32,
+        fields[3] as i32,
+        fields[4] as i32,
+        fields[5],
+    );
+    Some(week as f64 * SECONDS_PER_WEEK + tow)
+}
+
+pub fn read_antex(filename: &str) -> Result<Antex, Error> {
+    let file = File::open(filename)?;
+    let mut antex = Antex::default();
+    let mut antenna: Option<AntexAntenna> = None;
+    let mut frequency: Option<(String, AntexFrequency)> = None;
+    let mut in_rms = false;
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let label = line.get(60..).unwrap_or("").trim();
+        let data = line.get(..60.min(line.len())).unwrap_or("");
+
+        // pattern rows are longer than 60 characters and carry no label
+        if let Some((_, f)) = frequency.as_mut() {
+            if label != "END OF FREQUENCY" && label != "NORTH / EAST / UP" {
+                let mut tokens = line.split_whitespace();
+                let first = tokens.next().unwrap_or("");
+                let values: Vec<f64> = tokens
+                    .filter_map(|v| v.parse::<f64>().ok())
+                    .map(|v| v * 1e-3)
+                    .collect();
+                if first == "NOAZI" {
+                    f.noazi = values;
+                } else if let Ok(azimuth) = first.parse::<f64>() {
+                    f.grid.push((azimuth, values));
+                }
+                continue;
+            }
+        }
+        if in_rms {
+            in_rms = label != "END OF FREQ RMS";
+            continue;
+        }
+
+        match label {
+            "ANTEX VERSION / SYST" => {
+                antex.version = data.get(..8).unwrap_or("").trim().parse().unwrap_or(0.)
+            }
+            "PCV TYPE / REFANT" => antex.pcv_type = data.chars().next().unwrap_or('A'),
+            "START OF ANTENNA" => antenna = Some(AntexAntenna::default()),
+            "TYPE / SERIAL NO" => {
+                if let Some(a) = antenna.as_mut() {
+                    a.antenna_type = data.get(..20).unwrap_or("").trim_end().to_string();
+                    a.serial = data.get(20..40).unwrap_or("").trim().to_string();
+                    a.svn = data.get(40..50).unwrap_or("").trim().to_string();
+                    if a.is_satellite() {
+                        a.prn = a.serial.strip_prefix('G').and_then(|p| p.parse().ok());
+                    }
+                }
+            }
+            "DAZI" => {
+                if let Some(a) = antenna.as_mut() {
+                    a.dazi = data.trim().parse().unwrap_or(0.);
+                }
+            }
+            "ZEN1 / ZEN2 / DZEN" => {
+                if let Some(a) = antenna.as_mut() {
+                    let v: Vec<f64> = data
+                        .split_whitespace()
+                        .filter_map(|v| v.parse().ok())
+                        .collect();
+                    if v.len() == 3 {
+                        a.zenith = (v[0], v[1], v[2]);
+                    }
+                }
+            }
+            "VALID FROM" => {
+                if let Some(a) = antenna.as_mut() {
+                    a.valid_from = antex_time(data);
+                }
+            }
+            "VALID UNTIL" => {
+                if let Some(a) = antenna.as_mut() {
+                    a.valid_until = antex_time(data);
+                }
+            }
+            "START OF FREQUENCY" => {
+                frequency = Some((data.trim().to_string(), AntexFrequency::default()))
+            }
+            "NORTH / EAST / UP" => {
+                if let Some((_, f)) = frequency.as_mut() {
+                    let v: Vec<f64> = data
+                        .split_whitespace()
+                        .filter_map(|v| v.parse().ok())
+                        .collect();
+                    if v.len() == 3 {
+                        f.pco = Vector3::new(v[0], v[1], v[2]) * 1e-3;
+                    }
+                }
+            }
+            "END OF FREQUENCY" => {
+                if let (Some(a), Some((code, mut f))) = (antenna.as_mut(), frequency.take()) {
+                    if !a.is_satellite() {
+                        // north, east, up to east, north, up
+                        f.pco = Vector3::new(f.pco[1], f.pco[0], f.pco[2]);
+                    }
+                    a.frequencies.insert(code, f);
+                }
+            }
+            "START OF FREQ RMS" => in_rms = true,
+            "END OF ANTENNA" => {
+                if let Some(a) = antenna.take() {
+                    antex.antennas.push(a);
+                }
+            }
+            _ => {}
+        }
+    }
+    if antex.antennas.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "no antennas in antex file",
+        ));
+    }
+    Ok(antex)
+}
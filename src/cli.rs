@@ -0,0 +1,1639 @@
+use crate::codes::*;
+use crate::doppler::*;
+use crate::earth::*;
+use crate::mat::*;
+use crate::nmea::*;
+use crate::observables::*;
+use crate::rinex_clk::*;
+use crate::rinex_nav::*;
+use crate::rinex_obs::*;
+use crate::rtcm::*;
+use crate::samples::*;
+use crate::satellites::*;
+use crate::solver::*;
+use crate::sp3::*;
+use crate::tracks::*;
+use crate::ubx::*;
+use nalgebra::*;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Read, Write};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+// process exit codes
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_NO_RESULT: i32 = 1; // ran but nothing acquired, solved or found
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_INPUT: i32 = 3; // unreadable or malformed input, failed output
+
+const USAGE: &str = "usage: rgps <command> [arguments] [options]
+
+commands:
+  acquire <if-file>        search for gps l1 c/a signals in intermediate frequency samples
+      --sample-rate <Hz>   required
+      --if <Hz>            intermediate frequency, 0 for baseband (default 0)
+      --format <f>         int8, uint8, int16, float32, float64, 2bit, 4bit (default int8)
+      --complex            interleaved i/q samples, the in-phase component is searched
+      --skip-bytes <n>     --skip-seconds <s>
+      --prn <list>         e.g. 1,3,10-20, gps 1-63, sbas 120-158 (default 1-32)
+      --doppler <Hz>       search +/- range (default 5000)
+      --doppler-step <Hz>  (default 500)
+      --blocks <n>         1 ms blocks integrated non-coherently (default 1)
+      --threshold <r>      peak to second peak ratio for a detection (default 2.5)
+      --mat <file>         write the correlation maps
+  orbit <nav-file>         satellite states from a rinex 2 navigation file
+      --list               list the ephemerides instead
+      --prn <list>         --week <w>
+      --time <tow>         or --start <tow> --end <tow> [--step <s>] (default step 900)
+      --receiver <x,y,z>   or --lla <lat,lon,h>, adds azimuth and elevation
+      --elevation-mask <deg>  with a receiver (default 0)
+      --out <file>         .sp3, .kml, .gpx, .geojson or .mat
+  solve <obs-file>         single point positions from rinex observations or a ubx log
+      --nav <nav-file>     required for rinex observations
+      --week <w>           gps week for ubx logs (default current)
+      --elevation-mask <deg>  --no-troposphere  --no-ionosphere
+      --out <file>         .kml, .gpx, .geojson or .mat
+  convert <input> <output> convert between formats, chosen from the file contents and
+                           the output extension
+      --interval <s>       sp3 and trajectory interval (default 900)
+      --step <s>           ground track step (default 60)
+      --version <v>        rinex output version, 2 or 3 (default 3)
+      --week <w>           --leap-seconds <s> (default 18)
+  info <file>              summary of any supported file
+
+output:
+  --output <f>             text, csv or json (default text)
+
+exit codes:
+  0 success, 1 no result, 2 usage error, 3 input or output error";
+
+#[derive(Debug)]
+enum CliError {
+    Usage(String),
+    Input(Error),
+    NoResult(String),
+}
+
+impl From<Error> for CliError {
+    fn from(e: Error) -> Self {
+        CliError::Input(e)
+    }
+}
+
+type CliResult = Result<(), CliError>;
+
+fn usage(message: impl Into<String>) -> CliError {
+    CliError::Usage(message.into())
+}
+
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String], options: &[&str], flags: &[&str]) -> Result<Args, CliError> {
+        // --name value, --name=value and bare flags, --output is accepted everywhere
+        let mut parsed = Args {
+            positional: vec![],
+            options: HashMap::new(),
+            flags: vec![],
+        };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name,
+                None => {
+                    parsed.positional.push(arg.clone());
+                    continue;
+                }
+            };
+            let (name, inline) = match name.split_once('=') {
+                Some((n, v)) => (n, Some(v.to_string())),
+                None => (name, None),
+            };
+            if flags.contains(&name) {
+                parsed.flags.push(name.to_string());
+            } else if options.contains(&name) || name == "output" {
+                let value = match inline {
+                    Some(v) => v,
+                    None => iter
+                        .next()
+                        .cloned()
+                        .ok_or_else(|| usage(format!("--{} needs a value", name)))?,
+                };
+                parsed.options.insert(name.to_string(), value);
+            } else {
+                return Err(usage(format!("unknown option --{}", name)));
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|f| f == name)
+    }
+
+    fn option<T: FromStr>(&self, name: &str) -> Result<Option<T>, CliError> {
+        self.options
+            .get(name)
+            .map(|v| {
+                v.parse::<T>()
+                    .map_err(|_| usage(format!("invalid value {} for --{}", v, name)))
+            })
+            .transpose()
+    }
+
+    fn get<T: FromStr>(&self, name: &str, default: T) -> Result<T, CliError> {
+        Ok(self.option(name)?.unwrap_or(default))
+    }
+
+    fn input(&self, index: usize, what: &str) -> Result<&str, CliError> {
+        self.positional
+            .get(index)
+            .map(|s| s.as_str())
+            .ok_or_else(|| usage(format!("missing {}", what)))
+    }
+
+    fn prns(&self) -> Result<Vec<usize>, CliError> {
+        // comma separated prns and ranges, gps 1-63 and sbas 120-158 with the c/a
+        // code defined, ranges skip the numbers in between
+        let list = match self.options.get("prn") {
+            Some(list) => list,
+            None => return Ok((1..=32).collect()),
+        };
+        let valid = |prn: &usize| (1..=63).contains(prn) || (120..=158).contains(prn);
+        let mut prns = vec![];
+        for item in list.split(',') {
+            let bad = || usage(format!("invalid prn list {}", list));
+            let (a, b) = item.split_once('-').unwrap_or((item, item));
+            let a: usize = a.trim().parse().map_err(|_| bad())?;
+            let b: usize = b.trim().parse().map_err(|_| bad())?;
+            if !valid(&a) || !valid(&b) || b < a {
+                return Err(bad());
+            }
+            prns.extend((a..=b).filter(valid));
+        }
+        prns.sort();
+        prns.dedup();
+        Ok(prns)
+    }
+
+    fn vector(&self, name: &str) -> Result<Option<Vector3<f64>>, CliError> {
+        // three comma separated values
+        let text = match self.options.get(name) {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        let values: Vec<f64> = text
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| usage(format!("invalid value {} for --{}", text, name)))?;
+        match values[..] {
+            [x, y, z] => Ok(Some(Vector3::new(x, y, z))),
+            _ => Err(usage(format!("--{} needs three values", name))),
+        }
+    }
+
+    fn output(&self) -> Result<OutputFormat, CliError> {
+        match self.options.get("output").map(|s| s.as_str()) {
+            None | Some("text") => Ok(OutputFormat::Text),
+            Some("csv") => Ok(OutputFormat::Csv),
+            Some("json") => Ok(OutputFormat::Json),
+            Some(other) => Err(usage(format!("unknown output format {}", other))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Int(i64),
+    Float(f64, usize), // value and decimals
+    Text(String),
+    Bool(bool),
+    List(Vec<Value>),
+    Missing,
+}
+
+impl Value {
+    fn text(&self) -> String {
+        match self {
+            Value::Int(i) => i.to_string(),
+            Value::Float(f, d) => format!("{:.*}", d, f),
+            Value::Text(s) => s.clone(),
+            Value::Bool(b) => b.to_string(),
+            Value::List(l) => l.iter().map(|v| v.text()).collect::<Vec<_>>().join(" "),
+            Value::Missing => String::new(),
+        }
+    }
+
+    fn csv(&self) -> String {
+        let text = self.text();
+        if text.contains([',', '"', '\n']) {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        } else {
+            text
+        }
+    }
+
+    fn json(&self) -> String {
+        match self {
+            Value::Float(f, _) if !f.is_finite() => "null".to_string(),
+            Value::Int(_) | Value::Float(..) | Value::Bool(_) => self.text(),
+            Value::Text(s) => format!("\"{}\"", json_escape(s)),
+            Value::List(l) => format!(
+                "[{}]",
+                l.iter().map(|v| v.json()).collect::<Vec<_>>().join(",")
+            ),
+            Value::Missing => "null".to_string(),
+        }
+    }
+}
+
+fn float(value: f64, decimals: usize) -> Value {
+    Value::Float(value, decimals)
+}
+
+fn text(value: impl Into<String>) -> Value {
+    Value::Text(value.into())
+}
+
+fn optional(value: Option<f64>, decimals: usize) -> Value {
+    value.map_or(Value::Missing, |v| Value::Float(v, decimals))
+}
+
+fn prn_list(prns: &[usize]) -> Value {
+    Value::List(prns.iter().map(|&p| Value::Int(p as i64)).collect())
+}
+
+struct Table {
+    columns: Vec<&'static str>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+    fn new(columns: &[&'static str]) -> Self {
+        Table {
+            columns: columns.to_vec(),
+            rows: vec![],
+        }
+    }
+
+    fn write<W: Write>(&self, out: &mut W, format: OutputFormat) -> Result<(), Error> {
+        match format {
+            OutputFormat::Text => {
+                let cells: Vec<Vec<String>> = self
+                    .rows
+                    .iter()
+                    .map(|r| r.iter().map(|v| v.text()).collect())
+                    .collect();
+                let widths: Vec<usize> = (0..self.columns.len())
+                    .map(|i| {
+                        cells
+                            .iter()
+                            .map(|r| r[i].len())
+                            .chain([self.columns[i].len()])
+                            .max()
+                            .unwrap_or(0)
+                    })
+                    .collect();
+                let line = |values: Vec<&str>| {
+                    values
+                        .iter()
+                        .zip(&widths)
+                        .map(|(v, &w)| format!("{:>w$}", v))
+                        .collect::<Vec<_>>()
+                        .join("  ")
+                };
+                writeln!(out, "{}", line(self.columns.clone()))?;
+                for row in &cells {
+                    writeln!(out, "{}", line(row.iter().map(|s| s.as_str()).collect()))?;
+                }
+            }
+            OutputFormat::Csv => {
+                writeln!(out, "{}", self.columns.join(","))?;
+                for row in &self.rows {
+                    let values: Vec<String> = row.iter().map(|v| v.csv()).collect();
+                    writeln!(out, "{}", values.join(","))?;
+                }
+            }
+            OutputFormat::Json => {
+                writeln!(out, "[")?;
+                for (i, row) in self.rows.iter().enumerate() {
+                    let fields: Vec<String> = self
+                        .columns
+                        .iter()
+                        .zip(row)
+                        .map(|(c, v)| format!("\"{}\":{}", c, v.json()))
+                        .collect();
+                    let comma = if i + 1 < self.rows.len() { "," } else { "" };
+                    writeln!(out, "{{{}}}{}", fields.join(","), comma)?;
+                }
+                writeln!(out, "]")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn print(&self, format: OutputFormat) -> Result<(), Error> {
+        let mut out = BufWriter::new(std::io::stdout().lock());
+        self.write(&mut out, format)?;
+        out.flush()
+    }
+}
+
+fn print_summary(items: &[(&str, Value)], format: OutputFormat) -> Result<(), Error> {
+    // key value pairs, a json object or two column csv
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    match format {
+        OutputFormat::Text => {
+            let width = items.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+            for (key, value) in items {
+                writeln!(out, "{:<width$}  {}", key, value.text())?;
+            }
+        }
+        OutputFormat::Csv => {
+            writeln!(out, "key,value")?;
+            for (key, value) in items {
+                writeln!(out, "{},{}", key, value.csv())?;
+            }
+        }
+        OutputFormat::Json => {
+            let fields: Vec<String> = items
+                .iter()
+                .map(|(k, v)| format!("\"{}\":{}", k, v.json()))
+                .collect();
+            writeln!(out, "{{{}}}", fields.join(","))?;
+        }
+    }
+    out.flush()
+}
+
+fn gps_date(gps_week: i32, gps_time: f64) -> String {
+    let (y, mo, d, h, mi, s) = date_from_gps_time(gps_week, (gps_time * 1e3).round() / 1e3);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:06.3}", y, mo, d, h, mi, s)
+}
+
+fn current_week() -> i32 {
+    // gps week from the system clock, leap seconds do not matter at this resolution
+    let seconds = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.);
+    ((seconds - 315_964_800.) / SECONDS_PER_WEEK).floor() as i32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FileKind {
+    RinexObs,
+    RinexNav,
+    RinexClock,
+    Sp3,
+    Ubx,
+    Rtcm,
+    Nmea,
+    Mat,
+    Track, // kml, gpx or geojson, output only
+}
+
+fn detect_input(filename: &str) -> Result<FileKind, Error> {
+    // from the leading bytes of the file
+    let mut head = vec![0; 4096];
+    let n = File::open(filename)
+        .map_err(|e| Error::new(e.kind(), format!("{}: {}", filename, e)))?
+        .read(&mut head)?;
+    head.truncate(n);
+    let first_line = String::from_utf8_lossy(&head)
+        .lines()
+        .next()
+        .unwrap_or("")
+        .to_string();
+    let kind = if head.starts_with(b"MATLAB") {
+        Some(FileKind::Mat)
+    } else if head.starts_with(&[0xb5, 0x62]) {
+        Some(FileKind::Ubx)
+    } else if head.len() > 1 && head[0] == 0xd3 && head[1] & 0xfc == 0 {
+        Some(FileKind::Rtcm)
+    } else if first_line.contains("RINEX VERSION / TYPE") {
+        match first_line.chars().nth(20) {
+            Some('O') => Some(FileKind::RinexObs),
+            Some('N') => Some(FileKind::RinexNav),
+            Some('C') => Some(FileKind::RinexClock),
+            _ => None,
+        }
+    } else if first_line.starts_with('#')
+        && "abcd".contains(first_line.chars().nth(1).unwrap_or(' '))
+    {
+        Some(FileKind::Sp3)
+    } else if first_line.contains('$') {
+        Some(FileKind::Nmea)
+    } else {
+        None
+    };
+    kind.ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: unrecognised file format", filename),
+        )
+    })
+}
+
+fn detect_output(filename: &str) -> Option<FileKind> {
+    // from the extension, rinex 2 short names (.23o, .23n) and rinex 3 long names
+    // (_MO.rnx, _GN.rnx) included
+    let lower = filename.to_lowercase();
+    if TrackFormat::from_filename(&lower).is_some() {
+        return Some(FileKind::Track);
+    }
+    let extension = lower.rsplit_once('.')?.1;
+    match extension {
+        "sp3" => Some(FileKind::Sp3),
+        "mat" => Some(FileKind::Mat),
+        "obs" => Some(FileKind::RinexObs),
+        "nav" => Some(FileKind::RinexNav),
+        "rnx" if lower.ends_with("o.rnx") => Some(FileKind::RinexObs),
+        "rnx" if lower.ends_with("n.rnx") => Some(FileKind::RinexNav),
+        e if e.len() == 3 && e[..2].chars().all(|c| c.is_ascii_digit()) => match &e[2..] {
+            "o" => Some(FileKind::RinexObs),
+            "n" => Some(FileKind::RinexNav),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn sample_format(name: &str) -> Result<SampleFormat, CliError> {
+    match name {
+        "int8" => Ok(SampleFormat::Int8),
+        "uint8" => Ok(SampleFormat::Uint8),
+        "int16" => Ok(SampleFormat::Int16),
+        "float32" => Ok(SampleFormat::Float32),
+        "float64" => Ok(SampleFormat::Float64),
+        "2bit" => Ok(SampleFormat::Packed2Bit),
+        "4bit" => Ok(SampleFormat::Packed4Bit),
+        _ => Err(usage(format!("unknown sample format {}", name))),
+    }
+}
+
+struct Acquisition {
+    prn: usize,
+    doppler: f64,       // Hz
+    code_offset: usize, // samples from the start of the block to the code start
+    code_phase: f64,    // chips
+    metric: f64,        // peak over second peak
+    correlation: DMatrix<f64>,
+}
+
+fn acquire_prn(
+    prn: usize,
+    blocks: &[DVector<f64>],
+    dopplers: &DVector<f64>,
+    intermediate_freq: f64,
+    sample_rate: f64,
+) -> Acquisition {
+    // non-coherent sum of 1 ms correlation maps, the second peak is the largest value
+    // in the doppler column more than a chip away from the main peak
+    let n = blocks[0].len();
+    let time = DVector::from_fn(n, |i, _| i as f64 / sample_rate);
+    let mut code = gen_ca_code(prn);
+    bpsk_map(&mut code);
+    let mut correlation = DMatrix::zeros(n, dopplers.len());
+    for block in blocks {
+        correlation += correlation_magnitude(
+            dopplers.clone(),
+            &code,
+            Modulation::Bpsk,
+            block.clone(),
+            intermediate_freq,
+            time.clone(),
+        );
+    }
+    let (row, col) = correlation.iamax_full();
+    let peak = correlation[(row, col)];
+    let chip = (n as f64 / 1023.).ceil() as usize + 1;
+    let second = (0..n)
+        .filter(|&i| {
+            let d = (i as isize - row as isize).unsigned_abs();
+            d.min(n - d) > chip
+        })
+        .map(|i| correlation[(i, col)])
+        .fold(0., f64::max);
+    let code_offset = (row + n - n / 2) % n;
+    Acquisition {
+        prn,
+        doppler: dopplers[col],
+        code_offset,
+        code_phase: code_offset as f64 * 1023. / n as f64,
+        metric: if second > 0. { peak / second } else { 0. },
+        correlation,
+    }
+}
+
+fn acquire(args: &[String]) -> CliResult {
+    let args = Args::parse(
+        args,
+        &[
+            "sample-rate",
+            "if",
+            "format",
+            "skip-bytes",
+            "skip-seconds",
+            "prn",
+            "doppler",
+            "doppler-step",
+            "blocks",
+            "threshold",
+            "mat",
+        ],
+        &["complex"],
+    )?;
+    let filename = args.input(0, "intermediate frequency sample file")?;
+    let sample_rate: f64 = args
+        .option("sample-rate")?
+        .ok_or_else(|| usage("--sample-rate is required"))?;
+    let mut config = SampleConfig::new(
+        sample_format(&args.get("format", "int8".to_string())?)?,
+        args.flag("complex"),
+        sample_rate,
+        args.get("if", 0.)?,
+    );
+    config.skip_bytes = args.get("skip-bytes", 0)?;
+    let range: f64 = args.get("doppler", 5000.)?;
+    let step: f64 = args.get("doppler-step", 500.)?;
+    let count: usize = args.get("blocks", 1)?;
+    let threshold: f64 = args.get("threshold", 2.5)?;
+    if sample_rate < 2.046e6 || step <= 0. || range < 0. || count == 0 {
+        return Err(usage(
+            "sample rate, doppler step and blocks must be positive",
+        ));
+    }
+    let prns = args.prns()?;
+
+    let mut reader = SampleReader::open(filename, config)?;
+    reader.skip_seconds(args.get("skip-seconds", 0.)?)?;
+    let n = reader.config().samples_per_code();
+    let mut blocks = vec![];
+    for _ in 0..count {
+        let block = reader.read_block_real(n)?;
+        if block.len() < n {
+            return Err(CliError::Input(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("{}: fewer than {} ms of samples", filename, count),
+            )));
+        }
+        blocks.push(block);
+    }
+    let bins = (range / step).floor() as usize;
+    let dopplers = DVector::from_fn(2 * bins + 1, |i, _| (i as f64 - bins as f64) * step);
+    let intermediate_freq = reader.config().intermediate_freq;
+    let results: Vec<Acquisition> = prns
+        .par_iter()
+        .map(|&prn| acquire_prn(prn, &blocks, &dopplers, intermediate_freq, sample_rate))
+        .collect();
+
+    if let Some(mat) = args.options.get("mat") {
+        let mut writer = MatWriter::create(mat)?;
+        let lags = DVector::from_fn(n, |i, _| i as f64 / sample_rate);
+        writer.write_vector("delay", &lags)?;
+        writer.write_vector("doppler", &dopplers)?;
+        for r in &results {
+            // rows rotated so row i is a code start i samples into the block
+            let n = r.correlation.nrows();
+            let rotated = DMatrix::from_fn(n, r.correlation.ncols(), |i, j| {
+                r.correlation[((i + n / 2) % n, j)]
+            });
+            writer.write_matrix(&format!("correlation_g{:02}", r.prn), &rotated)?;
+        }
+        writer.flush()?;
+    }
+
+    let mut table = Table::new(&[
+        "prn",
+        "acquired",
+        "doppler",
+        "code_phase",
+        "code_offset",
+        "metric",
+    ]);
+    for r in &results {
+        table.rows.push(vec![
+            Value::Int(r.prn as i64),
+            Value::Bool(r.metric >= threshold),
+            float(r.doppler, 1),
+            float(r.code_phase, 3),
+            Value::Int(r.code_offset as i64),
+            float(r.metric, 3),
+        ]);
+    }
+    table.print(args.output()?)?;
+    if results.iter().any(|r| r.metric >= threshold) {
+        Ok(())
+    } else {
+        Err(CliError::NoResult("no satellites acquired".to_string()))
+    }
+}
+
+fn read_nav(filename: &str) -> Result<Vec<SatelliteData>, CliError> {
+    if detect_input(filename)? != FileKind::RinexNav {
+        return Err(CliError::Input(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is not a rinex navigation file", filename),
+        )));
+    }
+    if rinex_version(filename)? >= 3. {
+        return Err(CliError::Input(Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: only rinex 2 navigation files can be read", filename),
+        )));
+    }
+    let ephemerides = rinex2_nav_all(filename)?;
+    if ephemerides.is_empty() {
+        return Err(CliError::Input(Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: no gps ephemerides", filename),
+        )));
+    }
+    Ok(ephemerides)
+}
+
+fn nav_week(ephemerides: &[SatelliteData]) -> i32 {
+    // week of the earliest clock reference time
+    ephemerides
+        .iter()
+        .map(|e| e.toc_epoch())
+        .min_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)))
+        .map_or(0, |(w, _)| w)
+}
+
+fn orbit(args: &[String]) -> CliResult {
+    let args = Args::parse(
+        args,
+        &[
+            "prn",
+            "week",
+            "time",
+            "start",
+            "end",
+            "step",
+            "receiver",
+            "lla",
+            "elevation-mask",
+            "out",
+        ],
+        &["list"],
+    )?;
+    let ephemerides = read_nav(args.input(0, "navigation file")?)?;
+    let prns = args.prns()?;
+    let format = args.output()?;
+
+    if args.flag("list") {
+        let mut table = Table::new(&[
+            "prn", "toc_week", "toc", "toe", "iode", "healthy", "tgd", "date",
+        ]);
+        let mut sorted: Vec<&SatelliteData> = ephemerides
+            .iter()
+            .filter(|e| prns.contains(&e.prn()))
+            .collect();
+        sorted.sort_by(|a, b| {
+            let (wa, ta) = a.toc_epoch();
+            let (wb, tb) = b.toc_epoch();
+            a.prn()
+                .cmp(&b.prn())
+                .then(wa.cmp(&wb))
+                .then(ta.total_cmp(&tb))
+        });
+        for e in &sorted {
+            let (week, toc) = e.toc_epoch();
+            table.rows.push(vec![
+                Value::Int(e.prn() as i64),
+                Value::Int(week as i64),
+                float(toc, 1),
+                float(e.toe(), 1),
+                Value::Int(e.iode() as i64),
+                Value::Bool(e.healthy()),
+                float(e.tgd(), 12),
+                text(gps_date(week, toc)),
+            ]);
+        }
+        table.print(format)?;
+        return if sorted.is_empty() {
+            Err(CliError::NoResult(
+                "no ephemerides for the prns".to_string(),
+            ))
+        } else {
+            Ok(())
+        };
+    }
+
+    let week = args.get("week", nav_week(&ephemerides))?;
+    let (start, end) = match (
+        args.option::<f64>("time")?,
+        args.option::<f64>("start")?,
+        args.option::<f64>("end")?,
+    ) {
+        (Some(t), None, None) => (t, t),
+        (None, Some(s), Some(e)) if e >= s => (s, e),
+        _ => {
+            return Err(usage(
+                "give --time, or --start and --end with end after start",
+            ))
+        }
+    };
+    let step: f64 = args.get("step", 900.)?;
+    if step <= 0. {
+        return Err(usage("--step must be positive"));
+    }
+    let receiver = match (args.vector("receiver")?, args.vector("lla")?) {
+        (Some(_), Some(_)) => return Err(usage("give only one of --receiver and --lla")),
+        (Some(r), None) => Some(r),
+        (None, Some(lla)) => Some(geodetic2ecef(lla, 0)),
+        (None, None) => None,
+    };
+    let mask: f64 = args.get("elevation-mask", 0.)?;
+    let times: Vec<f64> = (0..=((end - start) / step + 1e-9).floor() as usize)
+        .map(|i| start + i as f64 * step)
+        .collect();
+
+    let mut columns = vec![
+        "prn",
+        "gps_week",
+        "gps_time",
+        "x",
+        "y",
+        "z",
+        "vx",
+        "vy",
+        "vz",
+        "clock_bias",
+        "latitude",
+        "longitude",
+        "height",
+    ];
+    if receiver.is_some() {
+        columns.extend(["azimuth", "elevation"]);
+    }
+    let mut table = Table::new(&columns);
+    for &t in &times {
+        let w = week + (t / SECONDS_PER_WEEK).floor() as i32;
+        let tow = t.rem_euclid(SECONDS_PER_WEEK);
+        for &prn in &prns {
+            let eph = match select_ephemeris(&ephemerides, prn, tow) {
+                Some(e) => e,
+                None => continue,
+            };
+            let sv = eph.state_at(tow);
+            let lla = ecef2geodetic(sv.position, 0);
+            let mut row = vec![Value::Int(prn as i64), Value::Int(w as i64), float(tow, 3)];
+            row.extend(sv.position.iter().map(|&v| float(v, 3)));
+            row.extend(sv.velocity.iter().map(|&v| float(v, 4)));
+            row.push(float(sv.clock_bias, 12));
+            row.extend([float(lla[0], 8), float(lla[1], 8), float(lla[2], 3)]);
+            if let Some(r) = receiver {
+                let (azimuth, elevation) = look_angles(r, sv.position);
+                if elevation < mask {
+                    continue;
+                }
+                row.extend([float(azimuth, 2), float(elevation, 2)]);
+            }
+            table.rows.push(row);
+        }
+    }
+
+    if let Some(out) = args.options.get("out") {
+        let selected: Vec<SatelliteData> = ephemerides
+            .iter()
+            .filter(|e| prns.contains(&e.prn()))
+            .cloned()
+            .collect();
+        match detect_output(out) {
+            Some(FileKind::Sp3) => {
+                let sp3 = broadcast_sp3(&selected, week, start, end - start + step, step, false);
+                write_sp3(out, &sp3)?;
+            }
+            Some(FileKind::Track) => {
+                let export = TrackExport {
+                    tracks: ground_tracks(&selected, week, start, end - start, step),
+                    waypoints: subsatellite_points(&selected, week, start),
+                    ..TrackExport::default()
+                };
+                export.write(out)?;
+            }
+            Some(FileKind::Mat) => {
+                let sp3 = broadcast_sp3(&selected, week, start, end - start + step, step, false);
+                write_trajectories_mat(out, &sp3_trajectories(&sp3))?;
+            }
+            _ => return Err(usage(format!("cannot write orbits to {}", out))),
+        }
+    }
+
+    table.print(format)?;
+    if table.rows.is_empty() {
+        Err(CliError::NoResult("no satellite states".to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+fn solution_table(solutions: &[PositionSolution]) -> Table {
+    let mut table = Table::new(&[
+        "gps_week",
+        "gps_time",
+        "x",
+        "y",
+        "z",
+        "latitude",
+        "longitude",
+        "height",
+        "clock_bias",
+        "satellites",
+        "gdop",
+        "pdop",
+        "hdop",
+        "vdop",
+    ]);
+    for s in solutions {
+        let lla = s.geodetic();
+        let mut row = vec![Value::Int(s.gps_week as i64), float(s.gps_time, 3)];
+        row.extend(s.position.iter().map(|&v| float(v, 3)));
+        row.extend([float(lla[0], 8), float(lla[1], 8), float(lla[2], 3)]);
+        row.extend([float(s.clock_bias, 3), Value::Int(s.prns.len() as i64)]);
+        row.extend(
+            [s.gdop, s.pdop, s.hdop, s.vdop]
+                .iter()
+                .map(|&d| float(d, 2)),
+        );
+        table.rows.push(row);
+    }
+    table
+}
+
+fn solve(args: &[String]) -> CliResult {
+    let args = Args::parse(
+        args,
+        &["nav", "week", "elevation-mask", "out"],
+        &["no-troposphere", "no-ionosphere"],
+    )?;
+    let filename = args.input(0, "observation file")?;
+    let format = args.output()?;
+    let out = args.options.get("out");
+    let out_kind = out.map(|o| detect_output(o));
+    if let Some(kind) = out_kind {
+        if !matches!(kind, Some(FileKind::Track) | Some(FileKind::Mat)) {
+            return Err(usage(format!("cannot write solutions to {}", out.unwrap())));
+        }
+    }
+
+    let mut ephemerides = vec![];
+    let mut nav_header = None;
+    if let Some(nav) = args.options.get("nav") {
+        ephemerides = read_nav(nav)?;
+        nav_header = rinex2_nav_header(nav).ok();
+    }
+    let (epochs, mut position) = match detect_input(filename)? {
+        FileKind::RinexObs => {
+            let (header, epochs) = read_rinex_obs(filename)?;
+            (epochs, header.approx_position)
+        }
+        FileKind::Ubx => {
+            let log = read_ubx_log(filename, args.get("week", current_week())?)?;
+            ephemerides.extend(log.ephemerides);
+            (log.epochs, Vector3::zeros())
+        }
+        _ => {
+            return Err(CliError::Input(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} is not a rinex observation file or ubx log", filename),
+            )))
+        }
+    };
+    if ephemerides.is_empty() {
+        return Err(usage("no ephemerides, give a navigation file with --nav"));
+    }
+    let mut config = SolverConfig {
+        elevation_mask: args.get("elevation-mask", 10.)?,
+        troposphere: !args.flag("no-troposphere"),
+        ..SolverConfig::default()
+    };
+    if let (Some(header), false) = (&nav_header, args.flag("no-ionosphere")) {
+        config = config.with_nav_header(header);
+    }
+
+    // each epoch starts from the previous solution
+    let mut solutions = vec![];
+    for epoch in &epochs {
+        if let Some(solution) = solve_position(epoch, &ephemerides, position, &config) {
+            position = solution.position;
+            solutions.push(solution);
+        }
+    }
+
+    if let Some(out) = out {
+        if out_kind == Some(Some(FileKind::Mat)) {
+            write_solutions_mat(out, &solutions)?;
+        } else {
+            let export = TrackExport {
+                tracks: vec![Track::new("solution", &solutions)],
+                ..TrackExport::default()
+            };
+            export.write(out)?;
+        }
+    }
+
+    solution_table(&solutions).print(format)?;
+    if solutions.is_empty() {
+        Err(CliError::NoResult(format!(
+            "none of the {} epochs could be solved",
+            epochs.len()
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+struct UbxLog {
+    epochs: Vec<ObservationEpoch>,
+    ephemerides: Vec<SatelliteData>,
+    others: Vec<UbxMessage>,
+}
+
+fn read_ubx_log(filename: &str, gps_week: i32) -> Result<UbxLog, Error> {
+    // raw observations and ephemerides, the remaining messages as read
+    let mut log = UbxLog {
+        epochs: vec![],
+        ephemerides: vec![],
+        others: vec![],
+    };
+    for message in read_ubx(filename, gps_week)? {
+        match message {
+            UbxMessage::Rawx(rawx) => log.epochs.push(rawx.observations()),
+            UbxMessage::Ephemeris(eph) => log.ephemerides.push(*eph),
+            other => log.others.push(other),
+        }
+    }
+    Ok(log)
+}
+
+fn ubx_obs_header(filename: &str, version: f64, messages: &[UbxMessage]) -> RinexObsHeader {
+    // marker from the file name, approximate position from the first nav-pvt fix
+    let marker = std::path::Path::new(filename)
+        .file_stem()
+        .map_or("UNKNOWN".to_string(), |s| {
+            s.to_string_lossy().to_uppercase()
+        });
+    let approx_position = messages
+        .iter()
+        .find_map(|m| match m {
+            UbxMessage::NavPvt(pvt) if pvt.quality() > 0 => Some(pvt.position()),
+            _ => None,
+        })
+        .unwrap_or_else(Vector3::zeros);
+    RinexObsHeader {
+        version,
+        marker_name: marker,
+        receiver: "U-BLOX".to_string(),
+        antenna: String::new(),
+        approx_position,
+        antenna_delta: Vector3::zeros(),
+        interval: 0.,
+        obs_types: HashMap::new(),
+    }
+}
+
+fn convert(args: &[String]) -> CliResult {
+    let args = Args::parse(
+        args,
+        &["interval", "step", "version", "week", "leap-seconds"],
+        &[],
+    )?;
+    let input = args.input(0, "input file")?;
+    let output = args.input(1, "output file")?;
+    let from = detect_input(input)?;
+    let to = detect_output(output)
+        .ok_or_else(|| usage(format!("unknown output format for {}", output)))?;
+    let interval: f64 = args.get("interval", 900.)?;
+    let step: f64 = args.get("step", 60.)?;
+    let version: f64 = args.get("version", 3.)?;
+    let leap_seconds: i32 = args.get("leap-seconds", 18)?;
+    if interval <= 0. || step <= 0. {
+        return Err(usage("--interval and --step must be positive"));
+    }
+
+    let records = match (from, to) {
+        (FileKind::RinexNav, FileKind::Sp3) => nav_to_sp3(input, output, interval)?.epochs.len(),
+        (FileKind::RinexNav, FileKind::RinexNav) => {
+            let ephemerides = read_nav(input)?;
+            let header = rinex2_nav_header(input).ok();
+            write_rinex_nav(output, version, header.as_ref(), &ephemerides)?;
+            ephemerides.len()
+        }
+        (FileKind::RinexNav, FileKind::Track) | (FileKind::RinexNav, FileKind::Mat) => {
+            // the day of the navigation file
+            let ephemerides = read_nav(input)?;
+            let (week, start) = broadcast_day(&ephemerides).unwrap_or((0, 0.));
+            if to == FileKind::Track {
+                let export = TrackExport {
+                    tracks: ground_tracks(&ephemerides, week, start, 86400., step),
+                    leap_seconds,
+                    ..TrackExport::default()
+                };
+                export.write(output)?;
+                export.tracks.len()
+            } else {
+                let sp3 = broadcast_sp3(&ephemerides, week, start, 86400., interval, false);
+                let trajectories = sp3_trajectories(&sp3);
+                write_trajectories_mat(output, &trajectories)?;
+                trajectories.len()
+            }
+        }
+        (FileKind::Sp3, FileKind::Sp3) => {
+            let sp3 = read_sp3(input)?;
+            write_sp3(output, &sp3)?;
+            sp3.epochs.len()
+        }
+        (FileKind::Sp3, FileKind::Mat) => {
+            let trajectories = sp3_trajectories(&read_sp3(input)?);
+            write_trajectories_mat(output, &trajectories)?;
+            trajectories.len()
+        }
+        (FileKind::RinexObs, FileKind::RinexObs) => {
+            let (mut header, epochs) = read_rinex_obs(input)?;
+            // rinex 2 and 3 name the observation codes differently
+            if let Some(types) = header.obs_types.get_mut(&'G') {
+                *types = convert_obs_types(types, version);
+            }
+            header.version = version;
+            write_rinex_obs(output, &header, &epochs)?;
+            epochs.len()
+        }
+        (FileKind::Ubx, FileKind::RinexObs) => {
+            let log = read_ubx_log(input, args.get("week", current_week())?)?;
+            write_rinex_obs(
+                output,
+                &ubx_obs_header(input, version, &log.others),
+                &log.epochs,
+            )?;
+            log.epochs.len()
+        }
+        (FileKind::Ubx, FileKind::RinexNav) => {
+            let log = read_ubx_log(input, args.get("week", current_week())?)?;
+            write_rinex_nav(output, version, None, &log.ephemerides)?;
+            log.ephemerides.len()
+        }
+        (FileKind::Ubx, FileKind::Track) => {
+            let fixes = ubx_track(&read_ubx(input, args.get("week", current_week())?)?);
+            let export = TrackExport {
+                tracks: vec![Track::new("ubx", &fixes)],
+                leap_seconds,
+                ..TrackExport::default()
+            };
+            export.write(output)?;
+            fixes.len()
+        }
+        (FileKind::Rtcm, FileKind::RinexNav) => {
+            let ephemerides: Vec<SatelliteData> =
+                read_rtcm(input, args.get("week", current_week())?)?
+                    .into_iter()
+                    .filter_map(|m| match m {
+                        RtcmMessage::Ephemeris(GnssSystem::Gps, eph) => Some(*eph),
+                        _ => None,
+                    })
+                    .collect();
+            write_rinex_nav(output, version, None, &ephemerides)?;
+            ephemerides.len()
+        }
+        (FileKind::Nmea, FileKind::Track) => {
+            let fixes = nmea_track(&read_nmea(input)?, leap_seconds);
+            let export = TrackExport {
+                tracks: vec![Track::new("nmea", &fixes)],
+                leap_seconds,
+                ..TrackExport::default()
+            };
+            export.write(output)?;
+            fixes.len()
+        }
+        _ => return Err(usage(format!("cannot convert {:?} to {:?}", from, to))),
+    };
+
+    print_summary(
+        &[
+            ("input", text(input)),
+            ("input_format", text(format!("{:?}", from))),
+            ("output", text(output)),
+            ("output_format", text(format!("{:?}", to))),
+            ("records", Value::Int(records as i64)),
+        ],
+        args.output()?,
+    )?;
+    if records == 0 {
+        Err(CliError::NoResult(format!(
+            "nothing to convert in {}",
+            input
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn time_span(times: impl IntoIterator<Item = (i32, f64)>) -> [(&'static str, Value); 2] {
+    // first and last epoch as calendar dates
+    let mut times: Vec<(i32, f64)> = times.into_iter().collect();
+    times.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    let date = |t: Option<&(i32, f64)>| t.map_or(Value::Missing, |&(w, s)| text(gps_date(w, s)));
+    [("start", date(times.first())), ("end", date(times.last()))]
+}
+
+fn rinex_version(filename: &str) -> Result<f64, Error> {
+    let mut line = String::new();
+    std::io::BufRead::read_line(
+        &mut std::io::BufReader::new(File::open(filename)?),
+        &mut line,
+    )?;
+    Ok(line
+        .get(..9)
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0.))
+}
+
+fn count<T>(items: &[T], pred: impl Fn(&T) -> bool) -> Value {
+    Value::Int(items.iter().filter(|&i| pred(i)).count() as i64)
+}
+
+fn info(args: &[String]) -> CliResult {
+    let args = Args::parse(args, &["week", "leap-seconds"], &[])?;
+    let filename = args.input(0, "file")?;
+    let kind = detect_input(filename)?;
+    let mut items: Vec<(&str, Value)> = vec![
+        ("file", text(filename)),
+        ("format", text(format!("{:?}", kind))),
+    ];
+    match kind {
+        FileKind::RinexObs => {
+            let (header, epochs) = read_rinex_obs(filename)?;
+            let mut prns: Vec<usize> = epochs
+                .iter()
+                .flat_map(|e| e.obs.iter().map(|o| o.prn))
+                .collect();
+            prns.sort();
+            prns.dedup();
+            let types = header.obs_types.get(&'G').cloned().unwrap_or_default();
+            items.extend([
+                ("version", float(header.version, 2)),
+                ("marker", text(header.marker_name.trim())),
+                ("receiver", text(header.receiver.trim())),
+                ("antenna", text(header.antenna.trim())),
+                (
+                    "approx_position",
+                    Value::List(
+                        header
+                            .approx_position
+                            .iter()
+                            .map(|&v| float(v, 3))
+                            .collect(),
+                    ),
+                ),
+                ("interval", float(header.interval, 3)),
+                (
+                    "obs_types",
+                    Value::List(types.into_iter().map(text).collect()),
+                ),
+                ("epochs", Value::Int(epochs.len() as i64)),
+                (
+                    "observations",
+                    Value::Int(epochs.iter().map(|e| e.obs.len()).sum::<usize>() as i64),
+                ),
+                ("satellites", prn_list(&prns)),
+            ]);
+            items.extend(time_span(epochs.iter().map(|e| (e.gps_week, e.gps_time))));
+        }
+        FileKind::RinexNav => {
+            let ephemerides = read_nav(filename)?;
+            let header = rinex2_nav_header(filename).ok();
+            let mut prns: Vec<usize> = ephemerides.iter().map(|e| e.prn()).collect();
+            prns.sort();
+            prns.dedup();
+            let list = |v: Option<[f64; 4]>| {
+                v.map_or(Value::Missing, |v| {
+                    Value::List(
+                        v.iter()
+                            .map(|&c| Value::Text(format!("{:.4e}", c)))
+                            .collect(),
+                    )
+                })
+            };
+            items.extend([
+                ("version", float(rinex_version(filename)?, 2)),
+                ("ephemerides", Value::Int(ephemerides.len() as i64)),
+                ("unhealthy", count(&ephemerides, |e| !e.healthy())),
+                ("satellites", prn_list(&prns)),
+                ("ion_alpha", list(header.as_ref().map(|h| h.ion_alpha()))),
+                ("ion_beta", list(header.as_ref().map(|h| h.ion_beta()))),
+                (
+                    "leap_seconds",
+                    header
+                        .as_ref()
+                        .map_or(Value::Missing, |h| Value::Int(h.leap_seconds() as i64)),
+                ),
+            ]);
+            items.extend(time_span(ephemerides.iter().map(|e| e.toc_epoch())));
+        }
+        FileKind::RinexClock => {
+            let clock = read_rinex_clock(filename)?;
+            let mut satellites: Vec<usize> = clock.satellites.keys().cloned().collect();
+            satellites.sort();
+            let samples: usize = clock.satellites.values().map(|s| s.len()).sum::<usize>()
+                + clock.receivers.values().map(|s| s.len()).sum::<usize>();
+            let mut times: Vec<f64> = clock
+                .satellites
+                .values()
+                .chain(clock.receivers.values())
+                .flat_map(|s| s.iter().map(|c| c.time))
+                .collect();
+            times.sort_by(f64::total_cmp);
+            let date = |t: Option<&f64>| {
+                t.map_or(Value::Missing, |&t| {
+                    let week = (t / SECONDS_PER_WEEK).floor();
+                    text(gps_date(week as i32, t - week * SECONDS_PER_WEEK))
+                })
+            };
+            items.extend([
+                ("version", float(clock.header.version, 2)),
+                (
+                    "analysis_center",
+                    text(clock.header.analysis_center.clone()),
+                ),
+                ("time_system", text(clock.header.time_system.clone())),
+                ("leap_seconds", Value::Int(clock.header.leap_seconds as i64)),
+                ("satellites", prn_list(&satellites)),
+                ("stations", Value::Int(clock.receivers.len() as i64)),
+                ("samples", Value::Int(samples as i64)),
+                ("start", date(times.first())),
+                ("end", date(times.last())),
+            ]);
+        }
+        FileKind::Sp3 => {
+            let sp3 = read_sp3(filename)?;
+            let mut satellites: Vec<usize> = sp3
+                .epochs
+                .iter()
+                .flat_map(|e| e.records.iter().map(|r| r.prn))
+                .collect();
+            satellites.sort();
+            satellites.dedup();
+            items.extend([
+                ("version", text(sp3.version.to_string())),
+                ("agency", text(sp3.agency.clone())),
+                ("coordinate_system", text(sp3.coordinate_system.clone())),
+                ("orbit_type", text(sp3.orbit_type.clone())),
+                ("time_system", text(sp3.time_system.clone())),
+                ("interval", float(sp3.interval, 3)),
+                ("epochs", Value::Int(sp3.epochs.len() as i64)),
+                ("satellites", prn_list(&satellites)),
+            ]);
+            items.extend(time_span(
+                sp3.epochs.iter().map(|e| (e.gps_week, e.gps_time)),
+            ));
+        }
+        FileKind::Ubx => {
+            let messages = read_ubx(filename, args.get("week", current_week())?)?;
+            let rawx: Vec<ObservationEpoch> = messages
+                .iter()
+                .filter_map(|m| match m {
+                    UbxMessage::Rawx(r) => Some(r.observations()),
+                    _ => None,
+                })
+                .collect();
+            let mut unsupported: Vec<String> = messages
+                .iter()
+                .filter_map(|m| match m {
+                    UbxMessage::Unsupported(class, id) => Some(format!("{:02x}-{:02x}", class, id)),
+                    _ => None,
+                })
+                .collect();
+            unsupported.sort();
+            unsupported.dedup();
+            items.extend([
+                ("messages", Value::Int(messages.len() as i64)),
+                ("rxm_rawx", Value::Int(rawx.len() as i64)),
+                (
+                    "rxm_sfrbx",
+                    count(&messages, |m| matches!(m, UbxMessage::Sfrbx(_))),
+                ),
+                (
+                    "ephemerides",
+                    count(&messages, |m| matches!(m, UbxMessage::Ephemeris(_))),
+                ),
+                (
+                    "nav_pvt",
+                    count(&messages, |m| matches!(m, UbxMessage::NavPvt(_))),
+                ),
+                (
+                    "nav_sat",
+                    count(&messages, |m| matches!(m, UbxMessage::NavSat(_))),
+                ),
+                (
+                    "unsupported",
+                    Value::List(unsupported.into_iter().map(text).collect()),
+                ),
+            ]);
+            items.extend(time_span(rawx.iter().map(|e| (e.gps_week, e.gps_time))));
+        }
+        FileKind::Rtcm => {
+            let messages = read_rtcm(filename, args.get("week", current_week())?)?;
+            let mut unsupported: Vec<usize> = messages
+                .iter()
+                .filter_map(|m| match m {
+                    RtcmMessage::Unsupported(number) => Some(*number as usize),
+                    _ => None,
+                })
+                .collect();
+            unsupported.sort();
+            unsupported.dedup();
+            items.extend([
+                ("messages", Value::Int(messages.len() as i64)),
+                (
+                    "station",
+                    count(&messages, |m| matches!(m, RtcmMessage::Station(_))),
+                ),
+                (
+                    "antenna",
+                    count(&messages, |m| matches!(m, RtcmMessage::Antenna(_))),
+                ),
+                (
+                    "ephemerides",
+                    count(&messages, |m| matches!(m, RtcmMessage::Ephemeris(..))),
+                ),
+                (
+                    "glonass_ephemerides",
+                    count(&messages, |m| matches!(m, RtcmMessage::GlonassEphemeris(_))),
+                ),
+                (
+                    "msm",
+                    count(&messages, |m| matches!(m, RtcmMessage::Msm(_))),
+                ),
+                ("unsupported", prn_list(&unsupported)),
+            ]);
+        }
+        FileKind::Nmea => {
+            let sentences = read_nmea(filename)?;
+            let fixes = nmea_track(&sentences, args.get("leap-seconds", 18)?);
+            items.extend([
+                ("sentences", Value::Int(sentences.len() as i64)),
+                (
+                    "gga",
+                    count(&sentences, |s| matches!(s, NmeaSentence::Gga(_))),
+                ),
+                (
+                    "rmc",
+                    count(&sentences, |s| matches!(s, NmeaSentence::Rmc(_))),
+                ),
+                (
+                    "gsa",
+                    count(&sentences, |s| matches!(s, NmeaSentence::Gsa(_))),
+                ),
+                (
+                    "gsv",
+                    count(&sentences, |s| matches!(s, NmeaSentence::Gsv(_))),
+                ),
+                (
+                    "gst",
+                    count(&sentences, |s| matches!(s, NmeaSentence::Gst(_))),
+                ),
+                (
+                    "pubx",
+                    count(&sentences, |s| matches!(s, NmeaSentence::Pubx(_))),
+                ),
+                ("fixes", Value::Int(fixes.len() as i64)),
+            ]);
+            items.extend(time_span(fixes.iter().map(|f| (f.gps_week, f.gps_time))));
+        }
+        FileKind::Mat => {
+            let mat = read_mat(filename)?;
+            let variables = mat
+                .names()
+                .into_iter()
+                .map(|name| {
+                    let size = mat.size(name).unwrap_or_default();
+                    let dims: Vec<String> = size.iter().map(|d| d.to_string()).collect();
+                    text(format!("{}[{}]", name, dims.join("x")))
+                })
+                .collect();
+            items.push(("variables", Value::List(variables)));
+        }
+        FileKind::Track => (),
+    }
+    print_summary(&items, args.output()?)?;
+    Ok(())
+}
+
+pub fn run(args: &[String]) -> i32 {
+    // command line entry point, returns the process exit code
+    let command = match args.first() {
+        Some(command) => command.as_str(),
+        None => {
+            eprintln!("{}", USAGE);
+            return EXIT_USAGE;
+        }
+    };
+    let rest = &args[1..];
+    if matches!(command, "help" | "--help" | "-h")
+        || rest.iter().any(|a| a == "--help" || a == "-h")
+    {
+        println!("{}", USAGE);
+        return EXIT_OK;
+    }
+    let result = match command {
+        "acquire" => acquire(rest),
+        "orbit" => orbit(rest),
+        "solve" => solve(rest),
+        "convert" => convert(rest),
+        "info" => info(rest),
+        _ => Err(usage(format!("unknown command {}", command))),
+    };
+    match result {
+        Ok(()) => EXIT_OK,
+        Err(CliError::Usage(message)) => {
+            eprintln!("rgps: {}\nrun rgps help for usage", message);
+            EXIT_USAGE
+        }
+        Err(CliError::NoResult(message)) => {
+            eprintln!("rgps: {}", message);
+            EXIT_NO_RESULT
+        }
+        // output closed early, e.g. piped into head
+        Err(CliError::Input(e)) if e.kind() == ErrorKind::BrokenPipe => EXIT_OK,
+        Err(CliError::Input(e)) => {
+            eprintln!("rgps: {}", e);
+            EXIT_INPUT
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn prns(list: &str) -> Result<Vec<usize>, CliError> {
+        Args::parse(&strings(&["--prn", list]), &["prn"], &[])?.prns()
+    }
+
+    fn path(name: &str) -> String {
+        let file = format!("rgps_{}_{}", std::process::id(), name);
+        std::env::temp_dir()
+            .join(file)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn parse_arguments() {
+        let args = Args::parse(
+            &strings(&["in.23o", "--prn", "1,3", "--out=x.sp3", "--list", "out.rnx"]),
+            &["prn", "out"],
+            &["list"],
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(args.positional, ["in.23o", "out.rnx"]);
+        assert_eq!(args.options["prn"], "1,3");
+        assert_eq!(args.options["out"], "x.sp3");
+        assert!(args.flag("list") && !args.flag("complex"));
+        assert_eq!(args.output().ok(), Some(OutputFormat::Text));
+
+        let args = Args::parse(&strings(&["--output", "json"]), &[], &[])
+            .ok()
+            .unwrap();
+        assert_eq!(args.output().ok(), Some(OutputFormat::Json));
+        let usage_error = |a: &[&str]| {
+            matches!(
+                Args::parse(&strings(a), &["prn"], &["list"]),
+                Err(CliError::Usage(_))
+            )
+        };
+        assert!(usage_error(&["--unknown"]));
+        assert!(usage_error(&["--prn"]));
+        assert!(!usage_error(&["--list", "--prn=5"]));
+    }
+
+    #[test]
+    fn prn_grammar() {
+        assert_eq!(
+            prns("1,3,10-20").unwrap(),
+            [vec![1, 3], (10..=20).collect()].concat()
+        );
+        assert_eq!(
+            prns("120-158").unwrap(),
+            (120..=158).collect::<Vec<usize>>()
+        );
+        assert_eq!(prns("3, 1,2-3").unwrap(), [1, 2, 3]);
+        // ranges across the gap keep the defined codes only
+        assert_eq!(
+            prns("60-125").unwrap(),
+            [60, 61, 62, 63, 120, 121, 122, 123, 124, 125]
+        );
+        for bad in ["0", "64", "159", "5-3", "1,,2", "a", "1-"] {
+            assert!(matches!(prns(bad), Err(CliError::Usage(_))), "{}", bad);
+        }
+        let default = Args::parse(&[], &["prn"], &[]).unwrap();
+        assert_eq!(default.prns().unwrap(), (1..=32).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn detect_file_kinds() {
+        assert_eq!(detect_output("site2180.23o"), Some(FileKind::RinexObs));
+        assert_eq!(detect_output("SITE2180.23N"), Some(FileKind::RinexNav));
+        assert_eq!(
+            detect_output("SITE00USA_R_20232180000_01D_30S_MO.rnx"),
+            Some(FileKind::RinexObs)
+        );
+        assert_eq!(
+            detect_output("SITE00USA_R_20232180000_01D_GN.rnx"),
+            Some(FileKind::RinexNav)
+        );
+        assert_eq!(detect_output("orbits.sp3"), Some(FileKind::Sp3));
+        assert_eq!(detect_output("maps.mat"), Some(FileKind::Mat));
+        assert_eq!(detect_output("track.kml"), Some(FileKind::Track));
+        assert_eq!(detect_output("notes.txt"), None);
+        assert_eq!(detect_output("site2180.23x"), None);
+        assert_eq!(detect_output("noextension"), None);
+
+        assert_eq!(detect_input("brdc2180.23n").ok(), Some(FileKind::RinexNav));
+        let obs = format!(
+            "{:9.2}{:11}{:<20}{:<20}RINEX VERSION / TYPE\n",
+            2.11, "", "OBSERVATION DATA", "G (GPS)"
+        );
+        let contents: [(&str, &[u8], Option<FileKind>); 8] = [
+            ("obs", obs.as_bytes(), Some(FileKind::RinexObs)),
+            (
+                "ubx",
+                &[0xb5, 0x62, 0x0a, 0x04, 0, 0, 0x0e, 0x34],
+                Some(FileKind::Ubx),
+            ),
+            ("rtcm", &[0xd3, 0x00, 0x13, 0x3e], Some(FileKind::Rtcm)),
+            (
+                "sp3",
+                b"#dP2023  8  6  0  0  0.00000000",
+                Some(FileKind::Sp3),
+            ),
+            ("nmea", b"junk $GPGGA,092750.000", Some(FileKind::Nmea)),
+            ("mat", b"MATLAB 5.0 MAT-file", Some(FileKind::Mat)),
+            ("text", b"hello\n", None),
+            ("empty", b"", None),
+        ];
+        for (name, bytes, kind) in contents {
+            let filename = path(name);
+            std::fs::write(&filename, bytes).unwrap();
+            let detected = detect_input(&filename);
+            std::fs::remove_file(&filename).unwrap();
+            match kind {
+                Some(k) => assert_eq!(detected.ok(), Some(k), "{}", name),
+                None => assert_eq!(detected.unwrap_err().kind(), ErrorKind::InvalidData),
+            }
+        }
+        assert_eq!(
+            detect_input(&path("missing")).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn exit_codes() {
+        let run = |a: &[&str]| run(&strings(a));
+        assert_eq!(run(&["help"]), EXIT_OK);
+        assert_eq!(
+            run(&[
+                "orbit",
+                "brdc2180.23n",
+                "--list",
+                "--prn",
+                "1",
+                "--output",
+                "csv"
+            ]),
+            EXIT_OK
+        );
+        assert_eq!(
+            run(&["orbit", "brdc2180.23n", "--list", "--prn", "63"]),
+            EXIT_NO_RESULT
+        );
+        assert_eq!(run(&[]), EXIT_USAGE);
+        assert_eq!(run(&["frobnicate"]), EXIT_USAGE);
+        assert_eq!(run(&["orbit"]), EXIT_USAGE);
+        assert_eq!(run(&["orbit", "brdc2180.23n", "--prn", "0"]), EXIT_USAGE);
+        assert_eq!(run(&["info", &path("missing")]), EXIT_INPUT);
+    }
+}
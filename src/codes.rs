@@ -1,5 +1,7 @@
 use nalgebra::*;
 use rustfft::{num_complex::Complex, FftPlanner};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind};
 use std::time::Instant;
 
 pub fn gold_gen(ic: DVector<i32>, taps: Vec<usize>) -> DMatrix<i32> {
@@ -12,7 +14,7 @@ pub fn gold_gen(ic: DVector<i32>, taps: Vec<usize>) -> DMatrix<i32> {
     for i in 0..(num - 1) as usize {
         let mut temp_val = g[(i, taps[0] - 1)];
         for j in 1..taps.len() {
-            temp_val = temp_val ^ g[(i, taps[j] - 1)];
+            temp_val ^= g[(i, taps[j] - 1)];
         }
         let temp_row = g.fixed_view::<1, 9>(i, 0).clone_owned();
         g.fixed_view_mut::<1, 9>(i + 1, 1).copy_from(&temp_row);
@@ -32,7 +34,7 @@ pub fn cycle_add(g1: DVector<i32>, g2: DMatrix<i32>, prn_taps: Vec<usize>) -> DV
     for i in 0..g1.len() {
         let mut temp = g2[(i, prn_taps[0] - 1)];
         for j in 1..prn_taps.len() {
-            temp = temp ^ g2[(i, prn_taps[j] - 1)];
+            temp ^= g2[(i, prn_taps[j] - 1)];
         }
         s[i] = g1[i] ^ temp;
     }
@@ -82,22 +84,27 @@ pub fn gen_prn(prn_num: usize, bpsk_flag: bool, length: usize, epoch: isize) ->
         vec![2, 8],
     ];
 
-    let prn_taps = prn_lib[prn_num - 1].clone();
-    let g1_taps = vec![3, 10];
-    let g2_taps = vec![2, 3, 6, 8, 9, 10];
-    let ic = DVector::from(vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1]);
+    let mut prn_code = if prn_num <= prn_lib.len() {
+        let prn_taps = prn_lib[prn_num - 1].clone();
+        let g1_taps = vec![3, 10];
+        let g2_taps = vec![2, 3, 6, 8, 9, 10];
+        let ic = DVector::from(vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1]);
 
-    let g1 = gold_gen(ic.clone(), g1_taps);
-    let g2 = gold_gen(ic.clone(), g2_taps);
-    let g1 = g1.column(9).clone_owned();
+        let g1 = gold_gen(ic.clone(), g1_taps);
+        let g2 = gold_gen(ic.clone(), g2_taps);
+        let g1 = g1.column(9).clone_owned();
 
-    let mut prn_code = cycle_add(g1, g2, prn_taps);
+        cycle_add(g1, g2, prn_taps)
+    } else {
+        // prns beyond the phase selector table (37-63, sbas 120-158) use the g2 delay
+        gen_ca_code(prn_num)
+    };
     if bpsk_flag {
         bpsk_map(&mut prn_code)
     };
 
     // Start index calculated with modulo operation to handle negative shifts correctly
-    let start = ((epoch % 1023) + 1023) % 1023;
+    let start = epoch.rem_euclid(1023);
 
     if length <= 1023 && start == 0 {
         return prn_code;
@@ -170,3 +177,743 @@ pub fn circ_corr<T: Copy + Into<f64>>(
     R /= R.len() as f64;
     (R, lag)
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GpsCode {
+    L1CA, // 1023 chips at 1.023 Mcps
+    L2CM, // 10230 chips at 511.5 kcps
+    L2CL, // 767250 chips at 511.5 kcps
+    L5I,  // 10230 chips at 10.23 Mcps, NH10 secondary
+    L5Q,  // 10230 chips at 10.23 Mcps, NH20 secondary
+    L1CD, // 10230 chips at 1.023 Mcps, BOC(1,1) data
+    L1CP, // 10230 chips at 1.023 Mcps, TMBOC(6,1,4/33) pilot
+}
+
+impl GpsCode {
+    pub fn length(&self) -> usize {
+        match self {
+            GpsCode::L1CA => 1023,
+            GpsCode::L2CL => 767250,
+            _ => 10230,
+        }
+    }
+
+    pub fn chip_rate(&self) -> f64 {
+        match self {
+            GpsCode::L1CA | GpsCode::L1CD | GpsCode::L1CP => 1.023e6,
+            GpsCode::L2CM | GpsCode::L2CL => 511.5e3,
+            GpsCode::L5I | GpsCode::L5Q => 10.23e6,
+        }
+    }
+
+    pub fn period(&self) -> f64 {
+        self.length() as f64 / self.chip_rate()
+    }
+}
+
+pub fn gen_code(code: GpsCode, prn_num: usize, bpsk_flag: bool) -> DVector<i32> {
+    // one period of the primary ranging code
+    let mut out = match code {
+        GpsCode::L1CA => gen_ca_code(prn_num),
+        GpsCode::L2CM => gen_l2c_code(L2CM_INIT[prn_num - 1], 10230),
+        GpsCode::L2CL => gen_l2c_code(L2CL_INIT[prn_num - 1], 767250),
+        GpsCode::L5I => gen_l5_code(L5I_XB_ADVANCE[prn_num - 1]),
+        GpsCode::L5Q => gen_l5_code(L5Q_XB_ADVANCE[prn_num - 1]),
+        GpsCode::L1CD => gen_l1c_code(L1CD_WEIL[prn_num - 1], L1CD_INSERT[prn_num - 1]),
+        GpsCode::L1CP => gen_l1c_code(L1CP_WEIL[prn_num - 1], L1CP_INSERT[prn_num - 1]),
+    };
+    if bpsk_flag {
+        bpsk_map(&mut out)
+    };
+    out
+}
+
+// g2 delays (chips) for c/a prn 1-63 followed by sbas prn 120-158, IS-GPS-200 table 3-I
+// and RTCA DO-229
+const CA_G2_DELAY: [usize; 102] = [
+    5, 6, 7, 8, 17, 18, 139, 140, 141, 251, 252, 254, 255, 256, 257, 258, 469, 470, 471, 472, 473,
+    474, 509, 512, 513, 514, 515, 516, 859, 860, 861, 862, 863, 950, 947, 948, 950, 67, 103, 91,
+    19, 679, 225, 625, 946, 638, 161, 1001, 554, 280, 710, 709, 775, 864, 558, 220, 397, 55, 898,
+    759, 367, 299, 1018, 145, 175, 52, 21, 237, 235, 886, 657, 634, 762, 355, 1012, 176, 603, 130,
+    359, 595, 68, 386, 797, 456, 499, 883, 307, 127, 211, 121, 118, 163, 628, 853, 484, 289, 811,
+    202, 1021, 463, 568, 904,
+];
+
+pub fn ca_g2_delay(prn_num: usize) -> usize {
+    match prn_num {
+        1..=63 => CA_G2_DELAY[prn_num - 1],
+        120..=158 => CA_G2_DELAY[63 + prn_num - 120],
+        _ => panic!("C/A code is not defined for PRN {}", prn_num),
+    }
+}
+
+pub fn gen_ca_code(prn_num: usize) -> DVector<i32> {
+    // c/a code (0/1) from the g2 delay, valid for gps prn 1-63 and sbas prn 120-158
+    let mut g1 = [1u8; 10];
+    let mut g2 = [1u8; 10];
+    let mut g1_out = [0i32; 1023];
+    let mut g2_out = [0i32; 1023];
+    for i in 0..1023 {
+        g1_out[i] = g1[9] as i32;
+        g2_out[i] = g2[9] as i32;
+        let f1 = g1[2] ^ g1[9];
+        let f2 = g2[1] ^ g2[2] ^ g2[5] ^ g2[7] ^ g2[8] ^ g2[9];
+        g1.rotate_right(1);
+        g2.rotate_right(1);
+        g1[0] = f1;
+        g2[0] = f2;
+    }
+    let delay = ca_g2_delay(prn_num);
+    DVector::from_fn(1023, |i, _| g1_out[i] ^ g2_out[(i + 1023 - delay) % 1023])
+}
+
+// initial register states (octal) for l2 cm and cl codes, IS-GPS-200 table 3-IIa
+const L2CM_INIT: [u32; 37] = [
+    0o742417664,
+    0o756014035,
+    0o002747144,
+    0o066265724,
+    0o601403471,
+    0o703232733,
+    0o124510070,
+    0o617316361,
+    0o047541621,
+    0o733031046,
+    0o713512145,
+    0o024437606,
+    0o021264003,
+    0o230655351,
+    0o001314400,
+    0o222021506,
+    0o540264026,
+    0o205521705,
+    0o064022144,
+    0o120161274,
+    0o044023533,
+    0o724744327,
+    0o045743577,
+    0o741201660,
+    0o700274134,
+    0o010247261,
+    0o713433445,
+    0o737324162,
+    0o311627434,
+    0o710452007,
+    0o722462133,
+    0o050172213,
+    0o500653703,
+    0o755077436,
+    0o136717361,
+    0o756675453,
+    0o435506112,
+];
+
+const L2CL_INIT: [u32; 37] = [
+    0o624145772,
+    0o506610362,
+    0o220360016,
+    0o710406104,
+    0o001143345,
+    0o053023326,
+    0o652521276,
+    0o206124777,
+    0o015563374,
+    0o561522076,
+    0o023163525,
+    0o117776450,
+    0o606516355,
+    0o003037343,
+    0o046515565,
+    0o671511621,
+    0o605402220,
+    0o002576207,
+    0o525163451,
+    0o266527765,
+    0o006760703,
+    0o501474556,
+    0o743747443,
+    0o615534726,
+    0o763621420,
+    0o720727474,
+    0o700521043,
+    0o222567263,
+    0o132765304,
+    0o746332245,
+    0o102300466,
+    0o255231716,
+    0o437661701,
+    0o717047302,
+    0o222614207,
+    0o561123307,
+    0o240713073,
+];
+
+fn l2c_shift(state: u32) -> u32 {
+    // 27 stage modular register with polynomial
+    // 1 + x^3 + x^4 + x^5 + x^6 + x^9 + x^11 + x^13 + x^16 + x^19 + x^21 + x^24 + x^27
+    (state >> 1) ^ ((state & 1) * 0o445112474)
+}
+
+pub fn gen_l2c_code(init: u32, length: usize) -> DVector<i32> {
+    let mut state = init;
+    let mut out = DVector::zeros(length);
+    for i in 0..length {
+        out[i] = (state & 1) as i32;
+        state = l2c_shift(state);
+    }
+    out
+}
+
+// xb code advance (chips) for l5 i and q codes, IS-GPS-705 table 3-Ia
+const L5I_XB_ADVANCE: [usize; 37] = [
+    266, 365, 804, 1138, 1509, 1559, 1756, 2084, 2170, 2303, 2527, 2687, 2930, 3471, 3940, 4132,
+    4332, 4924, 5343, 5443, 5641, 5816, 5898, 5918, 5955, 6243, 6345, 6477, 6518, 6875, 7168, 7187,
+    7329, 7577, 7720, 7777, 8057,
+];
+
+const L5Q_XB_ADVANCE: [usize; 37] = [
+    1701, 323, 5292, 2020, 5429, 7136, 1041, 5947, 4315, 148, 535, 1939, 5206, 5910, 3595, 5135,
+    6082, 6990, 3546, 1523, 4548, 4484, 1893, 3961, 7106, 5299, 4660, 276, 4389, 3783, 1591, 1601,
+    749, 1387, 1661, 3210, 708,
+];
+
+pub const L5I_NH10: [i32; 10] = [0, 0, 0, 0, 1, 1, 0, 1, 0, 1];
+pub const L5Q_NH20: [i32; 20] = [0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 0, 1, 0, 0, 1, 1, 1, 0];
+
+pub fn gen_l5_code(xb_advance: usize) -> DVector<i32> {
+    // xa: 1 + x^9 + x^10 + x^12 + x^13, short cycled to 8190 chips
+    // xb: 1 + x + x^3 + x^4 + x^6 + x^7 + x^8 + x^12 + x^13, full 8191 chip period
+    let mut xa = [1u8; 13];
+    let mut xb = [1u8; 13];
+    let mut xb_seq = [0u8; 8191];
+    for chip in xb_seq.iter_mut() {
+        *chip = xb[12];
+        let f = xb[0] ^ xb[2] ^ xb[3] ^ xb[5] ^ xb[6] ^ xb[7] ^ xb[11] ^ xb[12];
+        xb.rotate_right(1);
+        xb[0] = f;
+    }
+
+    let mut out = DVector::zeros(10230);
+    for i in 0..10230 {
+        if i % 8190 == 0 {
+            xa = [1u8; 13];
+        }
+        let xb_chip = xb_seq[(i + xb_advance) % 8191];
+        out[i] = (xa[12] ^ xb_chip) as i32;
+        let f = xa[8] ^ xa[9] ^ xa[11] ^ xa[12];
+        xa.rotate_right(1);
+        xa[0] = f;
+    }
+    out
+}
+
+pub fn gen_l5_tiered(prn_num: usize, quadrature: bool, bpsk_flag: bool) -> DVector<i32> {
+    // primary code modulated by the neuman-hofman secondary code (10 or 20 ms)
+    let (primary, nh): (DVector<i32>, &[i32]) = if quadrature {
+        (gen_code(GpsCode::L5Q, prn_num, false), &L5Q_NH20)
+    } else {
+        (gen_code(GpsCode::L5I, prn_num, false), &L5I_NH10)
+    };
+    let n = primary.len();
+    let mut out = DVector::from_fn(n * nh.len(), |i, _| primary[i % n] ^ nh[i / n]);
+    if bpsk_flag {
+        bpsk_map(&mut out)
+    };
+    out
+}
+
+// weil index and insertion index for l1c data and pilot codes, IS-GPS-800 table 3.2-2
+const L1CD_WEIL: [usize; 63] = [
+    5097, 5110, 5079, 4403, 4121, 5043, 5042, 5104, 4940, 5035, 4372, 5064, 5084, 5048, 4950, 5019,
+    5076, 3736, 4993, 5060, 5061, 5096, 4983, 4783, 4991, 4815, 4443, 4769, 4879, 4894, 4985, 5056,
+    4921, 5036, 4812, 4838, 4855, 4904, 4753, 4483, 4942, 4813, 4957, 4618, 4669, 4969, 5031, 5038,
+    4740, 4073, 4843, 4979, 4867, 4964, 5025, 4579, 4390, 4763, 4612, 4784, 3716, 4703, 4851,
+];
+
+const L1CD_INSERT: [usize; 63] = [
+    181, 359, 72, 1110, 1480, 5034, 4622, 1, 4547, 826, 6284, 4195, 368, 1, 4796, 523, 151, 713,
+    9850, 5734, 34, 6142, 190, 644, 467, 5384, 801, 594, 4450, 9437, 4307, 5906, 378, 9448, 9432,
+    5849, 5547, 9546, 9132, 403, 3766, 3, 684, 9711, 333, 6124, 10216, 4251, 9893, 9884, 4627,
+    4449, 9798, 985, 4272, 126, 10024, 434, 1029, 561, 289, 638, 4353,
+];
+
+const L1CP_WEIL: [usize; 63] = [
+    5111, 5109, 5108, 5106, 5103, 5101, 5100, 5098, 5095, 5094, 5093, 5091, 5090, 5081, 5080, 5069,
+    5068, 5054, 5044, 5027, 5026, 5014, 5004, 4980, 4915, 4909, 4893, 4885, 4832, 4824, 4591, 3706,
+    5092, 4986, 4965, 4920, 4917, 4858, 4847, 4790, 4770, 4318, 4126, 3961, 3790, 4911, 4881, 4827,
+    4795, 4789, 4725, 4675, 4539, 4535, 4458, 4197, 4096, 3484, 3481, 3393, 3175, 2360, 1852,
+];
+
+const L1CP_INSERT: [usize; 63] = [
+    412, 161, 1, 303, 207, 4971, 4496, 5, 4557, 485, 253, 4676, 1, 66, 4485, 282, 193, 5211, 729,
+    4848, 982, 5955, 9805, 670, 464, 29, 429, 394, 616, 9457, 4429, 4771, 365, 9705, 9489, 4193,
+    9947, 824, 864, 347, 677, 6544, 6312, 9804, 278, 9461, 444, 4839, 4144, 9875, 197, 1156, 4674,
+    10035, 4504, 5, 9937, 430, 5, 355, 909, 1622, 6284,
+];
+
+pub fn legendre_sequence(n: usize) -> Vec<i32> {
+    // 1 where the index is a non-zero quadratic residue modulo the prime n
+    let mut seq = vec![0; n];
+    for k in 1..n {
+        seq[(k * k) % n] = 1;
+    }
+    seq
+}
+
+pub fn gen_l1c_code(weil_index: usize, insertion_index: usize) -> DVector<i32> {
+    // 10223 chip weil code with the 7 chip expansion sequence inserted before
+    // the (1 based) insertion index
+    let n = 10223;
+    let legendre = legendre_sequence(n);
+    let expansion = [0, 1, 1, 0, 1, 0, 0];
+    let weil = |k: usize| legendre[k % n] ^ legendre[(k + weil_index) % n];
+    let p = insertion_index - 1;
+    DVector::from_fn(10230, |t, _| {
+        if t < p {
+            weil(t)
+        } else if t < p + 7 {
+            expansion[t - p]
+        } else {
+            weil(t - 7)
+        }
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Modulation {
+    Bpsk,  // gps c/a, l5, beidou b1i, glonass l1of, galileo e5a
+    Boc11, // sine phased boc(1,1)
+    CbocB, // galileo e1b data, boc(1,1) + boc(6,1) in phase
+    CbocC, // galileo e1c pilot, boc(1,1) - boc(6,1)
+}
+
+impl Modulation {
+    pub fn subchips(&self) -> usize {
+        // subcarrier samples per chip used when building replicas
+        match self {
+            Modulation::Bpsk => 1,
+            Modulation::Boc11 => 2,
+            Modulation::CbocB | Modulation::CbocC => 12,
+        }
+    }
+}
+
+pub fn modulate_subcarrier(code: &DVector<i32>, modulation: Modulation) -> DVector<f64> {
+    // expands a +1/-1 code into subchips carrying the subcarrier
+    let m = modulation.subchips();
+    let alpha = (10f64 / 11.).sqrt();
+    let beta = (1f64 / 11.).sqrt();
+    DVector::from_fn(code.len() * m, |i, _| {
+        let chip = code[i / m] as f64;
+        let k = i % m;
+        match modulation {
+            Modulation::Bpsk => chip,
+            Modulation::Boc11 => {
+                if k == 0 {
+                    chip
+                } else {
+                    -chip
+                }
+            }
+            Modulation::CbocB | Modulation::CbocC => {
+                let sc_a = if k < 6 { 1. } else { -1. };
+                let sc_b = if k.is_multiple_of(2) { 1. } else { -1. };
+                if modulation == Modulation::CbocB {
+                    chip * (alpha * sc_a + beta * sc_b)
+                } else {
+                    chip * (alpha * sc_a - beta * sc_b)
+                }
+            }
+        }
+    })
+}
+
+pub fn load_galileo_memory_codes(filename: &str) -> Result<Vec<(usize, DVector<i32>)>, Error> {
+    // galileo e1b/e1c primary codes are memory codes published as hex in the os sis icd
+    // annex, one code per line as "prn,hex" (or just hex, numbered from 1); the first
+    // hex digit holds the first four chips, msb first
+    let file = File::open(filename)?;
+    let mut codes = vec![];
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (prn, hex) = match line.split_once([',', ';', ' ', '\t']) {
+            Some((p, h)) => (
+                p.trim().parse::<usize>().map_err(|_| {
+                    Error::new(ErrorKind::InvalidData, format!("bad prn on line {}", i + 1))
+                })?,
+                h.trim(),
+            ),
+            None => (codes.len() + 1, line),
+        };
+        let mut code = Vec::with_capacity(hex.len() * 4);
+        for c in hex.chars() {
+            let v = c.to_digit(16).ok_or_else(|| {
+                Error::new(ErrorKind::InvalidData, format!("bad hex on line {}", i + 1))
+            })?;
+            for shift in [3, 2, 1, 0] {
+                code.push(((v >> shift) & 1) as i32);
+            }
+        }
+        // e1 codes are 4092 chips, exactly 1023 hex digits
+        code.truncate(4092);
+        codes.push((prn, DVector::from_vec(code)));
+    }
+    Ok(codes)
+}
+
+pub const GALILEO_E1_CHIP_RATE: f64 = 1.023e6;
+pub const GALILEO_E1_CODE_LENGTH: usize = 4092;
+pub const GALILEO_E5A_CHIP_RATE: f64 = 10.23e6;
+pub const GALILEO_E5A_I_SECONDARY: u32 = 0x842e9; // 20 bit cs20 code, msb first
+
+pub fn gen_e5a_code(reg2_start: u16) -> DVector<i32> {
+    // 10230 chip e5a primary code from two 14 stage registers, register 1 starts at
+    // all ones and register 2 at the prn specific start value from the os sis icd
+    e5a_sequence(reg2_start, 10230)
+}
+
+fn e5a_sequence(reg2_start: u16, length: usize) -> DVector<i32> {
+    let poly1: u16 = 0o40503;
+    let poly2: u16 = 0o50661;
+    let mut r1: u16 = 0x3fff;
+    let mut r2: u16 = reg2_start & 0x3fff;
+    let step = |r: u16, poly: u16| {
+        // feedback is the parity of the tapped stages, shifted into stage 1
+        let fb = ((r & poly & 0x3fff).count_ones() & 1) as u16;
+        ((r >> 1) | (fb << 13)) & 0x3fff
+    };
+    DVector::from_fn(length, |_, _| {
+        let out = ((r1 ^ r2) & 1) as i32;
+        r1 = step(r1, poly1);
+        r2 = step(r2, poly2);
+        out
+    })
+}
+
+pub fn load_galileo_e5a_start_values(filename: &str) -> Result<Vec<(usize, u16, u16)>, Error> {
+    // e5a register 2 start values are published in octal in the os sis icd, one prn
+    // per line as "prn,e5a-i,e5a-q"
+    let file = File::open(filename)?;
+    let mut values = vec![];
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("bad start value on line {}", i + 1),
+            )
+        };
+        let fields: Vec<&str> = line
+            .split([',', ';', ' ', '\t'])
+            .filter(|f| !f.is_empty())
+            .collect();
+        if fields.len() != 3 {
+            return Err(bad());
+        }
+        let prn = fields[0].trim().parse::<usize>().map_err(|_| bad())?;
+        let start_i = u16::from_str_radix(fields[1].trim(), 8).map_err(|_| bad())?;
+        let start_q = u16::from_str_radix(fields[2].trim(), 8).map_err(|_| bad())?;
+        values.push((prn, start_i, start_q));
+    }
+    Ok(values)
+}
+
+pub fn gen_e5a(
+    prn: usize,
+    pilot: bool,
+    start_values: &[(usize, u16, u16)],
+) -> Option<DVector<i32>> {
+    // e5a-i (data) and e5a-q (pilot) share the register polynomials and differ only in
+    // the register 2 start value
+    let &(_, start_i, start_q) = start_values.iter().find(|v| v.0 == prn)?;
+    Some(gen_e5a_code(if pilot { start_q } else { start_i }))
+}
+
+pub const BEIDOU_B1I_CHIP_RATE: f64 = 2.046e6;
+pub const BEIDOU_B1I_CODE_LENGTH: usize = 2046;
+
+// g2 phase selector stages for beidou b1i prn 1-37, BDS-SIS-ICD-B1I table 4-1
+const BEIDOU_B1I_TAPS: [[usize; 2]; 37] = [
+    [1, 3],
+    [1, 4],
+    [1, 5],
+    [1, 6],
+    [1, 8],
+    [1, 9],
+    [1, 10],
+    [1, 11],
+    [2, 7],
+    [3, 4],
+    [3, 5],
+    [3, 6],
+    [3, 8],
+    [3, 9],
+    [3, 10],
+    [3, 11],
+    [4, 5],
+    [4, 6],
+    [4, 8],
+    [4, 9],
+    [4, 10],
+    [4, 11],
+    [5, 6],
+    [5, 8],
+    [5, 9],
+    [5, 10],
+    [5, 11],
+    [6, 8],
+    [6, 9],
+    [6, 10],
+    [6, 11],
+    [8, 9],
+    [8, 10],
+    [8, 11],
+    [9, 10],
+    [9, 11],
+    [10, 11],
+];
+
+pub fn gen_b1i_code(prn_num: usize) -> DVector<i32> {
+    // the 2047 chip gold code is truncated by one chip
+    b1i_sequence(prn_num, BEIDOU_B1I_CODE_LENGTH)
+}
+
+fn b1i_sequence(prn_num: usize, length: usize) -> DVector<i32> {
+    // g1: 1 + x + x^7 + x^8 + x^9 + x^10 + x^11
+    // g2: 1 + x + x^2 + x^3 + x^4 + x^5 + x^8 + x^9 + x^11
+    // both start at 01010101010 (stages 1 to 11)
+    let taps = BEIDOU_B1I_TAPS[prn_num - 1];
+    let init = [0u8, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0];
+    let mut g1 = init;
+    let mut g2 = init;
+    DVector::from_fn(length, |_, _| {
+        let out = g1[10] ^ g2[taps[0] - 1] ^ g2[taps[1] - 1];
+        let f1 = g1[0] ^ g1[6] ^ g1[7] ^ g1[8] ^ g1[9] ^ g1[10];
+        let f2 = g2[0] ^ g2[1] ^ g2[2] ^ g2[3] ^ g2[4] ^ g2[7] ^ g2[8] ^ g2[10];
+        g1.rotate_right(1);
+        g2.rotate_right(1);
+        g1[0] = f1;
+        g2[0] = f2;
+        out as i32
+    })
+}
+
+pub const GLONASS_L1_CHIP_RATE: f64 = 0.511e6;
+pub const GLONASS_L1_CODE_LENGTH: usize = 511;
+
+pub fn gen_glonass_code() -> DVector<i32> {
+    // 1 + x^5 + x^9 m-sequence, all ones start, output from stage 7; identical for all
+    // satellites since glonass separates them by frequency
+    let mut r = [1u8; 9];
+    DVector::from_fn(GLONASS_L1_CODE_LENGTH, |_, _| {
+        let out = r[6];
+        let f = r[4] ^ r[8];
+        r.rotate_right(1);
+        r[0] = f;
+        out as i32
+    })
+}
+
+pub fn glonass_l1_frequency(channel: i32) -> f64 {
+    // fdma carrier for frequency channel k (-7..6), Hz
+    1602e6 + channel as f64 * 562.5e3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn octal(chips: &[i32]) -> u32 {
+        // chips (0/1) as a number, first chip most significant
+        chips.iter().fold(0, |acc, &c| (acc << 1) | c as u32)
+    }
+
+    #[test]
+    fn ca_first_chips() {
+        // IS-GPS-200 table 3-Ia, first 10 chips (octal)
+        for (prn, first) in [(1, 0o1440), (2, 0o1620), (3, 0o1710), (4, 0o1744)] {
+            assert_eq!(
+                octal(&gen_ca_code(prn).as_slice()[..10]),
+                first,
+                "prn {}",
+                prn
+            );
+        }
+    }
+
+    #[test]
+    fn l2c_end_states() {
+        // IS-GPS-200 table 3-IIa, register state at the last chip of each code
+        let table = [(1, 0o552566002, 0o267724236), (2, 0o034445034, 0o167516066)];
+        for (prn, cm_end, cl_end) in table {
+            for (init, length, end) in [
+                (L2CM_INIT[prn - 1], 10230, cm_end),
+                (L2CL_INIT[prn - 1], 767250, cl_end),
+            ] {
+                let state = (1..length).fold(init, |s, _| l2c_shift(s));
+                assert_eq!(state, end, "prn {} length {}", prn, length);
+                let code = gen_l2c_code(init, length);
+                assert_eq!(code[length - 1] as u32, end & 1);
+            }
+        }
+    }
+
+    #[test]
+    fn l5_initial_xb_states() {
+        // IS-GPS-705 table 3-Ia, xb register (stages 1 to 13) after the code advance
+        let i5_table = [
+            (1, "0101011100100"),
+            (3, "0100000001000"),
+            (4, "1011000100110"),
+        ];
+        let q5_table = [
+            (1, "1001011001100"),
+            (2, "0100011110110"),
+            (4, "0011101101010"),
+        ];
+        let xb_state = |advance: usize| {
+            let mut xb = [1u8; 13];
+            for _ in 0..advance {
+                let f = xb[0] ^ xb[2] ^ xb[3] ^ xb[5] ^ xb[6] ^ xb[7] ^ xb[11] ^ xb[12];
+                xb.rotate_right(1);
+                xb[0] = f;
+            }
+            xb.iter().map(|b| b.to_string()).collect::<String>()
+        };
+        for (prn, state) in i5_table {
+            assert_eq!(xb_state(L5I_XB_ADVANCE[prn - 1]), state, "i5 prn {}", prn);
+            // the code starts with xa (all ones) against the advanced xb output
+            let code = gen_code(GpsCode::L5I, prn, false);
+            let first: Vec<i32> = state.chars().rev().map(|c| (c == '0') as i32).collect();
+            assert_eq!(&code.as_slice()[..13], &first[..], "i5 prn {}", prn);
+        }
+        for (prn, state) in q5_table {
+            assert_eq!(xb_state(L5Q_XB_ADVANCE[prn - 1]), state, "q5 prn {}", prn);
+        }
+    }
+
+    #[test]
+    fn l1c_first_and_last_chips() {
+        // IS-GPS-800 table 3.2-2, initial and final 24 chips (octal)
+        let table = [
+            (GpsCode::L1CP, 1, 0o05752067, 0o20173742),
+            (GpsCode::L1CD, 1, 0o77001425, 0o52231646),
+            (GpsCode::L1CP, 2, 0o70146401, 0o35437154),
+            (GpsCode::L1CD, 2, 0o23342754, 0o46703351),
+        ];
+        for (code, prn, first, last) in table {
+            let chips = gen_code(code, prn, false);
+            assert_eq!(chips.len(), 10230);
+            assert_eq!(
+                octal(&chips.as_slice()[..24]),
+                first,
+                "{:?} prn {}",
+                code,
+                prn
+            );
+            assert_eq!(
+                octal(&chips.as_slice()[10206..]),
+                last,
+                "{:?} prn {}",
+                code,
+                prn
+            );
+        }
+    }
+
+    #[test]
+    fn l5_neuman_hofman_tiers() {
+        // nh10 has 4 ones and nh20 8 ones, each tier is the primary code or its inverse
+        assert_eq!(L5I_NH10.iter().filter(|&&c| c == 1).count(), 4);
+        assert_eq!(L5Q_NH20.iter().filter(|&&c| c == 1).count(), 8);
+        for (quadrature, nh) in [(false, &L5I_NH10[..]), (true, &L5Q_NH20[..])] {
+            let code = if quadrature {
+                GpsCode::L5Q
+            } else {
+                GpsCode::L5I
+            };
+            let primary = gen_code(code, 7, true);
+            let tiered = gen_l5_tiered(7, quadrature, true);
+            assert_eq!(tiered.len(), 10230 * nh.len());
+            for (k, &bit) in nh.iter().enumerate() {
+                let sign = 1 - 2 * bit;
+                let tier = tiered.rows(k * 10230, 10230);
+                assert!(tier
+                    .iter()
+                    .zip(primary.iter())
+                    .all(|(&t, &p)| t == sign * p));
+            }
+        }
+    }
+
+    #[test]
+    fn glonass_first_chips() {
+        // glonass icd: the code is read from stage 7 of the all ones register and
+        // begins 111111100, one period of the m-sequence has 256 ones
+        let code = gen_glonass_code();
+        assert_eq!(&code.as_slice()[..9], &[1, 1, 1, 1, 1, 1, 1, 0, 0]);
+        assert_eq!(code.iter().sum::<i32>(), 256);
+    }
+
+    #[test]
+    fn b1i_gold_family() {
+        // the first chip is stage 11 of g1 against the selected g2 stages of the
+        // 01010101010 start, and over the untruncated 2047 chips the codes form a
+        // gold family, all cross and out of phase correlations are -65, -1 or 63
+        let prns = [1, 2, 9, 17, 30, 37];
+        let codes: Vec<Vec<i32>> = prns
+            .iter()
+            .map(|&prn| {
+                let taps = BEIDOU_B1I_TAPS[prn - 1];
+                let first = taps[0].is_multiple_of(2) ^ taps[1].is_multiple_of(2);
+                let code = b1i_sequence(prn, 2 * 2047);
+                assert_eq!(code[0], first as i32, "prn {}", prn);
+                assert_eq!(gen_b1i_code(prn).as_slice(), &code.as_slice()[..2046]);
+                code.iter().map(|&c| 1 - 2 * c).collect()
+            })
+            .collect();
+        for a in 0..codes.len() {
+            assert_eq!(&codes[a][..2047], &codes[a][2047..], "prn {}", prns[a]);
+            for b in a..codes.len() {
+                for lag in (a == b) as usize..2047 {
+                    let r: i32 = (0..2047).map(|i| codes[a][i] * codes[b][i + lag]).sum();
+                    assert!(
+                        [-65, -1, 63].contains(&r),
+                        "prns {} {} lag {}",
+                        a + 1,
+                        b + 1,
+                        lag
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn e5a_maximal_length_registers() {
+        // register 1 alone (register 2 at zero) and register 2 alone (the difference to
+        // it) are 16383 chip m-sequences with 8192 ones, the start value is shifted out
+        // least significant bit first
+        let base = e5a_sequence(0, 2 * 16383);
+        let start = 0o30305;
+        let code = e5a_sequence(start, 2 * 16383);
+        let reg2: Vec<i32> = (0..2 * 16383).map(|i| code[i] ^ base[i]).collect();
+        for seq in [base.as_slice(), &reg2[..]] {
+            assert_eq!(&seq[..16383], &seq[16383..]);
+            assert_eq!(seq[..16383].iter().sum::<i32>(), 8192);
+        }
+        assert!(base.as_slice()[..14].iter().all(|&c| c == 1));
+        for (k, &chip) in reg2[..14].iter().enumerate() {
+            assert_eq!(chip as u16, (start >> k) & 1);
+        }
+        assert_eq!(gen_e5a_code(start).as_slice(), &code.as_slice()[..10230]);
+    }
+}
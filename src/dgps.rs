@@ -0,0 +1,240 @@
+use crate::earth::*;
+use crate::observables::*;
+use crate::rinex_obs::*;
+use crate::satellites::*;
+use crate::solver::*;
+use nalgebra::*;
+use std::io::Error;
+
+#[derive(Clone, Debug)]
+pub struct DgpsConfig {
+    pub max_age: f64, // sec, older corrections are not applied
+    pub solver: SolverConfig,
+}
+
+impl Default for DgpsConfig {
+    fn default() -> Self {
+        // the corrections already contain the atmosphere seen at the base, so the
+        // rover must not model it a second time
+        DgpsConfig {
+            max_age: 30.,
+            solver: SolverConfig {
+                troposphere: false,
+                ionosphere: None,
+                ..SolverConfig::default()
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PseudorangeCorrection {
+    pub prn: usize,
+    pub iode: f64, // ephemeris the correction was computed with
+    pub prc: f64,  // pseudorange correction (m), added to the measured pseudorange
+    pub rrc: f64,  // range rate correction (m/s)
+}
+
+#[derive(Clone, Debug)]
+pub struct DgpsCorrections {
+    pub gps_week: i32,
+    pub gps_time: f64, // reference time of the corrections
+    pub corrections: Vec<PseudorangeCorrection>,
+}
+
+impl DgpsCorrections {
+    pub fn get(&self, prn: usize) -> Option<&PseudorangeCorrection> {
+        self.corrections.iter().find(|c| c.prn == prn)
+    }
+
+    pub fn age(&self, gps_time: f64) -> f64 {
+        wrap_week(gps_time - self.gps_time)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DgpsSolution {
+    pub solution: PositionSolution,
+    pub correction_age: f64,   // sec
+    pub corrected: Vec<usize>, // prns with a correction applied
+}
+
+pub struct DgpsBase {
+    position: Vector3<f64>, // ecef (m)
+    config: DgpsConfig,
+    last: Option<DgpsCorrections>,
+}
+
+impl DgpsBase {
+    pub fn new(position: Vector3<f64>, config: DgpsConfig) -> Self {
+        DgpsBase {
+            position,
+            config,
+            last: None,
+        }
+    }
+
+    pub fn from_geodetic(lla: Vector3<f64>, config: DgpsConfig) -> Self {
+        // surveyed latitude (deg), longitude (deg) and height (m)
+        DgpsBase::new(geodetic2ecef(lla, 0), config)
+    }
+
+    pub fn position(&self) -> Vector3<f64> {
+        self.position
+    }
+
+    pub fn last(&self) -> Option<&DgpsCorrections> {
+        self.last.as_ref()
+    }
+
+    pub fn update(
+        &mut self,
+        epoch: &ObservationEpoch,
+        ephemerides: &[SatelliteData],
+    ) -> Option<DgpsCorrections> {
+        // corrections from the known base position, the common base clock bias is
+        // removed so that only satellite dependent errors remain
+        let geometry = satellite_geometry(
+            epoch,
+            ephemerides,
+            self.position,
+            Band::L1,
+            &self.config.solver,
+        );
+        if geometry.is_empty() {
+            return None;
+        }
+        let clock = geometry
+            .iter()
+            .map(|g| g.pseudorange - g.range)
+            .sum::<f64>()
+            / geometry.len() as f64;
+
+        let mut corrections = vec![];
+        for g in geometry.iter() {
+            let iode = match select_ephemeris(ephemerides, g.prn, epoch.gps_time) {
+                Some(eph) => eph.iode(),
+                None => continue,
+            };
+            let prc = g.range - g.pseudorange + clock;
+
+            // range rate from the previous set when it used the same ephemeris
+            let rrc = self
+                .last
+                .as_ref()
+                .and_then(|last| {
+                    let dt = last.age(epoch.gps_time);
+                    let prev = last.get(g.prn)?;
+                    if prev.iode == iode && dt > 0. && dt <= self.config.max_age {
+                        Some((prc - prev.prc) / dt)
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(0.);
+
+            corrections.push(PseudorangeCorrection {
+                prn: g.prn,
+                iode,
+                prc,
+                rrc,
+            });
+        }
+
+        let set = DgpsCorrections {
+            gps_week: epoch.gps_week,
+            gps_time: epoch.gps_time,
+            corrections,
+        };
+        self.last = Some(set.clone());
+        Some(set)
+    }
+}
+
+pub fn apply_corrections(
+    epoch: &ObservationEpoch,
+    corrections: &DgpsCorrections,
+) -> (ObservationEpoch, Vec<usize>) {
+    // l1 pseudoranges propagated to the rover epoch with the range rate correction,
+    // satellites without a correction are dropped
+    let dt = corrections.age(epoch.gps_time);
+    let mut corrected = vec![];
+    let mut out = epoch.clone();
+    out.obs.retain_mut(|o| match corrections.get(o.prn) {
+        Some(c) if o.band == Band::L1 && o.pseudorange != 0. => {
+            o.pseudorange += c.prc + c.rrc * dt;
+            corrected.push(o.prn);
+            true
+        }
+        _ => false,
+    });
+    (out, corrected)
+}
+
+pub fn solve_dgps(
+    epoch: &ObservationEpoch,
+    ephemerides: &[SatelliteData],
+    corrections: &DgpsCorrections,
+    initial: Vector3<f64>,
+    config: &DgpsConfig,
+) -> Option<DgpsSolution> {
+    let age = corrections.age(epoch.gps_time);
+    if age.abs() > config.max_age {
+        return None;
+    }
+    let (corrected_epoch, corrected) = apply_corrections(epoch, corrections);
+
+    // the rover must use the same ephemeris as the base for each correction
+    let matching: Vec<SatelliteData> = ephemerides
+        .iter()
+        .filter(|e| corrections.get(e.prn()).is_some_and(|c| c.iode == e.iode()))
+        .cloned()
+        .collect();
+
+    let solution = solve_position(&corrected_epoch, &matching, initial, &config.solver)?;
+    let corrected = corrected
+        .into_iter()
+        .filter(|prn| solution.prns.contains(prn))
+        .collect();
+    Some(DgpsSolution {
+        solution,
+        correction_age: age,
+        corrected,
+    })
+}
+
+pub fn dgps_rinex(
+    base_file: &str,
+    rover_file: &str,
+    ephemerides: &[SatelliteData],
+    base_lla: Vector3<f64>,
+    config: &DgpsConfig,
+) -> Result<Vec<DgpsSolution>, Error> {
+    // post processes a base and rover observation file pair, each rover epoch uses
+    // the latest base epoch at or before it
+    let (_, base_epochs) = read_rinex_obs(base_file)?;
+    let mut base = DgpsBase::from_geodetic(base_lla, config.clone());
+    let mut base_epochs = base_epochs.into_iter().peekable();
+    let mut position = Vector3::zeros();
+    let mut solutions = vec![];
+
+    for rover in RinexObsReader::open(rover_file)? {
+        let rover = rover?;
+        while let Some(next) = base_epochs.peek() {
+            if wrap_week(next.gps_time - rover.gps_time) > 0. {
+                break;
+            }
+            base.update(next, ephemerides);
+            base_epochs.next();
+        }
+        let corrections = match base.last() {
+            Some(c) => c.clone(),
+            None => continue,
+        };
+        if let Some(s) = solve_dgps(&rover, ephemerides, &corrections, position, config) {
+            position = s.solution.position;
+            solutions.push(s);
+        }
+    }
+    Ok(solutions)
+}
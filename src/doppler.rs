@@ -1,33 +1,57 @@
 use crate::codes::*;
-use crate::interpolation::*;
 use nalgebra::*;
 use std::f64::consts::*;
-use std::time::Instant;
 
-#[allow(unused_variables, non_snake_case)]
+#[allow(non_snake_case)]
 pub fn correlation_magnitude(
-    test_delay: DVector<f64>,
     test_doppler: DVector<f64>,
-    prn_number: usize,
+    code: &DVector<i32>,
+    modulation: Modulation,
     received_signal: DVector<f64>,
     carrier_freq: f64,
     time: DVector<f64>,
 ) -> DMatrix<f64> {
-    let prn_code = gen_prn(prn_number, true, 1023, 0).map(|val| val as f64);
-    let (_, prn_code_interp) = interp_n_points(&linspace(1., 1023., 1023), &prn_code, time.len());
+    // acquisition search over doppler for any +1/-1 ranging code and its modulation,
+    // the time vector must span exactly one code period. rows are circular code lags
+    // (fftshifted), one column per test doppler.
+    // the boc autocorrelation has side peaks half a chip either side of the main peak,
+    // so the boc/prn cross correlation is subtracted to cancel them (aspect)
+    let n = time.len();
+    let replica = code_replica(&modulate_subcarrier(code, modulation), n);
+    let prn_replica = code_replica(&code.map(|c| c as f64), n);
+    let beta = match modulation {
+        Modulation::Bpsk => 0.,
+        Modulation::Boc11 => 1.,
+        Modulation::CbocB | Modulation::CbocC => 10. / 11.,
+    };
 
-    let mut I_cc = DMatrix::zeros(time.len(), test_doppler.len());
-    let mut Q_cc = DMatrix::zeros(time.len(), test_doppler.len());
+    let mut result = DMatrix::zeros(n, test_doppler.len());
     for i in 0..test_doppler.len() {
-        let I_interp = interp_I(carrier_freq, test_doppler[i], &time, &prn_code_interp);
-        let Q_interp = interp_Q(carrier_freq, test_doppler[i], &time, &prn_code_interp);
-        let (corr_I, _) = circ_corr(&received_signal, &I_interp, 1.);
-        let (corr_Q, _) = circ_corr(&received_signal, &Q_interp, 1.);
-        I_cc.set_column(i, &corr_I);
-        Q_cc.set_column(i, &corr_Q);
+        // the carrier is wiped off before the circular code correlation, a circularly
+        // shifted carrier replica would jump in phase at the block edge
+        let factor = 2.0 * PI * (carrier_freq + test_doppler[i]);
+        let I_wiped = received_signal.zip_map(&time, |r, t| r * (factor * t).cos());
+        let Q_wiped = received_signal.zip_map(&time, |r, t| r * (factor * t).sin());
+        let mut power = code_power(&I_wiped, &Q_wiped, &replica);
+        if beta > 0. {
+            let cross = code_power(&I_wiped, &Q_wiped, &prn_replica);
+            power = (power - beta * cross).map(|x| x.max(0.));
+        }
+        result.set_column(i, &power);
     }
+    result
+}
+
+fn code_replica(code: &DVector<f64>, n: usize) -> DVector<f64> {
+    // one code period (chips or subchips) sampled at n points, nearest chip
+    let m = code.len();
+    DVector::from_fn(n, |i, _| code[i * m / n])
+}
 
-    I_cc.map(|x| x * x) + Q_cc.map(|x| x * x)
+fn code_power(i: &DVector<f64>, q: &DVector<f64>, replica: &DVector<f64>) -> DVector<f64> {
+    let (corr_i, _) = circ_corr(i, replica, 1.);
+    let (corr_q, _) = circ_corr(q, replica, 1.);
+    corr_i.map(|x| x * x) + corr_q.map(|x| x * x)
 }
 
 #[allow(non_snake_case)]
@@ -35,7 +35,7 @@ pub fn earth_constants(unit: usize) -> (f64, f64) {
 
 pub fn ecef2geodetic(x: Vector3<f64>, unit: usize) -> Vector3<f64> {
     // returns lat long altitude in (deg, deg, unit)
-    let maxiter = 1000 as usize;
+    let maxiter = 1000_usize;
     let (a, _, e, _, _) = wgs84(unit);
 
     let e2 = e.powi(2);
@@ -62,7 +62,7 @@ pub fn ecef2geodetic(x: Vector3<f64>, unit: usize) -> Vector3<f64> {
 
     // use newtons method to iterate on lat and h
     for _i in 0..maxiter {
-        if rho_error.abs() < 1e-8 || z_error.abs() < 1e-8 {
+        if rho_error.abs() < 1e-8 && z_error.abs() < 1e-8 {
             break;
         }
         let slat = lat.sin();
@@ -76,7 +76,7 @@ pub fn ecef2geodetic(x: Vector3<f64>, unit: usize) -> Vector3<f64> {
 
         // find jacobian
         let aa = drdl * clat - (r_n + h) * slat;
-        let cc = (1. - e2) * (drdl * slat + r_n * clat);
+        let cc = (1. - e2) * drdl * slat + (r_n * (1. - e2) + h) * clat;
         let bb = clat;
         let dd = slat;
 
@@ -108,13 +108,159 @@ pub fn geodetic2ecef(lla: Vector3<f64>, unit: usize) -> Vector3<f64> {
         (r_n * (1. - e2) + h) * slat,
     );
 
-    if lat < -90. || lat > 90. || long < -180. || long > 360. {
+    if !(-90. ..=90.).contains(&lat) || !(-180. ..=360.).contains(&long) {
         panic!("WGS Lat or Long is out of range");
     }
 
     x
 }
 
+pub fn enu_rotation(lla: Vector3<f64>) -> Matrix3<f64> {
+    // rotation from ecef to local east, north, up at geodetic lat/long (deg)
+    let deg2rad = PI / 180.;
+    let (slat, clat) = (lla[0] * deg2rad).sin_cos();
+    let (slong, clong) = (lla[1] * deg2rad).sin_cos();
+    Matrix3::new(
+        -slong,
+        clong,
+        0.,
+        -slat * clong,
+        -slat * slong,
+        clat,
+        clat * clong,
+        clat * slong,
+        slat,
+    )
+}
+
+pub fn ecef2enu(x: Vector3<f64>, reference: Vector3<f64>, unit: usize) -> Vector3<f64> {
+    // position x relative to the ecef reference point, in the reference's enu frame
+    enu_rotation(ecef2geodetic(reference, unit)) * (x - reference)
+}
+
+pub fn enu2ecef(enu: Vector3<f64>, reference: Vector3<f64>, unit: usize) -> Vector3<f64> {
+    reference + enu_rotation(ecef2geodetic(reference, unit)).transpose() * enu
+}
+
+pub fn look_angles(receiver: Vector3<f64>, satellite: Vector3<f64>) -> (f64, f64) {
+    // azimuth (0-360 deg, clockwise from north) and elevation (deg) of a satellite
+    let enu = ecef2enu(satellite, receiver, 0);
+    let rad2deg = 180. / PI;
+    let az = enu[0].atan2(enu[1]) * rad2deg;
+    let el = enu[2].atan2((enu[0].powi(2) + enu[1].powi(2)).sqrt()) * rad2deg;
+    (az.rem_euclid(360.), el)
+}
+
+pub fn sun_moon_position(gps_week: i32, gps_time: f64) -> (Vector3<f64>, Vector3<f64>) {
+    // low precision sun and moon ecef positions (m), montenbruck and gill 3.3.2,
+    // good to about 0.1% which is plenty for tides and satellite attitude
+    let deg2rad = PI / 180.;
+    let arcsec = deg2rad / 3600.;
+    let jd_gps = 2444244.5 + gps_week as f64 * 7. + gps_time / 86400.;
+    let t = (jd_gps + 51.184 / 86400. - 2451545.) / 36525.; // terrestrial time centuries
+    let eps = 23.43929111 * deg2rad;
+
+    // sun
+    let m = (357.5256 + 35999.049 * t) * deg2rad;
+    let lambda =
+        (282.9400 * deg2rad) + m + 6892. * arcsec * m.sin() + 72. * arcsec * (2. * m).sin();
+    let r = (149.619 - 2.499 * m.cos() - 0.021 * (2. * m).cos()) * 1e9;
+    let sun = Vector3::new(
+        r * lambda.cos(),
+        r * lambda.sin() * eps.cos(),
+        r * lambda.sin() * eps.sin(),
+    );
+
+    // moon
+    let l0 = (218.31617 + 481267.88088 * t - 1.3972 * t) * deg2rad;
+    let l = (134.96292 + 477198.86753 * t) * deg2rad;
+    let lp = (357.52543 + 35999.04944 * t) * deg2rad;
+    let f = (93.27283 + 483202.01873 * t) * deg2rad;
+    let d = (297.85027 + 445267.11135 * t) * deg2rad;
+    let lambda = l0
+        + (22640. * l.sin() + 769. * (2. * l).sin() - 4586. * (l - 2. * d).sin()
+            + 2370. * (2. * d).sin()
+            - 668. * lp.sin()
+            - 412. * (2. * f).sin()
+            - 212. * (2. * l - 2. * d).sin()
+            - 206. * (l + lp - 2. * d).sin()
+            + 192. * (l + 2. * d).sin()
+            - 165. * (lp - 2. * d).sin()
+            + 148. * (l - lp).sin()
+            - 125. * d.sin()
+            - 110. * (l + lp).sin()
+            - 55. * (2. * f - 2. * d).sin())
+            * arcsec;
+    let beta = (18520.
+        * (f + lambda - l0 + (412. * (2. * f).sin() + 541. * lp.sin()) * arcsec).sin()
+        - 526. * (f - 2. * d).sin()
+        + 44. * (l + f - 2. * d).sin()
+        - 31. * (-l + f - 2. * d).sin()
+        - 25. * (-2. * l + f).sin()
+        - 23. * (lp + f - 2. * d).sin()
+        + 21. * (-l + f).sin()
+        + 11. * (-lp + f - 2. * d).sin())
+        * arcsec;
+    let r = (385000.
+        - 20905. * l.cos()
+        - 3699. * (2. * d - l).cos()
+        - 2956. * (2. * d).cos()
+        - 570. * (2. * l).cos()
+        + 246. * (2. * l - 2. * d).cos()
+        - 205. * (lp - 2. * d).cos()
+        - 171. * (l + 2. * d).cos()
+        - 152. * (l + lp - 2. * d).cos())
+        * 1e3;
+    let ecliptic = Vector3::new(
+        r * beta.cos() * lambda.cos(),
+        r * beta.cos() * lambda.sin(),
+        r * beta.sin(),
+    );
+    let moon = Vector3::new(
+        ecliptic[0],
+        ecliptic[1] * eps.cos() - ecliptic[2] * eps.sin(),
+        ecliptic[1] * eps.sin() + ecliptic[2] * eps.cos(),
+    );
+
+    // inertial to earth fixed by sidereal time, ignoring precession and nutation
+    let jd_ut = jd_gps - 18. / 86400.;
+    let gmst = (280.46061837 + 360.98564736629 * (jd_ut - 2451545.)).rem_euclid(360.) * deg2rad;
+    let rot = Matrix3::new(
+        gmst.cos(),
+        gmst.sin(),
+        0.,
+        -gmst.sin(),
+        gmst.cos(),
+        0.,
+        0.,
+        0.,
+        1.,
+    );
+    (rot * sun, rot * moon)
+}
+
+pub fn solid_tide(position: Vector3<f64>, sun: Vector3<f64>, moon: Vector3<f64>) -> Vector3<f64> {
+    // degree 2 solid earth tide displacement (m, ecef) of a site, iers 2010 step 1
+    // with nominal love and shida numbers
+    let (a, _, _, _, _) = wgs84(0);
+    let (gm, _) = earth_constants(0);
+    let gm_sun = 1.32712442076e20;
+    let gm_moon = 4.9028e12;
+    let (h2, l2) = (0.6078, 0.0847);
+    let up = position.normalize();
+
+    let mut displacement = Vector3::zeros();
+    for (body, gm_body) in [(sun, gm_sun), (moon, gm_moon)] {
+        let r = body.norm();
+        let unit = body / r;
+        let cos = unit.dot(&up);
+        let scale = gm_body / gm * a.powi(4) / r.powi(3);
+        displacement +=
+            scale * (h2 * up * (1.5 * cos * cos - 0.5) + 3. * l2 * cos * (unit - cos * up));
+    }
+    displacement
+}
+
 // pub fn gps_constants(unit: usize) {
 //
 // }
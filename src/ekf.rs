@@ -0,0 +1,310 @@
+use crate::earth::*;
+use crate::observables::*;
+use crate::satellites::*;
+use crate::solver::*;
+use nalgebra::*;
+use std::f64::consts::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dynamics {
+    ConstantVelocity,     // white acceleration noise
+    ConstantAcceleration, // white jerk noise
+}
+
+#[derive(Clone, Debug)]
+pub struct ClockNoise {
+    // power law coefficients of the oscillator's fractional frequency noise
+    pub h0: f64,
+    pub h_minus1: f64,
+    pub h_minus2: f64,
+}
+
+impl ClockNoise {
+    pub fn tcxo() -> Self {
+        ClockNoise {
+            h0: 2e-19,
+            h_minus1: 7e-21,
+            h_minus2: 2e-20,
+        }
+    }
+
+    pub fn ocxo() -> Self {
+        ClockNoise {
+            h0: 2e-25,
+            h_minus1: 7e-25,
+            h_minus2: 6e-25,
+        }
+    }
+
+    pub fn process_noise(&self, dt: f64) -> Matrix2<f64> {
+        // bias (m) and drift (m/s) process noise over dt (van dierendonck)
+        let c2 = SPEED_OF_LIGHT * SPEED_OF_LIGHT;
+        let pi2 = PI * PI;
+        let qb = self.h0 / 2. * dt
+            + 2. * self.h_minus1 * dt * dt
+            + 2. / 3. * pi2 * self.h_minus2 * dt.powi(3);
+        let qbd = 2. * self.h_minus1 * dt + pi2 * self.h_minus2 * dt * dt;
+        let qd = self.h0 / (2. * dt) + 2. * self.h_minus1 + 8. / 3. * pi2 * self.h_minus2 * dt;
+        Matrix2::new(qb, qbd, qbd, qd) * c2
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EkfConfig {
+    pub dynamics: Dynamics,
+    pub motion_noise: f64, // acceleration (m^2/s^3) or jerk (m^2/s^5) spectral density
+    pub clock: ClockNoise,
+    pub pseudorange_sigma: f64, // m, scaled by the elevation weighting of the solver
+    pub doppler_sigma: f64,     // range rate sigma (m/s)
+    pub gate: f64,              // innovation gate in standard deviations
+    pub solver: SolverConfig,
+}
+
+impl Default for EkfConfig {
+    fn default() -> Self {
+        EkfConfig {
+            dynamics: Dynamics::ConstantVelocity,
+            motion_noise: 1.,
+            clock: ClockNoise::tcxo(),
+            pseudorange_sigma: 1.,
+            doppler_sigma: 0.1,
+            gate: 5.,
+            solver: SolverConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EkfSolution {
+    pub gps_week: i32,
+    pub gps_time: f64,
+    pub position: Vector3<f64>, // ecef (m)
+    pub velocity: Vector3<f64>, // ecef (m/s)
+    pub clock_bias: f64,        // m
+    pub clock_drift: f64,       // m/s
+    pub covariance: DMatrix<f64>,
+    pub used: usize,          // measurements accepted this epoch
+    pub rejected: Vec<usize>, // prns with a gated pseudorange or doppler
+}
+
+pub struct EkfNavigator {
+    config: EkfConfig,
+    x: DVector<f64>,
+    p: DMatrix<f64>,
+    gps_time: f64,
+    initialized: bool,
+}
+
+impl EkfNavigator {
+    pub fn new(config: EkfConfig) -> Self {
+        let n = state_size(config.dynamics);
+        EkfNavigator {
+            config,
+            x: DVector::zeros(n),
+            p: DMatrix::zeros(n, n),
+            gps_time: 0.,
+            initialized: false,
+        }
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    pub fn initialize(
+        &mut self,
+        position: Vector3<f64>,
+        velocity: Vector3<f64>,
+        clock_bias: f64,
+        clock_drift: f64,
+        gps_time: f64,
+    ) {
+        let n = self.x.len();
+        self.x = DVector::zeros(n);
+        self.x.fixed_rows_mut::<3>(0).copy_from(&position);
+        self.x.fixed_rows_mut::<3>(3).copy_from(&velocity);
+        self.x[n - 2] = clock_bias;
+        self.x[n - 1] = clock_drift;
+
+        // loose initial uncertainty, the first updates tighten it quickly
+        let mut p = DVector::from_element(n, 100f64.powi(2));
+        for i in 3..n - 2 {
+            p[i] = 10f64.powi(2);
+        }
+        p[n - 2] = 1e3f64.powi(2);
+        p[n - 1] = 100f64.powi(2);
+        self.p = DMatrix::from_diagonal(&p);
+        self.gps_time = gps_time;
+        self.initialized = true;
+    }
+
+    pub fn position(&self) -> Vector3<f64> {
+        Vector3::new(self.x[0], self.x[1], self.x[2])
+    }
+
+    pub fn velocity(&self) -> Vector3<f64> {
+        Vector3::new(self.x[3], self.x[4], self.x[5])
+    }
+
+    pub fn clock_bias(&self) -> f64 {
+        self.x[self.x.len() - 2]
+    }
+
+    pub fn clock_drift(&self) -> f64 {
+        self.x[self.x.len() - 1]
+    }
+
+    pub fn covariance(&self) -> &DMatrix<f64> {
+        &self.p
+    }
+
+    pub fn predict(&mut self, gps_time: f64) {
+        let dt = wrap_week(gps_time - self.gps_time);
+        self.gps_time = gps_time;
+        if dt <= 0. {
+            return;
+        }
+        let n = self.x.len();
+        let (f, q) = transition(self.config.dynamics, self.config.motion_noise, dt);
+        let mut phi = DMatrix::identity(n, n);
+        let mut qd = DMatrix::zeros(n, n);
+        let k = n - 2; // motion states per axis times three
+        let m = k / 3;
+        for axis in 0..3 {
+            for r in 0..m {
+                for c in 0..m {
+                    phi[(r * 3 + axis, c * 3 + axis)] = f[(r, c)];
+                    qd[(r * 3 + axis, c * 3 + axis)] = q[(r, c)];
+                }
+            }
+        }
+        phi[(k, k + 1)] = dt;
+        qd.fixed_view_mut::<2, 2>(k, k)
+            .copy_from(&self.config.clock.process_noise(dt));
+
+        self.x = &phi * &self.x;
+        self.p = &phi * &self.p * phi.transpose() + qd;
+    }
+
+    pub fn update_epoch(
+        &mut self,
+        epoch: &ObservationEpoch,
+        ephemerides: &[SatelliteData],
+    ) -> Option<EkfSolution> {
+        // predicts to the epoch and applies l1 pseudorange and doppler updates,
+        // initialising from a snapshot solution the first time
+        if !self.initialized {
+            let fix = solve_position(epoch, ephemerides, Vector3::zeros(), &self.config.solver)?;
+            let vel = solve_velocity_epoch(epoch, ephemerides, fix.position, &self.config.solver);
+            let (v, d) = vel
+                .map(|v| (v.velocity, v.clock_drift))
+                .unwrap_or((Vector3::zeros(), 0.));
+            self.initialize(fix.position, v, fix.clock_bias, d, epoch.gps_time);
+        } else {
+            self.predict(epoch.gps_time);
+        }
+
+        let n = self.x.len();
+        let geometry = satellite_geometry(
+            epoch,
+            ephemerides,
+            self.position(),
+            Band::L1,
+            &self.config.solver,
+        );
+        let mut used = 0;
+        let mut rejected = vec![];
+
+        for g in geometry.iter() {
+            // pseudorange
+            let mut h = DVector::zeros(n);
+            h.fixed_rows_mut::<3>(0).copy_from(&(-g.los));
+            h[n - 2] = 1.;
+            let range = (g.sv.position - self.position()).norm();
+            let innovation = g.pseudorange - (range + self.clock_bias());
+            let r = elevation_weight(g.elevation) * self.config.pseudorange_sigma.powi(2);
+            if self.scalar_update(&h, innovation, r) {
+                used += 1;
+            } else {
+                rejected.push(g.prn);
+            }
+
+            // doppler as range rate
+            let doppler = epoch.get(g.prn, Band::L1).map(|o| o.doppler).unwrap_or(0.);
+            if doppler != 0. {
+                let los = (g.sv.position - self.position()).normalize();
+                let mut h = DVector::zeros(n);
+                h.fixed_rows_mut::<3>(3).copy_from(&(-los));
+                h[n - 1] = 1.;
+                let measured = -Band::L1.wavelength() * doppler - g.sv.velocity.dot(&los)
+                    + g.sv.clock_drift * SPEED_OF_LIGHT;
+                let predicted = -los.dot(&self.velocity()) + self.clock_drift();
+                if self.scalar_update(&h, measured - predicted, self.config.doppler_sigma.powi(2)) {
+                    used += 1;
+                } else if !rejected.contains(&g.prn) {
+                    rejected.push(g.prn);
+                }
+            }
+        }
+
+        Some(EkfSolution {
+            gps_week: epoch.gps_week,
+            gps_time: epoch.gps_time,
+            position: self.position(),
+            velocity: self.velocity(),
+            clock_bias: self.clock_bias(),
+            clock_drift: self.clock_drift(),
+            covariance: self.p.clone(),
+            used,
+            rejected,
+        })
+    }
+
+    pub fn position_enu_sigma(&self) -> Vector3<f64> {
+        // one sigma east, north, up position uncertainty (m)
+        let rot = enu_rotation(ecef2geodetic(self.position(), 0));
+        let p = self.p.fixed_view::<3, 3>(0, 0).clone_owned();
+        let enu = rot * p * rot.transpose();
+        Vector3::new(enu[(0, 0)].sqrt(), enu[(1, 1)].sqrt(), enu[(2, 2)].sqrt())
+    }
+
+    fn scalar_update(&mut self, h: &DVector<f64>, innovation: f64, r: f64) -> bool {
+        // sequential update with innovation gating, joseph form for stability
+        let ph = &self.p * h;
+        let s = h.dot(&ph) + r;
+        if innovation * innovation > self.config.gate.powi(2) * s {
+            return false;
+        }
+        let k = &ph / s;
+        self.x += &k * innovation;
+        let n = self.x.len();
+        let ikh = DMatrix::identity(n, n) - &k * h.transpose();
+        self.p = &ikh * &self.p * ikh.transpose() + &k * k.transpose() * r;
+        true
+    }
+}
+
+fn state_size(dynamics: Dynamics) -> usize {
+    match dynamics {
+        Dynamics::ConstantVelocity => 8,
+        Dynamics::ConstantAcceleration => 11,
+    }
+}
+
+fn transition(dynamics: Dynamics, q: f64, dt: f64) -> (DMatrix<f64>, DMatrix<f64>) {
+    // single axis transition and process noise, ordered position, velocity(, accel)
+    match dynamics {
+        Dynamics::ConstantVelocity => (
+            dmatrix![1., dt; 0., 1.],
+            dmatrix![dt.powi(3) / 3., dt.powi(2) / 2.; dt.powi(2) / 2., dt] * q,
+        ),
+        Dynamics::ConstantAcceleration => (
+            dmatrix![1., dt, dt * dt / 2.; 0., 1., dt; 0., 0., 1.],
+            dmatrix![
+                dt.powi(5) / 20., dt.powi(4) / 8., dt.powi(3) / 6.;
+                dt.powi(4) / 8., dt.powi(3) / 3., dt.powi(2) / 2.;
+                dt.powi(3) / 6., dt.powi(2) / 2., dt
+            ] * q,
+        ),
+    }
+}
@@ -38,9 +38,7 @@ pub fn prev_interp<T: Copy + Into<f64>>(x: &DVector<T>, y: &DVector<T>, xi: f64)
     }
 
     for i in 1..x.len() {
-        if xi < x[i].into() {
-            return y[i - 1].into();
-        } else if (xi - x[i - 1].into()).abs() < f64::EPSILON {
+        if xi < x[i].into() || (xi - x[i - 1].into()).abs() < f64::EPSILON {
             return y[i - 1].into();
         }
     }
@@ -0,0 +1,292 @@
+use crate::observables::*;
+use crate::satellites::*;
+use nalgebra::*;
+use std::f64::consts::*;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind};
+
+#[derive(Clone, Debug)]
+pub struct IonexMap {
+    pub time: f64,             // gps sec since the start of gps time
+    pub tec: Vec<f64>,         // TECU, latitude rows of longitude values, NaN if missing
+    pub rms: Option<Vec<f64>>, // TECU
+}
+
+#[derive(Clone, Debug)]
+pub struct Ionex {
+    pub version: f64,
+    pub mapping_function: String,
+    pub base_radius: f64,           // km
+    pub height: f64,                // single layer height (km)
+    pub latitude: (f64, f64, f64),  // first, last and step (deg)
+    pub longitude: (f64, f64, f64), // first, last and step (deg)
+    pub interval: f64,              // sec
+    pub maps: Vec<IonexMap>,        // sorted by time
+}
+
+impl Ionex {
+    fn grid_size(&self) -> (usize, usize) {
+        let (lat1, lat2, dlat) = self.latitude;
+        let (lon1, lon2, dlon) = self.longitude;
+        (
+            ((lat2 - lat1) / dlat).round() as usize + 1,
+            ((lon2 - lon1) / dlon).round() as usize + 1,
+        )
+    }
+
+    fn grid_value(&self, values: &[f64], lat: f64, lon: f64) -> Option<f64> {
+        // bilinear between the four surrounding grid points
+        let (lat1, _, dlat) = self.latitude;
+        let (lon1, _, dlon) = self.longitude;
+        let (rows, cols) = self.grid_size();
+        let x = ((lon - lon1) / dlon).rem_euclid(360. / dlon.abs());
+        let y = (lat - lat1) / dlat;
+        if y < 0. || y > (rows - 1) as f64 {
+            return None;
+        }
+        let (i, j) = (y.floor() as usize, x.floor() as usize);
+        let (p, q) = (x - j as f64, y - i as f64);
+        let at = |i: usize, j: usize| {
+            values
+                .get(i.min(rows - 1) * cols + j.min(cols - 1))
+                .copied()
+        };
+        let v = (1. - p) * (1. - q) * at(i, j)?
+            + p * (1. - q) * at(i, j + 1)?
+            + (1. - p) * q * at(i + 1, j)?
+            + p * q * at(i + 1, j + 1)?;
+        if v.is_nan() {
+            None
+        } else {
+            Some(v)
+        }
+    }
+
+    pub fn vertical_tec(
+        &self,
+        lat: f64,
+        lon: f64,
+        gps_week: i32,
+        gps_time: f64,
+    ) -> Option<(f64, Option<f64>)> {
+        // vertical tec and rms (TECU) at geographic lat/long (deg), maps are rotated
+        // with the sun before interpolating between them in time
+        let t = gps_week as f64 * SECONDS_PER_WEEK + gps_time;
+        let i = self.maps.partition_point(|m| m.time <= t);
+        if i == 0 {
+            return None;
+        }
+        let a = &self.maps[i - 1];
+        let b = match self.maps.get(i) {
+            Some(b) => b,
+            None if a.time == t => a,
+            None => return None,
+        };
+        let rotated = |m: &IonexMap, values: &[f64]| {
+            self.grid_value(values, lat, lon + (t - m.time) * 360. / 86400.)
+        };
+        let f = if b.time > a.time {
+            (t - a.time) / (b.time - a.time)
+        } else {
+            0.
+        };
+        let tec = (1. - f) * rotated(a, &a.tec)? + f * rotated(b, &b.tec)?;
+        let rms = match (&a.rms, &b.rms) {
+            (Some(ra), Some(rb)) => rotated(a, ra)
+                .zip(rotated(b, rb))
+                .map(|(ra, rb)| (1. - f) * ra + f * rb),
+            _ => None,
+        };
+        Some((tec, rms))
+    }
+
+    pub fn pierce_point(&self, lla: Vector3<f64>, azimuth: f64, elevation: f64) -> (f64, f64, f64) {
+        // ionospheric pierce point lat/long (deg) on the single layer and the slant
+        // factor, receiver lat/long and satellite azimuth/elevation in deg
+        let deg2rad = PI / 180.;
+        let (lat, lon) = (lla[0] * deg2rad, lla[1] * deg2rad);
+        let az = azimuth * deg2rad;
+        let z = PI / 2. - elevation * deg2rad;
+        let zp = (self.base_radius / (self.base_radius + self.height) * z.sin()).asin();
+        let psi = z - zp;
+        let lat_ipp = (lat.sin() * psi.cos() + lat.cos() * psi.sin() * az.cos()).asin();
+        let lon_ipp = lon + (psi.sin() * az.sin() / lat_ipp.cos()).asin();
+        (lat_ipp / deg2rad, lon_ipp / deg2rad, 1. / zp.cos())
+    }
+
+    pub fn slant_delay(
+        &self,
+        lla: Vector3<f64>,
+        azimuth: f64,
+        elevation: f64,
+        gps_week: i32,
+        gps_time: f64,
+        band: Band,
+    ) -> Option<(f64, Option<f64>)> {
+        // slant ionospheric group delay (m) and its sigma from the rms maps
+        let (lat, lon, slant) = self.pierce_point(lla, azimuth, elevation);
+        let (tec, rms) = self.vertical_tec(lat, lon, gps_week, gps_time)?;
+        let scale = 40.3e16 / band.frequency().powi(2) * slant;
+        Some((tec * scale, rms.map(|r| r * scale)))
+    }
+}
+
+fn ionex_time(data: &str) -> Option<f64> {
+    let fields: Vec<f64> = data
+        .split_whitespace()
+        .filter_map(|v| v.parse().ok())
+        .collect();
+    if fields.len() < 6 {
+        return None;
+    }
+    let (week, tow) = gps_time_from_date(
+        fields[0] as i32,
+        fields[1] as i32,
+        fields[2] as i32,
+        fields[3] as i32,
+        fields[4] as i32,
+        fields[5],
+    );
+    Some(week as f64 * SECONDS_PER_WEEK + tow)
+}
+
+fn fixed_fields(data: &str, start: usize, width: usize, count: usize) -> Vec<f64> {
+    // 2X,nF6.1 style records where the numbers can run together
+    (0..count)
+        .filter_map(|k| data.get(start + k * width..start + (k + 1) * width))
+        .filter_map(|v| v.trim().parse().ok())
+        .collect()
+}
+
+pub fn read_ionex(filename: &str) -> Result<Ionex, Error> {
+    let file = File::open(filename)?;
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+    let mut ionex = Ionex {
+        version: 0.,
+        mapping_function: String::new(),
+        base_radius: 6371.,
+        height: 450.,
+        latitude: (87.5, -87.5, -2.5),
+        longitude: (-180., 180., 5.),
+        interval: 0.,
+        maps: vec![],
+    };
+    let mut exponent = -1;
+    let mut in_header = true;
+
+    // current block: tec or rms, map time and values
+    let mut block: Option<(bool, Option<f64>, Vec<f64>)> = None;
+    let mut row_left = 0;
+    let mut rms_maps: Vec<(f64, Vec<f64>)> = vec![];
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let label = line.get(60..).unwrap_or("").trim();
+        let data = line.get(..60.min(line.len())).unwrap_or("");
+
+        if in_header {
+            match label {
+                "IONEX VERSION / TYPE" => {
+                    ionex.version = data.get(..8).unwrap_or("").trim().parse().unwrap_or(0.)
+                }
+                "INTERVAL" => ionex.interval = data.trim().parse().unwrap_or(0.),
+                "MAPPING FUNCTION" => ionex.mapping_function = data.trim().to_string(),
+                "BASE RADIUS" => ionex.base_radius = data.trim().parse().unwrap_or(6371.),
+                "HGT1 / HGT2 / DHGT" => {
+                    let v = fixed_fields(data, 2, 6, 3);
+                    if v.len() == 3 && v[2] != 0. {
+                        return Err(invalid("3d ionex maps are not supported"));
+                    }
+                    if let Some(&h) = v.first() {
+                        ionex.height = h;
+                    }
+                }
+                "LAT1 / LAT2 / DLAT" | "LON1 / LON2 / DLON" => {
+                    let v = fixed_fields(data, 2, 6, 3);
+                    if v.len() != 3 || v[2] == 0. {
+                        return Err(invalid("bad ionex grid"));
+                    }
+                    if label.starts_with("LAT") {
+                        ionex.latitude = (v[0], v[1], v[2]);
+                    } else {
+                        ionex.longitude = (v[0], v[1], v[2]);
+                    }
+                }
+                "EXPONENT" => exponent = data.trim().parse().unwrap_or(-1),
+                "END OF HEADER" => in_header = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match label {
+            "START OF TEC MAP" | "START OF RMS MAP" => {
+                let (rows, cols) = ionex.grid_size();
+                block = Some((
+                    label == "START OF RMS MAP",
+                    None,
+                    Vec::with_capacity(rows * cols),
+                ));
+            }
+            "EPOCH OF CURRENT MAP" => {
+                if let Some((_, time, _)) = block.as_mut() {
+                    *time = ionex_time(data);
+                }
+            }
+            "EXPONENT" => exponent = data.trim().parse().unwrap_or(-1),
+            "LAT/LON1/LON2/DLON/H" => {
+                let v = fixed_fields(data, 2, 6, 5);
+                row_left = if v.len() == 5 && v[3] != 0. {
+                    ((v[2] - v[1]) / v[3]).round() as usize + 1
+                } else {
+                    0
+                };
+            }
+            "END OF TEC MAP" | "END OF RMS MAP" => {
+                if let Some((rms, Some(time), values)) = block.take() {
+                    if values.len() != ionex.grid_size().0 * ionex.grid_size().1 {
+                        return Err(invalid("incomplete ionex map"));
+                    }
+                    if rms {
+                        rms_maps.push((time, values));
+                    } else {
+                        ionex.maps.push(IonexMap {
+                            time,
+                            tec: values,
+                            rms: None,
+                        });
+                    }
+                }
+            }
+            "END OF FILE" => break,
+            _ => {
+                // I5 values, 16 per line, 9999 where there is no value
+                if let Some((_, _, values)) = block.as_mut() {
+                    let scale = 10f64.powi(exponent);
+                    for k in 0..16.min(row_left) {
+                        let v = match line
+                            .get(k * 5..(k + 1) * 5)
+                            .map(|v| v.trim().parse::<f64>())
+                        {
+                            Some(Ok(v)) => v,
+                            _ => break,
+                        };
+                        values.push(if v == 9999. { f64::NAN } else { v * scale });
+                        row_left -= 1;
+                    }
+                }
+            }
+        }
+    }
+
+    for (time, rms) in rms_maps {
+        if let Some(m) = ionex.maps.iter_mut().find(|m| m.time == time) {
+            m.rms = Some(rms);
+        }
+    }
+    ionex.maps.sort_by(|a, b| a.time.total_cmp(&b.time));
+    if ionex.maps.is_empty() {
+        return Err(invalid("no tec maps in ionex file"));
+    }
+    Ok(ionex)
+}
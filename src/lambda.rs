@@ -0,0 +1,240 @@
+use nalgebra::*;
+
+pub fn lambda(
+    float: &DVector<f64>,
+    covariance: &DMatrix<f64>,
+    candidates: usize,
+) -> Option<(Vec<DVector<f64>>, Vec<f64>)> {
+    // integer least squares by the lambda method (decorrelation then search), returns
+    // the best candidates with their squared distances, in increasing order
+    let n = float.len();
+    if n == 0 || covariance.nrows() != n || candidates == 0 {
+        return None;
+    }
+    let (mut l, mut d) = ltdl(covariance)?;
+    let mut z = DMatrix::identity(n, n);
+    reduction(&mut l, &mut d, &mut z);
+
+    let zs = z.transpose() * float;
+    let (zn, s) = search(&l, &d, &zs, candidates);
+
+    // back to the original ambiguities, z' a = e
+    let zt_inv = z.transpose().try_inverse()?;
+    let fixed = zn
+        .iter()
+        .map(|e| (&zt_inv * e).map(|v| v.round()))
+        .collect();
+    Some((fixed, s))
+}
+
+fn ltdl(q: &DMatrix<f64>) -> Option<(DMatrix<f64>, DVector<f64>)> {
+    // q = l' d l with l unit lower triangular
+    let n = q.nrows();
+    let mut a = q.clone();
+    let mut l = DMatrix::zeros(n, n);
+    let mut d = DVector::zeros(n);
+    for i in (0..n).rev() {
+        d[i] = a[(i, i)];
+        if d[i] <= 0. {
+            return None;
+        }
+        let s = d[i].sqrt();
+        for j in 0..=i {
+            l[(i, j)] = a[(i, j)] / s;
+        }
+        for j in 0..i {
+            for k in 0..=j {
+                a[(j, k)] -= l[(i, k)] * l[(i, j)];
+            }
+        }
+        let lii = l[(i, i)];
+        for j in 0..=i {
+            l[(i, j)] /= lii;
+        }
+    }
+    Some((l, d))
+}
+
+fn gauss(l: &mut DMatrix<f64>, z: &mut DMatrix<f64>, i: usize, j: usize) {
+    // integer gauss transformation
+    let n = l.nrows();
+    let mu = l[(i, j)].round();
+    if mu != 0. {
+        for k in i..n {
+            l[(k, j)] -= mu * l[(k, i)];
+        }
+        for k in 0..n {
+            z[(k, j)] -= mu * z[(k, i)];
+        }
+    }
+}
+
+fn permute(l: &mut DMatrix<f64>, d: &mut DVector<f64>, z: &mut DMatrix<f64>, j: usize, del: f64) {
+    let n = l.nrows();
+    let eta = d[j] / del;
+    let lam = d[j + 1] * l[(j + 1, j)] / del;
+    d[j] = eta * d[j + 1];
+    d[j + 1] = del;
+    for k in 0..j {
+        let a0 = l[(j, k)];
+        let a1 = l[(j + 1, k)];
+        l[(j, k)] = -l[(j + 1, j)] * a0 + a1;
+        l[(j + 1, k)] = eta * a0 + lam * a1;
+    }
+    l[(j + 1, j)] = lam;
+    for k in j + 2..n {
+        l.swap((k, j), (k, j + 1));
+    }
+    z.swap_columns(j, j + 1);
+}
+
+fn reduction(l: &mut DMatrix<f64>, d: &mut DVector<f64>, z: &mut DMatrix<f64>) {
+    // decorrelates the ambiguities, sorting the conditional variances
+    let n = l.nrows();
+    if n < 2 {
+        return;
+    }
+    let mut j = n as isize - 2;
+    let mut k = n as isize - 2;
+    while j >= 0 {
+        let ju = j as usize;
+        if j <= k {
+            for i in ju + 1..n {
+                gauss(l, z, i, ju);
+            }
+        }
+        let del = d[ju] + l[(ju + 1, ju)].powi(2) * d[ju + 1];
+        if del + 1e-6 < d[ju + 1] {
+            permute(l, d, z, ju, del);
+            k = j;
+            j = n as isize - 2;
+        } else {
+            j -= 1;
+        }
+    }
+}
+
+fn search(
+    l: &DMatrix<f64>,
+    d: &DVector<f64>,
+    zs: &DVector<f64>,
+    m: usize,
+) -> (Vec<DVector<f64>>, Vec<f64>) {
+    // depth first search of the decorrelated ambiguities, shrinking the ellipsoid
+    // as candidates are found
+    let n = zs.len();
+    let sgn = |x: f64| if x <= 0. { -1. } else { 1. };
+    let mut s = DMatrix::<f64>::zeros(n, n);
+    let mut dist = vec![0.; n];
+    let mut zb = vec![0.; n];
+    let mut z = vec![0.; n];
+    let mut step = vec![0.; n];
+    let mut found: Vec<(DVector<f64>, f64)> = vec![];
+    let mut maxdist = f64::MAX;
+    let mut imax = 0;
+
+    let mut k = n - 1;
+    zb[k] = zs[k];
+    z[k] = zb[k].round();
+    let mut y = zb[k] - z[k];
+    step[k] = sgn(y);
+    for _ in 0..10_000_000 {
+        let newdist = dist[k] + y * y / d[k];
+        if newdist < maxdist {
+            if k != 0 {
+                k -= 1;
+                dist[k] = newdist;
+                for i in 0..=k {
+                    s[(k, i)] = s[(k + 1, i)] + (z[k + 1] - zb[k + 1]) * l[(k + 1, i)];
+                }
+                zb[k] = zs[k] + s[(k, k)];
+                z[k] = zb[k].round();
+                y = zb[k] - z[k];
+                step[k] = sgn(y);
+            } else {
+                let candidate = DVector::from_column_slice(&z);
+                if found.len() < m {
+                    if found.is_empty() || newdist > found[imax].1 {
+                        imax = found.len();
+                    }
+                    found.push((candidate, newdist));
+                } else {
+                    if newdist < found[imax].1 {
+                        found[imax] = (candidate, newdist);
+                    }
+                    imax = (0..m)
+                        .max_by(|&a, &b| found[a].1.total_cmp(&found[b].1))
+                        .unwrap_or(0);
+                }
+                if found.len() == m {
+                    maxdist = found[imax].1;
+                }
+                z[0] += step[0];
+                y = zb[0] - z[0];
+                step[0] = -step[0] - sgn(step[0]);
+            }
+        } else {
+            if k == n - 1 {
+                break;
+            }
+            k += 1;
+            z[k] += step[k];
+            y = zb[k] - z[k];
+            step[k] = -step[k] - sgn(step[k]);
+        }
+    }
+    found.sort_by(|a, b| a.1.total_cmp(&b.1));
+    found.into_iter().unzip()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distance(a: &DVector<f64>, q_inv: &DMatrix<f64>, z: &DVector<f64>) -> f64 {
+        let e = a - z;
+        (e.transpose() * q_inv * &e)[0]
+    }
+
+    #[test]
+    fn worked_example() {
+        // three ambiguity example of de jonge & tiberius (1996), candidates checked
+        // against an exhaustive search of the integer grid around the float solution
+        let a = DVector::from_vec(vec![5.45, 3.10, 2.97]);
+        let q = DMatrix::from_row_slice(
+            3,
+            3,
+            &[
+                6.290, 5.978, 0.544, 5.978, 6.292, 2.340, 0.544, 2.340, 6.288,
+            ],
+        );
+        let (fixes, s) = lambda(&a, &q, 2).unwrap();
+        assert_eq!(fixes[0].as_slice(), &[5., 3., 4.]);
+
+        let q_inv = q.clone().try_inverse().unwrap();
+        let mut grid = vec![];
+        for i in -5..=15 {
+            for j in -7..=13 {
+                for k in -7..=13 {
+                    let z = DVector::from_vec(vec![i as f64, j as f64, k as f64]);
+                    grid.push((distance(&a, &q_inv, &z), z));
+                }
+            }
+        }
+        grid.sort_by(|x, y| x.0.total_cmp(&y.0));
+        for c in 0..2 {
+            assert_eq!(fixes[c], grid[c].1);
+            assert!((s[c] - grid[c].0).abs() < 1e-9, "{} {}", s[c], grid[c].0);
+        }
+        let ratio = s[1] / s[0];
+        assert!((ratio - grid[1].0 / grid[0].0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rejects_bad_covariance() {
+        let a = DVector::from_vec(vec![0.4, 1.2]);
+        let q = DMatrix::from_row_slice(2, 2, &[1., 2., 2., 1.]);
+        assert!(lambda(&a, &q, 2).is_none());
+        assert!(lambda(&a, &DMatrix::identity(3, 3), 2).is_none());
+    }
+}
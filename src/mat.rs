@@ -0,0 +1,642 @@
+use crate::earth::*;
+use crate::satellites::*;
+use crate::solver::*;
+use matfile::{MatFile, NumericData};
+use nalgebra::*;
+use ndarray::{ArrayD, IxDyn, ShapeBuilder};
+use rustfft::num_complex::Complex;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Write};
+
+// mat v5 data types and the double array class
+const MI_INT8: u32 = 1;
+const MI_INT32: u32 = 5;
+const MI_UINT32: u32 = 6;
+const MI_DOUBLE: u32 = 9;
+const MI_MATRIX: u32 = 14;
+const MX_DOUBLE_CLASS: u32 = 6;
+const COMPLEX_FLAG: u32 = 0x0800;
+
+fn padded(bytes: usize) -> usize {
+    bytes.div_ceil(8) * 8
+}
+
+fn element(data_type: u32, data: &[u8]) -> Vec<u8> {
+    // tag, data and zero padding to the next 8 byte boundary
+    let mut out = Vec::with_capacity(8 + padded(data.len()));
+    out.extend_from_slice(&data_type.to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    out.resize(8 + padded(data.len()), 0);
+    out
+}
+
+fn valid_name(name: &str) -> bool {
+    // matlab variable names, a letter followed by letters, digits or underscores
+    name.len() <= 63
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub struct MatWriter<W: Write> {
+    writer: W,
+}
+
+impl MatWriter<BufWriter<File>> {
+    pub fn create(filename: &str) -> Result<Self, Error> {
+        MatWriter::new(BufWriter::new(File::create(filename)?))
+    }
+}
+
+impl<W: Write> MatWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self, Error> {
+        // level 5 mat file, 116 bytes of text, no subsystem data, version 0x0100 and
+        // the little endian indicator
+        let mut header = format!(
+            "MATLAB 5.0 MAT-file, Platform: {}, Created on: {} by rgps",
+            std::env::consts::OS,
+            crate::rinex_obs::run_date()
+        )
+        .into_bytes();
+        header.resize(116, b' ');
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&0x0100u16.to_le_bytes());
+        header.extend_from_slice(b"IM");
+        writer.write_all(&header)?;
+        Ok(MatWriter { writer })
+    }
+
+    pub fn write_array(
+        &mut self,
+        name: &str,
+        dims: &[usize],
+        real: &[f64],
+        imag: Option<&[f64]>,
+    ) -> Result<(), Error> {
+        // double array in column major order, at least two dimensions
+        if !valid_name(name) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid mat variable name {}", name),
+            ));
+        }
+        let mut dims = dims.to_vec();
+        while dims.len() < 2 {
+            dims.push(1);
+        }
+        let count: usize = dims.iter().product();
+        if real.len() != count || imag.is_some_and(|i| i.len() != count) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} values do not match the dimensions {:?}", name, dims),
+            ));
+        }
+
+        let flags = MX_DOUBLE_CLASS | if imag.is_some() { COMPLEX_FLAG } else { 0 };
+        let mut body = element(MI_UINT32, &[flags.to_le_bytes(), [0; 4]].concat());
+        let dims_bytes: Vec<u8> = dims
+            .iter()
+            .flat_map(|&d| (d as i32).to_le_bytes())
+            .collect();
+        body.extend(element(MI_INT32, &dims_bytes));
+        body.extend(element(MI_INT8, name.as_bytes()));
+        let doubles = |v: &[f64]| v.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<u8>>();
+        body.extend(element(MI_DOUBLE, &doubles(real)));
+        if let Some(imag) = imag {
+            body.extend(element(MI_DOUBLE, &doubles(imag)));
+        }
+        self.writer.write_all(&element(MI_MATRIX, &body))
+    }
+
+    pub fn write_scalar(&mut self, name: &str, value: f64) -> Result<(), Error> {
+        self.write_array(name, &[1, 1], &[value], None)
+    }
+
+    pub fn write_vector(&mut self, name: &str, vector: &DVector<f64>) -> Result<(), Error> {
+        // column vector
+        self.write_array(name, &[vector.len(), 1], vector.as_slice(), None)
+    }
+
+    pub fn write_matrix(&mut self, name: &str, matrix: &DMatrix<f64>) -> Result<(), Error> {
+        // nalgebra storage is already column major
+        self.write_array(
+            name,
+            &[matrix.nrows(), matrix.ncols()],
+            matrix.as_slice(),
+            None,
+        )
+    }
+
+    pub fn write_complex_vector(
+        &mut self,
+        name: &str,
+        vector: &DVector<Complex<f64>>,
+    ) -> Result<(), Error> {
+        let real: Vec<f64> = vector.iter().map(|c| c.re).collect();
+        let imag: Vec<f64> = vector.iter().map(|c| c.im).collect();
+        self.write_array(name, &[vector.len(), 1], &real, Some(&imag))
+    }
+
+    pub fn write_ndarray(&mut self, name: &str, array: &ArrayD<f64>) -> Result<(), Error> {
+        // the transposed view iterates the original in column major order
+        let real: Vec<f64> = array.t().iter().copied().collect();
+        self.write_array(name, array.shape(), &real, None)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()
+    }
+}
+
+// real and optional imaginary parts
+type Values = (Vec<f64>, Option<Vec<f64>>);
+
+fn numeric(data: &NumericData) -> Values {
+    // real and imaginary parts of any numeric class as f64
+    macro_rules! convert {
+        ($real:expr, $imag:expr) => {
+            (
+                $real.iter().map(|&x| x as f64).collect(),
+                $imag
+                    .as_ref()
+                    .map(|i: &Vec<_>| i.iter().map(|&x| x as f64).collect()),
+            )
+        };
+    }
+    match data {
+        NumericData::Int8 { real, imag } => convert!(real, imag),
+        NumericData::UInt8 { real, imag } => convert!(real, imag),
+        NumericData::Int16 { real, imag } => convert!(real, imag),
+        NumericData::UInt16 { real, imag } => convert!(real, imag),
+        NumericData::Int32 { real, imag } => convert!(real, imag),
+        NumericData::UInt32 { real, imag } => convert!(real, imag),
+        NumericData::Int64 { real, imag } => convert!(real, imag),
+        NumericData::UInt64 { real, imag } => convert!(real, imag),
+        NumericData::Single { real, imag } => convert!(real, imag),
+        NumericData::Double { real, imag } => (real.clone(), imag.clone()),
+    }
+}
+
+pub struct MatData {
+    file: MatFile,
+}
+
+impl MatData {
+    pub fn names(&self) -> Vec<&str> {
+        self.file.arrays().iter().map(|a| a.name()).collect()
+    }
+
+    pub fn size(&self, name: &str) -> Option<&[usize]> {
+        self.file.find_by_name(name).map(|a| a.size().as_slice())
+    }
+
+    fn values(&self, name: &str) -> Option<(&[usize], Values)> {
+        let array = self.file.find_by_name(name)?;
+        Some((array.size(), numeric(array.data())))
+    }
+
+    pub fn array(&self, name: &str) -> Option<ArrayD<f64>> {
+        // real part with the matlab dimensions
+        let (size, (real, _)) = self.values(name)?;
+        ArrayD::from_shape_vec(IxDyn(size).f(), real).ok()
+    }
+
+    pub fn matrix(&self, name: &str) -> Option<DMatrix<f64>> {
+        let (size, (real, _)) = self.values(name)?;
+        match size {
+            [rows, cols] => Some(DMatrix::from_vec(*rows, *cols, real)),
+            _ => None,
+        }
+    }
+
+    pub fn vector(&self, name: &str) -> Option<DVector<f64>> {
+        // row or column vector
+        let (size, (real, _)) = self.values(name)?;
+        (size.iter().filter(|&&d| d > 1).count() <= 1).then(|| DVector::from_vec(real))
+    }
+
+    pub fn complex_vector(&self, name: &str) -> Option<DVector<Complex<f64>>> {
+        // complex intermediate frequency samples, real arrays get a zero imaginary part
+        let (size, (real, imag)) = self.values(name)?;
+        if size.iter().filter(|&&d| d > 1).count() > 1 {
+            return None;
+        }
+        let imag = imag.unwrap_or_else(|| vec![0.; real.len()]);
+        Some(DVector::from_iterator(
+            real.len(),
+            real.iter()
+                .zip(&imag)
+                .map(|(&re, &im)| Complex::new(re, im)),
+        ))
+    }
+
+    pub fn scalar(&self, name: &str) -> Option<f64> {
+        let (_, (real, _)) = self.values(name)?;
+        (real.len() == 1).then(|| real[0])
+    }
+
+    fn required<T>(&self, name: &str, value: Option<T>) -> Result<T, Error> {
+        value.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("missing or malformed mat variable {}", name),
+            )
+        })
+    }
+}
+
+pub fn read_mat(filename: &str) -> Result<MatData, Error> {
+    // level 5 mat files, compressed or not (v7.3 hdf5 files are not supported)
+    let file = MatFile::parse(BufReader::new(File::open(filename)?))
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", filename, e)))?;
+    Ok(MatData { file })
+}
+
+#[derive(Clone, Debug)]
+pub struct CorrelationMap {
+    pub correlation: DMatrix<f64>, // correlation_magnitude output, one column per doppler
+    pub delay: DVector<f64>,       // sec
+    pub doppler: DVector<f64>,     // Hz
+}
+
+pub fn write_correlation_mat(filename: &str, map: &CorrelationMap) -> Result<(), Error> {
+    let mut writer = MatWriter::create(filename)?;
+    writer.write_matrix("correlation", &map.correlation)?;
+    writer.write_vector("delay", &map.delay)?;
+    writer.write_vector("doppler", &map.doppler)?;
+    writer.flush()
+}
+
+pub fn read_correlation_mat(filename: &str) -> Result<CorrelationMap, Error> {
+    let mat = read_mat(filename)?;
+    Ok(CorrelationMap {
+        correlation: mat.required("correlation", mat.matrix("correlation"))?,
+        delay: mat.required("delay", mat.vector("delay"))?,
+        doppler: mat.required("doppler", mat.vector("doppler"))?,
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct Trajectory {
+    pub prn: usize,
+    pub t: DVector<f64>, // gps time of week (sec)
+    pub x: DVector<f64>, // ecef (m)
+    pub y: DVector<f64>,
+    pub z: DVector<f64>,
+}
+
+impl From<&SatelliteData> for Trajectory {
+    fn from(sat: &SatelliteData) -> Self {
+        // samples from the last propagate
+        let samples = sat.trajectory();
+        let n = samples.len();
+        Trajectory {
+            prn: sat.prn(),
+            t: DVector::from_iterator(n, samples.iter().map(|s| s.0)),
+            x: DVector::from_iterator(n, samples.iter().map(|s| s.1[0])),
+            y: DVector::from_iterator(n, samples.iter().map(|s| s.1[1])),
+            z: DVector::from_iterator(n, samples.iter().map(|s| s.1[2])),
+        }
+    }
+}
+
+pub fn sp3_trajectories(sp3: &Sp3Data) -> Vec<Trajectory> {
+    // one trajectory per satellite on the common sp3 epochs, nan where a satellite has no record
+    let n = sp3.epochs.len();
+    let mut prns: Vec<usize> = sp3
+        .epochs
+        .iter()
+        .flat_map(|e| e.records.iter().map(|r| r.prn))
+        .collect();
+    prns.sort();
+    prns.dedup();
+    prns.into_iter()
+        .map(|prn| {
+            let mut trajectory = Trajectory {
+                prn,
+                t: DVector::from_iterator(n, sp3.epochs.iter().map(|e| e.gps_time)),
+                x: DVector::from_element(n, f64::NAN),
+                y: DVector::from_element(n, f64::NAN),
+                z: DVector::from_element(n, f64::NAN),
+            };
+            for (i, epoch) in sp3.epochs.iter().enumerate() {
+                if let Some(r) = epoch.records.iter().find(|r| r.prn == prn) {
+                    trajectory.x[i] = r.position.x;
+                    trajectory.y[i] = r.position.y;
+                    trajectory.z[i] = r.position.z;
+                }
+            }
+            trajectory
+        })
+        .collect()
+}
+
+pub fn write_trajectories_mat(filename: &str, trajectories: &[Trajectory]) -> Result<(), Error> {
+    // prn as a row and t, x, y, z with one column per satellite, so a single
+    // trajectory gives plain column vectors
+    let n = trajectories.first().map_or(0, |t| t.t.len());
+    if trajectories
+        .iter()
+        .any(|t| [t.t.len(), t.x.len(), t.y.len(), t.z.len()] != [n; 4])
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "trajectories must have the same number of samples",
+        ));
+    }
+    let m = trajectories.len();
+    let mut writer = MatWriter::create(filename)?;
+    let prns: Vec<f64> = trajectories.iter().map(|t| t.prn as f64).collect();
+    writer.write_array("prn", &[1, m], &prns, None)?;
+    for (c, name) in ["t", "x", "y", "z"].iter().enumerate() {
+        let columns: Vec<&DVector<f64>> = trajectories
+            .iter()
+            .map(|t| [&t.t, &t.x, &t.y, &t.z][c])
+            .collect();
+        writer.write_matrix(name, &DMatrix::from_fn(n, m, |i, j| columns[j][i]))?;
+    }
+    writer.flush()
+}
+
+pub fn read_trajectories_mat(filename: &str) -> Result<Vec<Trajectory>, Error> {
+    let mat = read_mat(filename)?;
+    let prns = mat.required("prn", mat.vector("prn"))?;
+    let t = mat.required("t", mat.matrix("t"))?;
+    let x = mat.required("x", mat.matrix("x"))?;
+    let y = mat.required("y", mat.matrix("y"))?;
+    let z = mat.required("z", mat.matrix("z"))?;
+    if [t.ncols(), x.ncols(), y.ncols(), z.ncols()] != [prns.len(); 4] {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "trajectory columns do not match the prns",
+        ));
+    }
+    Ok(prns
+        .iter()
+        .enumerate()
+        .map(|(j, &prn)| Trajectory {
+            prn: prn as usize,
+            t: t.column(j).into_owned(),
+            x: x.column(j).into_owned(),
+            y: y.column(j).into_owned(),
+            z: z.column(j).into_owned(),
+        })
+        .collect())
+}
+
+pub fn write_solutions_mat(filename: &str, solutions: &[PositionSolution]) -> Result<(), Error> {
+    // one row per epoch, per satellite values padded with zeros to the largest
+    // number of satellites, covariance 4x4xn and design kx4xn. lla is for
+    // convenience only and not read back
+    let n = solutions.len();
+    let k = solutions.iter().map(|s| s.prns.len()).max().unwrap_or(0);
+    let rows = |f: &dyn Fn(&PositionSolution) -> Vec<f64>, cols: usize| {
+        DMatrix::from_fn(n, cols, |i, j| {
+            f(&solutions[i]).get(j).copied().unwrap_or(0.)
+        })
+    };
+    let mut writer = MatWriter::create(filename)?;
+    writer.write_matrix("gps_week", &rows(&|s| vec![s.gps_week as f64], 1))?;
+    writer.write_matrix("gps_time", &rows(&|s| vec![s.gps_time], 1))?;
+    writer.write_matrix("position", &rows(&|s| s.position.as_slice().to_vec(), 3))?;
+    writer.write_matrix(
+        "lla",
+        &rows(&|s| ecef2geodetic(s.position, 0).as_slice().to_vec(), 3),
+    )?;
+    writer.write_matrix("clock_bias", &rows(&|s| vec![s.clock_bias], 1))?;
+    writer.write_matrix(
+        "dop",
+        &rows(&|s| vec![s.gdop, s.pdop, s.hdop, s.vdop, s.tdop], 5),
+    )?;
+    writer.write_matrix(
+        "prns",
+        &rows(&|s| s.prns.iter().map(|&p| p as f64).collect(), k),
+    )?;
+    writer.write_matrix("residuals", &rows(&|s| s.residuals.as_slice().to_vec(), k))?;
+    writer.write_matrix("weights", &rows(&|s| s.weights.as_slice().to_vec(), k))?;
+    let covariance: Vec<f64> = solutions
+        .iter()
+        .flat_map(|s| s.covariance.as_slice().to_vec())
+        .collect();
+    writer.write_array("covariance", &[4, 4, n], &covariance, None)?;
+    let mut design = vec![0.; k * 4 * n];
+    for (e, s) in solutions.iter().enumerate() {
+        for i in 0..s.design.nrows().min(k) {
+            for j in 0..s.design.ncols().min(4) {
+                design[e * k * 4 + j * k + i] = s.design[(i, j)];
+            }
+        }
+    }
+    writer.write_array("design", &[k, 4, n], &design, None)?;
+    writer.flush()
+}
+
+pub fn read_solutions_mat(filename: &str) -> Result<Vec<PositionSolution>, Error> {
+    let mat = read_mat(filename)?;
+    let get = |name: &str| mat.required(name, mat.matrix(name));
+    let week = get("gps_week")?;
+    let time = get("gps_time")?;
+    let position = get("position")?;
+    let clock = get("clock_bias")?;
+    let dop = get("dop")?;
+    let prns = get("prns")?;
+    let residuals = get("residuals")?;
+    let weights = get("weights")?;
+    let covariance = mat.required("covariance", mat.array("covariance"))?;
+    let design = mat.required("design", mat.array("design"))?;
+    let n = week.nrows();
+    let k = prns.ncols();
+    if time.nrows() != n
+        || position.shape() != (n, 3)
+        || clock.nrows() != n
+        || dop.shape() != (n, 5)
+        || residuals.shape() != (n, k)
+        || weights.shape() != (n, k)
+        || covariance.shape() != [4, 4, n]
+        || design.shape() != [k, 4, n]
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "solution variables do not have matching dimensions",
+        ));
+    }
+
+    Ok((0..n)
+        .map(|e| {
+            // satellites are the leading non zero prns of the row
+            let used = (0..k).take_while(|&j| prns[(e, j)] > 0.).count();
+            PositionSolution {
+                gps_week: week[(e, 0)] as i32,
+                gps_time: time[(e, 0)],
+                position: Vector3::new(position[(e, 0)], position[(e, 1)], position[(e, 2)]),
+                clock_bias: clock[(e, 0)],
+                covariance: Matrix4::from_fn(|i, j| covariance[[i, j, e]]),
+                prns: (0..used).map(|j| prns[(e, j)] as usize).collect(),
+                residuals: DVector::from_fn(used, |j, _| residuals[(e, j)]),
+                design: DMatrix::from_fn(used, 4, |i, j| design[[i, j, e]]),
+                weights: DVector::from_fn(used, |j, _| weights[(e, j)]),
+                gdop: dop[(e, 0)],
+                pdop: dop[(e, 1)],
+                hdop: dop[(e, 2)],
+                vdop: dop[(e, 3)],
+                tdop: dop[(e, 4)],
+            }
+        })
+        .collect())
+}
+
+pub fn read_samples_mat(filename: &str, name: &str) -> Result<DVector<Complex<f64>>, Error> {
+    // intermediate frequency samples or a reference vector, real arrays get a zero
+    // imaginary part
+    let mat = read_mat(filename)?;
+    mat.required(name, mat.complex_vector(name))
+}
+
+pub fn write_samples_mat(
+    filename: &str,
+    name: &str,
+    samples: &DVector<Complex<f64>>,
+) -> Result<(), Error> {
+    let mut writer = MatWriter::create(filename)?;
+    writer.write_complex_vector(name, samples)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> String {
+        let file = format!("rgps_{}_{}.mat", std::process::id(), name);
+        std::env::temp_dir()
+            .join(file)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn value(i: usize) -> f64 {
+        // values over many magnitudes and the awkward ones, to catch any conversion
+        match i % 11 {
+            0 => -0.,
+            1 => f64::MIN_POSITIVE,
+            2 => f64::NAN,
+            _ => {
+                ((i as f64 + 0.5) * 1.618_033_988_749_895).sin()
+                    * 10f64.powi((i % 7) as i32 * 3 - 9)
+            }
+        }
+    }
+
+    fn assert_bits(a: &[f64], b: &[f64]) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b) {
+            assert_eq!(x.to_bits(), y.to_bits(), "{} != {}", x, y);
+        }
+    }
+
+    #[test]
+    fn correlation_round_trip() {
+        let map = CorrelationMap {
+            correlation: DMatrix::from_fn(8, 5, |i, j| value(i * 5 + j)),
+            delay: DVector::from_fn(8, |i, _| value(i + 40) * 1e-6),
+            doppler: DVector::from_fn(5, |i, _| i as f64 * 500. - 1000.),
+        };
+        let file = path("correlation");
+        write_correlation_mat(&file, &map).unwrap();
+        let read = read_correlation_mat(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(read.correlation.shape(), map.correlation.shape());
+        assert_bits(read.correlation.as_slice(), map.correlation.as_slice());
+        assert_bits(read.delay.as_slice(), map.delay.as_slice());
+        assert_bits(read.doppler.as_slice(), map.doppler.as_slice());
+    }
+
+    #[test]
+    fn trajectories_round_trip() {
+        for count in [1, 3] {
+            let trajectories: Vec<Trajectory> = (0..count)
+                .map(|k| Trajectory {
+                    prn: 3 + 7 * k,
+                    t: DVector::from_fn(6, |i, _| 345600. + 900. * i as f64),
+                    x: DVector::from_fn(6, |i, _| value(k * 18 + i) * 2.6e7),
+                    y: DVector::from_fn(6, |i, _| value(k * 18 + i + 6)),
+                    z: DVector::from_fn(6, |i, _| value(k * 18 + i + 12)),
+                })
+                .collect();
+            let file = path(&format!("trajectories{}", count));
+            write_trajectories_mat(&file, &trajectories).unwrap();
+            let read = read_trajectories_mat(&file).unwrap();
+            std::fs::remove_file(&file).unwrap();
+            assert_eq!(read.len(), count);
+            for (a, b) in read.iter().zip(&trajectories) {
+                assert_eq!(a.prn, b.prn);
+                assert_bits(a.t.as_slice(), b.t.as_slice());
+                assert_bits(a.x.as_slice(), b.x.as_slice());
+                assert_bits(a.y.as_slice(), b.y.as_slice());
+                assert_bits(a.z.as_slice(), b.z.as_slice());
+            }
+        }
+    }
+
+    #[test]
+    fn solutions_round_trip() {
+        // different satellite counts so that the per satellite values are padded
+        let solution = |e: usize, k: usize| PositionSolution {
+            gps_week: 2300 + e as i32,
+            gps_time: 345600.25 + e as f64,
+            position: Vector3::new(4027894.006, 307045.6, 4919474.91) + Vector3::repeat(value(e)),
+            clock_bias: value(e + 3),
+            covariance: Matrix4::from_fn(|i, j| value(e * 16 + i * 4 + j + 3)),
+            prns: (0..k).map(|j| 2 + 3 * j + e).collect(),
+            residuals: DVector::from_fn(k, |j, _| value(e + j + 4)),
+            design: DMatrix::from_fn(k, 4, |i, j| value(e * 40 + i * 4 + j + 3)),
+            weights: DVector::from_fn(k, |j, _| 1. / (j as f64 + 1.5)),
+            gdop: value(e + 5),
+            pdop: value(e + 6),
+            hdop: value(e + 7),
+            vdop: value(e + 8),
+            tdop: value(e + 9),
+        };
+        let solutions = vec![solution(0, 6), solution(1, 4), solution(2, 5)];
+        let file = path("solutions");
+        write_solutions_mat(&file, &solutions).unwrap();
+        let read = read_solutions_mat(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(read.len(), solutions.len());
+        for (a, b) in read.iter().zip(&solutions) {
+            assert_eq!(a.gps_week, b.gps_week);
+            assert_eq!(a.prns, b.prns);
+            assert_eq!(a.design.shape(), b.design.shape());
+            assert_bits(&[a.gps_time, a.clock_bias], &[b.gps_time, b.clock_bias]);
+            assert_bits(a.position.as_slice(), b.position.as_slice());
+            assert_bits(a.covariance.as_slice(), b.covariance.as_slice());
+            assert_bits(a.residuals.as_slice(), b.residuals.as_slice());
+            assert_bits(a.design.as_slice(), b.design.as_slice());
+            assert_bits(a.weights.as_slice(), b.weights.as_slice());
+            assert_bits(
+                &[a.gdop, a.pdop, a.hdop, a.vdop, a.tdop],
+                &[b.gdop, b.pdop, b.hdop, b.vdop, b.tdop],
+            );
+        }
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        let samples = DVector::from_fn(1000, |i, _| Complex::new(value(i), value(i + 500)));
+        let file = path("samples");
+        write_samples_mat(&file, "iq", &samples).unwrap();
+        let read = read_samples_mat(&file, "iq").unwrap();
+        assert!(read_samples_mat(&file, "missing").is_err());
+        std::fs::remove_file(&file).unwrap();
+        let parts = |v: &DVector<Complex<f64>>| -> (Vec<f64>, Vec<f64>) {
+            (
+                v.iter().map(|c| c.re).collect(),
+                v.iter().map(|c| c.im).collect(),
+            )
+        };
+        let (re, im) = parts(&read);
+        let (expected_re, expected_im) = parts(&samples);
+        assert_bits(&re, &expected_re);
+        assert_bits(&im, &expected_im);
+    }
+}
@@ -0,0 +1,778 @@
+use crate::earth::*;
+use crate::observables::*;
+use crate::satellites::*;
+use crate::solver::*;
+use nalgebra::*;
+use std::f64::consts::*;
+use std::fs::File;
+use std::io::{BufWriter, Error, Write};
+
+const KNOTS: f64 = 3600. / 1852.; // per m/s
+
+#[derive(Clone, Debug)]
+pub struct SatView {
+    pub prn: usize,
+    pub elevation: f64,   // deg
+    pub azimuth: f64,     // deg
+    pub cn0: Option<f64>, // dB-Hz
+}
+
+pub fn visible_satellites(
+    ephemerides: &[SatelliteData],
+    position: Vector3<f64>,
+    gps_time: f64,
+    elevation_mask: f64,
+    epoch: Option<&ObservationEpoch>,
+) -> Vec<SatView> {
+    // look angles of every healthy satellite above the mask, with the l1 cn0 when
+    // the satellite is tracked in the epoch
+    let mut prns: Vec<usize> = ephemerides.iter().map(|e| e.prn()).collect();
+    prns.sort();
+    prns.dedup();
+    prns.into_iter()
+        .filter_map(|prn| {
+            let sv = select_ephemeris(ephemerides, prn, gps_time)?.state_at(gps_time);
+            let (azimuth, elevation) = look_angles(position, sv.position);
+            if elevation < elevation_mask {
+                return None;
+            }
+            let cn0 = epoch
+                .and_then(|e| e.get(prn, Band::L1))
+                .map(|o| o.cn0)
+                .filter(|&c| c > 0.);
+            Some(SatView {
+                prn,
+                elevation,
+                azimuth,
+                cn0,
+            })
+        })
+        .collect()
+}
+
+pub fn nmea_checksum(body: &str) -> u8 {
+    // xor of the characters between $ and *
+    body.bytes().fold(0, |c, b| c ^ b)
+}
+
+fn sentence(body: String) -> String {
+    format!("${}*{:02X}", body, nmea_checksum(&body))
+}
+
+fn utc(gps_week: i32, gps_time: f64, leap_seconds: i32) -> (i32, i32, i32, String) {
+    // utc date and hhmmss.ss, rounded to the hundredth before splitting
+    let t = (gps_time - leap_seconds as f64) * 100.;
+    let (year, month, day, hour, minute, second) = date_from_gps_time(gps_week, t.round() / 100.);
+    let time = format!(
+        "{:02}{:02}{:05.2}",
+        hour,
+        minute,
+        (second * 100.).round() / 100.
+    );
+    (year, month, day, time)
+}
+
+fn degrees_minutes(angle: f64) -> (i64, String) {
+    // whole degrees and mm.mmmmm, the total minutes are rounded to 5 decimals before
+    // splitting so that 59.999999' carries into the degrees instead of giving 60.00000
+    let total = (angle.abs() * 60. * 1e5).round() as i64;
+    let (deg, rem) = (total / 6_000_000, total % 6_000_000);
+    (deg, format!("{:02}.{:05}", rem / 100_000, rem % 100_000))
+}
+
+fn latitude(lat: f64) -> String {
+    let (deg, min) = degrees_minutes(lat);
+    let hemi = if lat >= 0. { "N" } else { "S" };
+    format!("{:02}{},{}", deg, min, hemi)
+}
+
+fn longitude(lon: f64) -> String {
+    let lon = if lon > 180. { lon - 360. } else { lon };
+    let (deg, min) = degrees_minutes(lon);
+    let hemi = if lon >= 0. { "E" } else { "W" };
+    format!("{:03}{},{}", deg, min, hemi)
+}
+
+fn course(velocity: &VelocitySolution) -> (f64, f64) {
+    // course over ground (deg true) and horizontal speed (m/s)
+    let (e, n) = (velocity.velocity_enu[0], velocity.velocity_enu[1]);
+    ((e.atan2(n) * 180. / PI).rem_euclid(360.), e.hypot(n))
+}
+
+pub fn gga(solution: &PositionSolution, quality: u8, leap_seconds: i32) -> String {
+    // no geoid model, the altitude is the ellipsoidal height with zero separation
+    let lla = solution.geodetic();
+    let (_, _, _, time) = utc(solution.gps_week, solution.gps_time, leap_seconds);
+    sentence(format!(
+        "GPGGA,{},{},{},{},{:02},{:.1},{:.3},M,0.0,M,,",
+        time,
+        latitude(lla[0]),
+        longitude(lla[1]),
+        quality,
+        solution.prns.len(),
+        solution.hdop,
+        lla[2],
+    ))
+}
+
+pub fn rmc(
+    solution: &PositionSolution,
+    velocity: Option<&VelocitySolution>,
+    leap_seconds: i32,
+) -> String {
+    let lla = solution.geodetic();
+    let (year, month, day, time) = utc(solution.gps_week, solution.gps_time, leap_seconds);
+    let (cog, speed) = velocity.map(course).unwrap_or((0., 0.));
+    sentence(format!(
+        "GPRMC,{},A,{},{},{:.2},{:.1},{:02}{:02}{:02},,,A",
+        time,
+        latitude(lla[0]),
+        longitude(lla[1]),
+        speed * KNOTS,
+        cog,
+        day,
+        month,
+        year % 100,
+    ))
+}
+
+pub fn gsa(solution: &PositionSolution) -> String {
+    // automatic 3d fix, the first twelve satellites used
+    let mut prns: Vec<String> = solution
+        .prns
+        .iter()
+        .take(12)
+        .map(|p| format!("{:02}", p))
+        .collect();
+    prns.resize(12, String::new());
+    sentence(format!(
+        "GPGSA,A,3,{},{:.1},{:.1},{:.1}",
+        prns.join(","),
+        solution.pdop,
+        solution.hdop,
+        solution.vdop,
+    ))
+}
+
+pub fn gsv(satellites: &[SatView]) -> Vec<String> {
+    // four satellites per message
+    let total = satellites.len().div_ceil(4).max(1);
+    (0..total)
+        .map(|k| {
+            let mut body = format!("GPGSV,{},{},{:02}", total, k + 1, satellites.len());
+            for s in satellites.iter().skip(4 * k).take(4) {
+                let cn0 = s.cn0.map(|c| format!("{:02.0}", c)).unwrap_or_default();
+                body += &format!(
+                    ",{:02},{:02.0},{:03.0},{}",
+                    s.prn,
+                    s.elevation.max(0.).round(),
+                    s.azimuth.round().rem_euclid(360.),
+                    cn0
+                );
+            }
+            sentence(body)
+        })
+        .collect()
+}
+
+pub fn gst(solution: &PositionSolution, leap_seconds: i32) -> String {
+    // residual rms, error ellipse and lat/long/height sigmas (m) from the covariance
+    let (_, _, _, time) = utc(solution.gps_week, solution.gps_time, leap_seconds);
+    let n = solution.residuals.len().max(1) as f64;
+    let rms = (solution.residuals.norm_squared() / n).sqrt();
+    let r = enu_rotation(solution.geodetic());
+    let c = r * solution.covariance.fixed_view::<3, 3>(0, 0) * r.transpose();
+    let (ee, nn, en) = (c[(0, 0)], c[(1, 1)], c[(0, 1)]);
+    let mean = (ee + nn) / 2.;
+    let spread = (((ee - nn) / 2.).powi(2) + en * en).sqrt();
+    let major = (mean + spread).max(0.).sqrt();
+    let minor = (mean - spread).max(0.).sqrt();
+    let orientation = (0.5 * (2. * en).atan2(nn - ee) * 180. / PI).rem_euclid(180.);
+    sentence(format!(
+        "GPGST,{},{:.2},{:.2},{:.2},{:.1},{:.2},{:.2},{:.2}",
+        time,
+        rms,
+        major,
+        minor,
+        orientation,
+        nn.max(0.).sqrt(),
+        ee.max(0.).sqrt(),
+        c[(2, 2)].max(0.).sqrt(),
+    ))
+}
+
+pub fn vtg(velocity: &VelocitySolution) -> String {
+    let (cog, speed) = course(velocity);
+    sentence(format!(
+        "GPVTG,{:.1},T,,M,{:.2},N,{:.2},K,A",
+        cog,
+        speed * KNOTS,
+        speed * 3.6,
+    ))
+}
+
+pub fn zda(gps_week: i32, gps_time: f64, leap_seconds: i32) -> String {
+    let (year, month, day, time) = utc(gps_week, gps_time, leap_seconds);
+    sentence(format!(
+        "GPZDA,{},{:02},{:02},{},00,00",
+        time, day, month, year
+    ))
+}
+
+pub struct NmeaWriter<W: Write> {
+    writer: W,
+    pub quality: u8, // gga fix quality, 1 gps, 2 dgps, 4 rtk fixed, 5 rtk float
+    pub leap_seconds: i32,
+}
+
+impl NmeaWriter<BufWriter<File>> {
+    pub fn create(filename: &str, leap_seconds: i32) -> Result<Self, Error> {
+        Ok(NmeaWriter::new(
+            BufWriter::new(File::create(filename)?),
+            leap_seconds,
+        ))
+    }
+}
+
+impl<W: Write> NmeaWriter<W> {
+    pub fn new(writer: W, leap_seconds: i32) -> Self {
+        NmeaWriter {
+            writer,
+            quality: 1,
+            leap_seconds,
+        }
+    }
+
+    pub fn write_sentence(&mut self, sentence: &str) -> Result<(), Error> {
+        write!(self.writer, "{}\r\n", sentence)
+    }
+
+    pub fn write_epoch(
+        &mut self,
+        solution: &PositionSolution,
+        velocity: Option<&VelocitySolution>,
+        satellites: &[SatView],
+    ) -> Result<(), Error> {
+        // one epoch in the usual receiver order
+        let leap = self.leap_seconds;
+        self.write_sentence(&gga(solution, self.quality, leap))?;
+        self.write_sentence(&gsa(solution))?;
+        for s in gsv(satellites) {
+            self.write_sentence(&s)?;
+        }
+        self.write_sentence(&rmc(solution, velocity, leap))?;
+        if let Some(v) = velocity {
+            self.write_sentence(&vtg(v))?;
+        }
+        self.write_sentence(&gst(solution, leap))?;
+        self.write_sentence(&zda(solution.gps_week, solution.gps_time, leap))
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NmeaGga {
+    pub time: f64,      // utc seconds of day
+    pub latitude: f64,  // deg
+    pub longitude: f64, // deg
+    pub quality: u8,    // 0 invalid, 1 gps, 2 dgps, 4 rtk fixed, 5 rtk float
+    pub satellites: usize,
+    pub hdop: Option<f64>,
+    pub altitude: Option<f64>,   // above mean sea level (m)
+    pub separation: Option<f64>, // geoid above the ellipsoid (m)
+    pub age: Option<f64>,        // differential correction age (sec)
+}
+
+#[derive(Clone, Debug)]
+pub struct NmeaRmc {
+    pub time: f64,
+    pub valid: bool,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub speed: Option<f64>,  // m/s
+    pub course: Option<f64>, // deg true
+    pub date: Option<(i32, i32, i32)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NmeaGsa {
+    pub mode: char, // A automatic, M manual
+    pub fix: u8,    // 1 none, 2 2d, 3 3d
+    pub prns: Vec<usize>,
+    pub pdop: Option<f64>,
+    pub hdop: Option<f64>,
+    pub vdop: Option<f64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NmeaGsv {
+    pub messages: usize,
+    pub number: usize,
+    pub in_view: usize,
+    pub satellites: Vec<SatView>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NmeaGst {
+    pub time: f64,
+    pub rms: Option<f64>,
+    pub major: Option<f64>, // error ellipse sigmas (m)
+    pub minor: Option<f64>,
+    pub orientation: Option<f64>, // deg from true north
+    pub latitude_sigma: Option<f64>,
+    pub longitude_sigma: Option<f64>,
+    pub altitude_sigma: Option<f64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NmeaPubx {
+    // u-blox PUBX,00 position
+    pub time: f64,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub height: f64,        // above the ellipsoid (m)
+    pub status: String,     // NF, DR, G2, G3, D2, D3, RK, TT
+    pub h_acc: Option<f64>, // m
+    pub v_acc: Option<f64>,
+    pub speed: Option<f64>, // m/s
+    pub course: Option<f64>,
+    pub hdop: Option<f64>,
+    pub vdop: Option<f64>,
+    pub satellites: usize,
+}
+
+#[derive(Clone, Debug)]
+pub enum NmeaSentence {
+    Gga(NmeaGga),
+    Rmc(NmeaRmc),
+    Gsa(NmeaGsa),
+    Gsv(NmeaGsv),
+    Gst(NmeaGst),
+    Pubx(NmeaPubx),
+}
+
+fn field<T: std::str::FromStr>(fields: &[&str], i: usize) -> Option<T> {
+    fields.get(i).and_then(|f| f.trim().parse().ok())
+}
+
+fn nmea_time(value: &str) -> Option<f64> {
+    // hhmmss.ss to seconds of day
+    if value.len() < 6 {
+        return None;
+    }
+    let h: f64 = value.get(..2)?.parse().ok()?;
+    let m: f64 = value.get(2..4)?.parse().ok()?;
+    let s: f64 = value.get(4..)?.parse().ok()?;
+    Some(h * 3600. + m * 60. + s)
+}
+
+fn nmea_angle(value: &str, hemisphere: &str) -> Option<f64> {
+    // (d)ddmm.mmmm to signed degrees
+    let dot = value.find('.').unwrap_or(value.len());
+    if dot < 3 {
+        return None;
+    }
+    let deg: f64 = value.get(..dot - 2)?.parse().ok()?;
+    let min: f64 = value.get(dot - 2..)?.parse().ok()?;
+    let angle = deg + min / 60.;
+    match hemisphere {
+        "N" | "E" => Some(angle),
+        "S" | "W" => Some(-angle),
+        _ => None,
+    }
+}
+
+pub fn parse_nmea(line: &str) -> Option<NmeaSentence> {
+    // one sentence, anything before the $ is ignored, None for a bad checksum,
+    // a malformed or an unsupported sentence
+    let start = line.find('$')?;
+    let line = line[start + 1..].trim_end();
+    let (body, checksum) = line.split_once('*')?;
+    let checksum = u8::from_str_radix(checksum.get(..2)?, 16).ok()?;
+    if nmea_checksum(body) != checksum {
+        return None;
+    }
+    let f: Vec<&str> = body.split(',').collect();
+    let kind = f[0];
+
+    // any talker (GP, GN, GL, ...) for the standard sentences
+    let sentence = if kind == "PUBX" {
+        "PUBX"
+    } else {
+        kind.get(2..)?
+    };
+    match sentence {
+        "GGA" => Some(NmeaSentence::Gga(NmeaGga {
+            time: nmea_time(f.get(1)?)?,
+            latitude: nmea_angle(f.get(2)?, f.get(3)?)?,
+            longitude: nmea_angle(f.get(4)?, f.get(5)?)?,
+            quality: field(&f, 6)?,
+            satellites: field(&f, 7).unwrap_or(0),
+            hdop: field(&f, 8),
+            altitude: field(&f, 9),
+            separation: field(&f, 11),
+            age: field(&f, 13),
+        })),
+        "RMC" => {
+            let date = f.get(9).filter(|d| d.len() == 6).and_then(|d| {
+                let day = d.get(..2)?.parse().ok()?;
+                let month = d.get(2..4)?.parse().ok()?;
+                let year: i32 = d.get(4..6)?.parse().ok()?;
+                Some((2000 + year, month, day))
+            });
+            Some(NmeaSentence::Rmc(NmeaRmc {
+                time: nmea_time(f.get(1)?)?,
+                valid: *f.get(2)? == "A",
+                latitude: nmea_angle(f.get(3)?, f.get(4)?)?,
+                longitude: nmea_angle(f.get(5)?, f.get(6)?)?,
+                speed: field::<f64>(&f, 7).map(|s| s / KNOTS),
+                course: field(&f, 8),
+                date,
+            }))
+        }
+        "GSA" => Some(NmeaSentence::Gsa(NmeaGsa {
+            mode: f.get(1)?.chars().next().unwrap_or('A'),
+            fix: field(&f, 2)?,
+            prns: (3..15).filter_map(|i| field(&f, i)).collect(),
+            pdop: field(&f, 15),
+            hdop: field(&f, 16),
+            vdop: field(&f, 17),
+        })),
+        "GSV" => {
+            let satellites = f[4.min(f.len())..]
+                .chunks(4)
+                .filter_map(|s| {
+                    Some(SatView {
+                        prn: field(s, 0)?,
+                        elevation: field(s, 1).unwrap_or(0.),
+                        azimuth: field(s, 2).unwrap_or(0.),
+                        cn0: field(s, 3),
+                    })
+                })
+                .collect();
+            Some(NmeaSentence::Gsv(NmeaGsv {
+                messages: field(&f, 1)?,
+                number: field(&f, 2)?,
+                in_view: field(&f, 3)?,
+                satellites,
+            }))
+        }
+        "GST" => Some(NmeaSentence::Gst(NmeaGst {
+            time: nmea_time(f.get(1)?)?,
+            rms: field(&f, 2),
+            major: field(&f, 3),
+            minor: field(&f, 4),
+            orientation: field(&f, 5),
+            latitude_sigma: field(&f, 6),
+            longitude_sigma: field(&f, 7),
+            altitude_sigma: field(&f, 8),
+        })),
+        "PUBX" if f.get(1) == Some(&"00") => Some(NmeaSentence::Pubx(NmeaPubx {
+            time: nmea_time(f.get(2)?)?,
+            latitude: nmea_angle(f.get(3)?, f.get(4)?)?,
+            longitude: nmea_angle(f.get(5)?, f.get(6)?)?,
+            height: field(&f, 7)?,
+            status: f.get(8)?.to_string(),
+            h_acc: field(&f, 9),
+            v_acc: field(&f, 10),
+            speed: field::<f64>(&f, 11).map(|s| s / 3.6),
+            course: field(&f, 12),
+            hdop: field(&f, 15),
+            vdop: field(&f, 16),
+            satellites: field(&f, 18).unwrap_or(0),
+        })),
+        _ => None,
+    }
+}
+
+pub fn read_nmea(filename: &str) -> Result<Vec<NmeaSentence>, Error> {
+    // receiver logs often hold binary messages between the sentences, so the file
+    // is read as bytes and every line that does not parse is skipped
+    let bytes = std::fs::read(filename)?;
+    Ok(bytes
+        .split(|&b| b == b'\n' || b == b'\r')
+        .filter_map(|line| parse_nmea(&String::from_utf8_lossy(line)))
+        .collect())
+}
+
+#[derive(Clone, Debug)]
+pub struct NmeaFix {
+    pub gps_week: i32,
+    pub gps_time: f64,
+    pub lla: Vector3<f64>, // deg, deg, m above the ellipsoid
+    pub quality: u8,
+    pub satellites: usize,
+    pub hdop: Option<f64>,
+}
+
+pub fn nmea_track(sentences: &[NmeaSentence], leap_seconds: i32) -> Vec<NmeaFix> {
+    // GGA (or PUBX,00) positions on gps time, the date comes from RMC sentences and
+    // rolls over at midnight, fixes before the first date are dropped
+    let first_date = sentences.iter().find_map(|s| match s {
+        NmeaSentence::Rmc(r) => r.date.map(|d| (d, r.time)),
+        _ => None,
+    });
+    let (mut date, mut date_time) = match first_date {
+        Some(d) => d,
+        None => return vec![],
+    };
+    let gps = |time: f64, date: (i32, i32, i32), date_time: f64| {
+        // a time of day well before the date's sentence belongs to the next day
+        let day = if time < date_time - 43200. {
+            86400.
+        } else {
+            0.
+        };
+        let (week, tow) = gps_time_from_date(date.0, date.1, date.2, 0, 0, 0.);
+        let tow = tow + day + time + leap_seconds as f64;
+        (
+            week + (tow / SECONDS_PER_WEEK).floor() as i32,
+            tow.rem_euclid(SECONDS_PER_WEEK),
+        )
+    };
+
+    let mut fixes = vec![];
+    for s in sentences {
+        let (time, lla, quality, satellites, hdop) = match s {
+            NmeaSentence::Rmc(r) => {
+                if let Some(d) = r.date {
+                    (date, date_time) = (d, r.time);
+                }
+                continue;
+            }
+            NmeaSentence::Gga(g) => {
+                let height = g.altitude.map(|a| a + g.separation.unwrap_or(0.));
+                match height {
+                    Some(h) if g.quality > 0 => (
+                        g.time,
+                        Vector3::new(g.latitude, g.longitude, h),
+                        g.quality,
+                        g.satellites,
+                        g.hdop,
+                    ),
+                    _ => continue,
+                }
+            }
+            NmeaSentence::Pubx(p) if p.status != "NF" => (
+                p.time,
+                Vector3::new(p.latitude, p.longitude, p.height),
+                if p.status.starts_with('D') { 2 } else { 1 },
+                p.satellites,
+                p.hdop,
+            ),
+            _ => continue,
+        };
+        let (gps_week, gps_time) = gps(time, date, date_time);
+        fixes.push(NmeaFix {
+            gps_week,
+            gps_time,
+            lla,
+            quality,
+            satellites,
+            hdop,
+        });
+    }
+    fixes
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackDifference {
+    pub gps_week: i32,
+    pub gps_time: f64,
+    pub enu: Vector3<f64>, // third party minus reference, east, north, up (m)
+    pub horizontal: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackStatistics {
+    pub count: usize,
+    pub mean: Vector3<f64>, // enu (m)
+    pub rms: Vector3<f64>,
+    pub horizontal_50: f64, // m
+    pub horizontal_95: f64,
+    pub vertical_95: f64,
+}
+
+pub fn compare_track(
+    fixes: &[NmeaFix],
+    reference: &[(i32, f64, Vector3<f64>)],
+    tolerance: f64,
+) -> Vec<TrackDifference> {
+    // differences of a third party track against rgps positions (gps week, time,
+    // ecef), each fix is paired with the closest reference epoch within tolerance
+    let absolute = |week: i32, time: f64| week as f64 * SECONDS_PER_WEEK + time;
+    let mut reference: Vec<(f64, Vector3<f64>)> = reference
+        .iter()
+        .map(|&(w, t, x)| (absolute(w, t), x))
+        .collect();
+    reference.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    fixes
+        .iter()
+        .filter_map(|fix| {
+            let t = absolute(fix.gps_week, fix.gps_time);
+            let i = reference.partition_point(|r| r.0 < t);
+            let (_, position) = [i.checked_sub(1), Some(i)]
+                .into_iter()
+                .flatten()
+                .filter_map(|k| reference.get(k))
+                .filter(|r| (r.0 - t).abs() <= tolerance)
+                .min_by(|a, b| (a.0 - t).abs().total_cmp(&(b.0 - t).abs()))?;
+            let enu = ecef2enu(geodetic2ecef(fix.lla, 0), *position, 0);
+            Some(TrackDifference {
+                gps_week: fix.gps_week,
+                gps_time: fix.gps_time,
+                enu,
+                horizontal: enu[0].hypot(enu[1]),
+            })
+        })
+        .collect()
+}
+
+pub fn track_statistics(differences: &[TrackDifference]) -> Option<TrackStatistics> {
+    if differences.is_empty() {
+        return None;
+    }
+    let n = differences.len() as f64;
+    let mean = differences.iter().map(|d| d.enu).sum::<Vector3<f64>>() / n;
+    let rms = (differences
+        .iter()
+        .map(|d| d.enu.component_mul(&d.enu))
+        .sum::<Vector3<f64>>()
+        / n)
+        .map(|v| v.sqrt());
+    let percentile = |mut values: Vec<f64>, p: f64| {
+        values.sort_by(|a, b| a.total_cmp(b));
+        values[((values.len() - 1) as f64 * p).round() as usize]
+    };
+    let horizontal: Vec<f64> = differences.iter().map(|d| d.horizontal).collect();
+    Some(TrackStatistics {
+        count: differences.len(),
+        mean,
+        rms,
+        horizontal_50: percentile(horizontal.clone(), 0.5),
+        horizontal_95: percentile(horizontal, 0.95),
+        vertical_95: percentile(differences.iter().map(|d| d.enu[2].abs()).collect(), 0.95),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solution(lla: Vector3<f64>) -> PositionSolution {
+        PositionSolution {
+            gps_week: 2274,
+            gps_time: 7318.,
+            position: geodetic2ecef(lla, 0),
+            clock_bias: 0.,
+            covariance: Matrix4::identity(),
+            prns: vec![2, 5, 11, 13, 15, 18, 20, 29],
+            residuals: DVector::zeros(8),
+            design: DMatrix::zeros(8, 4),
+            weights: DVector::from_element(8, 1.),
+            gdop: 2.1,
+            pdop: 1.8,
+            hdop: 0.9,
+            vdop: 1.5,
+            tdop: 1.1,
+        }
+    }
+
+    #[test]
+    fn minutes_carry_into_degrees() {
+        assert_eq!(latitude(47. + 59.999999 / 60.), "4800.00000,N");
+        assert_eq!(latitude(-(47. + 59.999999 / 60.)), "4800.00000,S");
+        assert_eq!(longitude(-(122. + 59.999999 / 60.)), "12300.00000,W");
+        assert_eq!(longitude(7. + 30.123456 / 60.), "00730.12346,E");
+    }
+
+    #[test]
+    fn gga_round_trip() {
+        let lla = Vector3::new(47. + 59.999999 / 60., -122.5, 123.456);
+        let line = gga(&solution(lla), 4, 18);
+        let (body, checksum) = line[1..].split_once('*').unwrap();
+        assert_eq!(checksum, format!("{:02X}", nmea_checksum(body)));
+        assert!(body.starts_with("GPGGA,020140.00,4800.00000,N,12230.00000,W,4,08,0.9,"));
+
+        let Some(NmeaSentence::Gga(g)) = parse_nmea(&line) else {
+            panic!("{}", line);
+        };
+        assert_eq!(g.time, 7300.);
+        assert!((g.latitude - 48.).abs() < 1e-9);
+        assert!((g.longitude + 122.5).abs() < 1e-9);
+        assert_eq!(g.quality, 4);
+        assert_eq!(g.satellites, 8);
+        assert_eq!(g.hdop, Some(0.9));
+        assert!((g.altitude.unwrap() - 123.456).abs() < 1e-3);
+        assert_eq!(g.separation, Some(0.));
+    }
+
+    const GGA: &str = "$GPGGA,092750.000,5321.6802,N,00630.3372,W,1,8,1.03,61.7,M,55.2,M,,*76";
+    const PUBX: &str = "$PUBX,00,081350.00,4717.113210,N,00833.915187,E,546.589,G3,2.1,2.0,\
+                        0.007,77.52,0.007,,0.92,1.19,0.77,9,0,0*5F";
+
+    #[test]
+    fn parse_rejects_damaged_sentences() {
+        assert!(matches!(parse_nmea(GGA), Some(NmeaSentence::Gga(_))));
+        assert!(parse_nmea(&GGA.replace("*76", "*77")).is_none());
+        assert!(parse_nmea(&GGA.replace("5321", "5322")).is_none());
+        assert!(parse_nmea(GGA.strip_suffix("*76").unwrap()).is_none());
+        assert!(parse_nmea(GGA.strip_suffix('6').unwrap()).is_none());
+        assert!(parse_nmea(&GGA.replace('$', "")).is_none());
+
+        // binary bytes and line endings around the sentence are ignored
+        let Some(NmeaSentence::Gga(g)) = parse_nmea(&format!("\u{b5}b\x01\x07junk{}\r\n", GGA))
+        else {
+            panic!("leading junk");
+        };
+        assert_eq!(g.time, 9. * 3600. + 27. * 60. + 50.);
+        assert!((g.latitude - (53. + 21.6802 / 60.)).abs() < 1e-9);
+        assert!((g.longitude + 6. + 30.3372 / 60.).abs() < 1e-9);
+        assert_eq!((g.quality, g.satellites), (1, 8));
+        assert_eq!((g.altitude, g.separation), (Some(61.7), Some(55.2)));
+    }
+
+    #[test]
+    fn parse_pubx_position() {
+        let Some(NmeaSentence::Pubx(p)) = parse_nmea(PUBX) else {
+            panic!("pubx");
+        };
+        assert_eq!(p.time, 8. * 3600. + 13. * 60. + 50.);
+        assert!((p.latitude - (47. + 17.11321 / 60.)).abs() < 1e-9);
+        assert!((p.longitude - (8. + 33.915187 / 60.)).abs() < 1e-9);
+        assert_eq!(p.height, 546.589);
+        assert_eq!(p.status, "G3");
+        assert_eq!((p.h_acc, p.v_acc), (Some(2.1), Some(2.0)));
+        assert!((p.speed.unwrap() - 0.007 / 3.6).abs() < 1e-12);
+        assert_eq!(p.course, Some(77.52));
+        assert_eq!((p.hdop, p.vdop, p.satellites), (Some(0.92), Some(1.19), 9));
+        assert!(parse_nmea(&sentence("PUBX,03,20".to_string())).is_none());
+    }
+
+    #[test]
+    fn compare_pubx_track() {
+        // 2023-08-06 is the start of gps week 2274
+        let rmc = sentence("GPRMC,081350.00,A,4717.11321,N,00833.91519,E,0.0,,060823,,,A".into());
+        let sentences: Vec<NmeaSentence> = [rmc.as_str(), PUBX]
+            .iter()
+            .filter_map(|l| parse_nmea(l))
+            .collect();
+        let fixes = nmea_track(&sentences, 18);
+        assert_eq!(fixes.len(), 1);
+        assert_eq!((fixes[0].gps_week, fixes[0].gps_time), (2274, 29648.));
+
+        // reference one metre north of the fix, and a second epoch outside the tolerance
+        let fix = geodetic2ecef(fixes[0].lla, 0);
+        let north = enu2ecef(Vector3::new(0., 1., 0.), fix, 0);
+        let reference = [(2274, 29648.2, north), (2274, 29660., fix)];
+        let differences = compare_track(&fixes, &reference, 0.5);
+        assert_eq!(differences.len(), 1);
+        assert!((differences[0].enu - Vector3::new(0., -1., 0.)).norm() < 1e-6);
+        assert!((differences[0].horizontal - 1.).abs() < 1e-6);
+        assert!(compare_track(&fixes, &reference[1..], 0.5).is_empty());
+    }
+}
@@ -0,0 +1,734 @@
+use crate::rtcm::*;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+// the client talks to the caster over a plain socket rather than reqwest, ntrip 1
+// casters answer with "ICY 200 OK" which is not http, and the gga upload shares
+// the connection with the correction stream
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NtripVersion {
+    V1,
+    V2,
+}
+
+#[derive(Clone, Debug)]
+pub struct NtripConfig {
+    pub host: String,
+    pub port: u16,
+    pub mountpoint: String,
+    pub username: String, // empty for open casters
+    pub password: String,
+    pub version: NtripVersion,
+    pub gga_interval: f64,     // sec between gga uploads, 0 to never send
+    pub timeout: f64,          // sec without data before reconnecting
+    pub reconnect_delay: f64,  // sec
+    pub max_reconnects: usize, // consecutive failed attempts before giving up
+}
+
+impl Default for NtripConfig {
+    fn default() -> Self {
+        NtripConfig {
+            host: "localhost".to_string(),
+            port: 2101,
+            mountpoint: String::new(),
+            username: String::new(),
+            password: String::new(),
+            version: NtripVersion::V2,
+            gga_interval: 10.,
+            timeout: 10.,
+            reconnect_delay: 5.,
+            max_reconnects: 10,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NtripStream {
+    // STR record of the sourcetable
+    pub mountpoint: String,
+    pub identifier: String,
+    pub format: String, // e.g. RTCM 3.3
+    pub format_details: String,
+    pub carrier: u8, // 0 none, 1 l1, 2 l1 and l2
+    pub nav_system: String,
+    pub network: String,
+    pub country: String,
+    pub latitude: f64, // deg
+    pub longitude: f64,
+    pub nmea: bool,   // the stream needs a gga upload (vrs)
+    pub solution: u8, // 0 single base, 1 network
+    pub generator: String,
+    pub compression: String,
+    pub authentication: char, // N none, B basic, D digest
+    pub fee: bool,
+    pub bitrate: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Sourcetable {
+    pub streams: Vec<NtripStream>,
+    pub casters: Vec<Vec<String>>, // CAS records, fields after the type
+    pub networks: Vec<Vec<String>>, // NET records
+}
+
+impl Sourcetable {
+    pub fn stream(&self, mountpoint: &str) -> Option<&NtripStream> {
+        self.streams.iter().find(|s| s.mountpoint == mountpoint)
+    }
+
+    pub fn nearest(&self, lat: f64, lon: f64) -> Option<&NtripStream> {
+        // closest stream by great circle angle, lat/long in deg
+        let angle = |s: &NtripStream| {
+            let (p1, p2) = (lat.to_radians(), s.latitude.to_radians());
+            let dl = (lon - s.longitude).to_radians();
+            (p1.sin() * p2.sin() + p1.cos() * p2.cos() * dl.cos())
+                .clamp(-1., 1.)
+                .acos()
+        };
+        self.streams
+            .iter()
+            .min_by(|a, b| angle(a).total_cmp(&angle(b)))
+    }
+}
+
+pub fn parse_sourcetable(text: &str) -> Sourcetable {
+    let mut table = Sourcetable::default();
+    for line in text.lines() {
+        let fields: Vec<&str> = line.trim_end().split(';').collect();
+        let rest = || fields[1..].iter().map(|f| f.to_string()).collect();
+        match fields[0] {
+            "STR" if fields.len() >= 18 => {
+                let num = |i: usize| fields[i].trim().parse::<f64>().unwrap_or(0.);
+                table.streams.push(NtripStream {
+                    mountpoint: fields[1].to_string(),
+                    identifier: fields[2].to_string(),
+                    format: fields[3].to_string(),
+                    format_details: fields[4].to_string(),
+                    carrier: num(5) as u8,
+                    nav_system: fields[6].to_string(),
+                    network: fields[7].to_string(),
+                    country: fields[8].to_string(),
+                    latitude: num(9),
+                    longitude: num(10),
+                    nmea: fields[11].trim() == "1",
+                    solution: num(12) as u8,
+                    generator: fields[13].to_string(),
+                    compression: fields[14].to_string(),
+                    authentication: fields[15].chars().next().unwrap_or('N'),
+                    fee: fields[16].trim() == "Y",
+                    bitrate: num(17) as u32,
+                });
+            }
+            "CAS" => table.casters.push(rest()),
+            "NET" => table.networks.push(rest()),
+            _ => {}
+        }
+    }
+    table
+}
+
+fn base64(input: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for k in 0..4 {
+            if k <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * k)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn request(config: &NtripConfig, path: &str, gga: Option<&str>) -> String {
+    let mut req = match config.version {
+        NtripVersion::V1 => format!("GET /{} HTTP/1.0\r\n", path),
+        NtripVersion::V2 => format!(
+            "GET /{} HTTP/1.1\r\nHost: {}:{}\r\nNtrip-Version: Ntrip/2.0\r\n",
+            path, config.host, config.port
+        ),
+    };
+    req += "User-Agent: NTRIP rgps/0.1\r\n";
+    if !config.username.is_empty() {
+        let credentials = format!("{}:{}", config.username, config.password);
+        req += &format!(
+            "Authorization: Basic {}\r\n",
+            base64(credentials.as_bytes())
+        );
+    }
+    if let (NtripVersion::V2, Some(gga)) = (config.version, gga) {
+        req += &format!("Ntrip-GGA: {}\r\n", gga.trim_end());
+    }
+    if config.version == NtripVersion::V2 {
+        req += "Connection: close\r\n";
+    }
+    req + "\r\n"
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Body {
+    Raw,
+    Chunked(ChunkState),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum ChunkState {
+    Size(String),
+    Data(usize),
+    DataEnd(usize), // bytes of the trailing crlf still to skip
+    Done,           // last chunk seen
+}
+
+fn dechunk(state: &mut ChunkState, input: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+    // http/1.1 chunked transfer coding used by ntrip 2 casters
+    let mut i = 0;
+    while i < input.len() {
+        match state {
+            ChunkState::Size(line) => {
+                let b = input[i];
+                i += 1;
+                if b == b'\n' {
+                    let hex = line.split(';').next().unwrap_or("").trim();
+                    let size = usize::from_str_radix(hex, 16)
+                        .map_err(|_| Error::new(ErrorKind::InvalidData, "bad chunk size"))?;
+                    if size == 0 {
+                        *state = ChunkState::Done;
+                        return Ok(());
+                    }
+                    *state = ChunkState::Data(size);
+                } else if b != b'\r' {
+                    line.push(b as char);
+                }
+            }
+            ChunkState::Data(left) => {
+                let n = (*left).min(input.len() - i);
+                out.extend_from_slice(&input[i..i + n]);
+                i += n;
+                *left -= n;
+                if *left == 0 {
+                    *state = ChunkState::DataEnd(2);
+                }
+            }
+            ChunkState::DataEnd(left) => {
+                i += 1;
+                *left -= 1;
+                if *left == 0 {
+                    *state = ChunkState::Size(String::new());
+                }
+            }
+            ChunkState::Done => break,
+        }
+    }
+    Ok(())
+}
+
+fn open(
+    config: &NtripConfig,
+    path: &str,
+    gga: Option<&str>,
+) -> Result<(BufReader<TcpStream>, Body, bool), Error> {
+    // sends the request and reads the response head, returns the reader, the body
+    // coding and whether the caster answered with its sourcetable
+    let address = format!("{}:{}", config.host, config.port);
+    let stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(Duration::from_secs_f64(config.timeout.max(0.1))))?;
+    let mut writer = stream.try_clone()?;
+    writer.write_all(request(config, path, gga).as_bytes())?;
+    if let (NtripVersion::V1, Some(gga)) = (config.version, gga) {
+        writer.write_all(format!("{}\r\n", gga.trim_end()).as_bytes())?;
+    }
+
+    let mut reader = BufReader::new(stream);
+    let mut status = String::new();
+    reader.read_line(&mut status)?;
+    let status = status.trim_end().to_string();
+    if status.starts_with("ICY 200") {
+        return Ok((reader, Body::Raw, false));
+    }
+    if status.starts_with("SOURCETABLE 200") {
+        return Ok((reader, Body::Raw, true));
+    }
+    let code = status.split_whitespace().nth(1).unwrap_or("");
+    match code {
+        "200" => {}
+        "401" => return Err(Error::new(ErrorKind::PermissionDenied, status)),
+        "404" => return Err(Error::new(ErrorKind::NotFound, status)),
+        _ => return Err(Error::new(ErrorKind::ConnectionRefused, status)),
+    }
+
+    let mut body = Body::Raw;
+    let mut sourcetable = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        let lower = line.to_ascii_lowercase();
+        if lower.starts_with("transfer-encoding:") && lower.contains("chunked") {
+            body = Body::Chunked(ChunkState::Size(String::new()));
+        }
+        if lower.starts_with("content-type:") && lower.contains("gnss/sourcetable") {
+            sourcetable = true;
+        }
+    }
+    Ok((reader, body, sourcetable))
+}
+
+fn read_body(
+    reader: &mut BufReader<TcpStream>,
+    body: &mut Body,
+    out: &mut Vec<u8>,
+) -> Result<usize, Error> {
+    // one read from the socket, decoded bytes are appended to out, 0 at the end
+    if *body == Body::Chunked(ChunkState::Done) {
+        return Ok(0);
+    }
+    let mut buffer = [0u8; 4096];
+    let n = reader.read(&mut buffer)?;
+    if n == 0 {
+        return Ok(0);
+    }
+    match body {
+        Body::Raw => out.extend_from_slice(&buffer[..n]),
+        Body::Chunked(state) => dechunk(state, &buffer[..n], out)?,
+    }
+    Ok(n)
+}
+
+pub fn get_sourcetable(config: &NtripConfig) -> Result<Sourcetable, Error> {
+    let (mut reader, mut body, _) = open(config, "", None)?;
+    let mut bytes = vec![];
+    loop {
+        match read_body(&mut reader, &mut body, &mut bytes) {
+            Ok(0) => break,
+            Ok(_) => {
+                if bytes.windows(16).any(|w| w == b"ENDSOURCETABLE\r\n") {
+                    break;
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(parse_sourcetable(&String::from_utf8_lossy(&bytes)))
+}
+
+pub struct NtripClient {
+    config: NtripConfig,
+    connection: Option<(BufReader<TcpStream>, Body)>,
+    decoder: RtcmDecoder,
+    gga: Option<String>,
+    last_gga: Option<Instant>,
+    failures: usize,
+    pub bytes_received: usize,
+    pub reconnects: usize,
+}
+
+impl NtripClient {
+    pub fn new(config: NtripConfig, decoder: RtcmDecoder) -> Self {
+        NtripClient {
+            config,
+            connection: None,
+            decoder,
+            gga: None,
+            last_gga: None,
+            failures: 0,
+            bytes_received: 0,
+            reconnects: 0,
+        }
+    }
+
+    pub fn decoder(&self) -> &RtcmDecoder {
+        &self.decoder
+    }
+
+    pub fn set_gga(&mut self, gga: &str) {
+        // latest position for vrs mountpoints, e.g. from nmea::gga
+        self.gga = Some(gga.trim_end().to_string());
+    }
+
+    pub fn connect(&mut self) -> Result<(), Error> {
+        let (reader, body, sourcetable) =
+            open(&self.config, &self.config.mountpoint, self.gga.as_deref())?;
+        if sourcetable {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "mountpoint {} not in the sourcetable",
+                    self.config.mountpoint
+                ),
+            ));
+        }
+        self.connection = Some((reader, body));
+        self.last_gga = self.gga.as_ref().map(|_| Instant::now());
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self) {
+        self.connection = None;
+    }
+
+    fn send_gga(&mut self) -> Result<(), Error> {
+        let due = self
+            .last_gga
+            .is_none_or(|t| t.elapsed().as_secs_f64() >= self.config.gga_interval);
+        if self.config.gga_interval <= 0. || !due {
+            return Ok(());
+        }
+        if let (Some(gga), Some((reader, _))) = (&self.gga, self.connection.as_mut()) {
+            reader
+                .get_mut()
+                .write_all(format!("{}\r\n", gga).as_bytes())?;
+            self.last_gga = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    pub fn read(&mut self) -> Result<Vec<RtcmMessage>, Error> {
+        // waits for the next data from the caster and returns the decoded messages,
+        // reconnecting after errors, timeouts or the end of the stream until
+        // max_reconnects consecutive attempts have failed
+        loop {
+            if self.connection.is_none() {
+                if self.failures > 0 {
+                    sleep(Duration::from_secs_f64(self.config.reconnect_delay));
+                }
+                match self.connect() {
+                    Ok(()) => {}
+                    Err(e)
+                        if e.kind() == ErrorKind::PermissionDenied
+                            || e.kind() == ErrorKind::NotFound =>
+                    {
+                        return Err(e)
+                    }
+                    Err(e) => {
+                        self.failures += 1;
+                        if self.failures > self.config.max_reconnects {
+                            return Err(e);
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            let mut bytes = vec![];
+            let result = self.send_gga().and_then(|_| {
+                let (reader, body) = self.connection.as_mut().unwrap();
+                read_body(reader, body, &mut bytes)
+            });
+            match result {
+                Ok(n) if n > 0 => {
+                    self.failures = 0;
+                    self.bytes_received += bytes.len();
+                    return Ok(self.decoder.push(&bytes));
+                }
+                Ok(_) | Err(_) => {
+                    // end of stream, timeout or socket error
+                    self.connection = None;
+                    self.failures += 1;
+                    self.reconnects += 1;
+                    if self.failures > self.config.max_reconnects {
+                        return Err(Error::new(ErrorKind::TimedOut, "ntrip stream lost"));
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct RecordedCaster {
+    // minimal caster replaying recorded rtcm bytes on one mountpoint, for offline
+    // tests of the client and for feeding logged corrections to other tools
+    pub mountpoint: String,
+    pub data: Vec<u8>,
+    pub username: String,
+    pub password: String,
+    pub chunk: usize,  // bytes per write
+    pub interval: f64, // sec between writes
+}
+
+impl RecordedCaster {
+    pub fn new(mountpoint: &str, data: Vec<u8>) -> Self {
+        RecordedCaster {
+            mountpoint: mountpoint.to_string(),
+            data,
+            username: String::new(),
+            password: String::new(),
+            chunk: 512,
+            interval: 0.,
+        }
+    }
+
+    pub fn sourcetable(&self) -> String {
+        format!(
+            "STR;{};{};RTCM 3.3;1005(10),1077(1);2;GPS;rgps;XXX;0.00;0.00;0;0;rgps;none;{};N;0\r\nENDSOURCETABLE\r\n",
+            self.mountpoint,
+            self.mountpoint,
+            if self.username.is_empty() { 'N' } else { 'B' }
+        )
+    }
+
+    pub fn serve(&self, listener: &TcpListener, connections: usize) -> Result<(), Error> {
+        // answers the given number of connections one after the other
+        for _ in 0..connections {
+            let (stream, _) = listener.accept()?;
+            // a client going away is not an error of the caster
+            let _ = self.handle(stream);
+        }
+        Ok(())
+    }
+
+    fn handle(&self, stream: TcpStream) -> Result<(), Error> {
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let path = line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or("/")
+            .trim_start_matches('/')
+            .to_string();
+        let mut v2 = false;
+        let mut authorization = String::new();
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            let lower = header.to_ascii_lowercase();
+            if lower.starts_with("ntrip-version:") && lower.contains("ntrip/2.0") {
+                v2 = true;
+            }
+            if lower.starts_with("authorization:") {
+                authorization = header["authorization:".len()..].trim().to_string();
+            }
+        }
+
+        let table = self.sourcetable();
+        if path != self.mountpoint {
+            let head = if v2 {
+                format!(
+                    "HTTP/1.1 200 OK\r\nNtrip-Version: Ntrip/2.0\r\nContent-Type: gnss/sourcetable\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    table.len()
+                )
+            } else {
+                "SOURCETABLE 200 OK\r\nContent-Type: text/plain\r\n\r\n".to_string()
+            };
+            return writer.write_all((head + &table).as_bytes());
+        }
+        if !self.username.is_empty() {
+            let expected = format!(
+                "Basic {}",
+                base64(format!("{}:{}", self.username, self.password).as_bytes())
+            );
+            if authorization != expected {
+                let head = if v2 {
+                    "HTTP/1.1 401 Unauthorized\r\n\r\n"
+                } else {
+                    "HTTP/1.0 401 Unauthorized\r\n\r\n"
+                };
+                return writer.write_all(head.as_bytes());
+            }
+        }
+
+        if v2 {
+            writer.write_all(b"HTTP/1.1 200 OK\r\nNtrip-Version: Ntrip/2.0\r\nContent-Type: gnss/data\r\nTransfer-Encoding: chunked\r\n\r\n")?;
+        } else {
+            writer.write_all(b"ICY 200 OK\r\n")?;
+        }
+        for part in self.data.chunks(self.chunk.max(1)) {
+            if v2 {
+                writer.write_all(format!("{:x}\r\n", part.len()).as_bytes())?;
+                writer.write_all(part)?;
+                writer.write_all(b"\r\n")?;
+            } else {
+                writer.write_all(part)?;
+            }
+            if self.interval > 0. {
+                sleep(Duration::from_secs_f64(self.interval));
+            }
+        }
+        if v2 {
+            writer.write_all(b"0\r\n\r\n")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observables::*;
+    use nalgebra::Vector3;
+    use std::thread::{spawn, JoinHandle};
+
+    const WEEK: i32 = 2300;
+
+    fn station() -> RtcmStation {
+        RtcmStation {
+            station_id: 12,
+            gps: true,
+            position: Vector3::new(4027894.006, 307045.600, 4919474.910),
+            ..Default::default()
+        }
+    }
+
+    fn epoch() -> ObservationEpoch {
+        let obs = |prn, pseudorange| Observation {
+            prn,
+            band: Band::L1,
+            pseudorange,
+            carrier_phase: pseudorange / Band::L1.wavelength(),
+            doppler: -1200.,
+            cn0: 45.,
+            lli: false,
+        };
+        ObservationEpoch {
+            gps_week: WEEK,
+            gps_time: 345600.,
+            obs: vec![obs(5, 21000000.123), obs(17, 23456789.987)],
+        }
+    }
+
+    fn frames() -> Vec<u8> {
+        let mut data = encode_station(&station());
+        data.extend(RtcmEncoder::new(12).msm(&epoch(), true, false));
+        data
+    }
+
+    fn caster(caster: RecordedCaster, connections: usize) -> (u16, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = spawn(move || caster.serve(&listener, connections).unwrap());
+        (port, handle)
+    }
+
+    fn config(port: u16, version: NtripVersion) -> NtripConfig {
+        NtripConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            mountpoint: "TEST".to_string(),
+            version,
+            timeout: 2.,
+            reconnect_delay: 0.,
+            max_reconnects: 0,
+            ..Default::default()
+        }
+    }
+
+    fn read_all(client: &mut NtripClient) -> (Vec<RtcmMessage>, Error) {
+        // messages until the client gives up on the stream
+        let mut messages = vec![];
+        loop {
+            match client.read() {
+                Ok(m) => messages.extend(m),
+                Err(e) => return (messages, e),
+            }
+        }
+    }
+
+    fn check_messages(messages: &[RtcmMessage]) {
+        let station = messages.iter().find_map(|m| match m {
+            RtcmMessage::Station(s) => Some(s),
+            _ => None,
+        });
+        let station = station.expect("no station message");
+        assert_eq!(station.station_id, 12);
+        assert!((station.position - self::station().position).norm() < 1e-3);
+
+        let msm = messages.iter().find_map(|m| match m {
+            RtcmMessage::Msm(m) => Some(m),
+            _ => None,
+        });
+        let decoded = msm.expect("no msm message").observations().unwrap();
+        let expected = epoch();
+        assert_eq!(decoded.gps_time, expected.gps_time);
+        for o in &expected.obs {
+            let d = decoded.get(o.prn, o.band).expect("satellite missing");
+            assert!((d.pseudorange - o.pseudorange).abs() < 1e-2);
+        }
+    }
+
+    fn reads_stream(version: NtripVersion) {
+        let mut recorded = RecordedCaster::new("TEST", frames());
+        recorded.chunk = 7; // frames split across reads and chunks
+        let (port, handle) = caster(recorded, 1);
+        let mut client = NtripClient::new(config(port, version), RtcmDecoder::new(WEEK));
+        let (messages, _) = read_all(&mut client);
+        handle.join().unwrap();
+        check_messages(&messages);
+        assert_eq!(client.bytes_received, frames().len());
+        assert_eq!(client.decoder().crc_errors, 0);
+    }
+
+    #[test]
+    fn reads_stream_v1() {
+        reads_stream(NtripVersion::V1);
+    }
+
+    #[test]
+    fn reads_stream_v2() {
+        reads_stream(NtripVersion::V2);
+    }
+
+    #[test]
+    fn sourcetable() {
+        for version in [NtripVersion::V1, NtripVersion::V2] {
+            let recorded = RecordedCaster::new("TEST", frames());
+            let expected = parse_sourcetable(&recorded.sourcetable());
+            let (port, handle) = caster(recorded, 1);
+            let table = get_sourcetable(&config(port, version)).unwrap();
+            handle.join().unwrap();
+            let stream = table.stream("TEST").expect("mountpoint missing");
+            assert_eq!(stream.format, "RTCM 3.3");
+            assert_eq!(table.streams.len(), expected.streams.len());
+        }
+    }
+
+    #[test]
+    fn bad_password() {
+        for version in [NtripVersion::V1, NtripVersion::V2] {
+            let mut recorded = RecordedCaster::new("TEST", frames());
+            recorded.username = "user".to_string();
+            recorded.password = "secret".to_string();
+            let (port, handle) = caster(recorded, 1);
+            let mut config = config(port, version);
+            config.username = "user".to_string();
+            config.password = "wrong".to_string();
+            let mut client = NtripClient::new(config, RtcmDecoder::new(WEEK));
+            let error = client.read().unwrap_err();
+            handle.join().unwrap();
+            assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+        }
+    }
+
+    #[test]
+    fn reconnects_after_close() {
+        // the caster closes after each replay and stops listening after the second,
+        // the client reconnects once and gives up when the listener is gone
+        let (port, handle) = caster(RecordedCaster::new("TEST", frames()), 2);
+        let mut config = config(port, NtripVersion::V2);
+        config.max_reconnects = 1;
+        let mut client = NtripClient::new(config, RtcmDecoder::new(WEEK));
+        let (messages, _) = read_all(&mut client);
+        handle.join().unwrap();
+        let msm = messages
+            .iter()
+            .filter(|m| matches!(m, RtcmMessage::Msm(_)))
+            .count();
+        assert_eq!(msm, 2);
+        assert_eq!(client.reconnects, 2);
+        assert_eq!(client.bytes_received, 2 * frames().len());
+    }
+}
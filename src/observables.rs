@@ -0,0 +1,195 @@
+use crate::satellites::*;
+use nalgebra::*;
+
+pub const SPEED_OF_LIGHT: f64 = 299792458.; // m/s
+pub const CA_CHIP_RATE: f64 = 1.023e6; // chips/s
+pub const CA_CODE_LENGTH: f64 = 1023.; // chips
+pub const CA_CODE_PERIOD: f64 = 1e-3; // sec
+pub const NAV_BIT_PERIOD: f64 = 20e-3; // sec
+pub const CODES_PER_BIT: usize = 20;
+pub const SECONDS_PER_WEEK: f64 = 604800.;
+
+// shortest possible signal travel time from a gps satellite to the ground,
+// used to seed the receive epoch before a receiver clock estimate exists
+pub const NOMINAL_TRAVEL_TIME: f64 = 0.068802; // sec
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Band {
+    L1,
+    L2,
+    L5,
+}
+
+impl Band {
+    pub fn frequency(&self) -> f64 {
+        match self {
+            Band::L1 => 1575.42e6,
+            Band::L2 => 1227.6e6,
+            Band::L5 => 1176.45e6,
+        }
+    }
+
+    pub fn wavelength(&self) -> f64 {
+        SPEED_OF_LIGHT / self.frequency()
+    }
+
+    pub fn chip_rate(&self) -> f64 {
+        // tracked ranging code: l1 c/a, l2 cm, l5 i (chips/s)
+        match self {
+            Band::L1 => CA_CHIP_RATE,
+            Band::L2 => 511.5e3,
+            Band::L5 => 10.23e6,
+        }
+    }
+
+    pub fn code_period(&self) -> f64 {
+        // sec, l2 cm is 10230 chips, l5 i 10230 chips
+        match self {
+            Band::L1 => CA_CODE_PERIOD,
+            Band::L2 => 20e-3,
+            Band::L5 => 1e-3,
+        }
+    }
+
+    pub fn symbol_period(&self) -> f64 {
+        // navigation bit (l1 lnav) or fec symbol (l2 and l5 cnav) length (sec)
+        match self {
+            Band::L1 => NAV_BIT_PERIOD,
+            Band::L2 => 20e-3,
+            Band::L5 => 10e-3,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ChannelState {
+    // snapshot of a tracking channel taken at the receive epoch
+    pub prn: usize,
+    pub band: Band,
+    pub tow: f64, // gps time of week (sec) at the leading edge of the reference subframe
+    pub bit_count: usize, // nav bits (symbols on l2 and l5) since the reference subframe edge
+    pub code_count: usize, // whole code periods received since the last bit edge
+    pub code_phase: f64, // chips into the current code period
+    pub carrier_phase: f64, // accumulated carrier phase (cycles)
+    pub doppler: f64, // Hz
+    pub cn0: f64, // dB-Hz
+    pub locked: bool, // false once carrier lock has been lost since the last epoch
+}
+
+impl ChannelState {
+    pub fn transmit_time(&self) -> f64 {
+        // satellite time of transmission of the sample at the receive epoch, with the
+        // code and symbol lengths of the channel's band
+        let tx = self.tow
+            + self.bit_count as f64 * self.band.symbol_period()
+            + self.code_count as f64 * self.band.code_period()
+            + self.code_phase / self.band.chip_rate();
+        tx.rem_euclid(SECONDS_PER_WEEK)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Observation {
+    // values of 0.0 mean the observable is not available (as in rinex)
+    pub prn: usize,
+    pub band: Band,
+    pub pseudorange: f64,   // m
+    pub carrier_phase: f64, // cycles
+    pub doppler: f64,       // Hz
+    pub cn0: f64,           // dB-Hz
+    pub lli: bool,          // loss of lock indicator
+}
+
+#[derive(Clone, Debug)]
+pub struct ObservationEpoch {
+    pub gps_week: i32,
+    pub gps_time: f64, // receive time of week (sec)
+    pub obs: Vec<Observation>,
+}
+
+impl ObservationEpoch {
+    pub fn get(&self, prn: usize, band: Band) -> Option<&Observation> {
+        self.obs.iter().find(|o| o.prn == prn && o.band == band)
+    }
+
+    pub fn prns(&self, band: Band) -> Vec<usize> {
+        let mut prns: Vec<usize> = self
+            .obs
+            .iter()
+            .filter(|o| o.band == band && o.pseudorange != 0.)
+            .map(|o| o.prn)
+            .collect();
+        prns.sort();
+        prns.dedup();
+        prns
+    }
+
+    pub fn propagate_times(&self, prns: &[usize], band: Band) -> (DVector<f64>, DVector<f64>) {
+        // arrival time and pseudorange time (sec) for the given satellites, in the
+        // form expected by SatelliteData::propagate_est
+        let prange: Vec<f64> = prns
+            .iter()
+            .map(|&prn| {
+                self.get(prn, band)
+                    .map(|o| o.pseudorange / SPEED_OF_LIGHT)
+                    .unwrap_or(0.)
+            })
+            .collect();
+        (
+            DVector::from_element(prns.len(), self.gps_time),
+            DVector::from_vec(prange),
+        )
+    }
+}
+
+pub fn form_observables(
+    channels: &[ChannelState],
+    gps_week: i32,
+    rx_time: Option<f64>,
+) -> ObservationEpoch {
+    // forms raw pseudoranges at a common receive epoch from channel transmit times
+    // rx_time is the receiver clock time of week; when None it is initialised so
+    // that the earliest arriving satellite has the nominal travel time
+    let tx_times: Vec<f64> = channels.iter().map(|ch| ch.transmit_time()).collect();
+
+    let rx_time = match rx_time {
+        Some(t) => t,
+        None => {
+            let latest = tx_times
+                .iter()
+                .fold(f64::NEG_INFINITY, |acc, &t| acc.max(t));
+            (latest + NOMINAL_TRAVEL_TIME).rem_euclid(SECONDS_PER_WEEK)
+        }
+    };
+
+    let obs = channels
+        .iter()
+        .zip(tx_times.iter())
+        .map(|(ch, &tx)| Observation {
+            prn: ch.prn,
+            band: ch.band,
+            pseudorange: wrap_week(rx_time - tx) * SPEED_OF_LIGHT,
+            carrier_phase: ch.carrier_phase,
+            doppler: ch.doppler,
+            cn0: ch.cn0,
+            lli: !ch.locked,
+        })
+        .collect();
+
+    ObservationEpoch {
+        gps_week,
+        gps_time: rx_time,
+        obs,
+    }
+}
+
+pub fn steer_receive_time(epoch: &mut ObservationEpoch, clock_bias: f64) {
+    // removes an estimated receiver clock bias (sec) from the receive epoch and
+    // pseudoranges, keeping the epoch consistent for the next measurement
+    epoch.gps_time = (epoch.gps_time - clock_bias).rem_euclid(SECONDS_PER_WEEK);
+    for o in epoch.obs.iter_mut() {
+        if o.pseudorange != 0. {
+            o.pseudorange -= clock_bias * SPEED_OF_LIGHT;
+        }
+    }
+}
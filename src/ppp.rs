@@ -0,0 +1,472 @@
+use crate::antex::*;
+use crate::earth::*;
+use crate::observables::*;
+use crate::satellites::*;
+use crate::smoothing::*;
+use crate::solver::*;
+use nalgebra::*;
+use std::collections::HashMap;
+use std::f64::consts::*;
+
+#[derive(Clone, Debug)]
+pub struct PppConfig {
+    pub elevation_mask: f64,             // deg
+    pub code_sigma: f64,                 // zenith ionosphere free code sigma (m)
+    pub phase_sigma: f64,                // zenith ionosphere free phase sigma (m)
+    pub position_noise: f64,             // m^2/s, 0 for a static receiver
+    pub zwd_noise: f64,                  // m^2/s
+    pub ambiguity_sigma: f64,            // initial float ambiguity sigma (m)
+    pub gate: f64,                       // innovation gate in standard deviations
+    pub antenna_delta_enu: Vector3<f64>, // arp above the marker, east, north, up (m)
+    pub receiver_antenna: AntennaModel,
+    pub satellite_antennas: HashMap<usize, AntennaModel>,
+    pub tides: bool,
+    pub wind_up: bool,
+    pub slip: SlipConfig,
+}
+
+impl Default for PppConfig {
+    fn default() -> Self {
+        PppConfig {
+            elevation_mask: 10.,
+            code_sigma: 1.,
+            phase_sigma: 0.01,
+            position_noise: 0.,
+            zwd_noise: 3e-8,
+            ambiguity_sigma: 30.,
+            gate: 5.,
+            antenna_delta_enu: Vector3::zeros(),
+            receiver_antenna: AntennaModel::default(),
+            satellite_antennas: HashMap::new(),
+            tides: true,
+            wind_up: true,
+            slip: SlipConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PppSolution {
+    pub gps_week: i32,
+    pub gps_time: f64,
+    pub position: Vector3<f64>, // marker, ecef (m)
+    pub clock_bias: f64,        // m
+    pub zwd: f64,               // zenith wet delay (m)
+    pub covariance: Matrix3<f64>,
+    pub satellites: usize, // with phase used this epoch
+    pub rejected: Vec<usize>,
+}
+
+struct SatModel {
+    prn: usize,
+    code: f64,    // ionosphere free code (m)
+    phase: f64,   // ionosphere free phase (m), 0 when not available
+    modeled: f64, // everything but receiver clock, wet delay and ambiguity (m)
+    wind_up: f64, // m
+    los: Vector3<f64>,
+    elevation: f64,
+    wet_mapping: f64,
+}
+
+const CLOCK: usize = 3;
+const ZWD: usize = 4;
+const STATES: usize = 5; // position, clock, zwd, then one ambiguity per satellite
+
+pub struct PppEngine {
+    config: PppConfig,
+    x: DVector<f64>,
+    p: DMatrix<f64>,
+    prns: Vec<usize>, // satellite of each ambiguity state
+    gps_time: f64,
+    initialized: bool,
+    wind_up: HashMap<usize, f64>, // cycles, kept continuous between epochs
+    slips: CycleSlipDetector,
+}
+
+impl PppEngine {
+    pub fn new(config: PppConfig) -> Self {
+        let slips = CycleSlipDetector::new(config.slip.clone());
+        PppEngine {
+            config,
+            x: DVector::zeros(STATES),
+            p: DMatrix::zeros(STATES, STATES),
+            prns: vec![],
+            gps_time: 0.,
+            initialized: false,
+            wind_up: HashMap::new(),
+            slips,
+        }
+    }
+
+    pub fn position(&self) -> Vector3<f64> {
+        Vector3::new(self.x[0], self.x[1], self.x[2])
+    }
+
+    pub fn update(
+        &mut self,
+        epoch: &ObservationEpoch,
+        orbits: &Sp3Data,
+        clocks: &dyn SatelliteClock,
+    ) -> Option<PppSolution> {
+        // one epoch of ionosphere free code and phase, orbits from sp3 and clocks
+        // from sp3 or a rinex clock file
+        let (sun, _) = sun_moon_position(epoch.gps_week, epoch.gps_time);
+        if !self.initialized {
+            self.initialize(epoch, orbits, clocks, sun)?;
+        }
+        let dt = wrap_week(epoch.gps_time - self.gps_time).abs();
+        self.gps_time = epoch.gps_time;
+
+        // receiver clock as white noise, random walk wet delay and position
+        for i in 0..self.x.len() {
+            self.p[(CLOCK, i)] = 0.;
+            self.p[(i, CLOCK)] = 0.;
+        }
+        self.p[(CLOCK, CLOCK)] = 1e6f64.powi(2);
+        self.p[(ZWD, ZWD)] += self.config.zwd_noise * dt;
+        for i in 0..3 {
+            self.p[(i, i)] += self.config.position_noise * dt;
+        }
+
+        let position = self.position();
+        let models = self.models(epoch, orbits, clocks, position, sun);
+        let slipped: Vec<usize> = self
+            .slips
+            .detect(epoch)
+            .into_iter()
+            .map(|(p, _)| p)
+            .collect();
+        self.update_ambiguities(&models, &slipped);
+
+        // code first so the clock is settled before the phase is gated, the models
+        // are linearised at the predicted position
+        let n = self.x.len();
+        let mut rejected = vec![];
+        let mut used = 0;
+        for m in models.iter() {
+            let weight = elevation_weight(m.elevation);
+            let mut h = DVector::zeros(n);
+            h.fixed_rows_mut::<3>(0).copy_from(&(-m.los));
+            h[CLOCK] = 1.;
+            h[ZWD] = m.wet_mapping;
+            let predicted = m.modeled - m.los.dot(&(self.position() - position))
+                + self.x[CLOCK]
+                + self.x[ZWD] * m.wet_mapping;
+            let r = weight * self.config.code_sigma.powi(2);
+            if !self.scalar_update(&h, m.code - predicted, r) {
+                rejected.push(m.prn);
+            }
+        }
+        for m in models.iter().filter(|m| m.phase != 0.) {
+            if rejected.contains(&m.prn) {
+                continue;
+            }
+            let amb = match self.prns.iter().position(|&p| p == m.prn) {
+                Some(i) => i + STATES,
+                None => continue,
+            };
+            let weight = elevation_weight(m.elevation);
+            let mut h = DVector::zeros(n);
+            h.fixed_rows_mut::<3>(0).copy_from(&(-m.los));
+            h[CLOCK] = 1.;
+            h[ZWD] = m.wet_mapping;
+            h[amb] = 1.;
+            let predicted = m.modeled + m.wind_up - m.los.dot(&(self.position() - position))
+                + self.x[CLOCK]
+                + self.x[ZWD] * m.wet_mapping
+                + self.x[amb];
+            let r = weight * self.config.phase_sigma.powi(2);
+            if self.scalar_update(&h, m.phase - predicted, r) {
+                used += 1;
+            } else {
+                // most likely an undetected slip, start the ambiguity again
+                rejected.push(m.prn);
+                self.x[amb] = m.phase - m.code;
+                for i in 0..n {
+                    self.p[(amb, i)] = 0.;
+                    self.p[(i, amb)] = 0.;
+                }
+                self.p[(amb, amb)] = self.config.ambiguity_sigma.powi(2);
+            }
+        }
+
+        Some(PppSolution {
+            gps_week: epoch.gps_week,
+            gps_time: epoch.gps_time,
+            position: self.position(),
+            clock_bias: self.x[CLOCK],
+            zwd: self.x[ZWD],
+            covariance: self.p.fixed_view::<3, 3>(0, 0).clone_owned(),
+            satellites: used,
+            rejected,
+        })
+    }
+
+    fn initialize(
+        &mut self,
+        epoch: &ObservationEpoch,
+        orbits: &Sp3Data,
+        clocks: &dyn SatelliteClock,
+        sun: Vector3<f64>,
+    ) -> Option<()> {
+        // code only least squares for the starting position and clock
+        let mut position = Vector3::zeros();
+        let mut clock = 0.;
+        for _ in 0..10 {
+            let models = self.models(epoch, orbits, clocks, position, sun);
+            if models.len() < 4 {
+                return None;
+            }
+            let n = models.len();
+            let mut h = DMatrix::zeros(n, 4);
+            let mut dy = DVector::zeros(n);
+            for (i, m) in models.iter().enumerate() {
+                h[(i, 0)] = -m.los[0];
+                h[(i, 1)] = -m.los[1];
+                h[(i, 2)] = -m.los[2];
+                h[(i, 3)] = 1.;
+                dy[i] = m.code - (m.modeled + clock);
+            }
+            let dx = (h.transpose() * &h).try_inverse()? * h.transpose() * dy;
+            position += Vector3::new(dx[0], dx[1], dx[2]);
+            clock += dx[3];
+            if dx.norm() < 1e-3 {
+                break;
+            }
+        }
+        self.x = DVector::zeros(STATES);
+        self.x.fixed_rows_mut::<3>(0).copy_from(&position);
+        self.x[CLOCK] = clock;
+        self.x[ZWD] = 0.1;
+        self.p = DMatrix::from_diagonal(&DVector::from_vec(vec![
+            100.,
+            100.,
+            100.,
+            1e12,
+            0.3f64.powi(2),
+        ]));
+        self.prns.clear();
+        self.wind_up.clear();
+        self.slips.reset();
+        self.gps_time = epoch.gps_time;
+        self.initialized = true;
+        Some(())
+    }
+
+    fn update_ambiguities(&mut self, models: &[SatModel], slipped: &[usize]) {
+        let keep: Vec<usize> = (0..self.prns.len())
+            .filter(|&i| {
+                let prn = self.prns[i];
+                !slipped.contains(&prn) && models.iter().any(|m| m.prn == prn && m.phase != 0.)
+            })
+            .collect();
+        let mut index: Vec<usize> = (0..STATES).collect();
+        index.extend(keep.iter().map(|i| i + STATES));
+        self.x = DVector::from_iterator(index.len(), index.iter().map(|&i| self.x[i]));
+        self.p = self.p.select_rows(&index).select_columns(&index);
+        self.prns = keep.iter().map(|&i| self.prns[i]).collect();
+
+        for m in models.iter().filter(|m| m.phase != 0.) {
+            if self.prns.contains(&m.prn) {
+                continue;
+            }
+            let n = self.x.len();
+            self.x = self.x.clone().insert_row(n, m.phase - m.code);
+            self.p = self.p.clone().insert_row(n, 0.).insert_column(n, 0.);
+            self.p[(n, n)] = self.config.ambiguity_sigma.powi(2);
+            self.prns.push(m.prn);
+        }
+    }
+
+    fn models(
+        &mut self,
+        epoch: &ObservationEpoch,
+        orbits: &Sp3Data,
+        clocks: &dyn SatelliteClock,
+        position: Vector3<f64>,
+        sun: Vector3<f64>,
+    ) -> Vec<SatModel> {
+        let have_position = position.norm() > 1e6;
+        let lla = ecef2geodetic(position, 0);
+        let enu = enu_rotation(lla);
+
+        // receiver antenna phase centre including tides
+        let mut receiver = position;
+        if have_position {
+            let offset = self.config.antenna_delta_enu + self.config.receiver_antenna.pco;
+            receiver += enu.transpose() * offset;
+            if self.config.tides {
+                let (sun, moon) = sun_moon_position(epoch.gps_week, epoch.gps_time);
+                receiver += solid_tide(position, sun, moon);
+            }
+        }
+
+        let f1 = Band::L1.frequency();
+        let f2 = Band::L2.frequency();
+        let (g1, g2) = (f1 * f1 / (f1 * f1 - f2 * f2), f2 * f2 / (f1 * f1 - f2 * f2));
+        let mut models = vec![];
+
+        for prn in epoch.prns(Band::L1) {
+            let (o1, o2) = match (epoch.get(prn, Band::L1), epoch.get(prn, Band::L2)) {
+                (Some(o1), Some(o2)) if o2.pseudorange != 0. => (o1, o2),
+                _ => continue,
+            };
+            let code = g1 * o1.pseudorange - g2 * o2.pseudorange;
+            let phase = if o1.carrier_phase != 0. && o2.carrier_phase != 0. {
+                g1 * o1.carrier_phase * Band::L1.wavelength()
+                    - g2 * o2.carrier_phase * Band::L2.wavelength()
+            } else {
+                0.
+            };
+
+            // transmit time, precise clock and orbit
+            let mut tx = epoch.gps_time - code / SPEED_OF_LIGHT;
+            let clock = match clocks.sat_clock(prn, epoch.gps_week, tx) {
+                Some(c) => c,
+                None => continue,
+            };
+            tx -= clock;
+            let (sv, ahead) = match (
+                orbits.position(prn, epoch.gps_week, tx),
+                orbits.position(prn, epoch.gps_week, tx + 0.5),
+            ) {
+                (Some(a), Some(b)) => (a, b),
+                _ => continue,
+            };
+            let velocity = (ahead - sv) / 0.5;
+            let tau = if have_position {
+                (sv - receiver).norm() / SPEED_OF_LIGHT
+            } else {
+                code / SPEED_OF_LIGHT
+            };
+            let state = sagnac_rotate(
+                &SvState {
+                    prn,
+                    position: sv,
+                    velocity,
+                    clock_bias: clock,
+                    clock_drift: 0.,
+                },
+                tau,
+            );
+
+            let (ex, ey, ez) = nominal_attitude(state.position, sun);
+            let antenna = self.config.satellite_antennas.get(&prn);
+            let mut sv_apc = state.position;
+            if let Some(a) = antenna {
+                sv_apc += ex * a.pco[0] + ey * a.pco[1] + ez * a.pco[2];
+            }
+
+            let diff = sv_apc - receiver;
+            let range = diff.norm();
+            let los = diff / range;
+            let (_, elevation) = if have_position {
+                look_angles(position, sv_apc)
+            } else {
+                (0., 90.)
+            };
+            if have_position && elevation < self.config.elevation_mask {
+                continue;
+            }
+
+            // relativistic clock term and shapiro delay
+            let relativity = -2. * state.position.dot(&state.velocity) / SPEED_OF_LIGHT;
+            let (gm, _) = earth_constants(0);
+            let (rs, rr) = (state.position.norm(), receiver.norm());
+            let shapiro = if have_position {
+                2. * gm / SPEED_OF_LIGHT.powi(2) * ((rs + rr + range) / (rs + rr - range)).ln()
+            } else {
+                0.
+            };
+
+            let mut modeled = range - SPEED_OF_LIGHT * clock - relativity + shapiro;
+            let mut wet_mapping = 0.;
+            let mut wind_up = 0.;
+            if have_position {
+                let el = elevation * PI / 180.;
+                let nadir = (rr / rs * el.cos()).asin() * 180. / PI;
+                modeled += self.config.receiver_antenna.pcv_at(90. - elevation);
+                if let Some(a) = antenna {
+                    modeled += a.pcv_at(nadir);
+                }
+                let (hydrostatic, wet) = mapping_functions(el);
+                modeled += zenith_hydrostatic_delay(lla) * hydrostatic;
+                wet_mapping = wet;
+                if self.config.wind_up {
+                    let cycles = self.phase_wind_up(prn, ex, ey, receiver, state.position, &enu);
+                    wind_up = cycles * SPEED_OF_LIGHT / (f1 + f2);
+                }
+            }
+
+            models.push(SatModel {
+                prn,
+                code,
+                phase,
+                modeled,
+                wind_up,
+                los,
+                elevation,
+                wet_mapping,
+            });
+        }
+        models
+    }
+
+    fn phase_wind_up(
+        &mut self,
+        prn: usize,
+        ex: Vector3<f64>,
+        ey: Vector3<f64>,
+        receiver: Vector3<f64>,
+        satellite: Vector3<f64>,
+        enu: &Matrix3<f64>,
+    ) -> f64 {
+        // carrier wind up (cycles) between the satellite and a north, west receiver
+        // dipole (wu et al. 1993), kept continuous from the previous epoch
+        let k = (receiver - satellite).normalize();
+        let xr = Vector3::new(enu[(1, 0)], enu[(1, 1)], enu[(1, 2)]);
+        let yr = -Vector3::new(enu[(0, 0)], enu[(0, 1)], enu[(0, 2)]);
+        let ds = ex - k * k.dot(&ex) - k.cross(&ey);
+        let dr = xr - k * k.dot(&xr) + k.cross(&yr);
+        let cos = (ds.dot(&dr) / (ds.norm() * dr.norm())).clamp(-1., 1.);
+        let mut phi = cos.acos() / (2. * PI);
+        if k.dot(&ds.cross(&dr)) < 0. {
+            phi = -phi;
+        }
+        let previous = self.wind_up.get(&prn).copied().unwrap_or(0.);
+        phi += (previous - phi + 0.5).floor();
+        self.wind_up.insert(prn, phi);
+        phi
+    }
+
+    fn scalar_update(&mut self, h: &DVector<f64>, innovation: f64, r: f64) -> bool {
+        // sequential update with innovation gating, joseph form as in the navigator
+        let ph = &self.p * h;
+        let s = h.dot(&ph) + r;
+        if innovation * innovation > self.config.gate.powi(2) * s {
+            return false;
+        }
+        let k = &ph / s;
+        self.x += &k * innovation;
+        let n = self.x.len();
+        let ikh = DMatrix::identity(n, n) - &k * h.transpose();
+        self.p = &ikh * &self.p * ikh.transpose() + &k * k.transpose() * r;
+        true
+    }
+}
+
+pub fn zenith_hydrostatic_delay(lla: Vector3<f64>) -> f64 {
+    // saastamoinen with standard pressure (m)
+    let h = lla[2].clamp(0., 1e4);
+    let pres = 1013.25 * (1. - 2.2557e-5 * h).powf(5.2568);
+    0.0022768 * pres / (1. - 0.00266 * (2. * lla[0] * PI / 180.).cos() - 0.00028 * h / 1e3)
+}
+
+pub fn mapping_functions(elevation: f64) -> (f64, f64) {
+    // chao hydrostatic and wet mapping functions, elevation in rad
+    let (s, t) = (elevation.sin(), elevation.tan());
+    (
+        1. / (s + 0.00143 / (t + 0.0445)),
+        1. / (s + 0.00035 / (t + 0.017)),
+    )
+}
@@ -0,0 +1,380 @@
+use crate::earth::*;
+use crate::observables::*;
+use crate::satellites::*;
+use crate::solver::*;
+use nalgebra::*;
+use std::f64::consts::*;
+
+#[derive(Clone, Debug)]
+pub struct RaimConfig {
+    pub p_fa: f64,             // probability of false alarm per epoch
+    pub p_md: f64,             // probability of missed detection
+    pub sigma: f64,            // zenith pseudorange sigma (m), scaled by elevation
+    pub horizontal_limit: f64, // horizontal alert limit (m)
+    pub vertical_limit: f64,   // vertical alert limit (m)
+    pub max_exclusions: usize,
+    pub solver: SolverConfig,
+}
+
+impl Default for RaimConfig {
+    fn default() -> Self {
+        // alert limits for a non-precision approach, sigma for broadcast ephemeris
+        RaimConfig {
+            p_fa: 1e-5,
+            p_md: 1e-3,
+            sigma: 5.,
+            horizontal_limit: 556.,
+            vertical_limit: 1e9,
+            max_exclusions: 1,
+            solver: SolverConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntegrityStatus {
+    Ok,                 // consistency test passed, protection levels within limits
+    FaultExcluded,      // a fault was detected and the remaining solution passes
+    FaultDetected,      // a fault was detected but could not be excluded
+    AlertLimitExceeded, // consistent, but a protection level exceeds its alert limit
+    Unavailable,        // fewer than five satellites or singular geometry
+}
+
+#[derive(Clone, Debug)]
+pub struct RaimResult {
+    pub status: IntegrityStatus,
+    pub solution: Option<PositionSolution>,
+    pub test_statistic: f64, // sqrt of the weighted sum of squared residuals
+    pub threshold: f64,      // sqrt of the chi-square threshold
+    pub excluded: Vec<usize>,
+    pub hpl: f64, // m
+    pub vpl: f64, // m
+}
+
+pub fn raim_epoch(
+    epoch: &ObservationEpoch,
+    ephemerides: &[SatelliteData],
+    initial: Vector3<f64>,
+    config: &RaimConfig,
+) -> RaimResult {
+    // least squares position with a residual chi-square test, fault exclusion and
+    // horizontal/vertical protection levels
+    let mut solver = config.solver.clone();
+    let mut excluded = vec![];
+
+    loop {
+        let solution = match solve_position(epoch, ephemerides, initial, &solver) {
+            Some(s) => s,
+            None => return unavailable(None, excluded),
+        };
+        let check = match consistency(&solution, config) {
+            Some(c) => c,
+            None => return unavailable(Some(solution), excluded),
+        };
+
+        if check.statistic <= check.threshold {
+            let status = if check.hpl > config.horizontal_limit || check.vpl > config.vertical_limit
+            {
+                IntegrityStatus::AlertLimitExceeded
+            } else if excluded.is_empty() {
+                IntegrityStatus::Ok
+            } else {
+                IntegrityStatus::FaultExcluded
+            };
+            return RaimResult {
+                status,
+                solution: Some(solution),
+                test_statistic: check.statistic,
+                threshold: check.threshold,
+                excluded,
+                hpl: check.hpl,
+                vpl: check.vpl,
+            };
+        }
+
+        // exclusion needs a spare satellite after removing the suspect
+        if excluded.len() >= config.max_exclusions || solution.prns.len() < 6 {
+            return RaimResult {
+                status: IntegrityStatus::FaultDetected,
+                solution: Some(solution),
+                test_statistic: check.statistic,
+                threshold: check.threshold,
+                excluded,
+                hpl: check.hpl,
+                vpl: check.vpl,
+            };
+        }
+        let suspect = solution.prns[check.worst];
+        excluded.push(suspect);
+        solver.exclude.push(suspect);
+    }
+}
+
+struct Consistency {
+    statistic: f64,
+    threshold: f64,
+    hpl: f64,
+    vpl: f64,
+    worst: usize, // index of the largest normalised residual
+}
+
+fn consistency(solution: &PositionSolution, config: &RaimConfig) -> Option<Consistency> {
+    let n = solution.prns.len();
+    if n < 5 {
+        return None;
+    }
+    // absolute weights from the solver's relative elevation weighting
+    let w = solution
+        .weights
+        .map(|w| w * ZENITH_VARIANCE / config.sigma.powi(2));
+    let wm = DMatrix::from_diagonal(&w);
+
+    // geometry in the local frame so that horizontal and vertical separate
+    let rot = enu_rotation(solution.geodetic());
+    let mut h = solution.design.clone();
+    for i in 0..n {
+        let row = Vector3::new(h[(i, 0)], h[(i, 1)], h[(i, 2)]);
+        let enu = rot * row;
+        h[(i, 0)] = enu[0];
+        h[(i, 1)] = enu[1];
+        h[(i, 2)] = enu[2];
+    }
+    let cov = (h.transpose() * &wm * &h).try_inverse()?;
+    let k = &cov * h.transpose() * &wm;
+    let s = DMatrix::identity(n, n) - &h * &k;
+
+    let r = &solution.residuals;
+    let statistic = (0..n).map(|i| r[i] * r[i] * w[i]).sum::<f64>().sqrt();
+    let threshold = chi2_inverse(1. - config.p_fa, (n - 4) as f64).sqrt();
+
+    let mut worst = 0;
+    let mut worst_value = 0.;
+    let mut slope_h: f64 = 0.;
+    let mut slope_v: f64 = 0.;
+    for i in 0..n {
+        let sii = s[(i, i)];
+        if sii <= 1e-12 {
+            continue;
+        }
+        // normalised residual for identification
+        let normalised = r[i].abs() * (w[i] / sii).sqrt();
+        if normalised > worst_value {
+            worst_value = normalised;
+            worst = i;
+        }
+        let scale = 1. / (w[i] * sii).sqrt();
+        slope_h = slope_h.max((k[(0, i)].powi(2) + k[(1, i)].powi(2)).sqrt() * scale);
+        slope_v = slope_v.max(k[(2, i)].abs() * scale);
+    }
+
+    // protection levels from the worst slope and the bias that the test misses with
+    // probability p_md (brown & chin 1998, "gps raim: calculation of thresholds and
+    // protection radius using chi-square methods")
+    let pbias = noncentrality(threshold.powi(2), (n - 4) as f64, config.p_md).sqrt();
+
+    Some(Consistency {
+        statistic,
+        threshold,
+        hpl: slope_h * pbias,
+        vpl: slope_v * pbias,
+        worst,
+    })
+}
+
+fn unavailable(solution: Option<PositionSolution>, excluded: Vec<usize>) -> RaimResult {
+    RaimResult {
+        status: IntegrityStatus::Unavailable,
+        solution,
+        test_statistic: 0.,
+        threshold: 0.,
+        excluded,
+        hpl: f64::INFINITY,
+        vpl: f64::INFINITY,
+    }
+}
+
+pub fn ln_gamma(x: f64) -> f64 {
+    // lanczos approximation, x > 0
+    let g = 7.;
+    let coef = [
+        0.999_999_999_999_809_9,
+        676.5203681218851,
+        -1259.1392167224028,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507343278686905,
+        -0.13857109526572012,
+        9.984_369_578_019_572e-6,
+        1.5056327351493116e-7,
+    ];
+    if x < 0.5 {
+        return (PI / (PI * x).sin()).ln() - ln_gamma(1. - x);
+    }
+    let x = x - 1.;
+    let mut a = coef[0];
+    let t = x + g + 0.5;
+    for (i, c) in coef.iter().enumerate().skip(1) {
+        a += c / (x + i as f64);
+    }
+    0.5 * (2. * PI).ln() + (x + 0.5) * t.ln() - t + a.ln()
+}
+
+pub fn gamma_p(a: f64, x: f64) -> f64 {
+    // regularised lower incomplete gamma function
+    if x <= 0. {
+        return 0.;
+    }
+    if x < a + 1. {
+        // series expansion
+        let mut sum = 1. / a;
+        let mut term = sum;
+        for n in 1..500 {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        sum * (-x + a * x.ln() - ln_gamma(a)).exp()
+    } else {
+        // continued fraction for the upper function (lentz)
+        let tiny = 1e-300;
+        let mut b = x + 1. - a;
+        let mut c = 1. / tiny;
+        let mut d = 1. / b;
+        let mut h = d;
+        for i in 1..500 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1. / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.).abs() < 1e-15 {
+                break;
+            }
+        }
+        1. - (-x + a * x.ln() - ln_gamma(a)).exp() * h
+    }
+}
+
+pub fn chi2_cdf(x: f64, dof: f64) -> f64 {
+    gamma_p(dof / 2., x / 2.)
+}
+
+pub fn chi2_inverse(p: f64, dof: f64) -> f64 {
+    // quantile of the chi-square distribution by bisection
+    let mut lo = 0.;
+    let mut hi = dof.max(1.);
+    while chi2_cdf(hi, dof) < p {
+        hi *= 2.;
+    }
+    for _ in 0..200 {
+        let mid = 0.5 * (lo + hi);
+        if chi2_cdf(mid, dof) < p {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+        if hi - lo < 1e-10 * hi {
+            break;
+        }
+    }
+    0.5 * (lo + hi)
+}
+
+pub fn noncentral_chi2_cdf(x: f64, dof: f64, lambda: f64) -> f64 {
+    // poisson weighted sum of central chi-square distributions
+    let half = lambda / 2.;
+    if half <= 0. {
+        return chi2_cdf(x, dof);
+    }
+    let terms = (half + 10. * half.sqrt() + 50.) as usize;
+    (0..terms)
+        .map(|j| {
+            let j = j as f64;
+            let weight = (-half + j * half.ln() - ln_gamma(j + 1.)).exp();
+            weight * gamma_p(dof / 2. + j, x / 2.)
+        })
+        .sum()
+}
+
+pub fn noncentrality(threshold: f64, dof: f64, p_md: f64) -> f64 {
+    // noncentrality parameter for which a chi-square statistic stays below the
+    // threshold with probability p_md, by bisection
+    let mut lo = 0.;
+    let mut hi = threshold.max(1.);
+    while noncentral_chi2_cdf(threshold, dof, hi) > p_md {
+        hi *= 2.;
+    }
+    for _ in 0..200 {
+        let mid = 0.5 * (lo + hi);
+        if noncentral_chi2_cdf(threshold, dof, mid) > p_md {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+        if hi - lo < 1e-10 * hi {
+            break;
+        }
+    }
+    0.5 * (lo + hi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gamma_and_chi_square() {
+        // p(1, x) = 1 - exp(-x), p(1/2, x) = erf(sqrt(x))
+        for x in [0.1, 1., 5., 30.] {
+            assert!((gamma_p(1., x) - (1. - (-x).exp())).abs() < 1e-12);
+        }
+        assert!((gamma_p(0.5, 1.) - 0.842_700_792_949_715).abs() < 1e-12);
+        // tabulated chi-square quantiles
+        for (p, dof, quantile) in [
+            (1. - 1e-5, 1., 19.511),
+            (0.95, 4., 9.488),
+            (0.99, 10., 23.209),
+            (1. - 1e-3, 2., 13.816),
+        ] {
+            let x = chi2_inverse(p, dof);
+            assert!((x - quantile).abs() < 1e-3, "dof {} p {}: {}", dof, p, x);
+            assert!((chi2_cdf(x, dof) - p).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn noncentral_bias() {
+        // without noncentrality the distribution is the central one
+        for x in [0.5, 4., 12.] {
+            assert!((noncentral_chi2_cdf(x, 3., 0.) - chi2_cdf(x, 3.)).abs() < 1e-12);
+        }
+        // with one degree of freedom the statistic is a squared normal with mean
+        // sqrt(lambda) and pbias = sqrt(threshold) + z(1 - p_md), 7.507 for p_fa 1e-5
+        // and p_md 1e-3
+        let threshold = chi2_inverse(1. - 1e-5, 1.);
+        let lambda = noncentrality(threshold, 1., 1e-3);
+        let pbias = lambda.sqrt();
+        assert!(
+            (pbias - (threshold.sqrt() + 3.090_232)).abs() < 1e-4,
+            "{}",
+            pbias
+        );
+        assert!((noncentral_chi2_cdf(threshold, 1., lambda) - 1e-3).abs() < 1e-9);
+        // more redundancy needs a larger bias for the same missed detection
+        let lambda4 = noncentrality(chi2_inverse(1. - 1e-5, 4.), 4., 1e-3);
+        assert!(lambda4 > lambda);
+        assert!(
+            (noncentral_chi2_cdf(chi2_inverse(1. - 1e-5, 4.), 4., lambda4) - 1e-3).abs() < 1e-9
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use crate::observables::*;
+use crate::satellites::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind};
+
+#[derive(Clone, Debug)]
+pub struct RinexClockHeader {
+    pub version: f64,
+    pub analysis_center: String, // three character code, e.g. IGS
+    pub analysis_center_name: String,
+    pub data_types: Vec<String>, // AS, AR, CR, DR, MS
+    pub satellites: Vec<usize>,
+    pub stations: Vec<String>,
+    pub leap_seconds: i32,
+    pub time_system: String,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClockSample {
+    pub time: f64,          // gps sec since the start of gps time
+    pub bias: f64,          // sec
+    pub sigma: Option<f64>, // sec
+}
+
+#[derive(Clone, Debug)]
+pub struct RinexClock {
+    pub header: RinexClockHeader,
+    pub satellites: HashMap<usize, Vec<ClockSample>>,
+    pub receivers: HashMap<String, Vec<ClockSample>>,
+}
+
+impl RinexClock {
+    pub fn satellite_clock(
+        &self,
+        prn: usize,
+        gps_week: i32,
+        gps_time: f64,
+    ) -> Option<(f64, Option<f64>)> {
+        // bias and sigma (sec) at gps time, linear between the neighbouring samples
+        interpolate(self.satellites.get(&prn)?, gps_week, gps_time)
+    }
+
+    pub fn receiver_clock(
+        &self,
+        station: &str,
+        gps_week: i32,
+        gps_time: f64,
+    ) -> Option<(f64, Option<f64>)> {
+        interpolate(self.receivers.get(station)?, gps_week, gps_time)
+    }
+}
+
+impl SatelliteClock for RinexClock {
+    fn sat_clock(&self, prn: usize, gps_week: i32, gps_time: f64) -> Option<f64> {
+        self.satellite_clock(prn, gps_week, gps_time)
+            .map(|(bias, _)| bias)
+    }
+}
+
+fn interpolate(
+    samples: &[ClockSample],
+    gps_week: i32,
+    gps_time: f64,
+) -> Option<(f64, Option<f64>)> {
+    // samples are sorted by time, no extrapolation beyond one sample interval
+    let t = gps_week as f64 * SECONDS_PER_WEEK + gps_time;
+    let i = samples.partition_point(|s| s.time <= t);
+    if i == 0 {
+        return None;
+    }
+    let a = samples[i - 1];
+    if a.time == t {
+        return Some((a.bias, a.sigma));
+    }
+    let b = samples.get(i)?;
+    let f = (t - a.time) / (b.time - a.time);
+    let sigma = match (a.sigma, b.sigma) {
+        (Some(sa), Some(sb)) => Some(sa.max(sb)),
+        _ => None,
+    };
+    Some((a.bias + (b.bias - a.bias) * f, sigma))
+}
+
+pub fn read_rinex_clock(filename: &str) -> Result<RinexClock, Error> {
+    // rinex 2 and 3 clock files, satellite (AS) and receiver (AR) records, gps only
+    let file = File::open(filename)?;
+    let mut lines = BufReader::new(file).lines();
+    let mut header = RinexClockHeader {
+        version: 0.,
+        analysis_center: String::new(),
+        analysis_center_name: String::new(),
+        data_types: vec![],
+        satellites: vec![],
+        stations: vec![],
+        leap_seconds: 0,
+        time_system: "GPS".to_string(),
+    };
+
+    let mut ended = false;
+    for line in lines.by_ref() {
+        let line = line?;
+        let label = line.get(60..).unwrap_or("").trim();
+        let data = line.get(..60.min(line.len())).unwrap_or("");
+        match label {
+            "RINEX VERSION / TYPE" => {
+                header.version = data.get(..9).unwrap_or("").trim().parse().unwrap_or(0.)
+            }
+            "ANALYSIS CENTER" => {
+                header.analysis_center = data.get(..3).unwrap_or("").trim().to_string();
+                header.analysis_center_name = data.get(5..).unwrap_or("").trim().to_string();
+            }
+            "# / TYPES OF DATA" => header.data_types.extend(
+                data.get(6..)
+                    .unwrap_or("")
+                    .split_whitespace()
+                    .map(String::from),
+            ),
+            "PRN LIST" => header
+                .satellites
+                .extend(data.split_whitespace().filter_map(clock_prn)),
+            "SOLN STA NAME / NUM" => {
+                if let Some(name) = data.split_whitespace().next() {
+                    header.stations.push(name.to_string());
+                }
+            }
+            "LEAP SECONDS" => {
+                header.leap_seconds = data.get(..6).unwrap_or("").trim().parse().unwrap_or(0)
+            }
+            "TIME SYSTEM ID" => header.time_system = data.trim().to_string(),
+            "END OF HEADER" => {
+                ended = true;
+                break;
+            }
+            _ => {}
+        }
+    }
+    if !ended {
+        return Err(Error::new(ErrorKind::InvalidData, "missing END OF HEADER"));
+    }
+
+    // data records are whitespace separated in both versions, values beyond the
+    // second continue on the following line
+    let mut satellites: HashMap<usize, Vec<ClockSample>> = HashMap::new();
+    let mut receivers: HashMap<String, Vec<ClockSample>> = HashMap::new();
+    while let Some(line) = lines.next() {
+        let line = line?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() < 10 {
+            continue;
+        }
+        let kind = tokens[0];
+        let count = tokens[8].parse::<usize>().unwrap_or(0);
+        let mut values: Vec<f64> = tokens[9..].iter().filter_map(|v| parse_value(v)).collect();
+        if count > 2 {
+            if let Some(next) = lines.next() {
+                values.extend(next?.split_whitespace().filter_map(parse_value));
+            }
+        }
+        if kind != "AS" && kind != "AR" {
+            continue;
+        }
+        let field = |i: usize| tokens[i].parse::<f64>().unwrap_or(0.);
+        let (week, tow) = gps_time_from_date(
+            field(2) as i32,
+            field(3) as i32,
+            field(4) as i32,
+            field(5) as i32,
+            field(6) as i32,
+            field(7),
+        );
+        let sample = ClockSample {
+            time: week as f64 * SECONDS_PER_WEEK + tow,
+            bias: match values.first() {
+                Some(&v) => v,
+                None => continue,
+            },
+            sigma: values.get(1).copied(),
+        };
+        if kind == "AS" {
+            if let Some(prn) = clock_prn(tokens[1]) {
+                satellites.entry(prn).or_default().push(sample);
+            }
+        } else {
+            receivers
+                .entry(tokens[1].to_string())
+                .or_default()
+                .push(sample);
+        }
+    }
+    for samples in satellites.values_mut().chain(receivers.values_mut()) {
+        samples.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    Ok(RinexClock {
+        header,
+        satellites,
+        receivers,
+    })
+}
+
+fn clock_prn(id: &str) -> Option<usize> {
+    // G05, or 05 in older files
+    let id = id.trim();
+    let number = match id.chars().next()? {
+        'G' => &id[1..],
+        c if c.is_ascii_digit() => id,
+        _ => return None,
+    };
+    number.parse().ok()
+}
+
+fn parse_value(value: &str) -> Option<f64> {
+    value.replace(['D', 'd'], "e").parse().ok()
+}
@@ -0,0 +1,225 @@
+use crate::observables::*;
+use crate::rinex_obs::*;
+use crate::satellites::*;
+use std::fs::File;
+use std::io::{BufWriter, Error, Write};
+
+pub fn fortran_d(value: f64, decimals: usize, width: usize) -> String {
+    // fortran Dw.d, mantissa in [0.1, 1) and a two digit exponent, e.g. 0.1235D+03
+    if value == 0. || !value.is_finite() {
+        return format!("{:>width$}", format!("0.{}D+00", "0".repeat(decimals)));
+    }
+    // rounding to the printed digits first so 0.99999 does not become 1.0000D+00
+    let s = format!("{:.*e}", decimals - 1, value.abs());
+    let (mantissa, exponent) = s.split_once('e').unwrap_or((&s, "0"));
+    let digits = mantissa.replace('.', "");
+    let exponent = exponent.parse::<i32>().unwrap_or(0) + 1;
+    let sign = if value < 0. { "-" } else { "" };
+    let text = format!(
+        "{}0.{}D{}{:02}",
+        sign,
+        digits,
+        if exponent < 0 { '-' } else { '+' },
+        exponent.abs()
+    );
+    format!("{:>width$}", text)
+}
+
+fn write_header<W: Write>(
+    writer: &mut W,
+    version: f64,
+    header: Option<&RinexNavHeader>,
+) -> Result<(), Error> {
+    let v3 = version >= 3.;
+    let mut out = String::new();
+    if v3 {
+        out += &header_line(
+            &format!(
+                "{:9.2}{:11}{:<20}{:<20}",
+                version, "", "N: GNSS NAV DATA", "G: GPS"
+            ),
+            "RINEX VERSION / TYPE",
+        );
+    } else {
+        out += &header_line(
+            &format!("{:9.2}{:11}{:<40}", version, "", "N: GPS NAV DATA"),
+            "RINEX VERSION / TYPE",
+        );
+    }
+    out += &header_line(
+        &format!("{:<20}{:<20}{:<20}", "rgps", "", run_date()),
+        "PGM / RUN BY / DATE",
+    );
+    if let Some(h) = header {
+        let four = |v: [f64; 4]| v.iter().map(|&x| fortran_d(x, 4, 12)).collect::<String>();
+        let (a0, a1, t, w) = h.delta_utc();
+        if v3 {
+            out += &header_line(&format!("GPSA {}", four(h.ion_alpha())), "IONOSPHERIC CORR");
+            out += &header_line(&format!("GPSB {}", four(h.ion_beta())), "IONOSPHERIC CORR");
+            out += &header_line(
+                &format!(
+                    "GPUT {}{} {:6} {:4}",
+                    fortran_d(a0, 10, 17),
+                    fortran_d(a1, 9, 16),
+                    t,
+                    w
+                ),
+                "TIME SYSTEM CORR",
+            );
+        } else {
+            out += &header_line(&format!("  {}", four(h.ion_alpha())), "ION ALPHA");
+            out += &header_line(&format!("  {}", four(h.ion_beta())), "ION BETA");
+            out += &header_line(
+                &format!(
+                    "   {}{}{:9}{:9}",
+                    fortran_d(a0, 12, 19),
+                    fortran_d(a1, 12, 19),
+                    t,
+                    w
+                ),
+                "DELTA-UTC: A0,A1,T,W",
+            );
+        }
+        out += &header_line(&format!("{:6}", h.leap_seconds()), "LEAP SECONDS");
+    }
+    out += &header_line("", "END OF HEADER");
+    writer.write_all(out.as_bytes())
+}
+
+fn format_record(version: f64, eph: &SatelliteData) -> String {
+    // epoch line with the clock terms and the 7 broadcast orbit lines, the last one
+    // carrying only the transmission time and fit interval
+    let (clock, orbit) = eph.broadcast();
+    let (week, toc) = eph.toc_epoch();
+    let (y, mo, d, h, mi, s) = date_from_gps_time(week, toc.round());
+    let (mut out, indent) = if version >= 3. {
+        (
+            format!(
+                "G{:02} {:4} {:02} {:02} {:02} {:02} {:02}",
+                eph.prn(),
+                y,
+                mo,
+                d,
+                h,
+                mi,
+                s as i32
+            ),
+            4,
+        )
+    } else {
+        (
+            format!(
+                "{:2} {:02} {:2} {:2} {:2} {:2}{:5.1}",
+                eph.prn(),
+                y % 100,
+                mo,
+                d,
+                h,
+                mi,
+                s
+            ),
+            3,
+        )
+    };
+    for c in clock {
+        out += &fortran_d(c, 12, 19);
+    }
+    out += "\n";
+    for line in orbit.chunks(4) {
+        out += &" ".repeat(indent);
+        for &v in line {
+            out += &fortran_d(v, 12, 19);
+        }
+        out += "\n";
+    }
+    out
+}
+
+pub fn write_rinex_nav_to<W: Write>(
+    writer: &mut W,
+    version: f64,
+    header: Option<&RinexNavHeader>,
+    ephemerides: &[SatelliteData],
+) -> Result<(), Error> {
+    // gps navigation file, 2.11 for versions below 3 and 3.04 otherwise, records
+    // sorted by prn and clock reference time
+    let version = if version >= 3. { 3.04 } else { 2.11 };
+    write_header(writer, version, header)?;
+    let mut sorted: Vec<&SatelliteData> = ephemerides.iter().collect();
+    sorted.sort_by(|a, b| {
+        let time = |e: &SatelliteData| {
+            let (w, t) = e.toc_epoch();
+            w as f64 * SECONDS_PER_WEEK + t
+        };
+        a.prn().cmp(&b.prn()).then(time(a).total_cmp(&time(b)))
+    });
+    for eph in sorted {
+        writer.write_all(format_record(version, eph).as_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn write_rinex_nav(
+    filename: &str,
+    version: f64,
+    header: Option<&RinexNavHeader>,
+    ephemerides: &[SatelliteData],
+) -> Result<(), Error> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    write_rinex_nav_to(&mut writer, version, header, ephemerides)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fortran_d_format() {
+        assert_eq!(fortran_d(0.999999999999999, 12, 19), " 0.100000000000D+01");
+        assert_eq!(fortran_d(-1234.5, 12, 19), "-0.123450000000D+04");
+        assert_eq!(fortran_d(-1.5e-9, 12, 19), "-0.150000000000D-08");
+        assert_eq!(fortran_d(0.1, 12, 19), " 0.100000000000D+00");
+        assert_eq!(fortran_d(0., 12, 19), " 0.000000000000D+00");
+        assert_eq!(fortran_d(-0., 4, 11), " 0.0000D+00");
+        assert_eq!(fortran_d(7.1234567e-5, 4, 11), " 0.7123D-04");
+    }
+
+    #[test]
+    fn nav_round_trip() {
+        let header = rinex2_nav_header("brdc2180.23n").unwrap();
+        let mut ephemerides = rinex2_nav_all("brdc2180.23n").unwrap();
+        let filename = std::env::temp_dir()
+            .join(format!("rgps_{}.23n", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let mut text = vec![];
+        write_rinex_nav_to(&mut text, 2., Some(&header), &ephemerides).unwrap();
+        std::fs::write(&filename, &text).unwrap();
+        let read_header = rinex2_nav_header(&filename).unwrap();
+        let read = rinex2_nav_all(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+
+        assert_eq!(read_header.ion_alpha(), header.ion_alpha());
+        assert_eq!(read_header.ion_beta(), header.ion_beta());
+        assert_eq!(read_header.delta_utc(), header.delta_utc());
+        assert_eq!(read_header.leap_seconds(), header.leap_seconds());
+
+        // the writer sorts the records by prn and clock time
+        ephemerides.sort_by_key(|e| (e.prn(), e.toc_epoch().0, e.toc_epoch().1 as i64));
+        assert_eq!(read.len(), ephemerides.len());
+        for (r, e) in read.iter().zip(ephemerides.iter()) {
+            assert_eq!((r.prn(), r.toc_epoch()), (e.prn(), e.toc_epoch()));
+            let ((rc, ro), (ec, eo)) = (r.broadcast(), e.broadcast());
+            for (a, b) in rc.iter().chain(ro.iter()).zip(ec.iter().chain(eo.iter())) {
+                assert!(
+                    (a - b).abs() <= 1e-12 * b.abs(),
+                    "prn {} {} {}",
+                    e.prn(),
+                    a,
+                    b
+                );
+            }
+        }
+    }
+}
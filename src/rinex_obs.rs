@@ -0,0 +1,868 @@
+use crate::observables::*;
+use crate::satellites::*;
+use nalgebra::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Lines, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug)]
+pub struct RinexObsHeader {
+    pub version: f64,
+    pub marker_name: String,
+    pub receiver: String,
+    pub antenna: String,
+    pub approx_position: Vector3<f64>,         // ecef (m)
+    pub antenna_delta: Vector3<f64>,           // height, east, north (m)
+    pub interval: f64,                         // sec, 0 when not given
+    pub obs_types: HashMap<char, Vec<String>>, // per system, rinex 2 types are stored under 'G'
+}
+
+impl RinexObsHeader {
+    pub fn antenna_delta_enu(&self) -> Vector3<f64> {
+        // antenna delta reordered from h/e/n to the east, north, up used by PppConfig
+        Vector3::new(
+            self.antenna_delta[1],
+            self.antenna_delta[2],
+            self.antenna_delta[0],
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ObsKind {
+    Code,
+    Phase,
+    Doppler,
+    Snr,
+}
+
+fn obs_kind_band(code: &str) -> Option<(ObsKind, Band)> {
+    // maps a rinex 2 (C1, P2, L5) or rinex 3 (C1C, L2W, D5Q) observation code
+    let mut chars = code.chars();
+    let kind = match chars.next()? {
+        'C' | 'P' => ObsKind::Code,
+        'L' => ObsKind::Phase,
+        'D' => ObsKind::Doppler,
+        'S' => ObsKind::Snr,
+        _ => return None,
+    };
+    let band = match chars.next()? {
+        '1' => Band::L1,
+        '2' => Band::L2,
+        '5' => Band::L5,
+        _ => return None,
+    };
+    Some((kind, band))
+}
+
+fn obs_priority(code: &str) -> usize {
+    // lower is preferred when several codes map to the same observable, civil
+    // signals first so l1 pseudoranges match the broadcast group delay
+    let attribute = match code.len() {
+        3 => code.chars().nth(2).unwrap(),
+        _ => {
+            if code.starts_with('P') {
+                'P'
+            } else {
+                'C'
+            }
+        }
+    };
+    "CSLXIQPWYMZN".find(attribute).unwrap_or(99)
+}
+
+pub struct RinexObsReader {
+    lines: Lines<BufReader<File>>,
+    header: RinexObsHeader,
+}
+
+impl RinexObsReader {
+    pub fn open(filename: &str) -> Result<RinexObsReader, Error> {
+        let file = File::open(filename)?;
+        let mut lines = BufReader::new(file).lines();
+        let header = read_header(&mut lines)?;
+        Ok(RinexObsReader { lines, header })
+    }
+
+    pub fn header(&self) -> &RinexObsHeader {
+        &self.header
+    }
+
+    fn next_epoch(&mut self) -> Result<Option<ObservationEpoch>, Error> {
+        loop {
+            let line = match self.lines.next() {
+                Some(line) => line?,
+                None => return Ok(None),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let epoch = if self.header.version >= 3. {
+                self.read_epoch_v3(&line)?
+            } else {
+                self.read_epoch_v2(&line)?
+            };
+            if let Some(epoch) = epoch {
+                return Ok(Some(epoch));
+            }
+        }
+    }
+
+    fn read_epoch_v2(&mut self, line: &str) -> Result<Option<ObservationEpoch>, Error> {
+        let flag = field_i32(line, 26, 29);
+        let count = field_i32(line, 29, 32) as usize;
+        if flag > 1 {
+            // event records carry header lines instead of satellites
+            self.skip(count)?;
+            return Ok(None);
+        }
+        let (week, tow) = gps_time_from_date(
+            field_i32(line, 1, 3),
+            field_i32(line, 4, 6),
+            field_i32(line, 7, 9),
+            field_i32(line, 10, 12),
+            field_i32(line, 13, 15),
+            field_f64(line, 15, 26),
+        );
+
+        // satellite list, 12 per line with continuation lines
+        let mut sats = vec![];
+        let mut list = line.to_string();
+        for i in 0..count {
+            if i > 0 && i % 12 == 0 {
+                list = self.next_line()?;
+            }
+            let start = 32 + 3 * (i % 12);
+            sats.push(list.get(start..start + 3).unwrap_or("").to_string());
+        }
+
+        let types = self.header.obs_types.get(&'G').cloned().unwrap_or_default();
+        let lines_per_sat = types.len().div_ceil(5).max(1);
+        let mut obs = vec![];
+        for sat in sats {
+            let mut record = String::new();
+            for _ in 0..lines_per_sat {
+                let l = self.next_line()?;
+                // pad each line to 80 columns so fields stay aligned
+                record.push_str(&format!("{:<80}", l));
+            }
+            let system = sat.chars().next().unwrap_or('G');
+            if system != 'G' && system != ' ' {
+                continue;
+            }
+            let prn = sat
+                .get(1..)
+                .unwrap_or("")
+                .trim()
+                .parse::<usize>()
+                .unwrap_or(0);
+            let values: Vec<(f64, bool)> = (0..types.len())
+                .map(|j| {
+                    let start = (j / 5) * 80 + (j % 5) * 16;
+                    read_value(&record, start)
+                })
+                .collect();
+            obs.extend(build_observations(prn, &types, &values));
+        }
+
+        Ok(Some(ObservationEpoch {
+            gps_week: week,
+            gps_time: tow,
+            obs,
+        }))
+    }
+
+    fn read_epoch_v3(&mut self, line: &str) -> Result<Option<ObservationEpoch>, Error> {
+        if !line.starts_with('>') {
+            return Ok(None);
+        }
+        let flag = field_i32(line, 31, 32);
+        let count = field_i32(line, 32, 35) as usize;
+        if flag > 1 {
+            self.skip(count)?;
+            return Ok(None);
+        }
+        let (week, tow) = gps_time_from_date(
+            field_i32(line, 2, 6),
+            field_i32(line, 7, 9),
+            field_i32(line, 10, 12),
+            field_i32(line, 13, 15),
+            field_i32(line, 16, 18),
+            field_f64(line, 18, 29),
+        );
+
+        let types = self.header.obs_types.get(&'G').cloned().unwrap_or_default();
+        let mut obs = vec![];
+        for _ in 0..count {
+            let l = self.next_line()?;
+            let system = l.chars().next().unwrap_or(' ');
+            if system != 'G' {
+                continue;
+            }
+            let prn = l
+                .get(1..3)
+                .unwrap_or("")
+                .trim()
+                .parse::<usize>()
+                .unwrap_or(0);
+            let values: Vec<(f64, bool)> = (0..types.len())
+                .map(|j| read_value(&l, 3 + 16 * j))
+                .collect();
+            obs.extend(build_observations(prn, &types, &values));
+        }
+
+        Ok(Some(ObservationEpoch {
+            gps_week: week,
+            gps_time: tow,
+            obs,
+        }))
+    }
+
+    fn next_line(&mut self) -> Result<String, Error> {
+        self.lines
+            .next()
+            .unwrap_or_else(|| Err(Error::new(ErrorKind::UnexpectedEof, "truncated epoch")))
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), Error> {
+        for _ in 0..count {
+            self.next_line()?;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for RinexObsReader {
+    type Item = Result<ObservationEpoch, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_epoch().transpose()
+    }
+}
+
+pub fn read_rinex_obs(filename: &str) -> Result<(RinexObsHeader, Vec<ObservationEpoch>), Error> {
+    // whole file at once, use RinexObsReader to stream long files
+    let mut reader = RinexObsReader::open(filename)?;
+    let header = reader.header().clone();
+    let epochs = reader.by_ref().collect::<Result<Vec<_>, Error>>()?;
+    Ok((header, epochs))
+}
+
+pub fn header_line(data: &str, label: &str) -> String {
+    // 60 columns of data followed by the label
+    format!("{:<60}{}\n", data, label)
+}
+
+pub fn run_date() -> String {
+    // PGM / RUN BY / DATE field from the system clock, yyyymmdd hhmmss UTC
+    let unix = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.);
+    // the calendar conversion does not care about the time scale
+    let (y, mo, d, h, mi, s) = date_from_gps_time(0, (unix - 315_964_800.).floor());
+    format!(
+        "{:04}{:02}{:02} {:02}{:02}{:02} UTC",
+        y, mo, d, h, mi, s as i32
+    )
+}
+
+fn default_obs_types(version: f64, bands: &[Band]) -> Vec<String> {
+    // code, phase, doppler and snr for each band, civil signals
+    let mut types = vec![];
+    for band in [Band::L1, Band::L2, Band::L5] {
+        if !bands.contains(&band) {
+            continue;
+        }
+        let (n, attribute) = match band {
+            Band::L1 => ('1', 'C'),
+            Band::L2 => ('2', 'L'),
+            Band::L5 => ('5', 'Q'),
+        };
+        for kind in ['C', 'L', 'D', 'S'] {
+            if version >= 3. {
+                types.push(format!("{}{}{}", kind, n, attribute));
+            } else {
+                types.push(format!("{}{}", kind, n));
+            }
+        }
+    }
+    types
+}
+
+fn convert_obs_code(code: &str, version: f64) -> Option<String> {
+    // rinex 2 code to its usual rinex 3 signal (c1 is c/a, p1 and p2 the
+    // semicodeless p(y) tracking, c2 is l2c) and back, none for unknown codes
+    let (kind, band) = obs_kind_band(code)?;
+    let n = match band {
+        Band::L1 => '1',
+        Band::L2 => '2',
+        Band::L5 => '5',
+    };
+    let letter = match kind {
+        ObsKind::Code => 'C',
+        ObsKind::Phase => 'L',
+        ObsKind::Doppler => 'D',
+        ObsKind::Snr => 'S',
+    };
+    if version >= 3. {
+        if code.len() == 3 {
+            return Some(code.to_string());
+        }
+        let attribute = match (band, code.starts_with('P')) {
+            (Band::L1, false) => 'C',
+            (Band::L2, false) if kind == ObsKind::Code => 'L',
+            (Band::L5, _) => 'X',
+            _ => 'W',
+        };
+        Some(format!("{}{}{}", letter, n, attribute))
+    } else {
+        if code.len() == 2 {
+            return Some(code.to_string());
+        }
+        let p_code = kind == ObsKind::Code && "PWYMZN".contains(&code[2..]);
+        Some(format!("{}{}", if p_code { 'P' } else { letter }, n))
+    }
+}
+
+pub fn convert_obs_types(types: &[String], version: f64) -> Vec<String> {
+    // observation codes for the given rinex version, codes that map to the same
+    // rinex 2 type are kept once
+    let mut converted: Vec<String> = vec![];
+    for code in types.iter().filter_map(|t| convert_obs_code(t, version)) {
+        if !converted.contains(&code) {
+            converted.push(code);
+        }
+    }
+    converted
+}
+
+fn filled_obs_types(types: &[String]) -> Vec<bool> {
+    // an observation holds one value per kind and band, it goes in the column of
+    // the code the reader would have chosen and the other codes stay blank
+    let mut best: HashMap<(Band, u8), (usize, usize)> = HashMap::new();
+    for (i, code) in types.iter().enumerate() {
+        if let Some((kind, band)) = obs_kind_band(code) {
+            let priority = obs_priority(code);
+            let entry = best.entry((band, kind as u8)).or_insert((priority, i));
+            if priority < entry.0 {
+                *entry = (priority, i);
+            }
+        }
+    }
+    (0..types.len())
+        .map(|i| best.values().any(|&(_, k)| k == i))
+        .collect()
+}
+
+fn format_value(o: Option<&Observation>, code: &str) -> String {
+    // F14.3 with the loss of lock and signal strength flags on phase, blank when
+    // the observable is missing
+    let (kind, band) = match obs_kind_band(code) {
+        Some(kb) => kb,
+        None => return format!("{:16}", ""),
+    };
+    let o = match o.filter(|o| o.band == band) {
+        Some(o) => o,
+        None => return format!("{:16}", ""),
+    };
+    let value = match kind {
+        ObsKind::Code => o.pseudorange,
+        ObsKind::Phase => o.carrier_phase,
+        ObsKind::Doppler => o.doppler,
+        ObsKind::Snr => o.cn0,
+    };
+    if value == 0. || value.abs() >= 1e10 {
+        return format!("{:16}", "");
+    }
+    if kind == ObsKind::Phase {
+        let lli = if o.lli { "1" } else { " " };
+        let ssi = if o.cn0 > 0. {
+            ((o.cn0 / 6.).floor() as i32).clamp(1, 9).to_string()
+        } else {
+            " ".to_string()
+        };
+        format!("{:14.3}{}{}", value, lli, ssi)
+    } else {
+        format!("{:14.3}  ", value)
+    }
+}
+
+pub struct RinexObsWriter<W: Write> {
+    // gps observation file, 2.11 for versions below 3 and 3.04 otherwise, the
+    // header is written with the first epoch to fill in TIME OF FIRST OBS
+    writer: W,
+    header: RinexObsHeader,
+    types: Vec<String>,
+    filled: Vec<bool>, // per type, false for the blank columns
+    header_written: bool,
+}
+
+impl RinexObsWriter<BufWriter<File>> {
+    pub fn create(filename: &str, header: &RinexObsHeader) -> Result<Self, Error> {
+        Ok(RinexObsWriter::new(
+            BufWriter::new(File::create(filename)?),
+            header,
+        ))
+    }
+}
+
+impl<W: Write> RinexObsWriter<W> {
+    pub fn new(writer: W, header: &RinexObsHeader) -> Self {
+        // observation types from the header converted to the output version, all
+        // three bands when it has none
+        let mut header = header.clone();
+        header.version = if header.version >= 3. { 3.04 } else { 2.11 };
+        let types = match header.obs_types.get(&'G') {
+            Some(t) if !t.is_empty() => convert_obs_types(t, header.version),
+            _ => default_obs_types(header.version, &[Band::L1, Band::L2, Band::L5]),
+        };
+        header.obs_types.insert('G', types.clone());
+        let filled = filled_obs_types(&types);
+        RinexObsWriter {
+            writer,
+            header,
+            types,
+            filled,
+            header_written: false,
+        }
+    }
+
+    fn write_header(&mut self, first: &ObservationEpoch) -> Result<(), Error> {
+        let h = &self.header;
+        let v3 = h.version >= 3.;
+        let mut out = String::new();
+        let system = if v3 { "G: GPS" } else { "G (GPS)" };
+        out += &header_line(
+            &format!(
+                "{:9.2}{:11}{:<20}{:<20}",
+                h.version, "", "OBSERVATION DATA", system
+            ),
+            "RINEX VERSION / TYPE",
+        );
+        out += &header_line(
+            &format!("{:<20}{:<20}{:<20}", "rgps", "", run_date()),
+            "PGM / RUN BY / DATE",
+        );
+        out += &header_line(&h.marker_name, "MARKER NAME");
+        if v3 {
+            out += &header_line("NON_GEODETIC", "MARKER TYPE");
+        }
+        out += &header_line("", "OBSERVER / AGENCY");
+        out += &header_line(
+            &format!("{:<20}{:<20.20}{:<20}", "", h.receiver, ""),
+            "REC # / TYPE / VERS",
+        );
+        out += &header_line(&format!("{:<20}{:<20.20}", "", h.antenna), "ANT # / TYPE");
+        let xyz = |v: &Vector3<f64>| format!("{:14.4}{:14.4}{:14.4}", v[0], v[1], v[2]);
+        out += &header_line(&xyz(&h.approx_position), "APPROX POSITION XYZ");
+        out += &header_line(&xyz(&h.antenna_delta), "ANTENNA: DELTA H/E/N");
+        if v3 {
+            for (k, chunk) in self.types.chunks(13).enumerate() {
+                let mut data = if k == 0 {
+                    format!("G  {:3}", self.types.len())
+                } else {
+                    format!("{:6}", "")
+                };
+                for t in chunk {
+                    data += &format!(" {:<3}", t);
+                }
+                out += &header_line(&data, "SYS / # / OBS TYPES");
+            }
+        } else {
+            out += &header_line(&format!("{:6}{:6}", 1, 1), "WAVELENGTH FACT L1/2");
+            for (k, chunk) in self.types.chunks(9).enumerate() {
+                let mut data = if k == 0 {
+                    format!("{:6}", self.types.len())
+                } else {
+                    format!("{:6}", "")
+                };
+                for t in chunk {
+                    data += &format!("{:>6}", t);
+                }
+                out += &header_line(&data, "# / TYPES OF OBSERV");
+            }
+        }
+        if h.interval > 0. {
+            out += &header_line(&format!("{:10.3}", h.interval), "INTERVAL");
+        }
+        let (y, mo, d, hh, mi, s) = date_from_gps_time(first.gps_week, first.gps_time);
+        out += &header_line(
+            &format!(
+                "{:6}{:6}{:6}{:6}{:6}{:13.7}{:5}{:<3}",
+                y, mo, d, hh, mi, s, "", "GPS"
+            ),
+            "TIME OF FIRST OBS",
+        );
+        if v3 {
+            for t in self.types.iter().filter(|t| t.starts_with('L')) {
+                out += &header_line(&format!("G {:<3}  0.00000", t), "SYS / PHASE SHIFT");
+            }
+        }
+        out += &header_line("", "END OF HEADER");
+        self.writer.write_all(out.as_bytes())
+    }
+
+    pub fn write_epoch(&mut self, epoch: &ObservationEpoch) -> Result<(), Error> {
+        if !self.header_written {
+            self.write_header(epoch)?;
+            self.header_written = true;
+        }
+        let mut prns: Vec<usize> = epoch.obs.iter().map(|o| o.prn).collect();
+        prns.sort();
+        prns.dedup();
+        // round to the 0.1 us resolution of the epoch line before splitting the date
+        let tow = (epoch.gps_time * 1e7).round() / 1e7;
+        let (y, mo, d, h, mi, s) = date_from_gps_time(epoch.gps_week, tow);
+        let mut out = String::new();
+        if self.header.version >= 3. {
+            out += &format!(
+                "> {:4} {:02} {:02} {:02} {:02}{:11.7}  0{:3}\n",
+                y,
+                mo,
+                d,
+                h,
+                mi,
+                s,
+                prns.len()
+            );
+            for &prn in prns.iter() {
+                let mut line = format!("G{:02}", prn);
+                for (t, &filled) in self.types.iter().zip(self.filled.iter()) {
+                    let band = obs_kind_band(t).map(|kb| kb.1);
+                    let o = band.and_then(|b| epoch.get(prn, b)).filter(|_| filled);
+                    line += &format_value(o, t);
+                }
+                out += line.trim_end();
+                out += "\n";
+            }
+        } else {
+            out += &format!(
+                " {:02} {:2} {:2} {:2} {:2}{:11.7}  0{:3}",
+                y % 100,
+                mo,
+                d,
+                h,
+                mi,
+                s,
+                prns.len()
+            );
+            for (k, &prn) in prns.iter().enumerate() {
+                if k > 0 && k % 12 == 0 {
+                    out += &format!("\n{:32}", "");
+                }
+                out += &format!("G{:02}", prn);
+            }
+            out += "\n";
+            for &prn in prns.iter() {
+                for (chunk, filled) in self.types.chunks(5).zip(self.filled.chunks(5)) {
+                    let mut line = String::new();
+                    for (t, &filled) in chunk.iter().zip(filled.iter()) {
+                        let band = obs_kind_band(t).map(|kb| kb.1);
+                        let o = band.and_then(|b| epoch.get(prn, b)).filter(|_| filled);
+                        line += &format_value(o, t);
+                    }
+                    out += line.trim_end();
+                    out += "\n";
+                }
+            }
+        }
+        self.writer.write_all(out.as_bytes())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()
+    }
+}
+
+pub fn write_rinex_obs(
+    filename: &str,
+    header: &RinexObsHeader,
+    epochs: &[ObservationEpoch],
+) -> Result<(), Error> {
+    // whole file at once, observation types follow the bands present in the
+    // epochs when the header does not list any
+    let mut header = header.clone();
+    if header.obs_types.get(&'G').is_none_or(|t| t.is_empty()) {
+        let bands: Vec<Band> = [Band::L1, Band::L2, Band::L5]
+            .into_iter()
+            .filter(|&b| epochs.iter().any(|e| e.obs.iter().any(|o| o.band == b)))
+            .collect();
+        header
+            .obs_types
+            .insert('G', default_obs_types(header.version, &bands));
+    }
+    let mut writer = RinexObsWriter::create(filename, &header)?;
+    for epoch in epochs {
+        writer.write_epoch(epoch)?;
+    }
+    writer.flush()
+}
+
+fn read_header(lines: &mut Lines<BufReader<File>>) -> Result<RinexObsHeader, Error> {
+    let mut header = RinexObsHeader {
+        version: 0.,
+        marker_name: String::new(),
+        receiver: String::new(),
+        antenna: String::new(),
+        approx_position: Vector3::zeros(),
+        antenna_delta: Vector3::zeros(),
+        interval: 0.,
+        obs_types: HashMap::new(),
+    };
+    let mut last_system = 'G';
+    let mut v2_count = 0;
+
+    for line in lines.by_ref() {
+        let line = line?;
+        let label = line.get(60..).unwrap_or("").trim();
+        let data = line.get(..60.min(line.len())).unwrap_or("");
+        match label {
+            "RINEX VERSION / TYPE" => header.version = field_f64(data, 0, 9),
+            "MARKER NAME" => header.marker_name = data.trim().to_string(),
+            "REC # / TYPE / VERS" => {
+                header.receiver = data.get(20..40).unwrap_or("").trim().to_string()
+            }
+            "ANT # / TYPE" => header.antenna = data.get(20..40).unwrap_or("").trim().to_string(),
+            "APPROX POSITION XYZ" => {
+                header.approx_position = Vector3::new(
+                    field_f64(data, 0, 14),
+                    field_f64(data, 14, 28),
+                    field_f64(data, 28, 42),
+                )
+            }
+            "ANTENNA: DELTA H/E/N" => {
+                header.antenna_delta = Vector3::new(
+                    field_f64(data, 0, 14),
+                    field_f64(data, 14, 28),
+                    field_f64(data, 28, 42),
+                )
+            }
+            "INTERVAL" => header.interval = field_f64(data, 0, 10),
+            "# / TYPES OF OBSERV" => {
+                // rinex 2, up to 9 types per line
+                if !data[..6].trim().is_empty() {
+                    v2_count = field_i32(data, 0, 6) as usize;
+                }
+                let types = header.obs_types.entry('G').or_default();
+                for k in 0..9 {
+                    if types.len() >= v2_count {
+                        break;
+                    }
+                    let start = 10 + 6 * k;
+                    if let Some(t) = data.get(start..start + 2) {
+                        types.push(t.trim().to_string());
+                    }
+                }
+            }
+            "SYS / # / OBS TYPES" => {
+                // rinex 3, up to 13 types per line with continuation lines
+                let system = data.chars().next().unwrap_or(' ');
+                if system != ' ' {
+                    last_system = system;
+                }
+                let types = header.obs_types.entry(last_system).or_default();
+                for k in 0..13 {
+                    let start = 7 + 4 * k;
+                    if let Some(t) = data.get(start..start + 3) {
+                        if !t.trim().is_empty() {
+                            types.push(t.trim().to_string());
+                        }
+                    }
+                }
+            }
+            "END OF HEADER" => return Ok(header),
+            _ => {}
+        }
+    }
+    Err(Error::new(ErrorKind::InvalidData, "missing END OF HEADER"))
+}
+
+fn read_value(record: &str, start: usize) -> (f64, bool) {
+    // 14.3 value followed by the loss of lock and signal strength flags
+    let value = field_f64(record, start, start + 14);
+    let lli = record
+        .get(start + 14..start + 15)
+        .and_then(|s| s.trim().parse::<u8>().ok())
+        .map(|f| f & 1 == 1)
+        .unwrap_or(false);
+    (value, lli)
+}
+
+fn build_observations(prn: usize, types: &[String], values: &[(f64, bool)]) -> Vec<Observation> {
+    // one observation per band, choosing the preferred code where several exist
+    let mut obs: Vec<Observation> = vec![];
+    let mut chosen: HashMap<(Band, u8), usize> = HashMap::new();
+    for (code, &(value, lli)) in types.iter().zip(values.iter()) {
+        let (kind, band) = match obs_kind_band(code) {
+            Some(kb) => kb,
+            None => continue,
+        };
+        if value == 0. {
+            continue;
+        }
+        let key = (band, kind as u8);
+        let priority = obs_priority(code);
+        if let Some(&best) = chosen.get(&key) {
+            if best <= priority {
+                continue;
+            }
+        }
+        chosen.insert(key, priority);
+
+        let o = match obs.iter_mut().find(|o| o.band == band) {
+            Some(o) => o,
+            None => {
+                obs.push(Observation {
+                    prn,
+                    band,
+                    pseudorange: 0.,
+                    carrier_phase: 0.,
+                    doppler: 0.,
+                    cn0: 0.,
+                    lli: false,
+                });
+                obs.last_mut().unwrap()
+            }
+        };
+        match kind {
+            ObsKind::Code => o.pseudorange = value,
+            ObsKind::Phase => {
+                o.carrier_phase = value;
+                o.lli = lli;
+            }
+            ObsKind::Doppler => o.doppler = value,
+            ObsKind::Snr => o.cn0 = value,
+        }
+    }
+    obs
+}
+
+fn field_f64(line: &str, start: usize, end: usize) -> f64 {
+    line.get(start..end.min(line.len()))
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .and_then(|s| s.replace('D', "e").parse::<f64>().ok())
+        .unwrap_or(0.)
+}
+
+fn field_i32(line: &str, start: usize, end: usize) -> i32 {
+    line.get(start..end.min(line.len()))
+        .and_then(|s| s.trim().parse::<i32>().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> String {
+        let file = format!("rgps_{}_{}.obs", std::process::id(), name);
+        std::env::temp_dir()
+            .join(file)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn header(version: f64) -> RinexObsHeader {
+        RinexObsHeader {
+            version,
+            marker_name: "RGPS".to_string(),
+            receiver: "U-BLOX".to_string(),
+            antenna: "ANN-MB".to_string(),
+            approx_position: Vector3::new(-1288398.5743, -4721697.0284, 4078625.5369),
+            antenna_delta: Vector3::new(1.5, 0.1, -0.2),
+            interval: 1.,
+            obs_types: HashMap::new(),
+        }
+    }
+
+    fn epoch(gps_time: f64, lli: bool) -> ObservationEpoch {
+        // thirteen satellites for the rinex 2 continuation line, l2 on the even prns,
+        // values with the three decimals of the file
+        let mut obs = vec![];
+        for prn in 1..=13 {
+            for band in [Band::L1, Band::L2] {
+                if band == Band::L2 && prn % 2 == 1 {
+                    continue;
+                }
+                let pseudorange = 2e7 + 123456.789 * prn as f64;
+                obs.push(Observation {
+                    prn,
+                    band,
+                    pseudorange,
+                    carrier_phase: (pseudorange / band.wavelength() * 1e3).round() / 1e3,
+                    doppler: -1234.567 + 100. * prn as f64,
+                    cn0: 30. + 1.25 * prn as f64,
+                    lli: lli && prn == 5 && band == Band::L1,
+                });
+            }
+        }
+        ObservationEpoch {
+            gps_week: 2274,
+            gps_time,
+            obs,
+        }
+    }
+
+    #[test]
+    fn antenna_delta_to_enu() {
+        assert_eq!(
+            header(3.04).antenna_delta_enu(),
+            Vector3::new(0.1, -0.2, 1.5)
+        );
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        // a time 0.1 us before the minute and one rounding up to it, at 02:00:59.9999999
+        // and 02:01:01, and the last epoch of the day
+        let times = [7259.9999999, 7260.99999996, 7262.5, 86399.9999999];
+        let epochs: Vec<ObservationEpoch> = times
+            .iter()
+            .enumerate()
+            .map(|(k, &t)| epoch(t, k == 1))
+            .collect();
+        for version in [2.11, 3.04] {
+            let filename = path(&version.to_string());
+            write_rinex_obs(&filename, &header(version), &epochs).unwrap();
+            let text = std::fs::read_to_string(&filename).unwrap();
+            let (read_header, read_epochs) = read_rinex_obs(&filename).unwrap();
+            std::fs::remove_file(&filename).unwrap();
+            assert!(text.contains("59.9999999") && !text.contains("60.0000000"));
+
+            let expected = header(version);
+            assert_eq!(read_header.version, version);
+            assert_eq!(read_header.marker_name, expected.marker_name);
+            assert_eq!(read_header.receiver, expected.receiver);
+            assert_eq!(read_header.antenna, expected.antenna);
+            assert_eq!(read_header.approx_position, expected.approx_position);
+            assert_eq!(read_header.antenna_delta, expected.antenna_delta);
+            assert_eq!(read_header.interval, 1.);
+            assert_eq!(read_header.obs_types[&'G'].len(), 8);
+
+            assert_eq!(read_epochs.len(), epochs.len());
+            for (read, written) in read_epochs.iter().zip(epochs.iter()) {
+                assert_eq!(read.gps_week, 2274);
+                let tow = (written.gps_time * 1e7).round() / 1e7;
+                assert!((read.gps_time - tow).abs() < 1e-8, "{}", read.gps_time);
+                assert_eq!(read.obs.len(), written.obs.len());
+                for o in written.obs.iter() {
+                    let r = read.get(o.prn, o.band).unwrap();
+                    // three decimal values survive the 14.3 columns
+                    for (a, b) in [
+                        (r.pseudorange, o.pseudorange),
+                        (r.carrier_phase, o.carrier_phase),
+                        (r.doppler, o.doppler),
+                        (r.cn0, o.cn0),
+                    ] {
+                        assert!((a - b).abs() < 1e-6, "{} {}", a, b);
+                    }
+                    assert_eq!(r.lli, o.lli, "{} prn {} {:?}", version, o.prn, o.band);
+                }
+            }
+            assert!(read_epochs[1].get(5, Band::L1).unwrap().lli);
+        }
+    }
+}
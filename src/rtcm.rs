@@ -0,0 +1,1448 @@
+use crate::observables::*;
+use crate::satellites::*;
+use nalgebra::*;
+use std::collections::HashMap;
+use std::f64::consts::*;
+use std::fs::File;
+use std::io::{Error, Read};
+
+const PREAMBLE: u8 = 0xD3;
+const RANGE_MS: f64 = SPEED_OF_LIGHT / 1000.; // m per ms of range
+
+// gps user range accuracy index to metres, IS-GPS-200 20.3.3.3.1.3
+pub const URA: [f64; 16] = [
+    2.4, 3.4, 4.85, 6.85, 9.65, 13.65, 24., 48., 96., 192., 384., 768., 1536., 3072., 6144., 6144.,
+];
+
+fn p2(n: i32) -> f64 {
+    2f64.powi(n)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GnssSystem {
+    Gps,
+    Glonass,
+    Galileo,
+    Sbas,
+    Qzss,
+    Beidou,
+}
+
+impl GnssSystem {
+    fn from_msm(number: u16) -> Option<GnssSystem> {
+        match number / 10 {
+            107 => Some(GnssSystem::Gps),
+            108 => Some(GnssSystem::Glonass),
+            109 => Some(GnssSystem::Galileo),
+            110 => Some(GnssSystem::Sbas),
+            111 => Some(GnssSystem::Qzss),
+            112 => Some(GnssSystem::Beidou),
+            _ => None,
+        }
+    }
+}
+
+pub fn crc24q(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for &b in data {
+        crc ^= (b as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x100_0000 != 0 {
+                crc ^= 0x186_4CFB;
+            }
+        }
+    }
+    crc & 0xFF_FFFF
+}
+
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    // preamble, 6 reserved bits, 10 bit length, payload and crc-24q
+    let mut frame = vec![
+        PREAMBLE,
+        (payload.len() >> 8) as u8 & 0x03,
+        payload.len() as u8,
+    ];
+    frame.extend_from_slice(payload);
+    let crc = crc24q(&frame);
+    frame.extend_from_slice(&[(crc >> 16) as u8, (crc >> 8) as u8, crc as u8]);
+    frame
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0 }
+    }
+
+    fn u(&mut self, n: usize) -> u64 {
+        // reads past the end return zero bits, checked afterwards with overrun
+        let mut v = 0;
+        for _ in 0..n {
+            let byte = self.data.get(self.pos / 8).copied().unwrap_or(0);
+            v = (v << 1) | ((byte >> (7 - self.pos % 8)) & 1) as u64;
+            self.pos += 1;
+        }
+        v
+    }
+
+    fn s(&mut self, n: usize) -> i64 {
+        // two's complement
+        let v = self.u(n);
+        if n > 0 && (v >> (n - 1)) & 1 == 1 {
+            v as i64 - (1i64 << n)
+        } else {
+            v as i64
+        }
+    }
+
+    fn sm(&mut self, n: usize) -> i64 {
+        // sign and magnitude, used by glonass
+        let negative = self.u(1) == 1;
+        let magnitude = self.u(n - 1) as i64;
+        if negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    fn f(&mut self, n: usize, scale: f64) -> f64 {
+        self.s(n) as f64 * scale
+    }
+
+    fn uf(&mut self, n: usize, scale: f64) -> f64 {
+        self.u(n) as f64 * scale
+    }
+
+    fn smf(&mut self, n: usize, scale: f64) -> f64 {
+        self.sm(n) as f64 * scale
+    }
+
+    fn string(&mut self) -> String {
+        // 8 bit count followed by the characters
+        let n = self.u(8) as usize;
+        (0..n).map(|_| self.u(8) as u8 as char).collect()
+    }
+
+    fn overrun(&self) -> bool {
+        self.pos > self.data.len() * 8
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl BitWriter {
+    fn u(&mut self, n: usize, v: u64) {
+        for i in (0..n).rev() {
+            if self.pos.is_multiple_of(8) {
+                self.data.push(0);
+            }
+            if (v >> i) & 1 == 1 {
+                let last = self.data.len() - 1;
+                self.data[last] |= 1 << (7 - self.pos % 8);
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn s(&mut self, n: usize, v: i64) {
+        let mask = if n >= 64 { u64::MAX } else { (1 << n) - 1 };
+        self.u(n, v as u64 & mask);
+    }
+
+    fn f(&mut self, n: usize, value: f64, scale: f64) {
+        self.s(n, (value / scale).round() as i64);
+    }
+
+    fn uf(&mut self, n: usize, value: f64, scale: f64) {
+        self.u(n, (value / scale).round().max(0.) as u64);
+    }
+
+    fn string(&mut self, s: &str) {
+        let bytes = &s.as_bytes()[..s.len().min(31)];
+        self.u(8, bytes.len() as u64);
+        for &b in bytes {
+            self.u(8, b as u64);
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RtcmStation {
+    // 1005, or 1006 with the antenna height
+    pub station_id: u16,
+    pub itrf_year: u8,
+    pub gps: bool,
+    pub glonass: bool,
+    pub galileo: bool,
+    pub reference_station: bool, // physical (false) or non-physical (true)
+    pub single_oscillator: bool,
+    pub quarter_cycle: u8,
+    pub position: Vector3<f64>, // antenna reference point, ecef (m)
+    pub height: Option<f64>,    // arp above the marker (m)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RtcmAntenna {
+    // 1007, 1008 with the serial number, 1033 with the receiver as well
+    pub station_id: u16,
+    pub descriptor: String, // igs antenna type and radome
+    pub setup_id: u8,
+    pub serial: String,
+    pub receiver_type: String,
+    pub firmware: String,
+    pub receiver_serial: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct GlonassEphemeris {
+    // pz-90 state vector at tb, ICD GLONASS 4.4
+    pub slot: usize,
+    pub frequency_channel: i32,
+    pub health: u8,                 // Bn msb, 0 healthy
+    pub tk: f64,                    // frame start, sec of the moscow day
+    pub tb: f64,                    // reference time, sec of the moscow day
+    pub position: Vector3<f64>,     // m
+    pub velocity: Vector3<f64>,     // m/s
+    pub acceleration: Vector3<f64>, // luni-solar (m/s^2)
+    pub gamma: f64,                 // relative frequency offset
+    pub tau: f64,                   // clock bias (sec)
+    pub delta_tau: f64,             // l2 minus l1 delay (sec)
+    pub age: u8,                    // En (days)
+    pub day: u16,                   // NT, day in the four year interval
+}
+
+#[derive(Clone, Debug)]
+pub struct MsmCell {
+    pub satellite: usize,         // prn, glonass slot
+    pub signal: u8,               // rtcm signal id (1-32)
+    pub pseudorange: Option<f64>, // m
+    pub phase_range: Option<f64>, // m
+    pub range_rate: Option<f64>,  // m/s, msm7 only
+    pub lock_time: f64,           // minimum lock time (sec)
+    pub half_cycle: bool,
+    pub cn0: Option<f64>, // dB-Hz
+    pub lli: bool,        // lock time went down since the previous message
+}
+
+#[derive(Clone, Debug)]
+pub struct RtcmMsm {
+    pub system: GnssSystem,
+    pub msm: u8, // 4 or 7
+    pub station_id: u16,
+    pub gps_week: i32,
+    pub gps_time: f64, // epoch on the gps time scale
+    pub multiple: bool,
+    pub iods: u8,
+    pub extended_info: HashMap<usize, u8>, // per satellite, glonass frequency channel + 7
+    pub cells: Vec<MsmCell>,
+}
+
+impl RtcmMsm {
+    pub fn observations(&self) -> Option<ObservationEpoch> {
+        // gps msm as an observation epoch, the first signal of each band is used
+        if self.system != GnssSystem::Gps {
+            return None;
+        }
+        let mut obs: Vec<Observation> = vec![];
+        for c in self.cells.iter() {
+            let band = match gps_band(c.signal) {
+                Some(b) => b,
+                None => continue,
+            };
+            if obs.iter().any(|o| o.prn == c.satellite && o.band == band) {
+                continue;
+            }
+            let wavelength = band.wavelength();
+            obs.push(Observation {
+                prn: c.satellite,
+                band,
+                pseudorange: c.pseudorange.unwrap_or(0.),
+                carrier_phase: c.phase_range.map(|p| p / wavelength).unwrap_or(0.),
+                doppler: c.range_rate.map(|r| -r / wavelength).unwrap_or(0.),
+                cn0: c.cn0.unwrap_or(0.),
+                lli: c.lli,
+            });
+        }
+        Some(ObservationEpoch {
+            gps_week: self.gps_week,
+            gps_time: self.gps_time,
+            obs,
+        })
+    }
+}
+
+fn gps_band(signal: u8) -> Option<Band> {
+    // rtcm gps signal ids: 1C 1P 1W (2-4) 2C 2P 2W 2S 2L 2X (8-17) 5I 5Q 5X (22-24)
+    // 1S 1L 1X (30-32)
+    match signal {
+        2..=4 | 30..=32 => Some(Band::L1),
+        8..=17 => Some(Band::L2),
+        22..=24 => Some(Band::L5),
+        _ => None,
+    }
+}
+
+fn gps_signal(band: Band) -> u8 {
+    match band {
+        Band::L1 => 2,  // 1C
+        Band::L2 => 16, // 2L
+        Band::L5 => 24, // 5X
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum RtcmMessage {
+    Station(RtcmStation),
+    Antenna(RtcmAntenna),
+    // gps, galileo and beidou ephemerides on the gps time scale, each system with
+    // its own prn numbers
+    Ephemeris(GnssSystem, Box<SatelliteData>),
+    GlonassEphemeris(GlonassEphemeris),
+    Msm(RtcmMsm),
+    Unsupported(u16),
+}
+
+pub fn resolve_week(week: i32, modulus: i32, reference: i32) -> i32 {
+    // truncated week number to the full week closest to the reference
+    week + modulus * ((reference - week) as f64 / modulus as f64).round() as i32
+}
+
+fn normalize(week: i32, tow: f64) -> (i32, f64) {
+    let k = (tow / SECONDS_PER_WEEK).floor();
+    (week + k as i32, tow - k * SECONDS_PER_WEEK)
+}
+
+pub fn toc_week(week: i32, toe: f64, toc: f64) -> i32 {
+    // toc can sit in the week before or after toe
+    let dt = toc - toe;
+    if dt > SECONDS_PER_WEEK / 2. {
+        week - 1
+    } else if dt < -SECONDS_PER_WEEK / 2. {
+        week + 1
+    } else {
+        week
+    }
+}
+
+fn galileo_sisa(index: u64) -> f64 {
+    // signal in space accuracy (m), -1 when no accuracy prediction is available
+    match index {
+        0..=49 => index as f64 * 0.01,
+        50..=74 => 0.5 + (index - 50) as f64 * 0.02,
+        75..=99 => 1. + (index - 75) as f64 * 0.04,
+        100..=125 => 2. + (index - 100) as f64 * 0.16,
+        _ => -1.,
+    }
+}
+
+fn msm4_lock(indicator: u64) -> f64 {
+    // minimum lock time (sec) of the 4 bit indicator (DF402)
+    if indicator == 0 {
+        0.
+    } else {
+        p2(indicator as i32 + 4) / 1000.
+    }
+}
+
+fn msm4_indicator(lock_ms: f64) -> u64 {
+    if lock_ms < 32. {
+        0
+    } else {
+        ((lock_ms.log2().floor() as i64 - 4).clamp(1, 15)) as u64
+    }
+}
+
+fn msm7_lock(indicator: u64) -> f64 {
+    // minimum lock time (sec) of the extended 10 bit indicator (DF407)
+    let i = indicator as f64;
+    if indicator < 64 {
+        return i / 1000.;
+    }
+    let k = ((indicator - 64) / 32 + 1) as i32;
+    p2(k) * (i - 32. * k as f64) / 1000.
+}
+
+fn msm7_indicator(lock_ms: f64) -> u64 {
+    if lock_ms < 64. {
+        return lock_ms.max(0.) as u64;
+    }
+    let k = lock_ms.log2().floor() as i32 - 5;
+    if k > 20 {
+        return 704;
+    }
+    (lock_ms / p2(k)).floor() as u64 + 32 * k as u64
+}
+
+pub struct RtcmDecoder {
+    buffer: Vec<u8>,
+    pub gps_week: i32, // reference for truncated week numbers and msm epochs
+    pub leap_seconds: i32,
+    pub crc_errors: usize,
+    last_msm_time: Option<f64>,
+    locks: HashMap<(GnssSystem, usize, u8), f64>,
+}
+
+impl RtcmDecoder {
+    pub fn new(gps_week: i32) -> Self {
+        RtcmDecoder {
+            buffer: vec![],
+            gps_week,
+            leap_seconds: 18,
+            crc_errors: 0,
+            last_msm_time: None,
+            locks: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<RtcmMessage> {
+        // frames found in the stream so far, a bad crc drops the preamble byte and
+        // searches for the next one
+        self.buffer.extend_from_slice(bytes);
+        let mut messages = vec![];
+        let mut start = 0;
+        loop {
+            match self.buffer[start..].iter().position(|&b| b == PREAMBLE) {
+                Some(k) => start += k,
+                None => {
+                    start = self.buffer.len();
+                    break;
+                }
+            }
+            if self.buffer.len() < start + 3 {
+                break;
+            }
+            if self.buffer[start + 1] & 0xFC != 0 {
+                start += 1;
+                continue;
+            }
+            let length =
+                ((self.buffer[start + 1] as usize & 0x03) << 8) | self.buffer[start + 2] as usize;
+            if self.buffer.len() < start + length + 6 {
+                break;
+            }
+            let frame = &self.buffer[start..start + length + 6];
+            let crc = ((frame[length + 3] as u32) << 16)
+                | ((frame[length + 4] as u32) << 8)
+                | frame[length + 5] as u32;
+            if crc24q(&frame[..length + 3]) != crc {
+                self.crc_errors += 1;
+                start += 1;
+                continue;
+            }
+            let payload = frame[3..length + 3].to_vec();
+            if let Some(m) = self.decode(&payload) {
+                messages.push(m);
+            }
+            start += length + 6;
+        }
+        self.buffer.drain(..start);
+        messages
+    }
+
+    pub fn decode(&mut self, payload: &[u8]) -> Option<RtcmMessage> {
+        // one message payload without the frame
+        let mut r = BitReader::new(payload);
+        let number = r.u(12) as u16;
+        let message = match number {
+            1005 | 1006 => RtcmMessage::Station(decode_station(&mut r, number)),
+            1007 | 1008 | 1033 => RtcmMessage::Antenna(decode_antenna(&mut r, number)),
+            1019 => {
+                RtcmMessage::Ephemeris(GnssSystem::Gps, Box::new(decode_gps(&mut r, self.gps_week)))
+            }
+            1020 => RtcmMessage::GlonassEphemeris(decode_glonass(&mut r)),
+            1042 => RtcmMessage::Ephemeris(
+                GnssSystem::Beidou,
+                Box::new(decode_beidou(&mut r, self.gps_week)),
+            ),
+            1045 | 1046 => RtcmMessage::Ephemeris(
+                GnssSystem::Galileo,
+                Box::new(decode_galileo(&mut r, number, self.gps_week)),
+            ),
+            n if GnssSystem::from_msm(n).is_some() && (n % 10 == 4 || n % 10 == 7) => {
+                RtcmMessage::Msm(self.decode_msm(&mut r, n)?)
+            }
+            n => return Some(RtcmMessage::Unsupported(n)),
+        };
+        if r.overrun() {
+            return None;
+        }
+        Some(message)
+    }
+
+    fn decode_msm(&mut self, r: &mut BitReader, number: u16) -> Option<RtcmMsm> {
+        let system = GnssSystem::from_msm(number)?;
+        let msm7 = number % 10 == 7;
+        let station_id = r.u(12) as u16;
+        let epoch = r.u(30);
+        let multiple = r.u(1) == 1;
+        let iods = r.u(3) as u8;
+        r.u(7 + 2 + 2 + 1 + 3); // reserved, clock steering, external clock, smoothing
+
+        let satellites: Vec<usize> = (1..=64).filter(|_| r.u(1) == 1).collect();
+        let signals: Vec<u8> = (1..=32).filter(|_| r.u(1) == 1).collect();
+        if satellites.len() * signals.len() > 64 {
+            return None;
+        }
+        let mut cells = vec![];
+        for &sat in satellites.iter() {
+            for &sig in signals.iter() {
+                if r.u(1) == 1 {
+                    cells.push((sat, sig));
+                }
+            }
+        }
+
+        // satellite data, rough range (ms) and rate (m/s)
+        let n = satellites.len();
+        let rough_int: Vec<u64> = (0..n).map(|_| r.u(8)).collect();
+        let info: Vec<u8> = if msm7 {
+            (0..n).map(|_| r.u(4) as u8).collect()
+        } else {
+            vec![]
+        };
+        let rough_mod: Vec<f64> = (0..n).map(|_| r.uf(10, p2(-10))).collect();
+        let rough_rate: Vec<i64> = if msm7 {
+            (0..n).map(|_| r.s(14)).collect()
+        } else {
+            vec![]
+        };
+        let rough = |sat: usize| {
+            let i = satellites.iter().position(|&s| s == sat)?;
+            if rough_int[i] == 255 {
+                None
+            } else {
+                Some(rough_int[i] as f64 + rough_mod[i])
+            }
+        };
+
+        // signal data, fields are grouped over all cells
+        let m = cells.len();
+        let (pr_bits, pr_scale, cp_bits, cp_scale, lock_bits, cn0_bits, cn0_scale) = if msm7 {
+            (20, p2(-29), 24, p2(-31), 10, 10, p2(-4))
+        } else {
+            (15, p2(-24), 22, p2(-29), 4, 6, 1.)
+        };
+        let fine_pr: Vec<i64> = (0..m).map(|_| r.s(pr_bits)).collect();
+        let fine_cp: Vec<i64> = (0..m).map(|_| r.s(cp_bits)).collect();
+        let lock: Vec<u64> = (0..m).map(|_| r.u(lock_bits)).collect();
+        let half: Vec<bool> = (0..m).map(|_| r.u(1) == 1).collect();
+        let cn0: Vec<u64> = (0..m).map(|_| r.u(cn0_bits)).collect();
+        let fine_rate: Vec<i64> = if msm7 {
+            (0..m).map(|_| r.s(15)).collect()
+        } else {
+            vec![]
+        };
+
+        let mut out = vec![];
+        for (k, &(sat, sig)) in cells.iter().enumerate() {
+            let rough = rough(sat);
+            let valid = |v: i64, bits: usize| v != -(1 << (bits - 1));
+            let pseudorange = rough
+                .filter(|_| valid(fine_pr[k], pr_bits))
+                .map(|r| (r + fine_pr[k] as f64 * pr_scale) * RANGE_MS);
+            let phase_range = rough
+                .filter(|_| valid(fine_cp[k], cp_bits))
+                .map(|r| (r + fine_cp[k] as f64 * cp_scale) * RANGE_MS);
+            let range_rate = if msm7 {
+                let i = satellites.iter().position(|&s| s == sat).unwrap_or(0);
+                if rough_rate[i] != -8192 && valid(fine_rate[k], 15) {
+                    Some(rough_rate[i] as f64 + fine_rate[k] as f64 * 1e-4)
+                } else {
+                    None
+                }
+            } else {
+                None
+            };
+            let lock_time = if msm7 {
+                msm7_lock(lock[k])
+            } else {
+                msm4_lock(lock[k])
+            };
+            let previous = self.locks.insert((system, sat, sig), lock_time);
+            out.push(MsmCell {
+                satellite: sat,
+                signal: sig,
+                pseudorange,
+                phase_range,
+                range_rate,
+                lock_time,
+                half_cycle: half[k],
+                cn0: if cn0[k] == 0 {
+                    None
+                } else {
+                    Some(cn0[k] as f64 * cn0_scale)
+                },
+                lli: previous.is_some_and(|p| lock_time < p),
+            });
+        }
+
+        // epoch on the gps time scale, the week follows the reference and rolls over
+        // when the time of week wraps
+        let tow = match system {
+            GnssSystem::Glonass => {
+                let day = (epoch >> 27) as f64;
+                let tod = (epoch & 0x7FF_FFFF) as f64 / 1000.;
+                (day * 86400. + tod - 10800. + self.leap_seconds as f64)
+                    .rem_euclid(SECONDS_PER_WEEK)
+            }
+            GnssSystem::Beidou => (epoch as f64 / 1000. + 14.).rem_euclid(SECONDS_PER_WEEK),
+            _ => epoch as f64 / 1000.,
+        };
+        if let Some(last) = self.last_msm_time {
+            if tow - last < -SECONDS_PER_WEEK / 2. {
+                self.gps_week += 1;
+            }
+        }
+        self.last_msm_time = Some(tow);
+
+        Some(RtcmMsm {
+            system,
+            msm: if msm7 { 7 } else { 4 },
+            station_id,
+            gps_week: self.gps_week,
+            gps_time: tow,
+            multiple,
+            iods,
+            extended_info: satellites.iter().copied().zip(info).collect(),
+            cells: out,
+        })
+    }
+}
+
+fn decode_station(r: &mut BitReader, number: u16) -> RtcmStation {
+    let station_id = r.u(12) as u16;
+    let itrf_year = r.u(6) as u8;
+    let gps = r.u(1) == 1;
+    let glonass = r.u(1) == 1;
+    let galileo = r.u(1) == 1;
+    let reference_station = r.u(1) == 1;
+    let x = r.f(38, 1e-4);
+    let single_oscillator = r.u(1) == 1;
+    r.u(1);
+    let y = r.f(38, 1e-4);
+    let quarter_cycle = r.u(2) as u8;
+    let z = r.f(38, 1e-4);
+    let height = if number == 1006 {
+        Some(r.uf(16, 1e-4))
+    } else {
+        None
+    };
+    RtcmStation {
+        station_id,
+        itrf_year,
+        gps,
+        glonass,
+        galileo,
+        reference_station,
+        single_oscillator,
+        quarter_cycle,
+        position: Vector3::new(x, y, z),
+        height,
+    }
+}
+
+fn decode_antenna(r: &mut BitReader, number: u16) -> RtcmAntenna {
+    let mut antenna = RtcmAntenna {
+        station_id: r.u(12) as u16,
+        descriptor: r.string(),
+        setup_id: r.u(8) as u8,
+        ..RtcmAntenna::default()
+    };
+    if number != 1007 {
+        antenna.serial = r.string();
+    }
+    if number == 1033 {
+        antenna.receiver_type = r.string();
+        antenna.firmware = r.string();
+        antenna.receiver_serial = r.string();
+    }
+    antenna
+}
+
+fn decode_gps(r: &mut BitReader, reference_week: i32) -> SatelliteData {
+    // 1019, IS-GPS-200 scale factors, semicircles converted to radians
+    let prn = r.u(6) as usize;
+    let week = resolve_week(r.u(10) as i32, 1024, reference_week);
+    let ura = URA[r.u(4) as usize];
+    let l2_codes = r.u(2) as f64;
+    let idot = r.f(14, p2(-43)) * PI;
+    let iode = r.u(8) as f64;
+    let toc = r.uf(16, 16.);
+    let af2 = r.f(8, p2(-55));
+    let af1 = r.f(16, p2(-43));
+    let af0 = r.f(22, p2(-31));
+    let iodc = r.u(10) as f64;
+    let crs = r.f(16, p2(-5));
+    let delta_n = r.f(16, p2(-43)) * PI;
+    let m0 = r.f(32, p2(-31)) * PI;
+    let cuc = r.f(16, p2(-29));
+    let e = r.uf(32, p2(-33));
+    let cus = r.f(16, p2(-29));
+    let sqrt_a = r.uf(32, p2(-19));
+    let toe = r.uf(16, 16.);
+    let cic = r.f(16, p2(-29));
+    let raan = r.f(32, p2(-31)) * PI;
+    let cis = r.f(16, p2(-29));
+    let i0 = r.f(32, p2(-31)) * PI;
+    let crc = r.f(16, p2(-5));
+    let aop = r.f(32, p2(-31)) * PI;
+    let raandot = r.f(24, p2(-43)) * PI;
+    let tgd = r.f(8, p2(-31));
+    let health = r.u(6) as f64;
+    let l2_p = r.u(1) as f64;
+    let fit = if r.u(1) == 1 { 6. } else { 4. };
+    SatelliteData::from_broadcast(
+        prn,
+        toc_week(week, toe, toc),
+        toc,
+        [af0, af1, af2],
+        [
+            iode,
+            crs,
+            delta_n,
+            m0,
+            cuc,
+            e,
+            cus,
+            sqrt_a,
+            toe,
+            cic,
+            raan,
+            cis,
+            i0,
+            crc,
+            aop,
+            raandot,
+            idot,
+            l2_codes,
+            week as f64,
+            l2_p,
+            ura,
+            health,
+            tgd,
+            iodc,
+            0.,
+            fit,
+        ],
+    )
+}
+
+fn decode_galileo(r: &mut BitReader, number: u16, reference_week: i32) -> SatelliteData {
+    // 1045 f/nav and 1046 i/nav, gst weeks start at gps week 1024
+    let prn = r.u(6) as usize;
+    let week = resolve_week(r.u(12) as i32 + 1024, 4096, reference_week);
+    let iodnav = r.u(10) as f64;
+    let sisa = galileo_sisa(r.u(8));
+    let idot = r.f(14, p2(-43)) * PI;
+    let toc = r.uf(14, 60.);
+    let af2 = r.f(6, p2(-59));
+    let af1 = r.f(21, p2(-46));
+    let af0 = r.f(31, p2(-34));
+    let crs = r.f(16, p2(-5));
+    let delta_n = r.f(16, p2(-43)) * PI;
+    let m0 = r.f(32, p2(-31)) * PI;
+    let cuc = r.f(16, p2(-29));
+    let e = r.uf(32, p2(-33));
+    let cus = r.f(16, p2(-29));
+    let sqrt_a = r.uf(32, p2(-19));
+    let toe = r.uf(14, 60.);
+    let cic = r.f(16, p2(-29));
+    let raan = r.f(32, p2(-31)) * PI;
+    let cis = r.f(16, p2(-29));
+    let i0 = r.f(32, p2(-31)) * PI;
+    let crc = r.f(16, p2(-5));
+    let aop = r.f(32, p2(-31)) * PI;
+    let raandot = r.f(24, p2(-43)) * PI;
+    let bgd_e5a = r.f(10, p2(-32));
+
+    // rinex health bits: e1b dvs, e1b hs (2), e5a dvs, e5a hs (2), e5b dvs, e5b hs (2)
+    let (bgd_e5b, health, sources) = if number == 1045 {
+        let hs = r.u(2);
+        let dvs = r.u(1);
+        r.u(7);
+        (0., (hs << 4) | (dvs << 3), 258.)
+    } else {
+        let bgd_e5b = r.f(10, p2(-32));
+        let e5b_hs = r.u(2);
+        let e5b_dvs = r.u(1);
+        let e1_hs = r.u(2);
+        let e1_dvs = r.u(1);
+        r.u(2);
+        (
+            bgd_e5b,
+            (e5b_hs << 7) | (e5b_dvs << 6) | (e1_hs << 1) | e1_dvs,
+            517.,
+        )
+    };
+    SatelliteData::from_broadcast(
+        prn,
+        toc_week(week, toe, toc),
+        toc,
+        [af0, af1, af2],
+        [
+            iodnav,
+            crs,
+            delta_n,
+            m0,
+            cuc,
+            e,
+            cus,
+            sqrt_a,
+            toe,
+            cic,
+            raan,
+            cis,
+            i0,
+            crc,
+            aop,
+            raandot,
+            idot,
+            sources,
+            week as f64,
+            0.,
+            sisa,
+            health as f64,
+            bgd_e5a,
+            bgd_e5b,
+            0.,
+            0.,
+        ],
+    )
+}
+
+fn decode_beidou(r: &mut BitReader, reference_week: i32) -> SatelliteData {
+    // 1042, bdt weeks and times moved to gps time (bdt + 1356 weeks + 14 sec), the
+    // geostationary satellites (prn 1-5, 59-63) need the beidou geo rotation which
+    // state_at does not apply
+    let prn = r.u(6) as usize;
+    let week = resolve_week(r.u(13) as i32 + 1356, 8192, reference_week);
+    let ura = URA[r.u(4) as usize];
+    let idot = r.f(14, p2(-43)) * PI;
+    let aode = r.u(5) as f64;
+    let toc = r.uf(17, 8.);
+    let a2 = r.f(11, p2(-66));
+    let a1 = r.f(22, p2(-50));
+    let a0 = r.f(24, p2(-33));
+    let aodc = r.u(5) as f64;
+    let crs = r.f(18, p2(-6));
+    let delta_n = r.f(16, p2(-43)) * PI;
+    let m0 = r.f(32, p2(-31)) * PI;
+    let cuc = r.f(18, p2(-31));
+    let e = r.uf(32, p2(-33));
+    let cus = r.f(18, p2(-31));
+    let sqrt_a = r.uf(32, p2(-19));
+    let toe = r.uf(17, 8.);
+    let cic = r.f(18, p2(-31));
+    let raan = r.f(32, p2(-31)) * PI;
+    let cis = r.f(18, p2(-31));
+    let i0 = r.f(32, p2(-31)) * PI;
+    let crc = r.f(18, p2(-6));
+    let aop = r.f(32, p2(-31)) * PI;
+    let raandot = r.f(24, p2(-43)) * PI;
+    let tgd1 = r.f(10, 1e-10);
+    let tgd2 = r.f(10, 1e-10);
+    let health = r.u(1) as f64;
+
+    let (toe_week, toe) = normalize(week, toe + 14.);
+    let (toc_week, toc) = normalize(week, toc + 14.);
+    SatelliteData::from_broadcast(
+        prn,
+        toc_week,
+        toc,
+        [a0, a1, a2],
+        [
+            aode,
+            crs,
+            delta_n,
+            m0,
+            cuc,
+            e,
+            cus,
+            sqrt_a,
+            toe,
+            cic,
+            raan,
+            cis,
+            i0,
+            crc,
+            aop,
+            raandot,
+            idot,
+            0.,
+            toe_week as f64,
+            0.,
+            ura,
+            health,
+            tgd1,
+            tgd2,
+            0.,
+            aodc,
+        ],
+    )
+}
+
+fn decode_glonass(r: &mut BitReader) -> GlonassEphemeris {
+    let slot = r.u(6) as usize;
+    let frequency_channel = r.u(5) as i32 - 7;
+    r.u(2 + 2); // almanac health and availability, P1
+    let tk = r.uf(5, 3600.) + r.uf(6, 60.) + r.uf(1, 30.);
+    let health = r.u(1) as u8;
+    r.u(1); // P2
+    let tb = r.uf(7, 900.);
+    let mut position = Vector3::zeros();
+    let mut velocity = Vector3::zeros();
+    let mut acceleration = Vector3::zeros();
+    for k in 0..3 {
+        velocity[k] = r.smf(24, p2(-20)) * 1e3;
+        position[k] = r.smf(27, p2(-11)) * 1e3;
+        acceleration[k] = r.smf(5, p2(-30)) * 1e3;
+    }
+    r.u(1); // P3
+    let gamma = r.smf(11, p2(-40));
+    r.u(2 + 1); // P, ln
+    let tau = r.smf(22, p2(-30));
+    let delta_tau = r.smf(5, p2(-30));
+    let age = r.u(5) as u8;
+    r.u(1 + 4); // P4, FT
+    let day = r.u(11) as u16;
+    r.u(2 + 1 + 11 + 32 + 5 + 22 + 1 + 7); // M, additional data, NA, tau_c, N4, tau_gps, ln
+    GlonassEphemeris {
+        slot,
+        frequency_channel,
+        health,
+        tk,
+        tb,
+        position,
+        velocity,
+        acceleration,
+        gamma,
+        tau,
+        delta_tau,
+        age,
+        day,
+    }
+}
+
+pub fn encode_station(station: &RtcmStation) -> Vec<u8> {
+    let mut w = BitWriter::default();
+    w.u(12, if station.height.is_some() { 1006 } else { 1005 });
+    w.u(12, station.station_id as u64);
+    w.u(6, station.itrf_year as u64);
+    w.u(1, station.gps as u64);
+    w.u(1, station.glonass as u64);
+    w.u(1, station.galileo as u64);
+    w.u(1, station.reference_station as u64);
+    w.f(38, station.position[0], 1e-4);
+    w.u(1, station.single_oscillator as u64);
+    w.u(1, 0);
+    w.f(38, station.position[1], 1e-4);
+    w.u(2, station.quarter_cycle as u64);
+    w.f(38, station.position[2], 1e-4);
+    if let Some(h) = station.height {
+        w.uf(16, h, 1e-4);
+    }
+    encode_frame(&w.finish())
+}
+
+pub fn encode_antenna(antenna: &RtcmAntenna) -> Vec<u8> {
+    // the shortest message carrying every field that is set
+    let number = if !antenna.receiver_type.is_empty() {
+        1033
+    } else if !antenna.serial.is_empty() {
+        1008
+    } else {
+        1007
+    };
+    let mut w = BitWriter::default();
+    w.u(12, number);
+    w.u(12, antenna.station_id as u64);
+    w.string(&antenna.descriptor);
+    w.u(8, antenna.setup_id as u64);
+    if number != 1007 {
+        w.string(&antenna.serial);
+    }
+    if number == 1033 {
+        w.string(&antenna.receiver_type);
+        w.string(&antenna.firmware);
+        w.string(&antenna.receiver_serial);
+    }
+    encode_frame(&w.finish())
+}
+
+pub fn encode_gps_ephemeris(ephemeris: &SatelliteData) -> Vec<u8> {
+    // 1019
+    let (clock, o) = ephemeris.broadcast();
+    let ura = URA.iter().position(|&u| u >= o[20]).unwrap_or(15);
+    let mut w = BitWriter::default();
+    w.u(12, 1019);
+    w.u(6, ephemeris.prn() as u64);
+    w.u(10, (o[18] as i64).rem_euclid(1024) as u64);
+    w.u(4, ura as u64);
+    w.u(2, o[17] as u64);
+    w.f(14, o[16] / PI, p2(-43));
+    w.u(8, o[0] as u64);
+    w.uf(16, ephemeris.toc(), 16.);
+    w.f(8, clock[2], p2(-55));
+    w.f(16, clock[1], p2(-43));
+    w.f(22, clock[0], p2(-31));
+    w.u(10, o[23] as u64);
+    w.f(16, o[1], p2(-5));
+    w.f(16, o[2] / PI, p2(-43));
+    w.f(32, o[3] / PI, p2(-31));
+    w.f(16, o[4], p2(-29));
+    w.uf(32, o[5], p2(-33));
+    w.f(16, o[6], p2(-29));
+    w.uf(32, o[7], p2(-19));
+    w.uf(16, o[8], 16.);
+    w.f(16, o[9], p2(-29));
+    w.f(32, o[10] / PI, p2(-31));
+    w.f(16, o[11], p2(-29));
+    w.f(32, o[12] / PI, p2(-31));
+    w.f(16, o[13], p2(-5));
+    w.f(32, o[14] / PI, p2(-31));
+    w.f(24, o[15] / PI, p2(-43));
+    w.f(8, o[22], p2(-31));
+    w.u(6, o[21] as u64);
+    w.u(1, o[19] as u64);
+    w.u(1, (o[25] > 4.) as u64);
+    encode_frame(&w.finish())
+}
+
+pub struct RtcmEncoder {
+    pub station_id: u16,
+    pub iods: u8,
+    // whole cycles added to each carrier phase to keep the phase range near the
+    // code, the lock start time and the last time the signal was seen
+    tracks: HashMap<(usize, Band), (f64, f64, f64)>,
+}
+
+impl RtcmEncoder {
+    pub fn new(station_id: u16) -> Self {
+        RtcmEncoder {
+            station_id,
+            iods: 0,
+            tracks: HashMap::new(),
+        }
+    }
+
+    pub fn msm(&mut self, epoch: &ObservationEpoch, msm7: bool, multiple: bool) -> Vec<u8> {
+        // gps msm4 (1074) or msm7 (1077) frame from an observation epoch
+        let t = epoch.gps_week as f64 * SECONDS_PER_WEEK + epoch.gps_time;
+        let mut obs: Vec<&Observation> = epoch
+            .obs
+            .iter()
+            .filter(|o| o.pseudorange != 0. && (1..=64).contains(&o.prn))
+            .collect();
+        obs.sort_by_key(|o| (o.prn, gps_signal(o.band)));
+        obs.dedup_by_key(|o| (o.prn, o.band));
+
+        let mut satellites: Vec<usize> = obs.iter().map(|o| o.prn).collect();
+        satellites.dedup();
+        let mut signals: Vec<u8> = obs.iter().map(|o| gps_signal(o.band)).collect();
+        signals.sort();
+        signals.dedup();
+        // the cell mask is limited to 64 cells
+        while satellites.len() * signals.len() > 64 {
+            let last = satellites.pop().unwrap_or(0);
+            obs.retain(|o| o.prn != last);
+        }
+
+        let mut w = BitWriter::default();
+        w.u(12, if msm7 { 1077 } else { 1074 });
+        w.u(12, self.station_id as u64);
+        w.u(30, (epoch.gps_time * 1000.).round() as u64);
+        w.u(1, multiple as u64);
+        w.u(3, self.iods as u64);
+        w.u(7 + 2 + 2 + 1 + 3, 0);
+        let sat_mask = satellites.iter().fold(0u64, |m, &s| m | 1 << (64 - s));
+        w.u(64, sat_mask);
+        let sig_mask = signals.iter().fold(0u64, |m, &s| m | 1 << (32 - s));
+        w.u(32, sig_mask);
+        for &sat in satellites.iter() {
+            for &sig in signals.iter() {
+                let present = obs
+                    .iter()
+                    .any(|o| o.prn == sat && gps_signal(o.band) == sig);
+                w.u(1, present as u64);
+            }
+        }
+
+        // rough range from the first signal to 1/1024 ms, rough rate to 1 m/s
+        let first = |sat: usize| obs.iter().find(|o| o.prn == sat);
+        let rough: Vec<f64> = satellites
+            .iter()
+            .map(|&s| {
+                let pr = first(s).map(|o| o.pseudorange).unwrap_or(0.);
+                (pr / RANGE_MS * 1024.).round() / 1024.
+            })
+            .collect();
+        let rate = |o: &Observation| -o.doppler * o.band.wavelength();
+        let rough_rate: Vec<f64> = satellites
+            .iter()
+            .map(|&s| {
+                first(s)
+                    .filter(|o| o.doppler != 0.)
+                    .map(|o| rate(o).round())
+                    .unwrap_or(-8192.)
+            })
+            .collect();
+        for r in rough.iter() {
+            w.u(8, (r.floor() as u64).min(254));
+        }
+        if msm7 {
+            for _ in satellites.iter() {
+                w.u(4, 0);
+            }
+        }
+        for r in rough.iter() {
+            w.u(10, ((r - r.floor()) * 1024.).round() as u64);
+        }
+        if msm7 {
+            for r in rough_rate.iter() {
+                w.s(14, *r as i64);
+            }
+        }
+
+        // cell values
+        let (pr_bits, pr_scale, cp_bits, cp_scale, lock_bits, cn0_bits, cn0_scale) = if msm7 {
+            (20, p2(-29), 24, p2(-31), 10, 10, p2(-4))
+        } else {
+            (15, p2(-24), 22, p2(-29), 4, 6, 1.)
+        };
+        let fit = |v: f64, bits: usize| {
+            let limit = (1i64 << (bits - 1)) as f64;
+            if v.abs() < limit - 1. {
+                v.round() as i64
+            } else {
+                -(1i64 << (bits - 1))
+            }
+        };
+        let mut fine_pr = vec![];
+        let mut fine_cp = vec![];
+        let mut locks = vec![];
+        let mut cn0s = vec![];
+        let mut fine_rates = vec![];
+        for o in obs.iter() {
+            let i = satellites.iter().position(|&s| s == o.prn).unwrap_or(0);
+            let wavelength = o.band.wavelength();
+            fine_pr.push(fit(
+                (o.pseudorange / RANGE_MS - rough[i]) / pr_scale,
+                pr_bits,
+            ));
+
+            let key = (o.prn, o.band);
+            let (mut offset, mut start, last) =
+                self.tracks.get(&key).copied().unwrap_or((0., t, f64::MIN));
+            let phase = if o.carrier_phase != 0. {
+                let mut range = (o.carrier_phase + offset) * wavelength;
+                let drift = (range / RANGE_MS - rough[i]) / cp_scale;
+                if o.lli || t - last > 10. || drift.abs() >= (1i64 << (cp_bits - 2)) as f64 {
+                    offset = ((o.pseudorange - o.carrier_phase * wavelength) / wavelength).round();
+                    range = (o.carrier_phase + offset) * wavelength;
+                    start = t;
+                }
+                self.tracks.insert(key, (offset, start, t));
+                fit((range / RANGE_MS - rough[i]) / cp_scale, cp_bits)
+            } else {
+                self.tracks.remove(&key);
+                start = t;
+                -(1i64 << (cp_bits - 1))
+            };
+            fine_cp.push(phase);
+            let lock_ms = (t - start) * 1000.;
+            locks.push(if msm7 {
+                msm7_indicator(lock_ms)
+            } else {
+                msm4_indicator(lock_ms)
+            });
+            cn0s.push(
+                (o.cn0 / cn0_scale)
+                    .round()
+                    .clamp(0., ((1 << cn0_bits) - 1) as f64) as u64,
+            );
+            fine_rates.push(if o.doppler != 0. && rough_rate[i] != -8192. {
+                fit((rate(o) - rough_rate[i]) / 1e-4, 15)
+            } else {
+                -(1 << 14)
+            });
+        }
+        for v in fine_pr {
+            w.s(pr_bits, v);
+        }
+        for v in fine_cp {
+            w.s(cp_bits, v);
+        }
+        for v in locks {
+            w.u(lock_bits, v);
+        }
+        for _ in obs.iter() {
+            w.u(1, 0);
+        }
+        for v in cn0s {
+            w.u(cn0_bits, v);
+        }
+        if msm7 {
+            for v in fine_rates {
+                w.s(15, v);
+            }
+        }
+        encode_frame(&w.finish())
+    }
+}
+
+pub fn read_rtcm(filename: &str, gps_week: i32) -> Result<Vec<RtcmMessage>, Error> {
+    // every message in a binary rtcm 3 capture
+    let mut bytes = vec![];
+    File::open(filename)?.read_to_end(&mut bytes)?;
+    let mut decoder = RtcmDecoder::new(gps_week);
+    Ok(decoder.push(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn station() -> RtcmStation {
+        RtcmStation {
+            station_id: 1234,
+            itrf_year: 0,
+            gps: true,
+            glonass: true,
+            galileo: false,
+            reference_station: false,
+            single_oscillator: true,
+            quarter_cycle: 1,
+            position: Vector3::new(-1288398.5743, -4721697.0284, 4078625.5369),
+            height: Some(1.234),
+        }
+    }
+
+    fn epoch(gps_time: f64) -> ObservationEpoch {
+        // two bands for a few satellites, one of them l1 only
+        let mut obs = vec![];
+        for (i, prn) in [3usize, 8, 17, 22, 31].into_iter().enumerate() {
+            for band in [Band::L1, Band::L2] {
+                if prn == 22 && band == Band::L2 {
+                    continue;
+                }
+                let range = 2.05e7 + 7.3e5 * i as f64 + 123.456789 + 10. * gps_time;
+                obs.push(Observation {
+                    prn,
+                    band,
+                    pseudorange: range + if band == Band::L2 { 3.21 } else { 0. },
+                    carrier_phase: range / band.wavelength() + 1234.25 * (i + 1) as f64,
+                    doppler: -(10. + 97.3 * i as f64) / band.wavelength(),
+                    cn0: 38.3 + i as f64,
+                    lli: false,
+                });
+            }
+        }
+        ObservationEpoch {
+            gps_week: 2274,
+            gps_time,
+            obs,
+        }
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc24q(b"123456789"), 0xCDE703);
+        assert_eq!(crc24q(&[]), 0);
+    }
+
+    #[test]
+    fn station_round_trip() {
+        let mut decoder = RtcmDecoder::new(2274);
+        let mut without_height = station();
+        without_height.height = None;
+        let mut bytes = encode_station(&station());
+        bytes.extend(encode_station(&without_height));
+        let messages = decoder.push(&bytes);
+        assert_eq!(decoder.crc_errors, 0);
+        assert_eq!(messages.len(), 2);
+        for (m, expected) in messages.iter().zip([station(), without_height]) {
+            let RtcmMessage::Station(s) = m else {
+                panic!("{:?}", m);
+            };
+            assert_eq!(s.station_id, expected.station_id);
+            assert_eq!((s.gps, s.glonass, s.galileo), (true, true, false));
+            assert!(!s.reference_station && s.single_oscillator);
+            assert_eq!(s.quarter_cycle, 1);
+            assert!((s.position - expected.position).abs().max() < 0.5e-4);
+            match (s.height, expected.height) {
+                (Some(a), Some(b)) => assert!((a - b).abs() < 0.5e-4),
+                (a, b) => assert_eq!(a, b),
+            }
+        }
+    }
+
+    #[test]
+    fn gps_ephemeris_round_trip() {
+        let ephemerides = rinex2_nav_all("brdc2180.23n").unwrap();
+        let bytes: Vec<u8> = ephemerides.iter().flat_map(encode_gps_ephemeris).collect();
+        let mut decoder = RtcmDecoder::new(2274);
+        let messages = decoder.push(&bytes);
+        assert_eq!(decoder.crc_errors, 0);
+        assert_eq!(messages.len(), ephemerides.len());
+
+        // half of the least significant bit of each field, semicircles as radians
+        let sc = PI;
+        let orbit_lsb = [
+            1.,
+            p2(-5),
+            p2(-43) * sc,
+            p2(-31) * sc,
+            p2(-29),
+            p2(-33),
+            p2(-29),
+            p2(-19),
+            16.,
+            p2(-29),
+            p2(-31) * sc,
+            p2(-29),
+            p2(-31) * sc,
+            p2(-5),
+            p2(-31) * sc,
+            p2(-43) * sc,
+            p2(-43) * sc,
+            1.,
+            1.,
+            1.,
+        ];
+        for (m, original) in messages.iter().zip(ephemerides.iter()) {
+            let RtcmMessage::Ephemeris(GnssSystem::Gps, decoded) = m else {
+                panic!("{:?}", m);
+            };
+            assert_eq!(decoded.prn(), original.prn());
+            assert_eq!(decoded.toc_epoch(), original.toc_epoch());
+            let (c0, o0) = original.broadcast();
+            let (c1, o1) = decoded.broadcast();
+            for (k, lsb) in [p2(-31), p2(-43), p2(-55)].iter().enumerate() {
+                assert!(
+                    (c0[k] - c1[k]).abs() <= lsb / 2.,
+                    "prn {} af{}",
+                    original.prn(),
+                    k
+                );
+            }
+            for (k, lsb) in orbit_lsb.iter().enumerate() {
+                assert!(
+                    (o0[k] - o1[k]).abs() <= lsb / 2. + 1e-12 * o0[k].abs(),
+                    "prn {} orbit {} {} {}",
+                    original.prn(),
+                    k,
+                    o0[k],
+                    o1[k]
+                );
+            }
+            let ura = URA.iter().find(|&&u| u >= o0[20]).unwrap_or(&URA[15]);
+            assert_eq!(o1[20], *ura);
+            assert_eq!((o1[21], o1[23]), (o0[21], o0[23]));
+            assert!((o0[22] - o1[22]).abs() <= p2(-32));
+            assert_eq!(o1[25], if o0[25] > 4. { 6. } else { 4. });
+        }
+    }
+
+    fn msm_round_trip(msm7: bool) {
+        let mut encoder = RtcmEncoder::new(2003);
+        let mut decoder = RtcmDecoder::new(2274);
+        let epochs: Vec<ObservationEpoch> = (0..3).map(|k| epoch(7300. + k as f64)).collect();
+        let bytes: Vec<u8> = epochs
+            .iter()
+            .flat_map(|e| encoder.msm(e, msm7, false))
+            .collect();
+        let messages = decoder.push(&bytes);
+        assert_eq!(decoder.crc_errors, 0);
+        assert_eq!(messages.len(), epochs.len());
+
+        // pseudorange, phase and doppler resolution of the cells
+        let (pr_tol, cp_tol, cn0_tol) = if msm7 {
+            (p2(-30) * RANGE_MS, p2(-32) * RANGE_MS, p2(-5))
+        } else {
+            (p2(-25) * RANGE_MS, p2(-30) * RANGE_MS, 0.5)
+        };
+        let mut offsets = HashMap::new();
+        for (m, original) in messages.iter().zip(epochs.iter()) {
+            let RtcmMessage::Msm(msm) = m else {
+                panic!("{:?}", m);
+            };
+            assert_eq!(msm.msm, if msm7 { 7 } else { 4 });
+            assert_eq!(msm.station_id, 2003);
+            let decoded = msm.observations().unwrap();
+            assert_eq!(
+                (decoded.gps_week, decoded.gps_time),
+                (2274, original.gps_time)
+            );
+            assert_eq!(decoded.obs.len(), original.obs.len());
+            for o in original.obs.iter() {
+                let d = decoded.get(o.prn, o.band).unwrap();
+                let wavelength = o.band.wavelength();
+                assert!((d.pseudorange - o.pseudorange).abs() <= pr_tol + 1e-6);
+                // whole cycles added by the encoder stay fixed while the lock holds
+                let cycles = d.carrier_phase - o.carrier_phase;
+                assert!((cycles - cycles.round()).abs() * wavelength <= cp_tol + 1e-6);
+                let offset = *offsets.entry((o.prn, o.band)).or_insert(cycles.round());
+                assert_eq!(cycles.round(), offset);
+                assert!((d.cn0 - o.cn0).abs() <= cn0_tol);
+                assert!(!d.lli);
+                if msm7 {
+                    assert!((d.doppler - o.doppler).abs() * wavelength <= 0.5e-4 + 1e-9);
+                } else {
+                    assert_eq!(d.doppler, 0.);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn msm4_round_trip() {
+        msm_round_trip(false);
+    }
+
+    #[test]
+    fn msm7_round_trip() {
+        msm_round_trip(true);
+    }
+
+    #[test]
+    fn resync_after_bad_frames() {
+        // junk, a false preamble whose length runs into the next frame, a good station,
+        // a station with a corrupted crc and a final good station
+        let good = encode_station(&station());
+        // a preamble inside the corrupted frame could claim a length that holds back the
+        // frames after it until that many bytes arrive
+        assert!(!good[1..].contains(&PREAMBLE));
+        let mut bad = good.clone();
+        *bad.last_mut().unwrap() ^= 0x01;
+        let mut bytes = vec![0x00, 0xFF, PREAMBLE, 0x00, 0x02, 0x55];
+        bytes.extend(&good);
+        bytes.extend(&bad);
+        bytes.extend(&good);
+
+        let mut decoder = RtcmDecoder::new(2274);
+        let messages: Vec<RtcmMessage> = bytes.iter().flat_map(|b| decoder.push(&[*b])).collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages
+            .iter()
+            .all(|m| matches!(m, RtcmMessage::Station(_))));
+        assert_eq!(decoder.crc_errors, 2);
+
+        let mut whole = RtcmDecoder::new(2274);
+        assert_eq!(whole.push(&bytes).len(), 2);
+        assert_eq!(whole.crc_errors, decoder.crc_errors);
+    }
+}
@@ -0,0 +1,540 @@
+use crate::earth::*;
+use crate::lambda::*;
+use crate::observables::*;
+use crate::rinex_obs::*;
+use crate::satellites::*;
+use crate::smoothing::*;
+use crate::solver::*;
+use nalgebra::*;
+use std::io::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FixStatus {
+    Float,
+    Fixed,
+}
+
+#[derive(Clone, Debug)]
+pub struct RtkConfig {
+    pub bands: Vec<Band>,
+    pub code_sigma: f64,      // zenith undifferenced code sigma (m)
+    pub phase_sigma: f64,     // zenith undifferenced phase sigma (m)
+    pub position_noise: f64,  // m^2/s, 0 for a static rover
+    pub ambiguity_sigma: f64, // initial single difference ambiguity sigma (cycles)
+    pub ratio_threshold: f64,
+    pub min_fix: usize, // fewest double differences allowed in a partial fix
+    pub fix_and_hold: bool,
+    pub hold_sigma: f64, // cycles
+    pub slip: SlipConfig,
+    pub solver: SolverConfig,
+}
+
+impl Default for RtkConfig {
+    fn default() -> Self {
+        // atmosphere cancels in the double differences on short baselines
+        RtkConfig {
+            bands: vec![Band::L1],
+            code_sigma: 0.3,
+            phase_sigma: 0.003,
+            position_noise: 0.,
+            ambiguity_sigma: 30.,
+            ratio_threshold: 3.,
+            min_fix: 4,
+            fix_and_hold: true,
+            hold_sigma: 0.001,
+            slip: SlipConfig::default(),
+            solver: SolverConfig {
+                elevation_mask: 15.,
+                troposphere: false,
+                ionosphere: None,
+                ..SolverConfig::default()
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RtkSolution {
+    pub gps_week: i32,
+    pub gps_time: f64,
+    pub status: FixStatus,
+    pub position: Vector3<f64>,     // rover ecef (m)
+    pub baseline: Vector3<f64>,     // rover - base, ecef (m)
+    pub baseline_enu: Vector3<f64>, // east, north, up at the base (m)
+    pub covariance: Matrix3<f64>,   // ecef baseline
+    pub ratio: f64,                 // second best / best lambda distance
+    pub fixed: usize,               // double difference ambiguities fixed
+    pub satellites: usize,          // common satellites used
+}
+
+type AmbiguityKey = (usize, Band);
+
+pub struct RtkEngine {
+    config: RtkConfig,
+    base: Vector3<f64>,
+    x: DVector<f64>, // rover position then single difference ambiguities (cycles)
+    p: DMatrix<f64>,
+    keys: Vec<AmbiguityKey>,
+    gps_time: f64,
+    initialized: bool,
+    rover_slips: CycleSlipDetector,
+    base_slips: CycleSlipDetector,
+}
+
+struct Common {
+    prn: usize,
+    elevation: f64,
+    los: Vector3<f64>, // rover to satellite
+    range: f64,        // rover minus base geometric range (m)
+}
+
+impl RtkEngine {
+    pub fn new(base: Vector3<f64>, config: RtkConfig) -> Self {
+        let slip = config.slip.clone();
+        RtkEngine {
+            config,
+            base,
+            x: DVector::zeros(3),
+            p: DMatrix::zeros(3, 3),
+            keys: vec![],
+            gps_time: 0.,
+            initialized: false,
+            rover_slips: CycleSlipDetector::new(slip.clone()),
+            base_slips: CycleSlipDetector::new(slip),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.x = DVector::zeros(3);
+        self.p = DMatrix::zeros(3, 3);
+        self.keys.clear();
+        self.initialized = false;
+        self.rover_slips.reset();
+        self.base_slips.reset();
+    }
+
+    pub fn position(&self) -> Vector3<f64> {
+        Vector3::new(self.x[0], self.x[1], self.x[2])
+    }
+
+    pub fn update(
+        &mut self,
+        rover: &ObservationEpoch,
+        base: &ObservationEpoch,
+        ephemerides: &[SatelliteData],
+    ) -> Option<RtkSolution> {
+        // one epoch of double differenced code and phase, rover and base epochs must
+        // share the same receive time
+        if !self.initialized {
+            let fix = solve_position(rover, ephemerides, Vector3::zeros(), &self.config.solver)?;
+            self.x.fixed_rows_mut::<3>(0).copy_from(&fix.position);
+            self.p = DMatrix::from_diagonal_element(3, 3, 30f64.powi(2));
+            self.gps_time = rover.gps_time;
+            self.initialized = true;
+        }
+        let dt = wrap_week(rover.gps_time - self.gps_time).abs();
+        self.gps_time = rover.gps_time;
+        for i in 0..3 {
+            self.p[(i, i)] += self.config.position_noise * dt;
+        }
+
+        let common = self.common_satellites(rover, base, ephemerides);
+        if common.len() < 4 {
+            return None;
+        }
+
+        // restart ambiguities of satellites that slipped at either receiver
+        let mut slipped: Vec<usize> = self
+            .rover_slips
+            .detect(rover)
+            .into_iter()
+            .chain(self.base_slips.detect(base))
+            .map(|(prn, _)| prn)
+            .collect();
+        slipped.sort();
+        slipped.dedup();
+        self.update_ambiguity_states(rover, base, &common, &slipped);
+
+        let (y, h, r) = self.measurements(rover, base, &common);
+        if y.is_empty() {
+            return None;
+        }
+        let s = &h * &self.p * h.transpose() + r;
+        let s_inv = s.try_inverse()?;
+        let k = &self.p * h.transpose() * s_inv;
+        self.x += &k * y;
+        let n = self.x.len();
+        self.p = (DMatrix::identity(n, n) - &k * &h) * &self.p;
+        self.p = (&self.p + self.p.transpose()) / 2.;
+
+        let (status, position, covariance, ratio, fixed) = self.resolve(&common);
+        let baseline = position - self.base;
+        Some(RtkSolution {
+            gps_week: rover.gps_week,
+            gps_time: rover.gps_time,
+            status,
+            position,
+            baseline,
+            baseline_enu: ecef2enu(position, self.base, 0),
+            covariance,
+            ratio,
+            fixed,
+            satellites: common.len(),
+        })
+    }
+
+    fn common_satellites(
+        &self,
+        rover: &ObservationEpoch,
+        base: &ObservationEpoch,
+        ephemerides: &[SatelliteData],
+    ) -> Vec<Common> {
+        let config = &self.config.solver;
+        let at_rover = satellite_geometry(rover, ephemerides, self.position(), Band::L1, config);
+        let at_base = satellite_geometry(base, ephemerides, self.base, Band::L1, config);
+        at_rover
+            .iter()
+            .filter_map(|g| {
+                let b = at_base.iter().find(|b| b.prn == g.prn)?;
+                Some(Common {
+                    prn: g.prn,
+                    elevation: g.elevation,
+                    los: g.los,
+                    range: g.range - b.range,
+                })
+            })
+            .collect()
+    }
+
+    fn single_difference(
+        rover: &ObservationEpoch,
+        base: &ObservationEpoch,
+        prn: usize,
+        band: Band,
+    ) -> Option<(f64, f64)> {
+        // rover minus base phase (m) and code (m), when both are available
+        let r = rover.get(prn, band)?;
+        let b = base.get(prn, band)?;
+        if r.carrier_phase == 0.
+            || b.carrier_phase == 0.
+            || r.pseudorange == 0.
+            || b.pseudorange == 0.
+        {
+            return None;
+        }
+        Some((
+            (r.carrier_phase - b.carrier_phase) * band.wavelength(),
+            r.pseudorange - b.pseudorange,
+        ))
+    }
+
+    fn update_ambiguity_states(
+        &mut self,
+        rover: &ObservationEpoch,
+        base: &ObservationEpoch,
+        common: &[Common],
+        slipped: &[usize],
+    ) {
+        let mut observed = vec![];
+        for &band in self.config.bands.iter() {
+            for c in common.iter() {
+                if let Some((phase, code)) = Self::single_difference(rover, base, c.prn, band) {
+                    observed.push(((c.prn, band), (phase - code) / band.wavelength()));
+                }
+            }
+        }
+
+        // drop states that are no longer observed or have slipped
+        let keep: Vec<usize> = (0..self.keys.len())
+            .filter(|&i| {
+                let key = self.keys[i];
+                observed.iter().any(|(k, _)| *k == key) && !slipped.contains(&key.0)
+            })
+            .collect();
+        let mut index: Vec<usize> = (0..3).collect();
+        index.extend(keep.iter().map(|i| i + 3));
+        self.x = DVector::from_iterator(index.len(), index.iter().map(|&i| self.x[i]));
+        self.p = self.p.select_rows(&index).select_columns(&index);
+        self.keys = keep.iter().map(|&i| self.keys[i]).collect();
+
+        // new ambiguities start from phase minus code
+        let variance = self.config.ambiguity_sigma.powi(2);
+        for (key, value) in observed {
+            if self.keys.contains(&key) {
+                continue;
+            }
+            let n = self.x.len();
+            self.x = self.x.clone().insert_row(n, value);
+            self.p = self.p.clone().insert_row(n, 0.).insert_column(n, 0.);
+            self.p[(n, n)] = variance;
+            self.keys.push(key);
+        }
+    }
+
+    fn reference(&self, common: &[Common], band: Band) -> Option<usize> {
+        // highest satellite with an ambiguity on this band
+        common
+            .iter()
+            .filter(|c| self.keys.contains(&(c.prn, band)))
+            .max_by(|a, b| a.elevation.total_cmp(&b.elevation))
+            .map(|c| c.prn)
+    }
+
+    fn measurements(
+        &self,
+        rover: &ObservationEpoch,
+        base: &ObservationEpoch,
+        common: &[Common],
+    ) -> (DVector<f64>, DMatrix<f64>, DMatrix<f64>) {
+        // double difference phase and code innovations against ranges at the current
+        // rover estimate, with the design and the correlated covariance
+        let n = self.x.len();
+        let mut y = vec![];
+        let mut h: Vec<DVector<f64>> = vec![];
+        let mut blocks: Vec<(Vec<f64>, f64)> = vec![]; // sd variances and the reference's
+
+        for &band in self.config.bands.iter() {
+            let reference = match self.reference(common, band) {
+                Some(r) => r,
+                None => continue,
+            };
+            let rc = common.iter().find(|c| c.prn == reference).unwrap();
+            let (r_phase, r_code) = Self::single_difference(rover, base, reference, band).unwrap();
+            let r_amb = self
+                .keys
+                .iter()
+                .position(|k| *k == (reference, band))
+                .unwrap()
+                + 3;
+
+            for (sigma, is_phase) in [
+                (self.config.phase_sigma, true),
+                (self.config.code_sigma, false),
+            ] {
+                let sd_var = |el: f64| 2. * sigma * sigma * elevation_weight(el);
+                let mut variances = vec![];
+                for c in common.iter().filter(|c| c.prn != reference) {
+                    let amb = match self.keys.iter().position(|k| *k == (c.prn, band)) {
+                        Some(i) => i + 3,
+                        None => continue,
+                    };
+                    let (phase, code) = Self::single_difference(rover, base, c.prn, band).unwrap();
+                    let mut row = DVector::zeros(n);
+                    row.fixed_rows_mut::<3>(0).copy_from(&(rc.los - c.los));
+                    let mut predicted = c.range - rc.range;
+                    let measured = if is_phase {
+                        let lambda = band.wavelength();
+                        row[amb] = lambda;
+                        row[r_amb] = -lambda;
+                        predicted += lambda * (self.x[amb] - self.x[r_amb]);
+                        phase - r_phase
+                    } else {
+                        code - r_code
+                    };
+                    y.push(measured - predicted);
+                    h.push(row);
+                    variances.push(sd_var(c.elevation));
+                }
+                blocks.push((variances, sd_var(rc.elevation)));
+            }
+        }
+
+        let m = y.len();
+        let mut hm = DMatrix::zeros(m, n);
+        for (i, row) in h.iter().enumerate() {
+            hm.set_row(i, &row.transpose());
+        }
+        let mut r = DMatrix::zeros(m, m);
+        let mut offset = 0;
+        for (variances, reference) in blocks {
+            let k = variances.len();
+            for i in 0..k {
+                for j in 0..k {
+                    r[(offset + i, offset + j)] = reference;
+                }
+                r[(offset + i, offset + i)] += variances[i];
+            }
+            offset += k;
+        }
+        (DVector::from_vec(y), hm, r)
+    }
+
+    fn resolve(
+        &mut self,
+        common: &[Common],
+    ) -> (FixStatus, Vector3<f64>, Matrix3<f64>, f64, usize) {
+        // lambda on the double difference ambiguities, dropping the lowest satellites
+        // until the ratio test passes (partial fixing)
+        let float_position = self.position();
+        let float_cov = self.p.fixed_view::<3, 3>(0, 0).clone_owned();
+
+        // double differences as (ambiguity, reference ambiguity, elevation)
+        let mut pairs = vec![];
+        for &band in self.config.bands.iter() {
+            if let Some(reference) = self.reference(common, band) {
+                let r = self
+                    .keys
+                    .iter()
+                    .position(|k| *k == (reference, band))
+                    .unwrap()
+                    + 3;
+                for c in common.iter().filter(|c| c.prn != reference) {
+                    if let Some(i) = self.keys.iter().position(|k| *k == (c.prn, band)) {
+                        pairs.push((i + 3, r, c.elevation));
+                    }
+                }
+            }
+        }
+        pairs.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+        let n = self.x.len();
+        let mut best_ratio = 0.;
+        while pairs.len() >= self.config.min_fix.max(1) {
+            let m = pairs.len();
+            let mut d = DMatrix::zeros(m, n);
+            for (row, &(i, r, _)) in pairs.iter().enumerate() {
+                d[(row, i)] = 1.;
+                d[(row, r)] = -1.;
+            }
+            let a = &d * &self.x;
+            let qa = &d * &self.p * d.transpose();
+            let qba = self.p.rows(0, 3) * d.transpose();
+
+            if let Some((fixes, s)) = lambda(&a, &qa, 2) {
+                let ratio = if s.len() > 1 && s[0] > 0. {
+                    s[1] / s[0]
+                } else {
+                    0.
+                };
+                best_ratio = f64::max(best_ratio, ratio);
+                if ratio >= self.config.ratio_threshold {
+                    let qa_inv = match qa.clone().try_inverse() {
+                        Some(q) => q,
+                        None => break,
+                    };
+                    let da = &a - &fixes[0];
+                    let db = &qba * &qa_inv * &da;
+                    let position = float_position - Vector3::new(db[0], db[1], db[2]);
+                    let cov =
+                        float_cov - (&qba * &qa_inv * qba.transpose()).fixed_view::<3, 3>(0, 0);
+
+                    if self.config.fix_and_hold {
+                        self.hold(&d, &fixes[0]);
+                    }
+                    return (FixStatus::Fixed, position, cov, ratio, m);
+                }
+            }
+            pairs.pop();
+        }
+        (FixStatus::Float, float_position, float_cov, best_ratio, 0)
+    }
+
+    fn hold(&mut self, d: &DMatrix<f64>, fixed: &DVector<f64>) {
+        // constrains the float ambiguities to the validated integers
+        let m = d.nrows();
+        let r = DMatrix::from_diagonal_element(m, m, self.config.hold_sigma.powi(2));
+        let s = d * &self.p * d.transpose() + r;
+        if let Some(s_inv) = s.try_inverse() {
+            let k = &self.p * d.transpose() * s_inv;
+            self.x += &k * (fixed - d * &self.x);
+            let n = self.x.len();
+            self.p = (DMatrix::identity(n, n) - &k * d) * &self.p;
+            self.p = (&self.p + self.p.transpose()) / 2.;
+        }
+    }
+}
+
+pub fn rtk_rinex(
+    base_file: &str,
+    rover_file: &str,
+    ephemerides: &[SatelliteData],
+    base_position: Vector3<f64>,
+    config: &RtkConfig,
+) -> Result<Vec<RtkSolution>, Error> {
+    // post processes a base and rover observation file pair, rover epochs without a
+    // base epoch at the same time are skipped
+    let (_, base_epochs) = read_rinex_obs(base_file)?;
+    let mut engine = RtkEngine::new(base_position, config.clone());
+    let mut base_epochs = base_epochs.into_iter().peekable();
+    let mut solutions = vec![];
+
+    for rover in RinexObsReader::open(rover_file)? {
+        let rover = rover?;
+        while base_epochs
+            .peek()
+            .is_some_and(|b| wrap_week(b.gps_time - rover.gps_time) < -0.005)
+        {
+            base_epochs.next();
+        }
+        let base = match base_epochs.peek() {
+            Some(b) if wrap_week(b.gps_time - rover.gps_time).abs() <= 0.005 => b,
+            _ => continue,
+        };
+        if let Some(s) = engine.update(&rover, base, ephemerides) {
+            solutions.push(s);
+        }
+    }
+    Ok(solutions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn epoch(ephs: &[SatelliteData], rx: Vector3<f64>, clk: f64, t: f64) -> ObservationEpoch {
+        // zero noise l1 code and phase, each satellite carries an integer ambiguity
+        let mut obs = vec![];
+        for prn in 1..33 {
+            let Some(e) = select_ephemeris(ephs, prn, t) else {
+                continue;
+            };
+            let mut tau = 0.07;
+            let mut sv = e.state_at(t - tau);
+            for _ in 0..5 {
+                sv = sagnac_rotate(&e.state_at(t - clk / SPEED_OF_LIGHT - tau), tau);
+                tau = (sv.position - rx).norm() / SPEED_OF_LIGHT;
+            }
+            if look_angles(rx, sv.position).1 < 15. {
+                continue;
+            }
+            let range = (sv.position - rx).norm() + clk - sv.clock_bias * SPEED_OF_LIGHT;
+            obs.push(Observation {
+                prn,
+                band: Band::L1,
+                pseudorange: range,
+                carrier_phase: range / Band::L1.wavelength() + (prn * 1000) as f64,
+                doppler: 0.,
+                cn0: 45.,
+                lli: false,
+            });
+        }
+        ObservationEpoch {
+            gps_week: 2274,
+            gps_time: t,
+            obs,
+        }
+    }
+
+    #[test]
+    fn fixes_short_baseline() {
+        let ephs = rinex2_nav_all("brdc2180.23n").unwrap();
+        let base = geodetic2ecef(Vector3::new(40., -105., 1600.), 0);
+        let baseline = Vector3::new(1234.567, -2345.678, 12.345);
+        let rover = enu2ecef(baseline, base, 0);
+        let mut engine = RtkEngine::new(base, RtkConfig::default());
+        let mut last = None;
+        for k in 0..10 {
+            let t = 7300. + k as f64;
+            let r = epoch(&ephs, rover, 3e-4 * SPEED_OF_LIGHT, t);
+            let b = epoch(&ephs, base, -2e-5 * SPEED_OF_LIGHT, t);
+            assert!(r.obs.len() >= 6);
+            last = engine.update(&r, &b, &ephs);
+        }
+        let solution = last.unwrap();
+        assert_eq!(solution.status, FixStatus::Fixed);
+        assert!(solution.ratio >= 3.);
+        assert_eq!(solution.fixed, solution.satellites - 1);
+        assert!((solution.position - rover).norm() < 1e-3);
+        assert!((solution.baseline_enu - baseline).norm() < 1e-3);
+    }
+}
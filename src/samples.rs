@@ -0,0 +1,378 @@
+use nalgebra::*;
+use rustfft::num_complex::Complex;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    Int8,       // signed 8 bit (hackrf, usrp sc8)
+    Uint8,      // offset binary 8 bit (rtl-sdr)
+    Int16,      // signed 16 bit (usrp sc16)
+    Float32,    // ieee single (gnu radio complex)
+    Float64,    // ieee double
+    Packed2Bit, // sign/magnitude, four samples per byte, msb first (max2769)
+    Packed4Bit, // two's complement, two samples per byte, high nibble first
+}
+
+impl SampleFormat {
+    pub fn bits(&self) -> usize {
+        match self {
+            SampleFormat::Int8 | SampleFormat::Uint8 => 8,
+            SampleFormat::Int16 => 16,
+            SampleFormat::Float32 => 32,
+            SampleFormat::Float64 => 64,
+            SampleFormat::Packed2Bit => 2,
+            SampleFormat::Packed4Bit => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+#[derive(Clone, Debug)]
+pub struct SampleConfig {
+    pub format: SampleFormat,
+    pub complex: bool, // interleaved I/Q when true
+    pub iq_swap: bool, // Q stored before I
+    pub endianness: Endianness,
+    pub sample_rate: f64,       // Hz
+    pub intermediate_freq: f64, // Hz, 0 for baseband I/Q
+    pub skip_bytes: u64,        // header or settling data to skip at the start of the file
+}
+
+impl SampleConfig {
+    pub fn new(
+        format: SampleFormat,
+        complex: bool,
+        sample_rate: f64,
+        intermediate_freq: f64,
+    ) -> Self {
+        SampleConfig {
+            format,
+            complex,
+            iq_swap: false,
+            endianness: Endianness::Little,
+            sample_rate,
+            intermediate_freq,
+            skip_bytes: 0,
+        }
+    }
+
+    pub fn components(&self) -> usize {
+        if self.complex {
+            2
+        } else {
+            1
+        }
+    }
+
+    pub fn bytes_per_sample(&self) -> f64 {
+        (self.format.bits() * self.components()) as f64 / 8.
+    }
+
+    pub fn sample_period(&self) -> f64 {
+        1. / self.sample_rate
+    }
+
+    pub fn samples_per_code(&self) -> usize {
+        // samples in one 1 ms c/a code period
+        (self.sample_rate * 1e-3).round() as usize
+    }
+}
+
+pub struct SampleReader {
+    reader: BufReader<File>,
+    config: SampleConfig,
+    pending: VecDeque<f64>, // decoded components not yet returned
+    samples_read: u64,
+}
+
+impl SampleReader {
+    pub fn open(filename: &str, config: SampleConfig) -> Result<SampleReader, Error> {
+        let mut file = File::open(filename)?;
+        file.seek(SeekFrom::Start(config.skip_bytes))?;
+        Ok(SampleReader {
+            reader: BufReader::with_capacity(1 << 20, file),
+            config,
+            pending: VecDeque::new(),
+            samples_read: 0,
+        })
+    }
+
+    pub fn config(&self) -> &SampleConfig {
+        &self.config
+    }
+
+    pub fn samples_read(&self) -> u64 {
+        self.samples_read
+    }
+
+    pub fn time(&self) -> f64 {
+        // time (sec) of the next sample relative to the first sample after the skip
+        self.samples_read as f64 / self.config.sample_rate
+    }
+
+    pub fn total_samples(&self) -> Result<u64, Error> {
+        let len = self.reader.get_ref().metadata()?.len();
+        let bytes = len.saturating_sub(self.config.skip_bytes) as f64;
+        Ok((bytes / self.config.bytes_per_sample()).floor() as u64)
+    }
+
+    pub fn seek_sample(&mut self, sample: u64) -> Result<(), Error> {
+        // packed formats can only seek to sample indices that start on a byte
+        let bits = sample * (self.config.format.bits() * self.config.components()) as u64;
+        if !bits.is_multiple_of(8) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "sample index does not fall on a byte boundary",
+            ));
+        }
+        self.reader
+            .seek(SeekFrom::Start(self.config.skip_bytes + bits / 8))?;
+        self.pending.clear();
+        self.samples_read = sample;
+        Ok(())
+    }
+
+    pub fn skip_seconds(&mut self, seconds: f64) -> Result<(), Error> {
+        let target = self.samples_read + (seconds * self.config.sample_rate).round() as u64;
+        self.seek_sample(target)
+    }
+
+    pub fn read_block(&mut self, n: usize) -> Result<DVector<Complex<f64>>, Error> {
+        // reads up to n samples, returning fewer only at the end of the file
+        let needed = n * self.config.components();
+        let bits = self.config.format.bits();
+        while self.pending.len() < needed {
+            let missing = needed - self.pending.len();
+            let mut buffer = vec![0u8; (missing * bits).div_ceil(8)];
+            let count = read_full(&mut self.reader, &mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            self.decode(&buffer[..count]);
+        }
+
+        let available = self.pending.len() / self.config.components();
+        let n = n.min(available);
+        let mut block = DVector::from_element(n, Complex::new(0., 0.));
+        for i in 0..n {
+            let first = self.pending.pop_front().unwrap();
+            block[i] = if self.config.complex {
+                let second = self.pending.pop_front().unwrap();
+                if self.config.iq_swap {
+                    Complex::new(second, first)
+                } else {
+                    Complex::new(first, second)
+                }
+            } else {
+                Complex::new(first, 0.)
+            };
+        }
+        self.samples_read += n as u64;
+        Ok(block)
+    }
+
+    pub fn read_block_real(&mut self, n: usize) -> Result<DVector<f64>, Error> {
+        // in-phase component only, matching the real valued acquisition routines
+        Ok(self.read_block(n)?.map(|s| s.re))
+    }
+
+    pub fn blocks(&mut self, n: usize) -> SampleBlocks<'_> {
+        SampleBlocks {
+            reader: self,
+            block_size: n,
+        }
+    }
+
+    fn decode(&mut self, bytes: &[u8]) {
+        let big = self.config.endianness == Endianness::Big;
+        match self.config.format {
+            SampleFormat::Int8 => self.pending.extend(bytes.iter().map(|&b| b as i8 as f64)),
+            SampleFormat::Uint8 => self.pending.extend(bytes.iter().map(|&b| b as f64 - 127.5)),
+            SampleFormat::Int16 => self.pending.extend(bytes.chunks_exact(2).map(|c| {
+                let b = [c[0], c[1]];
+                (if big {
+                    i16::from_be_bytes(b)
+                } else {
+                    i16::from_le_bytes(b)
+                }) as f64
+            })),
+            SampleFormat::Float32 => self.pending.extend(bytes.chunks_exact(4).map(|c| {
+                let b = [c[0], c[1], c[2], c[3]];
+                (if big {
+                    f32::from_be_bytes(b)
+                } else {
+                    f32::from_le_bytes(b)
+                }) as f64
+            })),
+            SampleFormat::Float64 => self.pending.extend(bytes.chunks_exact(8).map(|c| {
+                let mut b = [0u8; 8];
+                b.copy_from_slice(c);
+                if big {
+                    f64::from_be_bytes(b)
+                } else {
+                    f64::from_le_bytes(b)
+                }
+            })),
+            SampleFormat::Packed2Bit => {
+                // sign bit followed by magnitude bit: 00 -> 1, 01 -> 3, 10 -> -1, 11 -> -3
+                let lut = [1., 3., -1., -3.];
+                for &b in bytes {
+                    for shift in [6, 4, 2, 0] {
+                        self.pending.push_back(lut[((b >> shift) & 0b11) as usize]);
+                    }
+                }
+            }
+            SampleFormat::Packed4Bit => {
+                for &b in bytes {
+                    for nibble in [b >> 4, b & 0x0f] {
+                        // sign extend the nibble
+                        self.pending.push_back(((nibble << 4) as i8 >> 4) as f64);
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct SampleBlocks<'a> {
+    reader: &'a mut SampleReader,
+    block_size: usize,
+}
+
+impl<'a> Iterator for SampleBlocks<'a> {
+    type Item = Result<DVector<Complex<f64>>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // only whole blocks are returned, a trailing partial block is dropped
+        match self.reader.read_block(self.block_size) {
+            Ok(block) if block.len() == self.block_size => Some(Ok(block)),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> Result<usize, Error> {
+    // like read_exact but returns the count read when the end of file is reached
+    let mut count = 0;
+    while count < buffer.len() {
+        match reader.read(&mut buffer[count..]) {
+            Ok(0) => break,
+            Ok(k) => count += k,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(count)
+}
+
+pub struct SampleWriter {
+    writer: BufWriter<File>,
+    config: SampleConfig,
+    scale: f64,     // multiplier applied before quantisation
+    bit_buffer: u8, // partially filled byte for packed formats
+    bit_count: usize,
+}
+
+impl SampleWriter {
+    pub fn create(filename: &str, config: SampleConfig, scale: f64) -> Result<SampleWriter, Error> {
+        let file = File::create(filename)?;
+        Ok(SampleWriter {
+            writer: BufWriter::with_capacity(1 << 20, file),
+            config,
+            scale,
+            bit_buffer: 0,
+            bit_count: 0,
+        })
+    }
+
+    pub fn write_block(&mut self, block: &DVector<Complex<f64>>) -> Result<(), Error> {
+        for s in block.iter() {
+            let (first, second) = if self.config.iq_swap {
+                (s.im, s.re)
+            } else {
+                (s.re, s.im)
+            };
+            self.write_component(first)?;
+            if self.config.complex {
+                self.write_component(second)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_block_real(&mut self, block: &DVector<f64>) -> Result<(), Error> {
+        self.write_block(&block.map(|x| Complex::new(x, 0.)))
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        // pads a trailing partial byte with zero bits
+        if self.bit_count > 0 {
+            let byte = self.bit_buffer << (8 - self.bit_count);
+            self.writer.write_all(&[byte])?;
+        }
+        self.writer.flush()
+    }
+
+    fn write_component(&mut self, value: f64) -> Result<(), Error> {
+        let v = value * self.scale;
+        let big = self.config.endianness == Endianness::Big;
+        match self.config.format {
+            SampleFormat::Int8 => self
+                .writer
+                .write_all(&[v.round().clamp(-128., 127.) as i8 as u8]),
+            SampleFormat::Uint8 => self
+                .writer
+                .write_all(&[(v + 127.5).round().clamp(0., 255.) as u8]),
+            SampleFormat::Int16 => {
+                let q = v.round().clamp(-32768., 32767.) as i16;
+                self.writer.write_all(&if big {
+                    q.to_be_bytes()
+                } else {
+                    q.to_le_bytes()
+                })
+            }
+            SampleFormat::Float32 => {
+                let q = v as f32;
+                self.writer.write_all(&if big {
+                    q.to_be_bytes()
+                } else {
+                    q.to_le_bytes()
+                })
+            }
+            SampleFormat::Float64 => self.writer.write_all(&if big {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }),
+            SampleFormat::Packed2Bit => {
+                // inverse of the reader lookup table, magnitude threshold at 2
+                let sign = if v < 0. { 0b10 } else { 0b00 };
+                let mag = if v.abs() >= 2. { 0b01 } else { 0b00 };
+                self.push_bits(sign | mag, 2)
+            }
+            SampleFormat::Packed4Bit => {
+                let q = v.round().clamp(-8., 7.) as i8 as u8 & 0x0f;
+                self.push_bits(q, 4)
+            }
+        }
+    }
+
+    fn push_bits(&mut self, bits: u8, n: usize) -> Result<(), Error> {
+        self.bit_buffer = (self.bit_buffer << n) | bits;
+        self.bit_count += n;
+        if self.bit_count == 8 {
+            self.writer.write_all(&[self.bit_buffer])?;
+            self.bit_buffer = 0;
+            self.bit_count = 0;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use crate::earth::*;
+use crate::observables::*;
 use nalgebra::*;
 use reqwest::blocking::get;
 use std::collections::HashMap;
@@ -65,104 +66,251 @@ impl SatelliteData {
     pub fn propagate(&mut self, time_vec: DVector<f64>) {
         // use to propagate gps satellites based on gps time
         // calculates trajectory in ECEF
+        // time_vec holds gps times of week (sec), times across a week crossover from
+        // toe are wrapped to the nearest week
         self.t = time_vec;
-        let (mu, earth_rot) = earth_constants(0);
-        let a = self.sqrt_a.powi(2);
-        let n0 = (mu / a.powi(3)).sqrt();
-        let n = n0 + self.delta_n;
-        let mut mk = &self.t.clone() * n;
-        mk = mk.add_scalar(self.m0);
-
-        // calculate eccentric anomalies
-        let e = self.e;
-        let mut ecc_anomk = dvector![];
-        for i in 0..mk.len() {
-            ecc_anomk[i] = approx_ecc_anom(mk[i], e);
-        }
-        // calculate true anomalies
-        let vk = ecc_anomk.map(|x| 2.0 * ((1.0 + e) / (1.0 - e)).sqrt() * (x / 2.0).tan());
-
-        // calculate argument of latitudes
-        let latk = vk.add_scalar(self.aop);
-        let s2latk = latk.map(|x| (2. * x).sin());
-        let c2latk = latk.map(|x| (2. * x).sin());
-
-        // calculate perturbations for arg of lat, radius, and inclination
-        let duk = self.cus * &s2latk + self.cuc * &c2latk;
-        let drk = self.crs * &s2latk + self.crs * &c2latk;
-        let dik = self.cis * &s2latk + self.cic * &c2latk;
-        let uk = latk + duk;
-        let temp = ecc_anomk.map(|x| 1. - e * x.cos());
-        let rk = a * temp + drk;
-        let ik = dik.add_scalar(self.i0) + self.idot * &self.t;
-
-        // calculate perifocal positions
-        let xkp = rk.component_mul(&uk.map(|x| x.cos()));
-        let ykp = rk.component_mul(&uk.map(|x| x.sin()));
-
-        // calculate omegas
-        let omk =
-            ((self.raandot + earth_rot) * &self.t).add_scalar(self.raan - earth_rot * self.toe);
-
-        let comk = omk.map(|x| x.cos());
-        let somk = omk.map(|x| x.sin());
-        let cik = ik.map(|x| x.cos());
-        let sik = ik.map(|x| x.sin());
-        // calculate position in ecef
-        self.x = xkp.component_mul(&comk) - ykp.component_mul(&cik.component_mul(&somk));
-        self.y = xkp.component_mul(&somk) + ykp.component_mul(&cik.component_mul(&comk));
-        self.z = ykp.component_mul(&sik);
+        self.propagate_t();
     }
     pub fn propagate_est(&mut self, arr_time: DVector<f64>, prange_time: DVector<f64>) {
         // used to propagate based on receiver time and psuedorange time
-        // calculates trajectory in ECEF
-        self.t = arr_time - prange_time.add_scalar(-self.toe);
-        let (mu, earth_rot) = earth_constants(0);
+        // calculates trajectory in ECEF at the transmit time (arrival - pseudorange/c),
+        // arr_time is a gps time of week and prange_time the travel time (sec)
+        self.t = arr_time - prange_time;
+        self.propagate_t();
+    }
+
+    fn propagate_t(&mut self) {
+        // ecef trajectory at each time of self.t, from the same model as state_at
+        let states: Vec<SvState> = self.t.iter().map(|&t| self.state_at(t)).collect();
+        let n = states.len();
+        self.x = DVector::from_iterator(n, states.iter().map(|s| s.position[0]));
+        self.y = DVector::from_iterator(n, states.iter().map(|s| s.position[1]));
+        self.z = DVector::from_iterator(n, states.iter().map(|s| s.position[2]));
+    }
+
+    pub fn from_broadcast(
+        prn: usize,
+        toc_week: i32,
+        toc: f64,
+        clock: [f64; 3],
+        orbit: [f64; 26],
+    ) -> SatelliteData {
+        // ephemeris from decoded navigation data, clock bias/drift/drift rate and the
+        // 26 orbit values in rinex broadcast orbit order (radians, sec, m, full week)
+        let (year, month, day, hour, minute, second) = date_from_gps_time(toc_week, toc);
+        SatelliteData {
+            prn: prn as i32,
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            sv_clock_bias: clock[0],
+            sv_clock_drift: clock[1],
+            sv_clock_drift_rate: clock[2],
+            iode: orbit[0],
+            crs: orbit[1],
+            delta_n: orbit[2],
+            m0: orbit[3],
+            cuc: orbit[4],
+            e: orbit[5],
+            cus: orbit[6],
+            sqrt_a: orbit[7],
+            toe: orbit[8],
+            cic: orbit[9],
+            raan: orbit[10],
+            cis: orbit[11],
+            i0: orbit[12],
+            crc: orbit[13],
+            aop: orbit[14],
+            raandot: orbit[15],
+            idot: orbit[16],
+            l2_codes: orbit[17],
+            gps_week: orbit[18],
+            l2_p_data_flag: orbit[19],
+            sv_accuracy: orbit[20],
+            sv_health: orbit[21],
+            tgd: orbit[22],
+            iodc: orbit[23],
+            transmission_time: orbit[24],
+            fit_interval: orbit[25],
+            x: dvector![],
+            y: dvector![],
+            z: dvector![],
+            t: dvector![],
+        }
+    }
+
+    pub fn broadcast(&self) -> ([f64; 3], [f64; 26]) {
+        // inverse of from_broadcast, the clock terms and orbit values
+        let clock = [
+            self.sv_clock_bias,
+            self.sv_clock_drift,
+            self.sv_clock_drift_rate,
+        ];
+        let orbit = [
+            self.iode,
+            self.crs,
+            self.delta_n,
+            self.m0,
+            self.cuc,
+            self.e,
+            self.cus,
+            self.sqrt_a,
+            self.toe,
+            self.cic,
+            self.raan,
+            self.cis,
+            self.i0,
+            self.crc,
+            self.aop,
+            self.raandot,
+            self.idot,
+            self.l2_codes,
+            self.gps_week,
+            self.l2_p_data_flag,
+            self.sv_accuracy,
+            self.sv_health,
+            self.tgd,
+            self.iodc,
+            self.transmission_time,
+            self.fit_interval,
+        ];
+        (clock, orbit)
+    }
+
+    pub fn prn(&self) -> usize {
+        self.prn as usize
+    }
+
+    pub fn trajectory(&self) -> Vec<(f64, Vector3<f64>)> {
+        // gps time of week and ecef position (m) of each sample from the last propagate
+        self.t
+            .iter()
+            .zip(self.x.iter().zip(self.y.iter().zip(self.z.iter())))
+            .map(|(&t, (&x, (&y, &z)))| (t, Vector3::new(x, y, z)))
+            .collect()
+    }
+
+    pub fn toe(&self) -> f64 {
+        self.toe
+    }
+
+    pub fn toc(&self) -> f64 {
+        // clock reference time of week (sec) from the epoch of the record
+        self.toc_epoch().1
+    }
+
+    pub fn toc_epoch(&self) -> (i32, f64) {
+        // clock reference time as gps week and time of week (sec)
+        gps_time_from_date(
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+        )
+    }
+
+    pub fn tgd(&self) -> f64 {
+        self.tgd
+    }
+
+    pub fn iode(&self) -> f64 {
+        self.iode
+    }
+
+    pub fn healthy(&self) -> bool {
+        self.sv_health == 0.
+    }
+
+    pub fn clock_correction(&self, t: f64) -> (f64, f64) {
+        // satellite clock bias (sec) and drift (sec/sec) at gps time of week t,
+        // including the relativistic correction and the l1 group delay
+        let (mu, _) = earth_constants(0);
+        let f_rel = -2. * mu.sqrt() / SPEED_OF_LIGHT.powi(2);
         let a = self.sqrt_a.powi(2);
-        let n0 = (mu / a.powi(3)).sqrt();
-        let n = n0 + self.delta_n;
-        let mut mk = &self.t.clone() * n;
-        mk = mk.add_scalar(self.m0);
+        let n = (mu / a.powi(3)).sqrt() + self.delta_n;
+        let ek = approx_ecc_anom(self.m0 + n * wrap_week(t - self.toe), self.e);
+        let ek_dot = n / (1. - self.e * ek.cos());
 
-        // calculate eccentric anomalies
+        let dt = wrap_week(t - self.toc());
+        let bias = self.sv_clock_bias
+            + self.sv_clock_drift * dt
+            + self.sv_clock_drift_rate * dt * dt
+            + f_rel * self.e * self.sqrt_a * ek.sin()
+            - self.tgd;
+        let drift = self.sv_clock_drift
+            + 2. * self.sv_clock_drift_rate * dt
+            + f_rel * self.e * self.sqrt_a * ek.cos() * ek_dot;
+        (bias, drift)
+    }
+
+    pub fn state_at(&self, t: f64) -> SvState {
+        // ecef position and velocity at gps time of week t (IS-GPS-200 table 20-IV)
+        let (mu, earth_rot) = earth_constants(0);
+        let a = self.sqrt_a.powi(2);
         let e = self.e;
-        let mut ecc_anomk = dvector![];
-        for i in 0..mk.len() {
-            ecc_anomk[i] = approx_ecc_anom(mk[i], e);
+        let n = (mu / a.powi(3)).sqrt() + self.delta_n;
+        let tk = wrap_week(t - self.toe);
+
+        let ek = approx_ecc_anom(self.m0 + n * tk, e);
+        let ek_dot = n / (1. - e * ek.cos());
+        let vk = ((1. - e * e).sqrt() * ek.sin()).atan2(ek.cos() - e);
+        let vk_dot = ek_dot * (1. - e * e).sqrt() / (1. - e * ek.cos());
+
+        let phik = vk + self.aop;
+        let (s2p, c2p) = (2. * phik).sin_cos();
+        let uk = phik + self.cus * s2p + self.cuc * c2p;
+        let rk = a * (1. - e * ek.cos()) + self.crs * s2p + self.crc * c2p;
+        let ik = self.i0 + self.idot * tk + self.cis * s2p + self.cic * c2p;
+
+        let uk_dot = vk_dot * (1. + 2. * (self.cus * c2p - self.cuc * s2p));
+        let rk_dot = a * e * ek.sin() * ek_dot + 2. * vk_dot * (self.crs * c2p - self.crc * s2p);
+        let ik_dot = self.idot + 2. * vk_dot * (self.cis * c2p - self.cic * s2p);
+
+        let xp = rk * uk.cos();
+        let yp = rk * uk.sin();
+        let xp_dot = rk_dot * uk.cos() - yp * uk_dot;
+        let yp_dot = rk_dot * uk.sin() + xp * uk_dot;
+
+        let omk = self.raan + (self.raandot - earth_rot) * tk - earth_rot * self.toe;
+        let omk_dot = self.raandot - earth_rot;
+        let (som, com) = omk.sin_cos();
+        let (si, ci) = ik.sin_cos();
+
+        let position = Vector3::new(xp * com - yp * ci * som, xp * som + yp * ci * com, yp * si);
+        let velocity = Vector3::new(
+            -xp * omk_dot * som + xp_dot * com
+                - yp_dot * som * ci
+                - yp * (omk_dot * com * ci - ik_dot * som * si),
+            xp * omk_dot * com + xp_dot * som + yp_dot * com * ci
+                - yp * (omk_dot * som * ci + ik_dot * com * si),
+            yp_dot * si + yp * ik_dot * ci,
+        );
+        let (clock_bias, clock_drift) = self.clock_correction(t);
+
+        SvState {
+            prn: self.prn as usize,
+            position,
+            velocity,
+            clock_bias,
+            clock_drift,
         }
-        // calculate true anomalies
-        let vk = ecc_anomk.map(|x| 2.0 * ((1.0 + e) / (1.0 - e)).sqrt() * (x / 2.0).tan());
-
-        // calculate argument of latitudes
-        let latk = vk.add_scalar(self.aop);
-        let s2latk = latk.map(|x| (2. * x).sin());
-        let c2latk = latk.map(|x| (2. * x).sin());
-
-        // calculate perturbations for arg of lat, radius, and inclination
-        let duk = self.cus * &s2latk + self.cuc * &c2latk;
-        let drk = self.crs * &s2latk + self.crs * &c2latk;
-        let dik = self.cis * &s2latk + self.cic * &c2latk;
-        let uk = latk + duk;
-        let temp = ecc_anomk.map(|x| 1. - e * x.cos());
-        let rk = a * temp + drk;
-        let ik = dik.add_scalar(self.i0) + self.idot * &self.t;
-
-        // calculate perifocal positions
-        let xkp = rk.component_mul(&uk.map(|x| x.cos()));
-        let ykp = rk.component_mul(&uk.map(|x| x.sin()));
-
-        // calculate omegas
-        let omk =
-            ((self.raandot + earth_rot) * &self.t).add_scalar(self.raan - earth_rot * self.toe);
-
-        let comk = omk.map(|x| x.cos());
-        let somk = omk.map(|x| x.sin());
-        let cik = ik.map(|x| x.cos());
-        let sik = ik.map(|x| x.sin());
-        // calculate position in ecef
-        self.x = xkp.component_mul(&comk) - ykp.component_mul(&cik.component_mul(&somk));
-        self.y = xkp.component_mul(&somk) + ykp.component_mul(&cik.component_mul(&comk));
-        self.z = ykp.component_mul(&sik);
+    }
+}
+
+pub fn wrap_week(dt: f64) -> f64 {
+    // keeps a time difference within half a week of zero (sec)
+    let half_week = 302400.;
+    if dt > half_week {
+        dt - 2. * half_week
+    } else if dt < -half_week {
+        dt + 2. * half_week
+    } else {
+        dt
     }
 }
 
@@ -550,17 +698,17 @@ pub fn rinex2_nav_header(filename: &str) -> Result<RinexNavHeader, Error> {
                 .collect();
             ion_beta.copy_from_slice(&tokens[0..4]);
         } else if line.contains("DELTA-UTC") {
-            // have to parse manually because there can be a
-            // negative sign between the first and second items
-            // delta_utc = ();
-            let tokens = line.replace("DELTA-UTC: A0,A1,T,W", "").replace("D", "e");
-            let tokens: Vec<_> = tokens.split_whitespace().collect();
-            let a0 = tokens[0][0..18].parse::<f64>();
-            let a1 = tokens[0][19..].parse::<f64>();
-            delta_utc.0 = a0.unwrap().clone();
-            delta_utc.1 = a1.unwrap().clone();
-            delta_utc.2 = tokens[1].parse::<i32>().unwrap();
-            delta_utc.3 = tokens[2].parse::<i32>().unwrap();
+            // fixed columns (3X,2D19.12,2I9), a negative sign can run the two
+            // values together
+            delta_utc.0 = parse_nav_field(&line, 3);
+            delta_utc.1 = parse_nav_field(&line, 22);
+            let int = |a: usize, b: usize| {
+                line.get(a..b)
+                    .and_then(|s| s.trim().parse::<i32>().ok())
+                    .unwrap_or(0)
+            };
+            delta_utc.2 = int(41, 50);
+            delta_utc.3 = int(50, 59);
         } else if line.contains("LEAP SECONDS") {
             leap_seconds = line
                 .replace("LEAP SECONDS", "")
@@ -582,6 +730,237 @@ pub fn rinex2_nav_header(filename: &str) -> Result<RinexNavHeader, Error> {
     })
 }
 
+#[derive(Clone, Debug)]
+pub struct SvState {
+    pub prn: usize,
+    pub position: Vector3<f64>, // ecef (m)
+    pub velocity: Vector3<f64>, // ecef (m/s)
+    pub clock_bias: f64,        // sec
+    pub clock_drift: f64,       // sec/sec
+}
+
+impl RinexNavHeader {
+    pub fn new(
+        ion_alpha: [f64; 4],
+        ion_beta: [f64; 4],
+        delta_utc: (f64, f64, i32, i32),
+        leap_seconds: i32,
+    ) -> Self {
+        // delta_utc is a0 (sec), a1 (sec/sec), reference time of week and week
+        RinexNavHeader {
+            ion_alpha,
+            ion_beta,
+            delta_utc,
+            leap_seconds,
+        }
+    }
+
+    pub fn ion_alpha(&self) -> [f64; 4] {
+        self.ion_alpha
+    }
+
+    pub fn ion_beta(&self) -> [f64; 4] {
+        self.ion_beta
+    }
+
+    pub fn delta_utc(&self) -> (f64, f64, i32, i32) {
+        self.delta_utc
+    }
+
+    pub fn leap_seconds(&self) -> i32 {
+        self.leap_seconds
+    }
+}
+
+pub fn gps_time_from_date(
+    year: i32,
+    month: i32,
+    day: i32,
+    hour: i32,
+    minute: i32,
+    second: f64,
+) -> (i32, f64) {
+    // gps week and time of week (sec), two digit years are taken as 1980-2079
+    let year = if year < 80 {
+        year + 2000
+    } else if year < 100 {
+        year + 1900
+    } else {
+        year
+    };
+    // days from the civil calendar (proleptic gregorian) to 1980-01-06
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 12)
+    } else {
+        (year, month)
+    };
+    let days = 365 * y + y / 4 - y / 100 + y / 400 + (153 * (m - 3) + 2) / 5 + day - 723_126;
+    let week = days.div_euclid(7);
+    let tow =
+        days.rem_euclid(7) as f64 * 86400. + hour as f64 * 3600. + minute as f64 * 60. + second;
+    (week, tow)
+}
+
+pub fn date_from_gps_time(week: i32, tow: f64) -> (i32, i32, i32, i32, i32, f64) {
+    // inverse of gps_time_from_date, returns (year, month, day, hour, minute, second)
+    let days = week * 7 + (tow / 86400.).floor() as i32 + 723_125;
+    let sod = tow - (tow / 86400.).floor() * 86400.;
+    // civil from days (shifted march based year)
+    let mut y = (10000 * days as i64 + 14780) / 3652425;
+    let mut ddd = days as i64 - (365 * y + y / 4 - y / 100 + y / 400);
+    if ddd < 0 {
+        y -= 1;
+        ddd = days as i64 - (365 * y + y / 4 - y / 100 + y / 400);
+    }
+    let mi = (100 * ddd + 52) / 3060;
+    let month = (mi + 2) % 12 + 1;
+    let year = y + (mi + 2) / 12;
+    let day = ddd - (mi * 306 + 5) / 10 + 1;
+    let hour = (sod / 3600.).floor();
+    let minute = ((sod - hour * 3600.) / 60.).floor();
+    let second = sod - hour * 3600. - minute * 60.;
+    (
+        year as i32,
+        month as i32,
+        day as i32,
+        hour as i32,
+        minute as i32,
+        second,
+    )
+}
+
+fn parse_nav_field(line: &str, start: usize) -> f64 {
+    // 19 character fortran field, blank or missing fields read as zero
+    line.get(start..(start + 19).min(line.len()))
+        .map(|s| s.trim().replace(['D', 'd'], "e"))
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<f64>().unwrap_or(0.))
+        .unwrap_or(0.)
+}
+
+pub fn rinex2_nav_all(filename: &str) -> Result<Vec<SatelliteData>, Error> {
+    // every ephemeris record in a rinex 2 gps navigation file
+    let file = File::open(filename)?;
+    let mut lines = BufReader::new(file).lines();
+
+    for line in lines.by_ref() {
+        if line?.contains("END OF HEADER") {
+            break;
+        }
+    }
+
+    let mut sats = vec![];
+    while let Some(line) = lines.next() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut orbit = vec![];
+        for _ in 0..7 {
+            let next = lines.next().ok_or_else(|| {
+                Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "truncated navigation record",
+                )
+            })??;
+            for k in 0..4 {
+                orbit.push(parse_nav_field(&next, 3 + 19 * k));
+            }
+        }
+        let int = |a: usize, b: usize| {
+            line.get(a..b)
+                .and_then(|s| s.trim().parse::<f64>().ok())
+                .unwrap_or(0.)
+        };
+
+        sats.push(SatelliteData {
+            prn: int(0, 2) as i32,
+            year: int(3, 5) as i32,
+            month: int(5, 8) as i32,
+            day: int(8, 11) as i32,
+            hour: int(11, 14) as i32,
+            minute: int(14, 17) as i32,
+            second: int(17, 22),
+            sv_clock_bias: parse_nav_field(&line, 22),
+            sv_clock_drift: parse_nav_field(&line, 41),
+            sv_clock_drift_rate: parse_nav_field(&line, 60),
+            iode: orbit[0],
+            crs: orbit[1],
+            delta_n: orbit[2],
+            m0: orbit[3],
+            cuc: orbit[4],
+            e: orbit[5],
+            cus: orbit[6],
+            sqrt_a: orbit[7],
+            toe: orbit[8],
+            cic: orbit[9],
+            raan: orbit[10],
+            cis: orbit[11],
+            i0: orbit[12],
+            crc: orbit[13],
+            aop: orbit[14],
+            raandot: orbit[15],
+            idot: orbit[16],
+            l2_codes: orbit[17],
+            gps_week: orbit[18],
+            l2_p_data_flag: orbit[19],
+            sv_accuracy: orbit[20],
+            sv_health: orbit[21],
+            tgd: orbit[22],
+            iodc: orbit[23],
+            transmission_time: orbit[24],
+            fit_interval: orbit[25],
+            x: dvector![],
+            y: dvector![],
+            z: dvector![],
+            t: dvector![],
+        });
+    }
+    Ok(sats)
+}
+
+pub fn select_ephemeris(
+    sats: &[SatelliteData],
+    prn: usize,
+    gps_time: f64,
+) -> Option<&SatelliteData> {
+    // healthy ephemeris for prn with toe closest to gps_time, within its fit interval
+    sats.iter()
+        .filter(|s| s.prn as usize == prn && s.healthy())
+        .filter(|s| {
+            let fit = if s.fit_interval > 0. {
+                s.fit_interval
+            } else {
+                4.
+            };
+            wrap_week(gps_time - s.toe).abs() <= fit * 3600. / 2. + 1.
+        })
+        .min_by(|a, b| {
+            wrap_week(gps_time - a.toe)
+                .abs()
+                .partial_cmp(&wrap_week(gps_time - b.toe).abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+}
+
+pub fn closest_ephemerides(sats: &[SatelliteData], gps_time: f64) -> Vec<&SatelliteData> {
+    // healthy ephemeris per prn with toe closest to gps_time, without the fit interval
+    // check of select_ephemeris, sorted by prn
+    let mut closest: Vec<&SatelliteData> = vec![];
+    for s in sats.iter().filter(|s| s.healthy()) {
+        match closest.iter_mut().find(|c| c.prn == s.prn) {
+            Some(c) => {
+                if wrap_week(gps_time - s.toe).abs() < wrap_week(gps_time - c.toe).abs() {
+                    *c = s;
+                }
+            }
+            None => closest.push(s),
+        }
+    }
+    closest.sort_by_key(|s| s.prn);
+    closest
+}
+
 // 1 Eccentricity:                             e
 // 2 Time of Applicability(s):                 TOE
 // 3 Orbital Inclination(rad):                 I_0
@@ -604,8 +983,244 @@ pub fn rinex2_nav_header(filename: &str) -> Result<RinexNavHeader, Error> {
 // 20 Af1(s/s):                                af1
 // 21 Af2(s/s/s):                              af2
 
-pub fn read_sp3() {
-    todo!()
+pub trait SatelliteClock {
+    // satellite clock offset (sec) from a precise product
+    fn sat_clock(&self, prn: usize, gps_week: i32, gps_time: f64) -> Option<f64>;
+}
+
+#[derive(Clone, Debug)]
+pub struct Sp3Record {
+    pub prn: usize,
+    pub position: Vector3<f64>,         // ecef (m)
+    pub clock: Option<f64>,             // sec, none when the clock is marked bad
+    pub velocity: Option<Vector3<f64>>, // ecef (m/s)
+    pub clock_rate: Option<f64>,        // sec/sec
+}
+
+#[derive(Clone, Debug)]
+pub struct Sp3Epoch {
+    pub gps_week: i32,
+    pub gps_time: f64, // sec of week
+    pub records: Vec<Sp3Record>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sp3Data {
+    pub version: char,
+    pub agency: String,
+    pub coordinate_system: String,
+    pub orbit_type: String,
+    pub time_system: String,
+    pub interval: f64, // sec
+    pub satellites: Vec<usize>,
+    pub epochs: Vec<Sp3Epoch>,
+}
+
+impl Sp3Data {
+    pub fn position(&self, prn: usize, gps_week: i32, gps_time: f64) -> Option<Vector3<f64>> {
+        // lagrange interpolation over the ten nearest epochs, only inside the span of
+        // the file where the interpolation is reliable
+        let t = gps_week as f64 * SECONDS_PER_WEEK + gps_time;
+        let samples: Vec<(f64, Vector3<f64>)> = self
+            .epochs
+            .iter()
+            .filter_map(|e| {
+                let r = e.records.iter().find(|r| r.prn == prn)?;
+                if r.position.norm() == 0. {
+                    return None;
+                }
+                Some((
+                    e.gps_week as f64 * SECONDS_PER_WEEK + e.gps_time - t,
+                    r.position,
+                ))
+            })
+            .collect();
+        if samples.len() < 2 || samples[0].0 > 0. || samples[samples.len() - 1].0 < 0. {
+            return None;
+        }
+        let nearest = samples
+            .iter()
+            .position(|s| s.0 >= 0.)
+            .unwrap_or(samples.len() - 1);
+        let order = 10.min(samples.len());
+        let start = nearest.saturating_sub(order / 2).min(samples.len() - order);
+        let points = &samples[start..start + order];
+
+        let mut position = Vector3::zeros();
+        for (i, (ti, pi)) in points.iter().enumerate() {
+            let mut weight = 1.;
+            for (j, (tj, _)) in points.iter().enumerate() {
+                if i != j {
+                    weight *= -tj / (ti - tj);
+                }
+            }
+            position += pi * weight;
+        }
+        Some(position)
+    }
+
+    pub fn clock(&self, prn: usize, gps_week: i32, gps_time: f64) -> Option<f64> {
+        // linear between the bracketing epochs, clocks are too noisy for higher order
+        let t = gps_week as f64 * SECONDS_PER_WEEK + gps_time;
+        let mut before: Option<(f64, f64)> = None;
+        for e in self.epochs.iter() {
+            let te = e.gps_week as f64 * SECONDS_PER_WEEK + e.gps_time;
+            let clock = match e
+                .records
+                .iter()
+                .find(|r| r.prn == prn)
+                .and_then(|r| r.clock)
+            {
+                Some(c) => c,
+                None => continue,
+            };
+            if te <= t {
+                before = Some((te, clock));
+            } else {
+                let (t0, c0) = before?;
+                return Some(c0 + (clock - c0) * (t - t0) / (te - t0));
+            }
+        }
+        before.filter(|(t0, _)| *t0 == t).map(|(_, c)| c)
+    }
+
+    pub fn state_at(&self, prn: usize, gps_week: i32, gps_time: f64) -> Option<SvState> {
+        // precise position and clock with velocity and drift by central differences
+        let dt = 0.5;
+        let position = self.position(prn, gps_week, gps_time)?;
+        let ahead = self.position(prn, gps_week, gps_time + dt)?;
+        let behind = self.position(prn, gps_week, gps_time - dt)?;
+        let clock_bias = self.clock(prn, gps_week, gps_time)?;
+        let clock_drift = match (
+            self.clock(prn, gps_week, gps_time + dt),
+            self.clock(prn, gps_week, gps_time - dt),
+        ) {
+            (Some(a), Some(b)) => (a - b) / (2. * dt),
+            _ => 0.,
+        };
+        Some(SvState {
+            prn,
+            position,
+            velocity: (ahead - behind) / (2. * dt),
+            clock_bias,
+            clock_drift,
+        })
+    }
+}
+
+impl SatelliteClock for Sp3Data {
+    fn sat_clock(&self, prn: usize, gps_week: i32, gps_time: f64) -> Option<f64> {
+        self.clock(prn, gps_week, gps_time)
+    }
+}
+
+fn sp3_prn(id: &str) -> Option<usize> {
+    // "G05", " 5" (sp3-a) or "05", other constellations are skipped
+    let id = id.trim_end();
+    let (system, number) = match id.len() {
+        0 => return None,
+        1 | 2 => (' ', id),
+        _ => (id.chars().next()?, &id[1..]),
+    };
+    if system != 'G' && system != ' ' {
+        return None;
+    }
+    number.trim().parse::<usize>().ok()
+}
+
+pub fn read_sp3(filename: &str) -> Result<Sp3Data, Error> {
+    // sp3 a, c and d precise orbits, gps satellites only
+    let file = File::open(filename)?;
+    let mut sp3 = Sp3Data {
+        version: ' ',
+        agency: String::new(),
+        coordinate_system: String::new(),
+        orbit_type: String::new(),
+        time_system: "GPS".to_string(),
+        interval: 0.,
+        satellites: vec![],
+        epochs: vec![],
+    };
+    let field = |line: &str, start: usize, end: usize| -> f64 {
+        line.get(start..end.min(line.len()))
+            .and_then(|s| s.trim().parse::<f64>().ok())
+            .unwrap_or(0.)
+    };
+    let mut first_time_system = true;
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.starts_with("EOF") {
+            break;
+        } else if line.starts_with("##") {
+            sp3.interval = field(&line, 24, 38);
+        } else if line.starts_with("#") {
+            sp3.version = line.chars().nth(1).unwrap_or(' ');
+            sp3.coordinate_system = line.get(46..51).unwrap_or("").trim().to_string();
+            sp3.orbit_type = line.get(52..55).unwrap_or("").trim().to_string();
+            sp3.agency = line.get(56..60).unwrap_or("").trim().to_string();
+        } else if line.starts_with("+ ") {
+            let mut start = 9;
+            while let Some(id) = line.get(start..start + 3) {
+                if let Some(prn) = sp3_prn(id).filter(|&p| p > 0) {
+                    sp3.satellites.push(prn);
+                }
+                start += 3;
+            }
+        } else if line.starts_with("%c") {
+            if first_time_system {
+                let system = line.get(9..12).unwrap_or("").trim();
+                if !system.is_empty() && system != "ccc" {
+                    sp3.time_system = system.to_string();
+                }
+                first_time_system = false;
+            }
+        } else if line.starts_with('*') {
+            let (week, tow) = gps_time_from_date(
+                field(&line, 3, 7) as i32,
+                field(&line, 8, 10) as i32,
+                field(&line, 11, 13) as i32,
+                field(&line, 14, 16) as i32,
+                field(&line, 17, 19) as i32,
+                field(&line, 20, 31),
+            );
+            sp3.epochs.push(Sp3Epoch {
+                gps_week: week,
+                gps_time: tow,
+                records: vec![],
+            });
+        } else if line.starts_with('P') || line.starts_with('V') {
+            let prn = match line.get(1..4).and_then(sp3_prn) {
+                Some(prn) => prn,
+                None => continue,
+            };
+            let epoch = match sp3.epochs.last_mut() {
+                Some(e) => e,
+                None => continue,
+            };
+            let xyz = Vector3::new(
+                field(&line, 4, 18),
+                field(&line, 18, 32),
+                field(&line, 32, 46),
+            );
+            let clock = field(&line, 46, 60);
+            let bad_clock = clock == 0. || clock >= 999999.;
+            if line.starts_with('P') {
+                epoch.records.push(Sp3Record {
+                    prn,
+                    position: xyz * 1e3,
+                    clock: (!bad_clock).then_some(clock * 1e-6),
+                    velocity: None,
+                    clock_rate: None,
+                });
+            } else if let Some(r) = epoch.records.iter_mut().find(|r| r.prn == prn) {
+                // dm/s and 1e-4 microsec/s
+                r.velocity = Some(xyz * 0.1);
+                r.clock_rate = (!bad_clock).then_some(clock * 1e-10);
+            }
+        }
+    }
+    Ok(sp3)
 }
 
 // almanacs (low precision): https://celestrak.org/GPS/almanac/Yuma/2023/
@@ -0,0 +1,288 @@
+use crate::codes::*;
+use crate::observables::*;
+use crate::samples::*;
+use nalgebra::*;
+use rustfft::num_complex::Complex;
+use std::f64::consts::*;
+use std::io::Error;
+
+#[derive(Clone, Debug)]
+pub struct Multipath {
+    pub delay: f64,          // extra delay relative to the direct path (chips)
+    pub relative_power: f64, // dB relative to the direct path, normally negative
+    pub phase: f64,          // carrier phase offset relative to the direct path (rad)
+}
+
+#[derive(Clone, Debug)]
+pub struct SimSatellite {
+    pub prn: usize,
+    pub code_delay: f64, // received code delay at the first sample (chips, 0..1023)
+    pub doppler: f64,    // carrier doppler (Hz), code doppler follows from it
+    pub cn0: f64,        // dB-Hz
+    pub carrier_phase: f64, // carrier phase at the first sample (rad)
+    pub nav_bits: Vec<i32>, // +1/-1 data bits, 20 ms each, repeated; empty for a pilot
+    pub multipath: Option<Multipath>,
+}
+
+impl SimSatellite {
+    pub fn new(prn: usize, code_delay: f64, doppler: f64, cn0: f64) -> Self {
+        SimSatellite {
+            prn,
+            code_delay,
+            doppler,
+            cn0,
+            carrier_phase: 0.,
+            nav_bits: vec![],
+            multipath: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CwInterference {
+    pub freq_offset: f64, // Hz relative to the intermediate frequency
+    pub power: f64,       // dB relative to the noise power in the sampled bandwidth
+}
+
+pub struct SignalSimulator {
+    config: SampleConfig,
+    sats: Vec<SimSatellite>,
+    codes: Vec<DVector<i32>>,
+    interference: Vec<CwInterference>,
+    noise: bool,
+    rng: GaussianNoise,
+    sample_index: u64,
+}
+
+impl SignalSimulator {
+    pub fn new(config: SampleConfig, sats: Vec<SimSatellite>, seed: u64) -> Self {
+        // noise has unit variance per real component, signal amplitudes are set from C/N0
+        let codes = sats.iter().map(|s| gen_prn(s.prn, true, 1023, 0)).collect();
+        SignalSimulator {
+            config,
+            sats,
+            codes,
+            interference: vec![],
+            noise: true,
+            rng: GaussianNoise::new(seed),
+            sample_index: 0,
+        }
+    }
+
+    pub fn add_interference(&mut self, cw: CwInterference) {
+        self.interference.push(cw);
+    }
+
+    pub fn set_noise(&mut self, noise: bool) {
+        // disabling noise gives clean signals for checking correlator alignment
+        self.noise = noise;
+    }
+
+    pub fn time(&self) -> f64 {
+        self.sample_index as f64 / self.config.sample_rate
+    }
+
+    pub fn generate(&mut self, n: usize) -> DVector<Complex<f64>> {
+        // next n samples, real in the in-phase component unless the config is complex
+        let fs = self.config.sample_rate;
+        let fi = self.config.intermediate_freq;
+        let complex = self.config.complex;
+
+        // noise density for unit variance per component, real (fs/2 bandwidth)
+        // and complex (fs bandwidth, two components) both give 2/fs
+        let n0 = 2. / fs;
+        let mut block = DVector::from_element(n, Complex::new(0., 0.));
+
+        for (sat, code) in self.sats.iter().zip(self.codes.iter()) {
+            let power = n0 * 10f64.powf(sat.cn0 / 10.);
+            let amplitude = if complex {
+                power.sqrt()
+            } else {
+                (2. * power).sqrt()
+            };
+            let chip_rate = CA_CHIP_RATE * (1. + sat.doppler / Band::L1.frequency());
+
+            let mut paths = vec![(0., amplitude, 0.)];
+            if let Some(mp) = &sat.multipath {
+                paths.push((
+                    mp.delay,
+                    amplitude * 10f64.powf(mp.relative_power / 20.),
+                    mp.phase,
+                ));
+            }
+
+            for i in 0..n {
+                let t = (self.sample_index + i as u64) as f64 / fs;
+                for &(delay, amp, phase) in paths.iter() {
+                    // chips elapsed at the receiver since code epoch zero
+                    let chips = t * chip_rate - sat.code_delay - delay;
+                    let chip = chips.rem_euclid(CA_CODE_LENGTH) as usize;
+                    let bit = if sat.nav_bits.is_empty() {
+                        1
+                    } else {
+                        let bit_index = (chips / (CA_CODE_LENGTH * CODES_PER_BIT as f64)).floor();
+                        let len = sat.nav_bits.len() as i64;
+                        sat.nav_bits[(bit_index as i64).rem_euclid(len) as usize]
+                    };
+                    let symbol = (code[chip] * bit) as f64 * amp;
+                    let arg = 2. * PI * (fi + sat.doppler) * t + sat.carrier_phase + phase;
+                    block[i] += if complex {
+                        Complex::new(arg.cos(), arg.sin()) * symbol
+                    } else {
+                        Complex::new(arg.cos() * symbol, 0.)
+                    };
+                }
+            }
+        }
+
+        for cw in self.interference.iter() {
+            // noise power is 1 (real) or 2 (complex) over the sampled bandwidth
+            let noise_power = if complex { 2. } else { 1. };
+            let power = noise_power * 10f64.powf(cw.power / 10.);
+            let amplitude = if complex {
+                power.sqrt()
+            } else {
+                (2. * power).sqrt()
+            };
+            for i in 0..n {
+                let t = (self.sample_index + i as u64) as f64 / fs;
+                let arg = 2. * PI * (fi + cw.freq_offset) * t;
+                block[i] += if complex {
+                    Complex::new(arg.cos(), arg.sin()) * amplitude
+                } else {
+                    Complex::new(arg.cos() * amplitude, 0.)
+                };
+            }
+        }
+
+        if self.noise {
+            for s in block.iter_mut() {
+                s.re += self.rng.sample();
+                if complex {
+                    s.im += self.rng.sample();
+                }
+            }
+        }
+
+        self.sample_index += n as u64;
+        block
+    }
+
+    pub fn generate_real(&mut self, n: usize) -> DVector<f64> {
+        self.generate(n).map(|s| s.re)
+    }
+
+    pub fn write_file(&mut self, filename: &str, seconds: f64, scale: f64) -> Result<(), Error> {
+        // writes a recording in the configured sample format, one code period per block
+        let total = (seconds * self.config.sample_rate).round() as usize;
+        let block_size = self.config.samples_per_code().max(1);
+        let mut writer = SampleWriter::create(filename, self.config.clone(), scale)?;
+        let mut written = 0;
+        while written < total {
+            let n = block_size.min(total - written);
+            writer.write_block(&self.generate(n))?;
+            written += n;
+        }
+        writer.finish()
+    }
+}
+
+pub struct GaussianNoise {
+    state: u64,
+    spare: Option<f64>,
+}
+
+impl GaussianNoise {
+    pub fn new(seed: u64) -> Self {
+        // xorshift state must be non-zero
+        GaussianNoise {
+            state: seed.max(1),
+            spare: None,
+        }
+    }
+
+    pub fn uniform(&mut self) -> f64 {
+        // xorshift64*, uniform on (0, 1)
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let r = self.state.wrapping_mul(0x2545f4914f6cdd1d);
+        ((r >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    }
+
+    pub fn sample(&mut self) -> f64 {
+        // zero mean, unit variance (box-muller)
+        if let Some(s) = self.spare.take() {
+            return s;
+        }
+        let u1 = self.uniform();
+        let u2 = self.uniform();
+        let r = (-2. * u1.ln()).sqrt();
+        self.spare = Some(r * (2. * PI * u2).sin());
+        r * (2. * PI * u2).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::doppler::*;
+
+    const FS: f64 = 4.092e6;
+    const IF: f64 = 1.25e6;
+
+    fn simulator(seed: u64) -> SignalSimulator {
+        let config = SampleConfig::new(SampleFormat::Float64, false, FS, IF);
+        let sats = vec![
+            SimSatellite::new(7, 250., 2000., 50.),
+            SimSatellite::new(19, 700., -3500., 47.),
+        ];
+        SignalSimulator::new(config, sats, seed)
+    }
+
+    #[test]
+    fn same_seed_same_samples() {
+        let a = simulator(11).generate_real(8184);
+        let b = simulator(11).generate_real(8184);
+        let c = simulator(12).generate_real(8184);
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn acquisition_finds_simulated_satellites() {
+        // 1 ms blocks summed non-coherently over 4 ms, rows of the correlation map are
+        // circular lags centred on the middle row
+        let n = 4092;
+        let mut sim = simulator(3);
+        let blocks: Vec<DVector<f64>> = (0..4).map(|_| sim.generate_real(n)).collect();
+        let time = DVector::from_fn(n, |i, _| i as f64 / FS);
+        let dopplers = DVector::from_fn(21, |i, _| i as f64 * 500. - 5000.);
+
+        for (prn, delay, doppler) in [(7, 250., 2000.), (19, 700., -3500.)] {
+            let code = gen_prn(prn, true, 1023, 0);
+            let mut map = DMatrix::zeros(n, dopplers.len());
+            for block in &blocks {
+                map += correlation_magnitude(
+                    dopplers.clone(),
+                    &code,
+                    Modulation::Bpsk,
+                    block.clone(),
+                    IF,
+                    time.clone(),
+                );
+            }
+            let (row, col) = map.iamax_full();
+            // acquisition resolves the code phase to half a chip
+            let offset = (row + n - n / 2) % n;
+            let chips = offset as f64 * CA_CHIP_RATE / FS;
+            assert_eq!(dopplers[col], doppler, "prn {}", prn);
+            assert!(
+                (chips - delay).abs() <= 0.5,
+                "prn {} code phase {}",
+                prn,
+                chips
+            );
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use crate::observables::*;
+use crate::satellites::*;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlipIndicator {
+    LossOfLock,       // lli flag set by the receiver or tracking loop
+    DataGap,          // observations missing for longer than the allowed gap
+    PhaseCode,        // jump in carrier minus code
+    GeometryFree,     // jump in the l1 - l2 phase combination
+    MelbourneWubbena, // wide lane ambiguity outside its running mean
+}
+
+#[derive(Clone, Debug)]
+pub struct SlipConfig {
+    pub primary: Band,
+    pub secondary: Band,        // second frequency for the dual frequency tests
+    pub max_gap: f64,           // sec
+    pub phase_code: f64,        // m, per epoch change of phase minus code
+    pub geometry_free: f64,     // m, per epoch change of the geometry free phase
+    pub melbourne_wubbena: f64, // standard deviations from the wide lane running mean
+}
+
+impl Default for SlipConfig {
+    fn default() -> Self {
+        SlipConfig {
+            primary: Band::L1,
+            secondary: Band::L2,
+            max_gap: 30.,
+            phase_code: 10.,
+            geometry_free: 0.05,
+            melbourne_wubbena: 5.,
+        }
+    }
+}
+
+const MW_MIN_EPOCHS: usize = 10;
+const MW_SIGMA_FLOOR: f64 = 0.25; // wide lane cycles
+
+#[derive(Clone, Debug)]
+struct SlipState {
+    gps_time: f64,
+    phase_code: f64,
+    geometry_free: Option<f64>,
+    mw_mean: f64,
+    mw_m2: f64, // sum of squared deviations from the mean
+    mw_count: usize,
+}
+
+pub struct CycleSlipDetector {
+    config: SlipConfig,
+    states: HashMap<usize, SlipState>,
+}
+
+impl CycleSlipDetector {
+    pub fn new(config: SlipConfig) -> Self {
+        CycleSlipDetector {
+            config,
+            states: HashMap::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.states.clear();
+    }
+
+    pub fn detect(&mut self, epoch: &ObservationEpoch) -> Vec<(usize, SlipIndicator)> {
+        // checks every satellite with primary code and phase against its state from
+        // the previous epoch, the first detector that fires is reported
+        let primary = self.config.primary;
+        let secondary = self.config.secondary;
+        let mut slips = vec![];
+
+        for o in epoch.obs.iter().filter(|o| o.band == primary) {
+            if o.pseudorange == 0. || o.carrier_phase == 0. {
+                self.states.remove(&o.prn);
+                continue;
+            }
+            let phase_code = o.carrier_phase * primary.wavelength() - o.pseudorange;
+            let second = epoch
+                .get(o.prn, secondary)
+                .filter(|s| s.carrier_phase != 0.);
+            let geometry_free = second.map(|s| {
+                o.carrier_phase * primary.wavelength() - s.carrier_phase * secondary.wavelength()
+            });
+            let mw = second
+                .filter(|s| s.pseudorange != 0.)
+                .map(|s| melbourne_wubbena(o, s));
+
+            let slip = self.states.get(&o.prn).and_then(|state| {
+                let gap = wrap_week(epoch.gps_time - state.gps_time).abs();
+                let gf_jump = match (geometry_free, state.geometry_free) {
+                    (Some(a), Some(b)) => (a - b).abs(),
+                    _ => 0.,
+                };
+                let mw_jump = match mw {
+                    // wait for a usable noise estimate, the floor covers quiet data
+                    Some(m) if state.mw_count >= MW_MIN_EPOCHS => {
+                        let sigma = (state.mw_m2 / (state.mw_count - 1) as f64).sqrt();
+                        (m - state.mw_mean).abs() / sigma.max(MW_SIGMA_FLOOR)
+                    }
+                    _ => 0.,
+                };
+                if o.lli || second.is_some_and(|s| s.lli) {
+                    Some(SlipIndicator::LossOfLock)
+                } else if gap > self.config.max_gap {
+                    Some(SlipIndicator::DataGap)
+                } else if (phase_code - state.phase_code).abs() > self.config.phase_code {
+                    Some(SlipIndicator::PhaseCode)
+                } else if gf_jump > self.config.geometry_free {
+                    Some(SlipIndicator::GeometryFree)
+                } else if mw_jump > self.config.melbourne_wubbena {
+                    Some(SlipIndicator::MelbourneWubbena)
+                } else {
+                    None
+                }
+            });
+
+            let state = match (slip, self.states.get_mut(&o.prn)) {
+                (None, Some(state)) => state,
+                _ => {
+                    self.states.insert(
+                        o.prn,
+                        SlipState {
+                            gps_time: epoch.gps_time,
+                            phase_code,
+                            geometry_free,
+                            mw_mean: 0.,
+                            mw_m2: 0.,
+                            mw_count: 0,
+                        },
+                    );
+                    self.states.get_mut(&o.prn).unwrap()
+                }
+            };
+            state.gps_time = epoch.gps_time;
+            state.phase_code = phase_code;
+            state.geometry_free = geometry_free;
+            if let Some(m) = mw {
+                // running mean of the wide lane ambiguity since the last slip
+                state.mw_count += 1;
+                let delta = m - state.mw_mean;
+                state.mw_mean += delta / state.mw_count as f64;
+                state.mw_m2 += delta * (m - state.mw_mean);
+            }
+            if let Some(s) = slip {
+                slips.push((o.prn, s));
+            }
+        }
+        slips
+    }
+}
+
+pub fn melbourne_wubbena(first: &Observation, second: &Observation) -> f64 {
+    // wide lane phase minus narrow lane code, in wide lane cycles
+    let f1 = first.band.frequency();
+    let f2 = second.band.frequency();
+    let l1 = first.carrier_phase * first.band.wavelength();
+    let l2 = second.carrier_phase * second.band.wavelength();
+    let wide_lane = (f1 * l1 - f2 * l2) / (f1 - f2);
+    let narrow_lane = (f1 * first.pseudorange + f2 * second.pseudorange) / (f1 + f2);
+    (wide_lane - narrow_lane) / (SPEED_OF_LIGHT / (f1 - f2))
+}
+
+#[derive(Clone, Debug)]
+pub struct HatchConfig {
+    pub window: usize, // epochs, e.g. 100 at 1 Hz
+    pub slip: SlipConfig,
+}
+
+impl Default for HatchConfig {
+    fn default() -> Self {
+        HatchConfig {
+            window: 100,
+            slip: SlipConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct HatchState {
+    count: usize,
+    smoothed: f64,   // m
+    last_phase: f64, // m
+}
+
+pub struct HatchFilter {
+    window: usize,
+    band: Band,
+    detector: CycleSlipDetector,
+    states: HashMap<usize, HatchState>,
+}
+
+impl HatchFilter {
+    pub fn new(config: HatchConfig) -> Self {
+        HatchFilter {
+            window: config.window.max(1),
+            band: config.slip.primary,
+            detector: CycleSlipDetector::new(config.slip),
+            states: HashMap::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.detector.reset();
+        self.states.clear();
+    }
+
+    pub fn count(&self, prn: usize) -> usize {
+        // epochs averaged into the current smoothed value, 0 when not tracked
+        self.states.get(&prn).map(|s| s.count).unwrap_or(0)
+    }
+
+    pub fn smooth(
+        &mut self,
+        epoch: &ObservationEpoch,
+    ) -> (ObservationEpoch, Vec<(usize, SlipIndicator)>) {
+        // returns the epoch with carrier smoothed pseudoranges on the primary band
+        // and the slips found, smoothing restarts for satellites that slipped or
+        // lost phase
+        let slips = self.detector.detect(epoch);
+        for (prn, _) in slips.iter() {
+            self.states.remove(prn);
+        }
+
+        let mut smoothed = epoch.clone();
+        let mut seen = vec![];
+        for o in smoothed.obs.iter_mut().filter(|o| o.band == self.band) {
+            if o.pseudorange == 0. || o.carrier_phase == 0. {
+                continue;
+            }
+            seen.push(o.prn);
+            let phase = o.carrier_phase * self.band.wavelength();
+            let state = self.states.entry(o.prn).or_insert(HatchState {
+                count: 0,
+                smoothed: o.pseudorange,
+                last_phase: phase,
+            });
+            state.count = (state.count + 1).min(self.window);
+            let n = state.count as f64;
+            state.smoothed =
+                o.pseudorange / n + (n - 1.) / n * (state.smoothed + phase - state.last_phase);
+            state.last_phase = phase;
+            o.pseudorange = state.smoothed;
+        }
+        // satellites without phase this epoch start over when they return
+        self.states.retain(|prn, _| seen.contains(prn));
+        (smoothed, slips)
+    }
+}
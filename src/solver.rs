@@ -0,0 +1,399 @@
+use crate::earth::*;
+use crate::ionex::*;
+use crate::observables::*;
+use crate::satellites::*;
+use nalgebra::*;
+use std::f64::consts::*;
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub enum IonosphereModel {
+    Klobuchar([f64; 4], [f64; 4]), // broadcast alpha and beta
+    Gim(Arc<Ionex>),               // global ionosphere maps
+}
+
+#[derive(Clone, Debug)]
+pub struct SolverConfig {
+    pub elevation_mask: f64, // deg
+    pub troposphere: bool,
+    pub ionosphere: Option<IonosphereModel>,
+    pub max_iterations: usize,
+    pub exclude: Vec<usize>, // prns left out of the solution
+}
+
+impl Default for SolverConfig {
+    fn default() -> Self {
+        SolverConfig {
+            elevation_mask: 10.,
+            troposphere: true,
+            ionosphere: None,
+            max_iterations: 10,
+            exclude: vec![],
+        }
+    }
+}
+
+impl SolverConfig {
+    pub fn with_nav_header(mut self, header: &RinexNavHeader) -> Self {
+        self.ionosphere = Some(IonosphereModel::Klobuchar(
+            header.ion_alpha(),
+            header.ion_beta(),
+        ));
+        self
+    }
+
+    pub fn with_ionex(mut self, ionex: Arc<Ionex>) -> Self {
+        self.ionosphere = Some(IonosphereModel::Gim(ionex));
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SatGeometry {
+    pub prn: usize,
+    pub sv: SvState, // at transmit time, rotated into the ecef frame at reception
+    pub pseudorange: f64, // measured, corrected for satellite clock and atmosphere (m)
+    pub range: f64,  // geometric range (m)
+    pub los: Vector3<f64>, // unit vector from receiver to satellite
+    pub azimuth: f64, // deg
+    pub elevation: f64, // deg
+    pub variance: f64, // pseudorange variance (m^2)
+}
+
+#[derive(Clone, Debug)]
+pub struct PositionSolution {
+    pub gps_week: i32,
+    pub gps_time: f64,
+    pub position: Vector3<f64>, // ecef (m)
+    pub clock_bias: f64,        // receiver clock bias (m)
+    pub covariance: Matrix4<f64>,
+    pub prns: Vec<usize>,
+    pub residuals: DVector<f64>, // m
+    pub design: DMatrix<f64>,    // rows [-los, 1] for each satellite
+    pub weights: DVector<f64>,   // 1 / variance
+    pub gdop: f64,
+    pub pdop: f64,
+    pub hdop: f64,
+    pub vdop: f64,
+    pub tdop: f64,
+}
+
+impl PositionSolution {
+    pub fn geodetic(&self) -> Vector3<f64> {
+        ecef2geodetic(self.position, 0)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VelocitySolution {
+    pub velocity: Vector3<f64>,     // ecef (m/s)
+    pub velocity_enu: Vector3<f64>, // east, north, up (m/s)
+    pub clock_drift: f64,           // receiver clock drift (m/s)
+    pub covariance: Matrix4<f64>,   // ecef velocity and drift
+    pub covariance_enu: Matrix3<f64>,
+    pub residuals: DVector<f64>, // m/s
+}
+
+// pseudorange variance at zenith used by the elevation weighting (m^2)
+pub const ZENITH_VARIANCE: f64 = 0.18;
+
+pub fn elevation_weight(elevation: f64) -> f64 {
+    // variance scale relative to zenith, 0.3 m + 0.3 m / sin(el) in quadrature
+    let sin_el = (elevation.max(5.) * PI / 180.).sin();
+    (1. + 1. / (sin_el * sin_el)) / 2.
+}
+
+pub fn tropo_delay(lla: Vector3<f64>, elevation: f64) -> f64 {
+    // saastamoinen model with a standard atmosphere (m), elevation in deg
+    if elevation <= 0. || lla[2] < -100. || lla[2] > 1e4 {
+        return 0.;
+    }
+    let deg2rad = PI / 180.;
+    let h = lla[2].max(0.);
+    let pres = 1013.25 * (1. - 2.2557e-5 * h).powf(5.2568);
+    let temp = 15. - 6.5e-3 * h + 273.16;
+    let humidity = 0.7;
+    let e = 6.108 * humidity * ((17.15 * temp - 4684.) / (temp - 38.45)).exp();
+    let z = PI / 2. - elevation * deg2rad;
+    let dry = 0.0022768 * pres
+        / (1. - 0.00266 * (2. * lla[0] * deg2rad).cos() - 0.00028 * h / 1e3)
+        / z.cos();
+    let wet = 0.002277 * (1255. / temp + 0.05) * e / z.cos();
+    dry + wet
+}
+
+pub fn klobuchar_delay(
+    alpha: [f64; 4],
+    beta: [f64; 4],
+    lla: Vector3<f64>,
+    azimuth: f64,
+    elevation: f64,
+    gps_time: f64,
+) -> f64 {
+    // broadcast l1 ionospheric delay (m), IS-GPS-200 20.3.3.5.2.5
+    let deg2rad = PI / 180.;
+    let el = elevation / 180.; // semicircles
+    let az = azimuth * deg2rad;
+    let psi = 0.0137 / (el + 0.11) - 0.022;
+    let phi_i = (lla[0] / 180. + psi * az.cos()).clamp(-0.416, 0.416);
+    let lambda_i = lla[1] / 180. + psi * az.sin() / (phi_i * PI).cos();
+    let phi_m = phi_i + 0.064 * ((lambda_i - 1.617) * PI).cos();
+    let t = (43200. * lambda_i + gps_time).rem_euclid(86400.);
+    let f = 1. + 16. * (0.53 - el).powi(3);
+    let amp = (alpha[0] + phi_m * (alpha[1] + phi_m * (alpha[2] + phi_m * alpha[3]))).max(0.);
+    let per = (beta[0] + phi_m * (beta[1] + phi_m * (beta[2] + phi_m * beta[3]))).max(72000.);
+    let x = 2. * PI * (t - 50400.) / per;
+    let delay = if x.abs() < 1.57 {
+        f * (5e-9 + amp * (1. - x * x / 2. + x.powi(4) / 24.))
+    } else {
+        f * 5e-9
+    };
+    delay * SPEED_OF_LIGHT
+}
+
+pub fn sagnac_rotate(sv: &SvState, travel_time: f64) -> SvState {
+    // rotates a satellite state by the earth rotation during signal travel
+    let (_, earth_rot) = earth_constants(0);
+    let (s, c) = (earth_rot * travel_time).sin_cos();
+    let rot = Matrix3::new(c, s, 0., -s, c, 0., 0., 0., 1.);
+    SvState {
+        position: rot * sv.position,
+        velocity: rot * sv.velocity,
+        ..sv.clone()
+    }
+}
+
+pub fn satellite_geometry(
+    epoch: &ObservationEpoch,
+    ephemerides: &[SatelliteData],
+    position: Vector3<f64>,
+    band: Band,
+    config: &SolverConfig,
+) -> Vec<SatGeometry> {
+    // satellite states and corrected pseudoranges for every usable observation
+    let lla = ecef2geodetic(position, 0);
+    let have_position = position.norm() > 1e6;
+    let mut geometry = vec![];
+
+    for prn in epoch.prns(band) {
+        if config.exclude.contains(&prn) {
+            continue;
+        }
+        let pr = epoch.get(prn, band).unwrap().pseudorange;
+        let eph = match select_ephemeris(ephemerides, prn, epoch.gps_time) {
+            Some(eph) => eph,
+            None => continue,
+        };
+
+        // transmit time from the pseudorange, then the satellite clock
+        let mut tx = epoch.gps_time - pr / SPEED_OF_LIGHT;
+        let (clock, _) = eph.clock_correction(tx);
+        tx -= clock;
+        let sv = eph.state_at(tx);
+
+        let mut tau = (sv.position - position).norm() / SPEED_OF_LIGHT;
+        if !have_position {
+            tau = pr / SPEED_OF_LIGHT;
+        }
+        let sv = sagnac_rotate(&sv, tau);
+        let diff = sv.position - position;
+        let range = diff.norm();
+        let los = diff / range;
+
+        let (azimuth, elevation) = if have_position {
+            look_angles(position, sv.position)
+        } else {
+            (0., 90.)
+        };
+        if have_position && elevation < config.elevation_mask {
+            continue;
+        }
+
+        let mut corrected = pr + sv.clock_bias * SPEED_OF_LIGHT;
+        if have_position {
+            if config.troposphere {
+                corrected -= tropo_delay(lla, elevation);
+            }
+            // both models give the l1 delay, scaled to the band
+            let scale = (Band::L1.frequency() / band.frequency()).powi(2);
+            match &config.ionosphere {
+                Some(IonosphereModel::Klobuchar(alpha, beta)) => {
+                    corrected -= scale
+                        * klobuchar_delay(*alpha, *beta, lla, azimuth, elevation, epoch.gps_time);
+                }
+                Some(IonosphereModel::Gim(ionex)) => {
+                    let gim = ionex.slant_delay(
+                        lla,
+                        azimuth,
+                        elevation,
+                        epoch.gps_week,
+                        epoch.gps_time,
+                        Band::L1,
+                    );
+                    if let Some((delay, _)) = gim {
+                        corrected -= scale * delay;
+                    }
+                }
+                None => {}
+            }
+        }
+
+        let variance = ZENITH_VARIANCE * elevation_weight(elevation);
+
+        geometry.push(SatGeometry {
+            prn,
+            sv,
+            pseudorange: corrected,
+            range,
+            los,
+            azimuth,
+            elevation,
+            variance,
+        });
+    }
+    geometry
+}
+
+pub fn solve_position(
+    epoch: &ObservationEpoch,
+    ephemerides: &[SatelliteData],
+    initial: Vector3<f64>,
+    config: &SolverConfig,
+) -> Option<PositionSolution> {
+    // iterative weighted least squares on l1 pseudoranges, the initial position can be
+    // the origin when nothing better is known
+    let mut position = initial;
+    let mut clock_bias = 0.;
+
+    for _ in 0..config.max_iterations {
+        let geometry = satellite_geometry(epoch, ephemerides, position, Band::L1, config);
+        let n = geometry.len();
+        if n < 4 {
+            return None;
+        }
+        let mut h = DMatrix::zeros(n, 4);
+        let mut dy = DVector::zeros(n);
+        let mut w = DVector::zeros(n);
+        for (i, g) in geometry.iter().enumerate() {
+            h[(i, 0)] = -g.los[0];
+            h[(i, 1)] = -g.los[1];
+            h[(i, 2)] = -g.los[2];
+            h[(i, 3)] = 1.;
+            dy[i] = g.pseudorange - (g.range + clock_bias);
+            w[i] = 1. / g.variance;
+        }
+
+        let hw = h.transpose() * DMatrix::from_diagonal(&w);
+        let normal = &hw * &h;
+        let q = normal.try_inverse()?;
+        let dx = &q * (&hw * &dy);
+        position += Vector3::new(dx[0], dx[1], dx[2]);
+        clock_bias += dx[3];
+
+        if dx.norm() < 1e-4 {
+            // final residuals and dilution of precision at the converged position
+            let geometry = satellite_geometry(epoch, ephemerides, position, Band::L1, config);
+            if geometry.len() != n {
+                continue;
+            }
+            let residuals = DVector::from_iterator(
+                n,
+                geometry
+                    .iter()
+                    .map(|g| g.pseudorange - (g.range + clock_bias)),
+            );
+            let cov = Matrix4::from_fn(|r, c| q[(r, c)]);
+            let dop = (h.transpose() * &h).try_inverse()?;
+            let rot = enu_rotation(ecef2geodetic(position, 0));
+            let dop_enu = rot * dop.fixed_view::<3, 3>(0, 0) * rot.transpose();
+
+            return Some(PositionSolution {
+                gps_week: epoch.gps_week,
+                gps_time: epoch.gps_time,
+                position,
+                clock_bias,
+                covariance: cov,
+                prns: geometry.iter().map(|g| g.prn).collect(),
+                residuals,
+                design: h,
+                weights: w,
+                gdop: dop.trace().sqrt(),
+                pdop: (dop[(0, 0)] + dop[(1, 1)] + dop[(2, 2)]).sqrt(),
+                hdop: (dop_enu[(0, 0)] + dop_enu[(1, 1)]).sqrt(),
+                vdop: dop_enu[(2, 2)].sqrt(),
+                tdop: dop[(3, 3)].sqrt(),
+            });
+        }
+    }
+    None
+}
+
+pub fn solve_velocity(
+    dopplers: &[f64],
+    sv: &[SvState],
+    wavelength: f64,
+    position: Vector3<f64>,
+) -> Option<VelocitySolution> {
+    // least squares receiver velocity and clock drift from doppler (Hz), satellite
+    // velocities (m/s) and clock drifts (s/s) at the receiver position
+    let n = dopplers.len().min(sv.len());
+    if n < 4 {
+        return None;
+    }
+    let mut h = DMatrix::zeros(n, 4);
+    let mut y = DVector::zeros(n);
+    for i in 0..n {
+        let diff = sv[i].position - position;
+        let los = diff / diff.norm();
+        // measured range rate, positive doppler means a closing range
+        let range_rate = -wavelength * dopplers[i];
+        h[(i, 0)] = -los[0];
+        h[(i, 1)] = -los[1];
+        h[(i, 2)] = -los[2];
+        h[(i, 3)] = 1.;
+        y[i] = range_rate - sv[i].velocity.dot(&los) + sv[i].clock_drift * SPEED_OF_LIGHT;
+    }
+
+    let q = (h.transpose() * &h).try_inverse()?;
+    let x = &q * h.transpose() * &y;
+    let residuals = &y - &h * &x;
+
+    // a posteriori unit variance, with a nominal 0.1 m/s when there is no redundancy
+    let sigma2 = if n > 4 {
+        residuals.norm_squared() / (n - 4) as f64
+    } else {
+        0.01
+    };
+    let covariance = Matrix4::from_fn(|r, c| q[(r, c)] * sigma2);
+    let velocity = Vector3::new(x[0], x[1], x[2]);
+    let rot = enu_rotation(ecef2geodetic(position, 0));
+
+    Some(VelocitySolution {
+        velocity,
+        velocity_enu: rot * velocity,
+        clock_drift: x[3],
+        covariance,
+        covariance_enu: rot * covariance.fixed_view::<3, 3>(0, 0) * rot.transpose(),
+        residuals,
+    })
+}
+
+pub fn solve_velocity_epoch(
+    epoch: &ObservationEpoch,
+    ephemerides: &[SatelliteData],
+    position: Vector3<f64>,
+    config: &SolverConfig,
+) -> Option<VelocitySolution> {
+    // velocity from the l1 doppler of an observation epoch (tracking or rinex D1C)
+    let geometry = satellite_geometry(epoch, ephemerides, position, Band::L1, config);
+    let mut dopplers = vec![];
+    let mut states = vec![];
+    for g in geometry.iter() {
+        let doppler = epoch.get(g.prn, Band::L1).map(|o| o.doppler).unwrap_or(0.);
+        if doppler != 0. {
+            dopplers.push(doppler);
+            states.push(g.sv.clone());
+        }
+    }
+    solve_velocity(&dopplers, &states, Band::L1.wavelength(), position)
+}
@@ -0,0 +1,289 @@
+use crate::observables::*;
+use crate::rinex_obs::*;
+use crate::satellites::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+
+const SECONDS_PER_DAY: f64 = 86400.;
+const MJD_GPS_EPOCH: i32 = 44244; // 1980 jan 6
+const BAD_CLOCK: f64 = 999999.999999;
+
+fn satellite_lines(sp3: &Sp3Data) -> (Vec<usize>, String) {
+    // '+' and '++' lines, 17 ids per line and at least 5 lines of each as required by sp3-d
+    let mut sats = sp3.satellites.clone();
+    if sats.is_empty() {
+        sats = sp3
+            .epochs
+            .iter()
+            .flat_map(|e| e.records.iter().map(|r| r.prn))
+            .collect();
+    }
+    sats.sort();
+    sats.dedup();
+    let lines = sats.len().div_ceil(17).max(5);
+    let mut out = String::new();
+    for i in 0..lines {
+        out += &if i == 0 {
+            format!("+  {:3}   ", sats.len())
+        } else {
+            "+        ".to_string()
+        };
+        for j in 0..17 {
+            out += &match sats.get(i * 17 + j) {
+                Some(prn) => format!("G{:02}", prn),
+                None => "  0".to_string(),
+            };
+        }
+        out += "\n";
+    }
+    for _ in 0..lines {
+        // accuracy exponents unknown
+        out += &format!("++       {}\n", "  0".repeat(17));
+    }
+    (sats, out)
+}
+
+fn write_header<W: Write>(writer: &mut W, sp3: &Sp3Data) -> Result<Vec<usize>, Error> {
+    let (week, tow) = sp3
+        .epochs
+        .first()
+        .map(|e| (e.gps_week, e.gps_time))
+        .unwrap_or((0, 0.));
+    let (y, mo, d, h, mi, s) = date_from_gps_time(week, tow);
+    let velocities = sp3
+        .epochs
+        .iter()
+        .any(|e| e.records.iter().any(|r| r.velocity.is_some()));
+    let mjd = MJD_GPS_EPOCH + week * 7 + (tow / SECONDS_PER_DAY).floor() as i32;
+    let day_fraction = tow.rem_euclid(SECONDS_PER_DAY) / SECONDS_PER_DAY;
+    let time_system = if sp3.time_system.is_empty() {
+        "GPS"
+    } else {
+        &sp3.time_system
+    };
+
+    let mut out = format!(
+        "#d{}{:4} {:2} {:2} {:2} {:2} {:11.8} {:7} {:<5} {:<5} {:<3} {:<4}\n",
+        if velocities { 'V' } else { 'P' },
+        y,
+        mo,
+        d,
+        h,
+        mi,
+        s,
+        sp3.epochs.len(),
+        "ORBIT",
+        sp3.coordinate_system,
+        sp3.orbit_type,
+        sp3.agency
+    );
+    out += &format!(
+        "## {:4} {:15.8} {:14.8} {:5} {:15.13}\n",
+        week, tow, sp3.interval, mjd, day_fraction
+    );
+    let (sats, lines) = satellite_lines(sp3);
+    out += &lines;
+    out += &format!(
+        "%c G  cc {:<3} ccc cccc cccc cccc cccc ccccc ccccc ccccc ccccc\n",
+        time_system
+    );
+    out += "%c cc cc ccc ccc cccc cccc cccc cccc ccccc ccccc ccccc ccccc\n";
+    out += "%f  1.2500000  1.025000000  0.00000000000  0.000000000000000\n";
+    out += "%f  0.0000000  0.000000000  0.00000000000  0.000000000000000\n";
+    out += "%i    0    0    0    0      0      0      0      0         0\n";
+    out += "%i    0    0    0    0      0      0      0      0         0\n";
+    out += &format!("/* {:<77}\n", format!("written by rgps {}", run_date()));
+    for _ in 0..3 {
+        out += "/*\n";
+    }
+    writer.write_all(out.as_bytes())?;
+    Ok(sats)
+}
+
+pub fn write_sp3_to<W: Write>(writer: &mut W, sp3: &Sp3Data) -> Result<(), Error> {
+    // sp3-d, positions in km and clocks in microsec, velocity lines in dm/s and
+    // 1e-4 microsec/s when present
+    let sats = write_header(writer, sp3)?;
+    for epoch in &sp3.epochs {
+        let (y, mo, d, h, mi, s) = date_from_gps_time(epoch.gps_week, epoch.gps_time);
+        let mut out = format!("*  {:4} {:2} {:2} {:2} {:2} {:11.8}\n", y, mo, d, h, mi, s);
+        for &prn in &sats {
+            let r = match epoch.records.iter().find(|r| r.prn == prn) {
+                Some(r) => r,
+                None => continue,
+            };
+            let p = r.position * 1e-3;
+            out += &format!(
+                "PG{:02}{:14.6}{:14.6}{:14.6}{:14.6}\n",
+                prn,
+                p.x,
+                p.y,
+                p.z,
+                r.clock.map_or(BAD_CLOCK, |c| c * 1e6)
+            );
+            if let Some(v) = r.velocity {
+                let v = v * 10.;
+                out += &format!(
+                    "VG{:02}{:14.6}{:14.6}{:14.6}{:14.6}\n",
+                    prn,
+                    v.x,
+                    v.y,
+                    v.z,
+                    r.clock_rate.map_or(BAD_CLOCK, |c| c * 1e10)
+                );
+            }
+        }
+        writer.write_all(out.as_bytes())?;
+    }
+    writer.write_all(b"EOF\n")
+}
+
+pub fn write_sp3(filename: &str, sp3: &Sp3Data) -> Result<(), Error> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    write_sp3_to(&mut writer, sp3)?;
+    writer.flush()
+}
+
+pub fn broadcast_sp3(
+    ephemerides: &[SatelliteData],
+    gps_week: i32,
+    start: f64,
+    duration: f64,
+    interval: f64,
+    velocities: bool,
+) -> Sp3Data {
+    // broadcast orbits sampled every interval seconds over [start, start + duration).
+    // positions refer to the antenna phase centre in wgs84, clocks are the af0/af1/af2
+    // polynomial without tgd or the relativistic term, as in igs products
+    let mut prns: Vec<usize> = ephemerides.iter().map(|e| e.prn()).collect();
+    prns.sort();
+    prns.dedup();
+    let count = if interval > 0. {
+        (duration / interval - 1e-9).ceil().max(0.) as usize
+    } else {
+        0
+    };
+
+    let mut epochs = vec![];
+    let mut satellites = vec![];
+    for i in 0..count {
+        let t = start + i as f64 * interval;
+        let week = gps_week + (t / SECONDS_PER_WEEK).floor() as i32;
+        let tow = t.rem_euclid(SECONDS_PER_WEEK);
+        let mut records = vec![];
+        for &prn in &prns {
+            let eph = match select_ephemeris(ephemerides, prn, tow) {
+                Some(e) => e,
+                None => continue,
+            };
+            let state = eph.state_at(tow);
+            let (clock, _) = eph.broadcast();
+            let dt = wrap_week(tow - eph.toc());
+            records.push(Sp3Record {
+                prn,
+                position: state.position,
+                clock: Some(clock[0] + clock[1] * dt + clock[2] * dt * dt),
+                velocity: velocities.then_some(state.velocity),
+                clock_rate: velocities.then_some(clock[1] + 2. * clock[2] * dt),
+            });
+            satellites.push(prn);
+        }
+        epochs.push(Sp3Epoch {
+            gps_week: week,
+            gps_time: tow,
+            records,
+        });
+    }
+    satellites.sort();
+    satellites.dedup();
+    // no empty epochs before the first or after the last usable ephemeris
+    let first = epochs.iter().position(|e| !e.records.is_empty());
+    let last = epochs.iter().rposition(|e| !e.records.is_empty());
+    epochs = match (first, last) {
+        (Some(a), Some(b)) => epochs.drain(a..=b).collect(),
+        _ => vec![],
+    };
+
+    Sp3Data {
+        version: 'd',
+        agency: "RGPS".to_string(),
+        coordinate_system: "WGS84".to_string(),
+        orbit_type: "BCT".to_string(),
+        time_system: "GPS".to_string(),
+        interval,
+        satellites,
+        epochs,
+    }
+}
+
+pub fn broadcast_day(ephemerides: &[SatelliteData]) -> Option<(i32, f64)> {
+    // gps week and start of the day holding most of the clock reference times
+    let mut days: HashMap<(i32, i32), usize> = HashMap::new();
+    for eph in ephemerides {
+        let (week, toc) = eph.toc_epoch();
+        *days
+            .entry((week, (toc / SECONDS_PER_DAY).floor() as i32))
+            .or_default() += 1;
+    }
+    days.into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+        .map(|((week, day), _)| (week, day as f64 * SECONDS_PER_DAY))
+}
+
+pub fn nav_to_sp3(nav_filename: &str, sp3_filename: &str, interval: f64) -> Result<Sp3Data, Error> {
+    // one day of broadcast orbits from a rinex 2 navigation file
+    let ephemerides = rinex2_nav_all(nav_filename)?;
+    let (week, start) = broadcast_day(&ephemerides)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "no ephemerides in navigation file"))?;
+    let sp3 = broadcast_sp3(&ephemerides, week, start, SECONDS_PER_DAY, interval, false);
+    write_sp3(sp3_filename, &sp3)?;
+    Ok(sp3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broadcast_round_trip() {
+        let ephemerides = rinex2_nav_all("brdc2180.23n").unwrap();
+        let (week, start) = broadcast_day(&ephemerides).unwrap();
+        assert_eq!((week, start), (2274, 0.));
+        let sp3 = broadcast_sp3(&ephemerides, week, start, SECONDS_PER_DAY, 900., true);
+        // toe from 00:00 to 04:00, each valid for two hours either side, gives the
+        // epochs from 00:00 to 06:00 at 15 minutes
+        assert_eq!(sp3.epochs.len(), 25);
+        assert_eq!(sp3.epochs.last().unwrap().gps_time, 21600.);
+
+        let mut text = vec![];
+        write_sp3_to(&mut text, &sp3).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("#dV2023  8  6  0  0  0.00000000      25 ORBIT"));
+        let filename = std::env::temp_dir()
+            .join(format!("rgps_{}.sp3", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        std::fs::write(&filename, &text).unwrap();
+        let read = read_sp3(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+
+        assert_eq!(read.interval, 900.);
+        assert_eq!(read.satellites, sp3.satellites);
+        assert_eq!(read.epochs.len(), sp3.epochs.len());
+        for (r, e) in read.epochs.iter().zip(sp3.epochs.iter()) {
+            assert_eq!((r.gps_week, r.gps_time), (e.gps_week, e.gps_time));
+            assert_eq!(r.records.len(), e.records.len());
+            for (a, b) in r.records.iter().zip(e.records.iter()) {
+                // 1 mm, 1e-12 s, 1e-7 m/s and 1e-16 s/s in the file
+                assert_eq!(a.prn, b.prn);
+                assert!((a.position - b.position).abs().max() <= 0.5e-3 + 1e-9);
+                assert!((a.clock.unwrap() - b.clock.unwrap()).abs() <= 0.5e-12 + 1e-18);
+                let dv = a.velocity.unwrap() - b.velocity.unwrap();
+                assert!(dv.abs().max() <= 0.5e-7 + 1e-12);
+                let dr = a.clock_rate.unwrap() - b.clock_rate.unwrap();
+                assert!(dr.abs() <= 0.5e-16 + 1e-22);
+            }
+        }
+    }
+}
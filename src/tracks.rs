@@ -0,0 +1,516 @@
+use crate::dgps::*;
+use crate::earth::*;
+use crate::ekf::*;
+use crate::nmea::*;
+use crate::observables::*;
+use crate::rtk::*;
+use crate::satellites::*;
+use crate::solver::*;
+use crate::ubx::*;
+use nalgebra::*;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+
+#[derive(Clone, Debug)]
+pub struct TrackPoint {
+    pub gps_week: i32,
+    pub gps_time: f64,
+    pub lla: Vector3<f64>, // deg, deg, m above the ellipsoid
+    pub quality: u8,       // gga fix quality, 0 invalid, 1 gps, 2 dgps, 4 rtk fixed, 5 rtk float
+    pub satellites: usize,
+    pub hdop: Option<f64>,
+    pub vdop: Option<f64>,
+    pub pdop: Option<f64>,
+}
+
+impl TrackPoint {
+    pub fn new(gps_week: i32, gps_time: f64, position: Vector3<f64>, quality: u8) -> Self {
+        TrackPoint {
+            gps_week,
+            gps_time,
+            lla: ecef2geodetic(position, 0),
+            quality,
+            satellites: 0,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+        }
+    }
+}
+
+impl From<&PositionSolution> for TrackPoint {
+    fn from(s: &PositionSolution) -> Self {
+        TrackPoint {
+            satellites: s.prns.len(),
+            hdop: Some(s.hdop),
+            vdop: Some(s.vdop),
+            pdop: Some(s.pdop),
+            ..TrackPoint::new(s.gps_week, s.gps_time, s.position, 1)
+        }
+    }
+}
+
+impl From<&DgpsSolution> for TrackPoint {
+    fn from(s: &DgpsSolution) -> Self {
+        TrackPoint {
+            quality: 2,
+            ..TrackPoint::from(&s.solution)
+        }
+    }
+}
+
+impl From<&RtkSolution> for TrackPoint {
+    fn from(s: &RtkSolution) -> Self {
+        let quality = match s.status {
+            FixStatus::Fixed => 4,
+            FixStatus::Float => 5,
+        };
+        TrackPoint {
+            satellites: s.satellites,
+            ..TrackPoint::new(s.gps_week, s.gps_time, s.position, quality)
+        }
+    }
+}
+
+impl From<&EkfSolution> for TrackPoint {
+    fn from(s: &EkfSolution) -> Self {
+        TrackPoint {
+            satellites: s.used,
+            ..TrackPoint::new(s.gps_week, s.gps_time, s.position, 1)
+        }
+    }
+}
+
+impl From<&NmeaFix> for TrackPoint {
+    fn from(f: &NmeaFix) -> Self {
+        TrackPoint {
+            gps_week: f.gps_week,
+            gps_time: f.gps_time,
+            lla: f.lla,
+            quality: f.quality,
+            satellites: f.satellites,
+            hdop: f.hdop,
+            vdop: None,
+            pdop: None,
+        }
+    }
+}
+
+impl From<&UbxNavPvt> for TrackPoint {
+    fn from(p: &UbxNavPvt) -> Self {
+        TrackPoint {
+            gps_week: p.gps_week,
+            gps_time: p.gps_time,
+            lla: p.lla,
+            quality: p.quality(),
+            satellites: p.satellites,
+            hdop: None,
+            vdop: None,
+            pdop: Some(p.pdop),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub name: String,
+    pub points: Vec<TrackPoint>,
+}
+
+impl Track {
+    pub fn new<'a, T: 'a>(name: &str, solutions: impl IntoIterator<Item = &'a T>) -> Self
+    where
+        TrackPoint: From<&'a T>,
+    {
+        Track {
+            name: name.to_string(),
+            points: solutions.into_iter().map(TrackPoint::from).collect(),
+        }
+    }
+
+    fn segments(&self) -> Vec<&[TrackPoint]> {
+        // split where the track crosses the antimeridian so lines do not wrap the globe
+        let mut segments = vec![];
+        let mut start = 0;
+        for i in 1..self.points.len() {
+            if (self.points[i].lla[1] - self.points[i - 1].lla[1]).abs() > 180. {
+                segments.push(&self.points[start..i]);
+                start = i;
+            }
+        }
+        if start < self.points.len() {
+            segments.push(&self.points[start..]);
+        }
+        segments
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Waypoint {
+    pub name: String,
+    pub point: TrackPoint,
+}
+
+pub fn ground_track(
+    ephemeris: &SatelliteData,
+    gps_week: i32,
+    start: f64,
+    duration: f64,
+    step: f64,
+) -> Track {
+    // sub-satellite points every step seconds from a single ephemeris, on the ground
+    let n = if step > 0. {
+        (duration / step).floor() as usize + 1
+    } else {
+        1
+    };
+    let mut sv = ephemeris.clone();
+    sv.propagate(DVector::from_fn(n, |i, _| start + i as f64 * step));
+    let points = sv
+        .trajectory()
+        .into_iter()
+        .map(|(t, position)| {
+            let mut point = TrackPoint::new(
+                gps_week + (t / SECONDS_PER_WEEK).floor() as i32,
+                t.rem_euclid(SECONDS_PER_WEEK),
+                position,
+                1,
+            );
+            point.lla[2] = 0.;
+            point
+        })
+        .collect();
+    Track {
+        name: format!("G{:02}", ephemeris.prn()),
+        points,
+    }
+}
+
+pub fn ground_tracks(
+    ephemerides: &[SatelliteData],
+    gps_week: i32,
+    start: f64,
+    duration: f64,
+    step: f64,
+) -> Vec<Track> {
+    // one track per satellite from the healthy ephemeris with toe closest to the middle
+    closest_ephemerides(ephemerides, start + duration / 2.)
+        .into_iter()
+        .map(|eph| ground_track(eph, gps_week, start, duration, step))
+        .collect()
+}
+
+pub fn subsatellite_points(
+    ephemerides: &[SatelliteData],
+    gps_week: i32,
+    gps_time: f64,
+) -> Vec<Waypoint> {
+    // healthy satellites with a valid ephemeris at gps_time, on the ground
+    let mut prns: Vec<usize> = ephemerides.iter().map(|e| e.prn()).collect();
+    prns.sort();
+    prns.dedup();
+    prns.into_iter()
+        .filter_map(|prn| {
+            let mut sv = select_ephemeris(ephemerides, prn, gps_time)?.clone();
+            sv.propagate(DVector::from_element(1, gps_time));
+            let (_, position) = sv.trajectory()[0];
+            let mut point = TrackPoint::new(gps_week, gps_time, position, 1);
+            point.lla[2] = 0.;
+            Some(Waypoint {
+                name: format!("G{:02}", prn),
+                point,
+            })
+        })
+        .collect()
+}
+
+fn quality_style(quality: u8) -> (&'static str, [u8; 3]) {
+    // label and colour, green fixed, yellow float, blue dgps, red single
+    match quality {
+        0 => ("invalid", [128, 128, 128]),
+        1 => ("single", [255, 0, 0]),
+        2 => ("dgps", [0, 0, 255]),
+        4 => ("rtk fixed", [0, 255, 0]),
+        5 => ("rtk float", [255, 255, 0]),
+        _ => ("other", [255, 255, 255]),
+    }
+}
+
+const QUALITIES: [u8; 6] = [0, 1, 2, 4, 5, 6];
+
+fn iso_time(gps_week: i32, gps_time: f64, leap_seconds: i32) -> String {
+    // utc to the millisecond, rounded before splitting into the date
+    let t = ((gps_time - leap_seconds as f64) * 1e3).round() / 1e3;
+    let (y, mo, d, h, mi, s) = date_from_gps_time(gps_week, t);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:06.3}Z",
+        y,
+        mo,
+        d,
+        h,
+        mi,
+        (s * 1e3).round() / 1e3
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn json_escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '"' => "\\\"".to_string(),
+            '\\' => "\\\\".to_string(),
+            c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrackFormat {
+    Kml,
+    Gpx,
+    GeoJson,
+}
+
+impl TrackFormat {
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        let extension = filename.rsplit_once('.')?.1.to_lowercase();
+        match extension.as_str() {
+            "kml" => Some(TrackFormat::Kml),
+            "gpx" => Some(TrackFormat::Gpx),
+            "geojson" | "json" => Some(TrackFormat::GeoJson),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackExport {
+    pub tracks: Vec<Track>,
+    pub waypoints: Vec<Waypoint>,
+    pub leap_seconds: i32, // gps - utc, for the utc time stamps
+}
+
+impl Default for TrackExport {
+    fn default() -> Self {
+        TrackExport {
+            tracks: vec![],
+            waypoints: vec![],
+            leap_seconds: 18,
+        }
+    }
+}
+
+impl TrackExport {
+    fn kml_point(&self, name: &str, p: &TrackPoint) -> String {
+        let mut data = format!(
+            "<Data name=\"quality\"><value>{}</value></Data><Data name=\"satellites\"><value>{}</value></Data>",
+            p.quality, p.satellites
+        );
+        for (label, dop) in [("hdop", p.hdop), ("vdop", p.vdop), ("pdop", p.pdop)] {
+            if let Some(dop) = dop {
+                data += &format!("<Data name=\"{}\"><value>{:.2}</value></Data>", label, dop);
+            }
+        }
+        format!(
+            "<Placemark><name>{}</name><styleUrl>#q{}</styleUrl><TimeStamp><when>{}</when></TimeStamp>\
+             <ExtendedData>{}</ExtendedData><Point><altitudeMode>absolute</altitudeMode>\
+             <coordinates>{:.9},{:.9},{:.3}</coordinates></Point></Placemark>\n",
+            xml_escape(name),
+            if QUALITIES.contains(&p.quality) { p.quality } else { 6 },
+            iso_time(p.gps_week, p.gps_time, self.leap_seconds),
+            data,
+            p.lla[1],
+            p.lla[0],
+            p.lla[2]
+        )
+    }
+
+    pub fn write_kml<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        // one folder per track with the line and a placemark per epoch styled by fix
+        // quality, then the waypoints
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n",
+        );
+        for q in QUALITIES {
+            let (label, [r, g, b]) = quality_style(q);
+            out += &format!(
+                "<Style id=\"q{}\"><IconStyle><color>ff{:02x}{:02x}{:02x}</color><scale>0.5</scale>\
+                 <Icon><href>http://maps.google.com/mapfiles/kml/shapes/shaded_dot.png</href></Icon>\
+                 </IconStyle><LabelStyle><scale>0</scale></LabelStyle><BalloonStyle><text>{} $[name] $[description]</text></BalloonStyle></Style>\n",
+                q, b, g, r, label
+            );
+        }
+        out += "<Style id=\"line\"><LineStyle><color>ffffffff</color><width>2</width></LineStyle></Style>\n";
+        writer.write_all(out.as_bytes())?;
+
+        for track in &self.tracks {
+            let mut out = format!("<Folder><name>{}</name>\n", xml_escape(&track.name));
+            out += &format!(
+                "<Placemark><name>{}</name><styleUrl>#line</styleUrl><MultiGeometry>\n",
+                xml_escape(&track.name)
+            );
+            for segment in track.segments() {
+                out += "<LineString><altitudeMode>absolute</altitudeMode><coordinates>\n";
+                for p in segment {
+                    out += &format!("{:.9},{:.9},{:.3}\n", p.lla[1], p.lla[0], p.lla[2]);
+                }
+                out += "</coordinates></LineString>\n";
+            }
+            out += "</MultiGeometry></Placemark>\n";
+            for p in &track.points {
+                out += &self.kml_point(&iso_time(p.gps_week, p.gps_time, self.leap_seconds), p);
+            }
+            out += "</Folder>\n";
+            writer.write_all(out.as_bytes())?;
+        }
+        for w in &self.waypoints {
+            writer.write_all(self.kml_point(&w.name, &w.point).as_bytes())?;
+        }
+        writer.write_all(b"</Document>\n</kml>\n")
+    }
+
+    fn gpx_point(&self, tag: &str, name: Option<&str>, p: &TrackPoint) -> String {
+        // gpx has no rtk fix type, rtk solutions are reported as dgps with the type
+        let fix = match p.quality {
+            0 => "none",
+            1 => "3d",
+            _ => "dgps",
+        };
+        let mut out = format!(
+            "<{} lat=\"{:.9}\" lon=\"{:.9}\"><ele>{:.3}</ele><time>{}</time>",
+            tag,
+            p.lla[0],
+            p.lla[1],
+            p.lla[2],
+            iso_time(p.gps_week, p.gps_time, self.leap_seconds)
+        );
+        if let Some(name) = name {
+            out += &format!("<name>{}</name>", xml_escape(name));
+        }
+        out += &format!(
+            "<type>{}</type><fix>{}</fix><sat>{}</sat>",
+            quality_style(p.quality).0,
+            fix,
+            p.satellites
+        );
+        for (label, dop) in [("hdop", p.hdop), ("vdop", p.vdop), ("pdop", p.pdop)] {
+            if let Some(dop) = dop {
+                out += &format!("<{}>{:.2}</{}>", label, dop, label);
+            }
+        }
+        out + &format!("</{}>\n", tag)
+    }
+
+    pub fn write_gpx<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        // gpx 1.1, waypoints first as the schema requires
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<gpx version=\"1.1\" creator=\"rgps\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+        );
+        for w in &self.waypoints {
+            out += &self.gpx_point("wpt", Some(&w.name), &w.point);
+        }
+        writer.write_all(out.as_bytes())?;
+        for track in &self.tracks {
+            let mut out = format!("<trk><name>{}</name>\n", xml_escape(&track.name));
+            for segment in track.segments() {
+                out += "<trkseg>\n";
+                for p in segment {
+                    out += &self.gpx_point("trkpt", None, p);
+                }
+                out += "</trkseg>\n";
+            }
+            out += "</trk>\n";
+            writer.write_all(out.as_bytes())?;
+        }
+        writer.write_all(b"</gpx>\n")
+    }
+
+    fn geojson_point(&self, properties: &str, p: &TrackPoint) -> String {
+        let (label, [r, g, b]) = quality_style(p.quality);
+        let mut out = format!(
+            "{{\"type\":\"Feature\",\"geometry\":{{\"type\":\"Point\",\"coordinates\":[{:.9},{:.9},{:.3}]}},\
+             \"properties\":{{{}\"time\":\"{}\",\"gps_week\":{},\"gps_time\":{:.3},\"quality\":{},\
+             \"fix\":\"{}\",\"marker-color\":\"#{:02x}{:02x}{:02x}\",\"satellites\":{}",
+            p.lla[1],
+            p.lla[0],
+            p.lla[2],
+            properties,
+            iso_time(p.gps_week, p.gps_time, self.leap_seconds),
+            p.gps_week,
+            p.gps_time,
+            p.quality,
+            label,
+            r,
+            g,
+            b,
+            p.satellites
+        );
+        for (label, dop) in [("hdop", p.hdop), ("vdop", p.vdop), ("pdop", p.pdop)] {
+            if let Some(dop) = dop {
+                out += &format!(",\"{}\":{:.2}", label, dop);
+            }
+        }
+        out + "}}"
+    }
+
+    pub fn write_geojson<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        // a line feature per track followed by a point feature per epoch carrying the
+        // time, fix quality, colour and dops, then the waypoints
+        let mut features = vec![];
+        for track in &self.tracks {
+            let lines: Vec<String> = track
+                .segments()
+                .iter()
+                .map(|segment| {
+                    let coordinates: Vec<String> = segment
+                        .iter()
+                        .map(|p| format!("[{:.9},{:.9},{:.3}]", p.lla[1], p.lla[0], p.lla[2]))
+                        .collect();
+                    format!("[{}]", coordinates.join(","))
+                })
+                .collect();
+            let name = json_escape(&track.name);
+            features.push(format!(
+                "{{\"type\":\"Feature\",\"geometry\":{{\"type\":\"MultiLineString\",\"coordinates\":[{}]}},\
+                 \"properties\":{{\"track\":\"{}\"}}}}",
+                lines.join(","),
+                name
+            ));
+            for p in &track.points {
+                features.push(self.geojson_point(&format!("\"track\":\"{}\",", name), p));
+            }
+        }
+        for w in &self.waypoints {
+            features.push(
+                self.geojson_point(&format!("\"name\":\"{}\",", json_escape(&w.name)), &w.point),
+            );
+        }
+        writer.write_all(b"{\"type\":\"FeatureCollection\",\"features\":[\n")?;
+        writer.write_all(features.join(",\n").as_bytes())?;
+        writer.write_all(b"\n]}\n")
+    }
+
+    pub fn write(&self, filename: &str) -> Result<(), Error> {
+        // format from the extension, .kml, .gpx or .geojson/.json
+        let format = TrackFormat::from_filename(filename).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown track format for {}", filename),
+            )
+        })?;
+        let mut writer = BufWriter::new(File::create(filename)?);
+        match format {
+            TrackFormat::Kml => self.write_kml(&mut writer)?,
+            TrackFormat::Gpx => self.write_gpx(&mut writer)?,
+            TrackFormat::GeoJson => self.write_geojson(&mut writer)?,
+        }
+        writer.flush()
+    }
+}
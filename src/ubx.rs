@@ -0,0 +1,851 @@
+use crate::earth::*;
+use crate::nmea::*;
+use crate::observables::*;
+use crate::rtcm::*;
+use crate::satellites::*;
+use nalgebra::*;
+use std::collections::HashMap;
+use std::f64::consts::*;
+use std::fs::File;
+use std::io::{Error, Read};
+
+const SYNC: [u8; 2] = [0xB5, 0x62];
+const MAX_PAYLOAD: usize = 8192; // longer lengths are treated as a false sync
+const LNAV_PREAMBLE: u64 = 0x8B;
+
+fn p2(n: i32) -> f64 {
+    2f64.powi(n)
+}
+
+pub fn ubx_checksum(data: &[u8]) -> (u8, u8) {
+    // 8 bit fletcher over class, id, length and payload
+    let (mut a, mut b) = (0u8, 0u8);
+    for &x in data {
+        a = a.wrapping_add(x);
+        b = b.wrapping_add(a);
+    }
+    (a, b)
+}
+
+fn system(gnss_id: u8) -> Option<GnssSystem> {
+    match gnss_id {
+        0 => Some(GnssSystem::Gps),
+        1 => Some(GnssSystem::Sbas),
+        2 => Some(GnssSystem::Galileo),
+        3 => Some(GnssSystem::Beidou),
+        5 => Some(GnssSystem::Qzss),
+        6 => Some(GnssSystem::Glonass),
+        _ => None,
+    }
+}
+
+fn gps_band(signal: u8) -> Option<Band> {
+    // ubx gps signal ids: 0 L1C/A, 3 L2CL, 4 L2CM, 6 L5I, 7 L5Q
+    match signal {
+        0 => Some(Band::L1),
+        3 | 4 => Some(Band::L2),
+        6 | 7 => Some(Band::L5),
+        _ => None,
+    }
+}
+
+// little endian field readers, offsets into the payload
+fn u1(p: &[u8], i: usize) -> u8 {
+    p[i]
+}
+
+fn i1(p: &[u8], i: usize) -> i8 {
+    p[i] as i8
+}
+
+fn u2(p: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([p[i], p[i + 1]])
+}
+
+fn i2(p: &[u8], i: usize) -> i16 {
+    u2(p, i) as i16
+}
+
+fn u4(p: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([p[i], p[i + 1], p[i + 2], p[i + 3]])
+}
+
+fn i4(p: &[u8], i: usize) -> i32 {
+    u4(p, i) as i32
+}
+
+fn r4(p: &[u8], i: usize) -> f32 {
+    f32::from_bits(u4(p, i))
+}
+
+fn r8(p: &[u8], i: usize) -> f64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&p[i..i + 8]);
+    f64::from_le_bytes(b)
+}
+
+#[derive(Clone, Debug)]
+pub struct UbxMeasurement {
+    pub system: GnssSystem,
+    pub prn: usize,
+    pub signal: u8,                 // ubx signal id
+    pub frequency_slot: i8,         // glonass k, 0 for the other systems
+    pub pseudorange: Option<f64>,   // m
+    pub carrier_phase: Option<f64>, // cycles
+    pub doppler: f64,               // Hz, positive for approaching satellites
+    pub cn0: f64,                   // dB-Hz
+    pub lock_time: f64,             // sec
+    pub half_cycle: bool,           // half cycle ambiguity resolved
+    pub pseudorange_sigma: f64,     // m
+    pub phase_sigma: f64,           // cycles
+    pub lli: bool,                  // lock time went back since the last epoch
+}
+
+#[derive(Clone, Debug)]
+pub struct UbxRawx {
+    // RXM-RAWX
+    pub gps_week: i32,
+    pub gps_time: f64, // receive time of week (sec)
+    pub leap_seconds: Option<i8>,
+    pub clock_reset: bool,
+    pub measurements: Vec<UbxMeasurement>,
+}
+
+impl UbxRawx {
+    pub fn observations(&self) -> ObservationEpoch {
+        // gps measurements as an observation epoch, one signal per band
+        let mut obs: Vec<Observation> = vec![];
+        for m in self.measurements.iter() {
+            if m.system != GnssSystem::Gps {
+                continue;
+            }
+            let band = match gps_band(m.signal) {
+                Some(b) => b,
+                None => continue,
+            };
+            if obs.iter().any(|o| o.prn == m.prn && o.band == band) {
+                continue;
+            }
+            obs.push(Observation {
+                prn: m.prn,
+                band,
+                pseudorange: m.pseudorange.unwrap_or(0.),
+                carrier_phase: m.carrier_phase.unwrap_or(0.),
+                doppler: m.doppler,
+                cn0: m.cn0,
+                lli: m.lli || (m.carrier_phase.is_some() && !m.half_cycle),
+            });
+        }
+        ObservationEpoch {
+            gps_week: self.gps_week,
+            gps_time: self.gps_time,
+            obs,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UbxSfrbx {
+    // RXM-SFRBX, words as sent by the receiver (gps lnav: 30 bit words with parity
+    // in the low 6 bits, already polarity corrected)
+    pub system: GnssSystem,
+    pub prn: usize,
+    pub signal: u8,
+    pub frequency_slot: i8,
+    pub words: Vec<u32>,
+}
+
+impl UbxSfrbx {
+    pub fn lnav_subframe(&self) -> Option<(u8, [u8; 30])> {
+        // gps l1 c/a subframe id and the 240 data bits without parity
+        if self.system != GnssSystem::Gps || self.words.len() < 10 {
+            return None;
+        }
+        let mut data = [0u8; 30];
+        for (k, w) in self.words[..10].iter().enumerate() {
+            let bits = (w >> 6) & 0xFF_FFFF;
+            data[3 * k] = (bits >> 16) as u8;
+            data[3 * k + 1] = (bits >> 8) as u8;
+            data[3 * k + 2] = bits as u8;
+        }
+        if bits_u(&data, 0, 8) != LNAV_PREAMBLE {
+            return None;
+        }
+        Some((bits_u(&data, 43, 3) as u8, data))
+    }
+}
+
+fn bits_u(data: &[u8], start: usize, n: usize) -> u64 {
+    (start..start + n).fold(0, |v, k| {
+        (v << 1) | ((data[k / 8] >> (7 - k % 8)) & 1) as u64
+    })
+}
+
+fn bits_s(data: &[u8], start: usize, n: usize) -> i64 {
+    let v = bits_u(data, start, n);
+    if (v >> (n - 1)) & 1 == 1 {
+        v as i64 - (1i64 << n)
+    } else {
+        v as i64
+    }
+}
+
+fn decode_lnav(prn: usize, sf: &[[u8; 30]; 3], reference_week: i32) -> Option<SatelliteData> {
+    // subframes 1 to 3, bit offsets into the 240 data bits, IS-GPS-200 scale factors
+    let (s1, s2, s3) = (&sf[0], &sf[1], &sf[2]);
+    let iodc = ((bits_u(s1, 70, 2) << 8) | bits_u(s1, 168, 8)) as f64;
+    let iode = bits_u(s2, 48, 8);
+    if iode != bits_u(s3, 216, 8) || iode != iodc as u64 & 0xFF {
+        return None;
+    }
+    let f = |s: &[u8; 30], start: usize, n: usize, scale: f64| bits_s(s, start, n) as f64 * scale;
+    let uf = |s: &[u8; 30], start: usize, n: usize, scale: f64| bits_u(s, start, n) as f64 * scale;
+
+    let week = resolve_week(bits_u(s1, 48, 10) as i32, 1024, reference_week);
+    let l2_codes = uf(s1, 58, 2, 1.);
+    let ura = URA[bits_u(s1, 60, 4) as usize];
+    let health = uf(s1, 64, 6, 1.);
+    let l2_p = uf(s1, 72, 1, 1.);
+    let tgd = f(s1, 160, 8, p2(-31));
+    let toc = uf(s1, 176, 16, 16.);
+    let af2 = f(s1, 192, 8, p2(-55));
+    let af1 = f(s1, 200, 16, p2(-43));
+    let af0 = f(s1, 216, 22, p2(-31));
+    // the how tow count is the start of the next subframe
+    let transmission_time = uf(s1, 24, 17, 6.) - 6.;
+
+    let crs = f(s2, 56, 16, p2(-5));
+    let delta_n = f(s2, 72, 16, p2(-43)) * PI;
+    let m0 = f(s2, 88, 32, p2(-31)) * PI;
+    let cuc = f(s2, 120, 16, p2(-29));
+    let e = uf(s2, 136, 32, p2(-33));
+    let cus = f(s2, 168, 16, p2(-29));
+    let sqrt_a = uf(s2, 184, 32, p2(-19));
+    let toe = uf(s2, 216, 16, 16.);
+    let fit = if bits_u(s2, 232, 1) == 1 { 6. } else { 4. };
+
+    let cic = f(s3, 48, 16, p2(-29));
+    let raan = f(s3, 64, 32, p2(-31)) * PI;
+    let cis = f(s3, 96, 16, p2(-29));
+    let i0 = f(s3, 112, 32, p2(-31)) * PI;
+    let crc = f(s3, 144, 16, p2(-5));
+    let aop = f(s3, 160, 32, p2(-31)) * PI;
+    let raandot = f(s3, 192, 24, p2(-43)) * PI;
+    let idot = f(s3, 224, 14, p2(-43)) * PI;
+
+    Some(SatelliteData::from_broadcast(
+        prn,
+        toc_week(week, toe, toc),
+        toc,
+        [af0, af1, af2],
+        [
+            iode as f64,
+            crs,
+            delta_n,
+            m0,
+            cuc,
+            e,
+            cus,
+            sqrt_a,
+            toe,
+            cic,
+            raan,
+            cis,
+            i0,
+            crc,
+            aop,
+            raandot,
+            idot,
+            l2_codes,
+            week as f64,
+            l2_p,
+            ura,
+            health,
+            tgd,
+            iodc,
+            transmission_time,
+            fit,
+        ],
+    ))
+}
+
+#[derive(Clone, Debug)]
+pub struct UbxNavPvt {
+    pub gps_week: i32,
+    pub gps_time: f64,
+    pub fix_type: u8, // 0 none, 2 2d, 3 3d, 4 gnss + dead reckoning, 5 time only
+    pub fix_ok: bool,
+    pub differential: bool,
+    pub carrier_solution: u8, // 0 none, 1 float, 2 fixed
+    pub satellites: usize,
+    pub lla: Vector3<f64>,        // deg, deg, m above the ellipsoid
+    pub height_msl: f64,          // m
+    pub horizontal_accuracy: f64, // m
+    pub vertical_accuracy: f64,   // m
+    pub velocity: Vector3<f64>,   // north, east, down (m/s)
+    pub speed_accuracy: f64,      // m/s
+    pub pdop: f64,
+}
+
+impl UbxNavPvt {
+    pub fn position(&self) -> Vector3<f64> {
+        geodetic2ecef(self.lla, 0)
+    }
+
+    pub fn quality(&self) -> u8 {
+        // as the gga fix quality
+        match (self.fix_ok && self.fix_type >= 2, self.carrier_solution) {
+            (false, _) => 0,
+            (true, 2) => 4,
+            (true, 1) => 5,
+            _ if self.differential => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UbxSatInfo {
+    pub system: GnssSystem,
+    pub prn: usize,
+    pub cn0: f64,       // dB-Hz
+    pub elevation: f64, // deg, -91 if unknown
+    pub azimuth: f64,   // deg
+    pub residual: f64,  // pseudorange residual (m)
+    pub quality: u8,    // signal quality indicator 0..7
+    pub used: bool,
+    pub health: u8, // 0 unknown, 1 healthy, 2 unhealthy
+}
+
+#[derive(Clone, Debug)]
+pub struct UbxNavSat {
+    pub gps_time: f64, // sec of week
+    pub satellites: Vec<UbxSatInfo>,
+}
+
+impl UbxNavSat {
+    pub fn views(&self) -> Vec<SatView> {
+        // gps satellites with a known direction, for nmea gsv output
+        self.satellites
+            .iter()
+            .filter(|s| s.system == GnssSystem::Gps && s.elevation >= -90.)
+            .map(|s| SatView {
+                prn: s.prn,
+                elevation: s.elevation,
+                azimuth: s.azimuth,
+                cn0: if s.cn0 > 0. { Some(s.cn0) } else { None },
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum UbxMessage {
+    Rawx(UbxRawx),
+    Sfrbx(UbxSfrbx),
+    // gps ephemeris assembled from sfrbx subframes 1 to 3
+    Ephemeris(Box<SatelliteData>),
+    NavPvt(UbxNavPvt),
+    NavSat(UbxNavSat),
+    Unsupported(u8, u8), // class, id
+}
+
+pub struct UbxDecoder {
+    buffer: Vec<u8>,
+    pub gps_week: i32, // reference for the 10 bit lnav week, updated from RAWX
+    pub checksum_errors: usize,
+    locks: HashMap<(GnssSystem, usize, u8), f64>,
+    subframes: HashMap<usize, [Option<[u8; 30]>; 3]>,
+    ephemerides: HashMap<usize, (u64, u64)>, // last iode and toe sent per prn
+}
+
+impl UbxDecoder {
+    pub fn new(gps_week: i32) -> Self {
+        UbxDecoder {
+            buffer: vec![],
+            gps_week,
+            checksum_errors: 0,
+            locks: HashMap::new(),
+            subframes: HashMap::new(),
+            ephemerides: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<UbxMessage> {
+        // messages found in the stream so far, a bad checksum or length drops the
+        // first sync byte and searches for the next sync pair
+        self.buffer.extend_from_slice(bytes);
+        let mut messages = vec![];
+        let mut start = 0;
+        loop {
+            match self.buffer[start..].windows(2).position(|w| w == SYNC) {
+                Some(k) => start += k,
+                None => {
+                    // keep a trailing first sync byte
+                    start = self.buffer.len().saturating_sub(1).max(start);
+                    if self.buffer.last() != Some(&SYNC[0]) {
+                        start = self.buffer.len();
+                    }
+                    break;
+                }
+            }
+            if self.buffer.len() < start + 6 {
+                break;
+            }
+            let length = u2(&self.buffer, start + 4) as usize;
+            if length > MAX_PAYLOAD {
+                start += 1;
+                continue;
+            }
+            if self.buffer.len() < start + length + 8 {
+                break;
+            }
+            let frame = &self.buffer[start + 2..start + length + 8];
+            let (a, b) = ubx_checksum(&frame[..length + 4]);
+            if (a, b) != (frame[length + 4], frame[length + 5]) {
+                self.checksum_errors += 1;
+                start += 1;
+                continue;
+            }
+            let (class, id) = (frame[0], frame[1]);
+            let payload = frame[4..length + 4].to_vec();
+            messages.extend(self.decode(class, id, &payload));
+            start += length + 8;
+        }
+        self.buffer.drain(..start);
+        messages
+    }
+
+    pub fn decode(&mut self, class: u8, id: u8, payload: &[u8]) -> Vec<UbxMessage> {
+        // one message payload, an sfrbx completing an ephemeris also returns it
+        match (class, id) {
+            (0x02, 0x15) => self
+                .decode_rawx(payload)
+                .map(UbxMessage::Rawx)
+                .into_iter()
+                .collect(),
+            (0x02, 0x13) => match decode_sfrbx(payload) {
+                Some(s) => {
+                    let ephemeris = self.add_subframe(&s);
+                    let mut messages = vec![UbxMessage::Sfrbx(s)];
+                    messages.extend(ephemeris.map(|e| UbxMessage::Ephemeris(Box::new(e))));
+                    messages
+                }
+                None => vec![],
+            },
+            (0x01, 0x07) => decode_nav_pvt(payload, self.gps_week)
+                .map(UbxMessage::NavPvt)
+                .into_iter()
+                .collect(),
+            (0x01, 0x35) => decode_nav_sat(payload)
+                .map(UbxMessage::NavSat)
+                .into_iter()
+                .collect(),
+            _ => vec![UbxMessage::Unsupported(class, id)],
+        }
+    }
+
+    fn decode_rawx(&mut self, p: &[u8]) -> Option<UbxRawx> {
+        if p.len() < 16 {
+            return None;
+        }
+        let count = u1(p, 11) as usize;
+        if p.len() < 16 + 32 * count {
+            return None;
+        }
+        let status = u1(p, 12);
+        let gps_week = u2(p, 8) as i32;
+        self.gps_week = gps_week;
+        let mut measurements = vec![];
+        for k in 0..count {
+            let m = &p[16 + 32 * k..48 + 32 * k];
+            let system = match system(u1(m, 20)) {
+                Some(s) => s,
+                None => continue,
+            };
+            let prn = u1(m, 21) as usize;
+            let signal = u1(m, 22);
+            let tracking = u1(m, 30);
+            let lock_time = u2(m, 24) as f64 * 1e-3;
+            // a lock time below the last one means the phase was reacquired
+            let key = (system, prn, signal);
+            let lli = self.locks.get(&key).is_some_and(|&last| lock_time < last);
+            self.locks.insert(key, lock_time);
+            measurements.push(UbxMeasurement {
+                system,
+                prn,
+                signal,
+                frequency_slot: if system == GnssSystem::Glonass {
+                    u1(m, 23) as i8 - 7
+                } else {
+                    0
+                },
+                pseudorange: if tracking & 1 != 0 {
+                    Some(r8(m, 0))
+                } else {
+                    None
+                },
+                carrier_phase: if tracking & 2 != 0 {
+                    Some(r8(m, 8))
+                } else {
+                    None
+                },
+                doppler: r4(m, 16) as f64,
+                cn0: u1(m, 26) as f64,
+                lock_time,
+                half_cycle: tracking & 4 != 0,
+                pseudorange_sigma: 0.01 * p2((u1(m, 27) & 0x0F) as i32),
+                phase_sigma: 0.004 * (u1(m, 28) & 0x0F) as f64,
+                lli,
+            });
+        }
+        Some(UbxRawx {
+            gps_week,
+            gps_time: r8(p, 0),
+            leap_seconds: if status & 1 != 0 {
+                Some(i1(p, 10))
+            } else {
+                None
+            },
+            clock_reset: status & 2 != 0,
+            measurements,
+        })
+    }
+
+    fn add_subframe(&mut self, sfrbx: &UbxSfrbx) -> Option<SatelliteData> {
+        // keeps the last subframes 1 to 3 of each gps satellite and returns the
+        // ephemeris once a new consistent set is complete
+        let (id, data) = sfrbx.lnav_subframe()?;
+        if !(1..=3).contains(&id) {
+            return None;
+        }
+        let slots = self.subframes.entry(sfrbx.prn).or_insert([None; 3]);
+        slots[id as usize - 1] = Some(data);
+        let sf = [slots[0]?, slots[1]?, slots[2]?];
+        let eph = decode_lnav(sfrbx.prn, &sf, self.gps_week)?;
+        let key = (eph.iode() as u64, bits_u(&sf[1], 216, 16));
+        if self.ephemerides.get(&sfrbx.prn) == Some(&key) {
+            return None;
+        }
+        self.ephemerides.insert(sfrbx.prn, key);
+        Some(eph)
+    }
+}
+
+fn decode_sfrbx(p: &[u8]) -> Option<UbxSfrbx> {
+    if p.len() < 8 {
+        return None;
+    }
+    let count = u1(p, 4) as usize;
+    if p.len() < 8 + 4 * count {
+        return None;
+    }
+    let system = system(u1(p, 0))?;
+    Some(UbxSfrbx {
+        system,
+        prn: u1(p, 1) as usize,
+        signal: u1(p, 2),
+        frequency_slot: if system == GnssSystem::Glonass {
+            u1(p, 3) as i8 - 7
+        } else {
+            0
+        },
+        words: (0..count).map(|k| u4(p, 8 + 4 * k)).collect(),
+    })
+}
+
+fn decode_nav_pvt(p: &[u8], reference_week: i32) -> Option<UbxNavPvt> {
+    if p.len() < 92 {
+        return None;
+    }
+    let gps_time = u4(p, 0) as f64 * 1e-3;
+    // the week comes from the utc date when it is valid, the gps time of week is
+    // ahead of utc by the leap seconds so the week can roll over in between
+    let valid = u1(p, 11);
+    let gps_week = if valid & 3 == 3 {
+        let second = u1(p, 10) as f64 + i4(p, 16) as f64 * 1e-9;
+        let (week, tow) = gps_time_from_date(
+            u2(p, 4) as i32,
+            u1(p, 6) as i32,
+            u1(p, 7) as i32,
+            u1(p, 8) as i32,
+            u1(p, 9) as i32,
+            second,
+        );
+        if gps_time - tow < -SECONDS_PER_WEEK / 2. {
+            week + 1
+        } else {
+            week
+        }
+    } else {
+        reference_week
+    };
+    let flags = u1(p, 21);
+    Some(UbxNavPvt {
+        gps_week,
+        gps_time,
+        fix_type: u1(p, 20),
+        fix_ok: flags & 1 != 0,
+        differential: flags & 2 != 0,
+        carrier_solution: flags >> 6,
+        satellites: u1(p, 23) as usize,
+        lla: Vector3::new(
+            i4(p, 28) as f64 * 1e-7,
+            i4(p, 24) as f64 * 1e-7,
+            i4(p, 32) as f64 * 1e-3,
+        ),
+        height_msl: i4(p, 36) as f64 * 1e-3,
+        horizontal_accuracy: u4(p, 40) as f64 * 1e-3,
+        vertical_accuracy: u4(p, 44) as f64 * 1e-3,
+        velocity: Vector3::new(
+            i4(p, 48) as f64 * 1e-3,
+            i4(p, 52) as f64 * 1e-3,
+            i4(p, 56) as f64 * 1e-3,
+        ),
+        speed_accuracy: u4(p, 68) as f64 * 1e-3,
+        pdop: u2(p, 76) as f64 * 0.01,
+    })
+}
+
+fn decode_nav_sat(p: &[u8]) -> Option<UbxNavSat> {
+    if p.len() < 8 {
+        return None;
+    }
+    let count = u1(p, 5) as usize;
+    if p.len() < 8 + 12 * count {
+        return None;
+    }
+    let mut satellites = vec![];
+    for k in 0..count {
+        let s = &p[8 + 12 * k..20 + 12 * k];
+        let system = match system(u1(s, 0)) {
+            Some(system) => system,
+            None => continue,
+        };
+        let flags = u4(s, 8);
+        let elevation = i1(s, 3);
+        satellites.push(UbxSatInfo {
+            system,
+            prn: u1(s, 1) as usize,
+            cn0: u1(s, 2) as f64,
+            // elevation outside +-90 means the direction is not known
+            elevation: if elevation.abs() <= 90 {
+                elevation as f64
+            } else {
+                -91.
+            },
+            azimuth: i2(s, 4) as f64,
+            residual: i2(s, 6) as f64 * 0.1,
+            quality: (flags & 7) as u8,
+            used: flags & 8 != 0,
+            health: ((flags >> 4) & 3) as u8,
+        });
+    }
+    Some(UbxNavSat {
+        gps_time: u4(p, 0) as f64 * 1e-3,
+        satellites,
+    })
+}
+
+pub fn ubx_track(messages: &[UbxMessage]) -> Vec<NmeaFix> {
+    // NAV-PVT solutions as fixes, for compare_track against rgps positions
+    messages
+        .iter()
+        .filter_map(|m| match m {
+            UbxMessage::NavPvt(p) if p.quality() > 0 => Some(NmeaFix {
+                gps_week: p.gps_week,
+                gps_time: p.gps_time,
+                lla: p.lla,
+                quality: p.quality(),
+                satellites: p.satellites,
+                hdop: None,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn read_ubx(filename: &str, gps_week: i32) -> Result<Vec<UbxMessage>, Error> {
+    // every message in a binary ubx log
+    let mut bytes = vec![];
+    File::open(filename)?.read_to_end(&mut bytes)?;
+    let mut decoder = UbxDecoder::new(gps_week);
+    Ok(decoder.push(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(class: u8, id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut body = vec![class, id, payload.len() as u8, (payload.len() >> 8) as u8];
+        body.extend_from_slice(payload);
+        let (a, b) = ubx_checksum(&body);
+        let mut frame = SYNC.to_vec();
+        frame.extend(body);
+        frame.extend([a, b]);
+        frame
+    }
+
+    fn put(data: &mut [u8; 30], start: usize, n: usize, value: i64) {
+        for k in 0..n {
+            let bit = (value >> (n - 1 - k)) & 1;
+            data[(start + k) / 8] |= (bit as u8) << (7 - (start + k) % 8);
+        }
+    }
+
+    fn lnav_subframes(ephemeris: &SatelliteData) -> [[u8; 30]; 3] {
+        // subframes 1 to 3 in the layout read by decode_lnav
+        let (clock, o) = ephemeris.broadcast();
+        let q = |v: f64, scale: f64| (v / scale).round() as i64;
+        let mut sf = [[0u8; 30]; 3];
+        for (k, s) in sf.iter_mut().enumerate() {
+            put(s, 0, 8, LNAV_PREAMBLE as i64);
+            put(s, 24, 17, 1000 + k as i64);
+            put(s, 43, 3, k as i64 + 1);
+        }
+        let ura = URA.iter().position(|&u| u >= o[20]).unwrap_or(15);
+        let iodc = o[23] as i64;
+        put(&mut sf[0], 48, 10, o[18] as i64 % 1024);
+        put(&mut sf[0], 58, 2, o[17] as i64);
+        put(&mut sf[0], 60, 4, ura as i64);
+        put(&mut sf[0], 64, 6, o[21] as i64);
+        put(&mut sf[0], 70, 2, iodc >> 8);
+        put(&mut sf[0], 72, 1, o[19] as i64);
+        put(&mut sf[0], 160, 8, q(o[22], p2(-31)));
+        put(&mut sf[0], 168, 8, iodc);
+        put(&mut sf[0], 176, 16, q(ephemeris.toc(), 16.));
+        put(&mut sf[0], 192, 8, q(clock[2], p2(-55)));
+        put(&mut sf[0], 200, 16, q(clock[1], p2(-43)));
+        put(&mut sf[0], 216, 22, q(clock[0], p2(-31)));
+
+        put(&mut sf[1], 48, 8, o[0] as i64);
+        put(&mut sf[1], 56, 16, q(o[1], p2(-5)));
+        put(&mut sf[1], 72, 16, q(o[2] / PI, p2(-43)));
+        put(&mut sf[1], 88, 32, q(o[3] / PI, p2(-31)));
+        put(&mut sf[1], 120, 16, q(o[4], p2(-29)));
+        put(&mut sf[1], 136, 32, q(o[5], p2(-33)));
+        put(&mut sf[1], 168, 16, q(o[6], p2(-29)));
+        put(&mut sf[1], 184, 32, q(o[7], p2(-19)));
+        put(&mut sf[1], 216, 16, q(o[8], 16.));
+        put(&mut sf[1], 232, 1, (o[25] > 4.) as i64);
+
+        put(&mut sf[2], 48, 16, q(o[9], p2(-29)));
+        put(&mut sf[2], 64, 32, q(o[10] / PI, p2(-31)));
+        put(&mut sf[2], 96, 16, q(o[11], p2(-29)));
+        put(&mut sf[2], 112, 32, q(o[12] / PI, p2(-31)));
+        put(&mut sf[2], 144, 16, q(o[13], p2(-5)));
+        put(&mut sf[2], 160, 32, q(o[14] / PI, p2(-31)));
+        put(&mut sf[2], 192, 24, q(o[15] / PI, p2(-43)));
+        put(&mut sf[2], 216, 8, o[0] as i64);
+        put(&mut sf[2], 224, 14, q(o[16] / PI, p2(-43)));
+        sf
+    }
+
+    fn sfrbx(prn: usize, subframe: &[u8; 30]) -> Vec<u8> {
+        // gps l1 c/a, ten words with the data in bits 29 to 6 and zero parity
+        let mut payload = vec![0, prn as u8, 0, 0, 10, 0, 2, 0];
+        for w in subframe.chunks(3) {
+            let word = ((w[0] as u32) << 16 | (w[1] as u32) << 8 | w[2] as u32) << 6;
+            payload.extend(word.to_le_bytes());
+        }
+        frame(0x02, 0x13, &payload)
+    }
+
+    #[test]
+    fn checksum_of_known_frames() {
+        // MON-VER and NAV-PVT polls
+        assert_eq!(ubx_checksum(&[0x0A, 0x04, 0x00, 0x00]), (0x0E, 0x34));
+        assert_eq!(ubx_checksum(&[0x01, 0x07, 0x00, 0x00]), (0x08, 0x19));
+        assert_eq!(
+            frame(0x0A, 0x04, &[]),
+            [0xB5, 0x62, 0x0A, 0x04, 0, 0, 0x0E, 0x34]
+        );
+    }
+
+    #[test]
+    fn decoder_resyncs() {
+        let good = frame(0x0A, 0x04, &[1, 2, 3, 4]);
+        let unsupported = |m: &[UbxMessage]| {
+            m.iter()
+                .filter(|m| matches!(m, UbxMessage::Unsupported(0x0A, 0x04)))
+                .count()
+        };
+
+        // bad checksum
+        let mut bad = good.clone();
+        *bad.last_mut().unwrap() ^= 0xFF;
+        let mut decoder = UbxDecoder::new(2274);
+        let messages = decoder.push(&[bad.clone(), good.clone()].concat());
+        assert_eq!((unsupported(&messages), decoder.checksum_errors), (1, 1));
+
+        // length above MAX_PAYLOAD is a false sync, not a frame to wait for
+        let mut decoder = UbxDecoder::new(2274);
+        let messages = decoder.push(&[&[0xB5, 0x62, 0x01, 0x07, 0xFF, 0xFF], &good[..]].concat());
+        assert_eq!((unsupported(&messages), decoder.checksum_errors), (1, 0));
+
+        // a truncated frame swallows the start of the next one, which is found again
+        let mut decoder = UbxDecoder::new(2274);
+        let truncated = &frame(0x01, 0x07, &[0; 12])[..10];
+        let messages = decoder.push(&[truncated, &good, &good].concat());
+        assert_eq!((unsupported(&messages), decoder.checksum_errors), (2, 1));
+
+        // a frame cut at the end of a push is completed by the next one
+        let mut decoder = UbxDecoder::new(2274);
+        assert!(decoder.push(&good[..good.len() - 3]).is_empty());
+        assert_eq!(unsupported(&decoder.push(&good[good.len() - 3..])), 1);
+        assert_eq!(decoder.checksum_errors, 0);
+    }
+
+    #[test]
+    fn chunked_input() {
+        let stream: Vec<u8> = (0..20u8)
+            .flat_map(|k| frame(0x0A, 0x04, &vec![k; k as usize]))
+            .collect();
+        for size in [1, 2, 3, 7, 64] {
+            let mut decoder = UbxDecoder::new(2274);
+            let messages: Vec<UbxMessage> =
+                stream.chunks(size).flat_map(|c| decoder.push(c)).collect();
+            assert_eq!(messages.len(), 20, "chunks of {}", size);
+            assert_eq!(decoder.checksum_errors, 0);
+        }
+    }
+
+    #[test]
+    fn sfrbx_ephemeris() {
+        // decode_lnav needs iode to repeat the low byte of iodc, as in every record here
+        let ephemerides = rinex2_nav_all("brdc2180.23n").unwrap();
+        assert!(ephemerides
+            .iter()
+            .all(|e| e.broadcast().1[23] as u64 & 0xFF == e.iode() as u64));
+
+        let mut decoder = UbxDecoder::new(2274);
+        for original in ephemerides.iter() {
+            let sf = lnav_subframes(original);
+            let stream: Vec<u8> = sf.iter().flat_map(|s| sfrbx(original.prn(), s)).collect();
+            let messages = decoder.push(&stream);
+            assert_eq!(messages.len(), 4);
+            let UbxMessage::Ephemeris(decoded) = &messages[3] else {
+                panic!("{:?}", messages[3]);
+            };
+
+            let (c0, o0) = original.broadcast();
+            let (c1, o1) = decoded.broadcast();
+            assert_eq!(decoded.prn(), original.prn());
+            assert_eq!(decoded.toc_epoch(), original.toc_epoch());
+            assert_eq!((o1[0], o1[8], o1[18]), (o0[0], o0[8], o0[18]));
+            assert_eq!((o1[21], o1[23]), (o0[21], o0[23]));
+            assert_eq!(o1[25], if o0[25] > 4. { 6. } else { 4. });
+            assert!((c0[0] - c1[0]).abs() <= p2(-32));
+            let t = original.toe() + 3600.;
+            let (s0, s1) = (original.state_at(t), decoded.state_at(t));
+            assert!(
+                (s0.position - s1.position).norm() < 1e-3,
+                "prn {}",
+                original.prn()
+            );
+            assert!((s0.clock_bias - s1.clock_bias).abs() < 1e-12);
+        }
+        assert_eq!(decoder.checksum_errors, 0);
+    }
+}